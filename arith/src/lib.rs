@@ -0,0 +1,375 @@
+// An example plugin that exports several functions, each registered with `#[plugin_export]` (from
+// the `host-derive` crate): `add(a: Int, b: Int) -> Int`, which saturates rather than overflowing,
+// `count(text: String, pattern: String?) -> Int`, which counts the occurrences of `pattern` in
+// `text` (or its characters, without a pattern), `halve(x: Double) -> Double` and
+// `is_even(x: Int) -> Bool`. They're described and called through `plugin_describe_function`,
+// `plugin_describe_function_arg` and `plugin_call`, in order of name; `plugin_metadata` and
+// `plugin_entrypoint` describe and call the first of them, for hosts that only know about one.
+//
+// The FFI types are copied from the host, as in the `scale` plugin.
+use host_derive::plugin_export;
+use std::ffi::{CStr, CString};
+use std::panic::catch_unwind;
+use std::sync::OnceLock;
+
+#[repr(C)]
+pub enum PluginValue {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    String(*const i8),
+    Json(*const i8),
+    Handle(u64),
+    List {
+        items: *const PluginValue,
+        len: usize,
+    },
+    Bytes {
+        ptr: *const u8,
+        len: usize,
+    },
+    Custom {
+        type_id: u32,
+        ptr: *const u8,
+        len: usize,
+    },
+    // No value, returned by a plugin that succeeded but has nothing to return (e.g., a lookup that
+    // found nothing); this is distinct from an error
+    Null,
+    // A file descriptor (on Windows, a HANDLE) for a file opened by the host, which controls which
+    // files the plugin can access. It's only borrowed by the plugin, which must not close it; the
+    // host closes it after the call. File descriptors can be passed as arguments, but not returned.
+    Fd(i64),
+    // The outcome of an operation within a successful call, like a Rust `Result`. Results passed
+    // as arguments are borrowed by the plugin; returned ones must point to a boxed value
+    // (`Box<PluginValue>`), which is freed by the host along with the value it contains.
+    ResultOk(*const PluginValue),
+    ResultErr(*const PluginValue),
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum PluginType {
+    Bool,
+    Int,
+    UInt,
+    Double,
+    String,
+    Json,
+    Handle,
+    List,
+    Bytes,
+    Fd,
+    Result,
+}
+
+#[repr(C)]
+pub enum PluginResult {
+    Ok(PluginValue),
+    PartialOk {
+        value: PluginValue,
+        warning: *mut i8,
+    },
+    // The host is responsible for freeing the error message
+    Err {
+        code: PluginErrorCode,
+        message: *mut i8,
+        // An optional JSON payload with structured details of the error (e.g., which argument was
+        // invalid), or null; the host is also responsible for freeing it
+        payload: *mut i8,
+    },
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum PluginErrorCode {
+    Failed,
+    InvalidArgument,
+    Panicked,
+    Cancelled,
+}
+
+#[repr(C)]
+pub struct PluginMetadata {
+    pub name: *const i8,
+    pub arg_types: *const PluginType,
+    pub arg_types_len: usize,
+    pub return_type: PluginType,
+    pub required_host_capabilities: u64,
+}
+
+#[repr(C)]
+pub struct ArgDescriptor {
+    pub name: *const i8,
+    pub arg_type: PluginType,
+    pub optional: bool,
+    pub doc: *const i8,
+    pub secret: bool,
+    pub min: *const PluginValue,
+    pub max: *const PluginValue,
+    pub max_len: usize,
+}
+
+// A function registered by `#[plugin_export]`. `call` converts the arguments (whose count has
+// already been checked) and the result, returning an error message if an argument has the wrong type.
+pub struct PluginFunction {
+    pub name: &'static CStr,
+    pub arg_names: &'static [&'static CStr],
+    pub arg_types: &'static [PluginType],
+    // Whether each argument is optional; optional arguments come after all required ones
+    pub optional: &'static [bool],
+    pub return_type: PluginType,
+    pub call: fn(&[PluginValue]) -> Result<PluginValue, String>,
+}
+
+impl PluginFunction {
+    // The number of arguments the host must pass, i.e. up to and including the last required one
+    fn required_args(&self) -> usize {
+        self.optional.iter().rposition(|o| !o).map_or(0, |i| i + 1)
+    }
+}
+
+inventory::collect!(PluginFunction);
+
+// A type that `#[plugin_export]` functions can take and return, borrowing from the arguments for
+// `'a`
+pub trait ExportValue<'a>: Sized {
+    const TYPE: PluginType;
+    // Whether the argument may be omitted by the host, in which case it's `from_missing`
+    const OPTIONAL: bool = false;
+
+    // The argument as this type, or None if it has another type
+    fn from_arg(value: &'a PluginValue) -> Option<Self>;
+
+    // The value of an argument the host omitted, or None if it's required
+    fn from_missing() -> Option<Self> {
+        None
+    }
+
+    fn into_value(self) -> PluginValue;
+}
+
+// An optional argument, which is None if the host omits it or passes Null; returned, None is Null
+impl<'a, T: ExportValue<'a>> ExportValue<'a> for Option<T> {
+    const TYPE: PluginType = T::TYPE;
+    const OPTIONAL: bool = true;
+
+    fn from_arg(value: &'a PluginValue) -> Option<Self> {
+        match value {
+            PluginValue::Null => Some(None),
+            value => T::from_arg(value).map(Some),
+        }
+    }
+
+    fn from_missing() -> Option<Self> {
+        Some(None)
+    }
+
+    fn into_value(self) -> PluginValue {
+        self.map_or(PluginValue::Null, T::into_value)
+    }
+}
+
+impl ExportValue<'_> for i64 {
+    const TYPE: PluginType = PluginType::Int;
+
+    fn from_arg(value: &PluginValue) -> Option<Self> {
+        match value {
+            PluginValue::Int(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> PluginValue {
+        PluginValue::Int(self)
+    }
+}
+
+impl ExportValue<'_> for f64 {
+    const TYPE: PluginType = PluginType::Double;
+
+    fn from_arg(value: &PluginValue) -> Option<Self> {
+        match value {
+            PluginValue::Double(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> PluginValue {
+        PluginValue::Double(self)
+    }
+}
+
+impl ExportValue<'_> for bool {
+    const TYPE: PluginType = PluginType::Bool;
+
+    fn from_arg(value: &PluginValue) -> Option<Self> {
+        match value {
+            PluginValue::Bool(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> PluginValue {
+        PluginValue::Bool(self)
+    }
+}
+
+// Strings borrow the host's argument, which must be valid UTF-8
+impl<'a> ExportValue<'a> for &'a str {
+    const TYPE: PluginType = PluginType::String;
+
+    fn from_arg(value: &'a PluginValue) -> Option<Self> {
+        match value {
+            PluginValue::String(s) if !s.is_null() => unsafe { CStr::from_ptr(*s) }.to_str().ok(),
+            _ => None,
+        }
+    }
+
+    // The host takes ownership of the copy
+    fn into_value(self) -> PluginValue {
+        PluginValue::String(CString::new(self).unwrap_or_default().into_raw())
+    }
+}
+
+#[plugin_export]
+fn add(a: i64, b: i64) -> i64 {
+    a.saturating_add(b)
+}
+
+#[plugin_export]
+fn count(text: &str, pattern: Option<&str>) -> i64 {
+    match pattern {
+        Some(pattern) => text.matches(pattern).count() as i64,
+        None => text.chars().count() as i64,
+    }
+}
+
+#[plugin_export]
+fn halve(x: f64) -> f64 {
+    x / 2.0
+}
+
+#[plugin_export]
+fn is_even(x: i64) -> bool {
+    x % 2 == 0
+}
+
+// The registered functions, sorted by name so that their indexes don't depend on link order
+fn functions() -> &'static [&'static PluginFunction] {
+    static FUNCTIONS: OnceLock<Vec<&'static PluginFunction>> = OnceLock::new();
+    FUNCTIONS.get_or_init(|| {
+        let mut functions: Vec<_> = inventory::iter::<PluginFunction>.into_iter().collect();
+        functions.sort_by_key(|f| f.name);
+        functions
+    })
+}
+
+fn describe(function: &PluginFunction) -> PluginMetadata {
+    PluginMetadata {
+        name: function.name.as_ptr(),
+        arg_types: function.arg_types.as_ptr(),
+        arg_types_len: function.arg_types.len(),
+        return_type: function.return_type,
+        required_host_capabilities: 0,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn plugin_metadata() -> PluginMetadata {
+    describe(functions()[0])
+}
+
+// Past the last function, the name is null, which ends the host's list
+#[no_mangle]
+pub extern "C" fn plugin_describe_function(index: usize) -> PluginMetadata {
+    match functions().get(index) {
+        Some(function) => describe(function),
+        None => PluginMetadata {
+            name: std::ptr::null(),
+            arg_types: std::ptr::null(),
+            arg_types_len: 0,
+            return_type: PluginType::Bool,
+            required_host_capabilities: 0,
+        },
+    }
+}
+
+// Describes argument `index` of the function at `function`, marking the arguments that are
+// `Option`s as optional. Past the last argument (or function), the name is null.
+#[no_mangle]
+pub extern "C" fn plugin_describe_function_arg(function: usize, index: usize) -> ArgDescriptor {
+    let descriptor = |name: *const i8, arg_type, optional| ArgDescriptor {
+        name,
+        arg_type,
+        optional,
+        doc: std::ptr::null(),
+        secret: false,
+        min: std::ptr::null(),
+        max: std::ptr::null(),
+        max_len: 0,
+    };
+    match functions().get(function) {
+        Some(f) if index < f.arg_names.len() => descriptor(
+            f.arg_names[index].as_ptr(),
+            f.arg_types[index],
+            f.optional[index],
+        ),
+        _ => descriptor(std::ptr::null(), PluginType::Bool, false),
+    }
+}
+
+fn plugin_error(code: PluginErrorCode, message: impl Into<String>) -> PluginResult {
+    PluginResult::Err {
+        code,
+        message: CString::new(message.into()).unwrap().into_raw(),
+        payload: std::ptr::null_mut(),
+    }
+}
+
+// See the `repeat` plugin for a description of how the entrypoint works
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn plugin_call(
+    index: usize,
+    args: *const PluginValue,
+    args_len: usize,
+) -> PluginResult {
+    let Some(function) = functions().get(index) else {
+        return plugin_error(PluginErrorCode::InvalidArgument, "no function at index");
+    };
+
+    // a buggy host could pass a null pointer, which we must not dereference
+    if args_len > 0 && args.is_null() {
+        return plugin_error(PluginErrorCode::InvalidArgument, "args is null");
+    }
+
+    // trailing optional arguments may be omitted
+    if args_len < function.required_args() || args_len > function.arg_types.len() {
+        return plugin_error(
+            PluginErrorCode::InvalidArgument,
+            format!(
+                "args_len should be {} to {}",
+                function.required_args(),
+                function.arg_types.len()
+            ),
+        );
+    }
+
+    let args = if args_len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(args, args_len) }
+    };
+    match catch_unwind(|| (function.call)(args)) {
+        Ok(Ok(value)) => PluginResult::Ok(value),
+        Ok(Err(message)) => plugin_error(PluginErrorCode::InvalidArgument, message),
+        Err(_) => plugin_error(PluginErrorCode::Panicked, "function panicked"),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn plugin_entrypoint(args: *const PluginValue, args_len: usize) -> PluginResult {
+    plugin_call(0, args, args_len)
+}
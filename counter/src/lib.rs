@@ -0,0 +1,123 @@
+// An example of a stateful plugin, implementing `counter() -> UInt`, which returns how many times
+// it has been called. It exports `plugin_reset`, which sets the count back to zero, so that a host
+// (e.g. a test harness) can start from a clean state with `Plugin::reset` rather than reloading it.
+//
+// The FFI types are copied from the host, as in the `scale` plugin.
+use std::ptr::null;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[repr(C)]
+pub enum PluginValue {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    String(*const i8),
+    Json(*const i8),
+    Handle(u64),
+    List {
+        items: *const PluginValue,
+        len: usize,
+    },
+    Bytes {
+        ptr: *const u8,
+        len: usize,
+    },
+    Custom {
+        type_id: u32,
+        ptr: *const u8,
+        len: usize,
+    },
+    // No value, returned by a plugin that succeeded but has nothing to return (e.g., a lookup that
+    // found nothing); this is distinct from an error
+    Null,
+    // A file descriptor (on Windows, a HANDLE) for a file opened by the host, which controls which
+    // files the plugin can access. It's only borrowed by the plugin, which must not close it; the
+    // host closes it after the call. File descriptors can be passed as arguments, but not returned.
+    Fd(i64),
+    // The outcome of an operation within a successful call, like a Rust `Result`. Results passed
+    // as arguments are borrowed by the plugin; returned ones must point to a boxed value
+    // (`Box<PluginValue>`), which is freed by the host along with the value it contains.
+    ResultOk(*const PluginValue),
+    ResultErr(*const PluginValue),
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum PluginType {
+    Bool,
+    Int,
+    UInt,
+    Double,
+    String,
+    Json,
+    Handle,
+    List,
+    Bytes,
+    Fd,
+    Result,
+}
+
+#[repr(C)]
+pub enum PluginResult {
+    Ok(PluginValue),
+    PartialOk {
+        value: PluginValue,
+        warning: *mut i8,
+    },
+    // The host is responsible for freeing the error message
+    Err {
+        code: PluginErrorCode,
+        message: *mut i8,
+        // An optional JSON payload with structured details of the error (e.g., which argument was
+        // invalid), or null; the host is also responsible for freeing it
+        payload: *mut i8,
+    },
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum PluginErrorCode {
+    Failed,
+    InvalidArgument,
+    Panicked,
+    Cancelled,
+}
+
+#[repr(C)]
+pub struct PluginMetadata {
+    pub name: *const i8,
+    pub arg_types: *const PluginType,
+    pub arg_types_len: usize,
+    pub return_type: PluginType,
+    pub required_host_capabilities: u64,
+}
+
+// The number of calls since the plugin was loaded or last reset
+static CALLS: AtomicU64 = AtomicU64::new(0);
+
+#[no_mangle]
+pub extern "C" fn plugin_metadata() -> PluginMetadata {
+    PluginMetadata {
+        name: c"counter".as_ptr(),
+        arg_types: null(),
+        arg_types_len: 0,
+        return_type: PluginType::UInt,
+        required_host_capabilities: 0,
+    }
+}
+
+// See the `repeat` plugin for a description of how the entrypoint works. The host checks the
+// argument count against our metadata, so we take no arguments and can't fail.
+#[no_mangle]
+pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _args_len: usize) -> PluginResult {
+    PluginResult::Ok(PluginValue::UInt(CALLS.fetch_add(1, Ordering::Relaxed) + 1))
+}
+
+// Returns the plugin to the state it was in when it was loaded. The host doesn't call this during
+// a call, so there's nothing else to synchronize with.
+#[no_mangle]
+pub extern "C" fn plugin_reset() -> PluginResult {
+    CALLS.store(0, Ordering::Relaxed);
+    PluginResult::Ok(PluginValue::Null)
+}
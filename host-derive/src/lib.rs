@@ -0,0 +1,166 @@
+// A derive macro for `host::IntoPluginArgs`, which converts a struct into a plugin's positional
+// arguments, one field per argument in declaration order. This is re-exported by the host crate
+// when it's built with the `derive` feature, and the generated code refers to it as `::host`.
+//
+// Also `#[plugin_export]`, for plugins that export several functions (see the `arith` plugin).
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, FnArg, ItemFn, Pat, ReturnType};
+
+#[proc_macro_derive(IntoPluginArgs)]
+pub fn derive_into_plugin_args(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Error::new_spanned(
+                    &input,
+                    "IntoPluginArgs can only be derived for structs with named fields",
+                )
+                .to_compile_error()
+                .into();
+            }
+        },
+        _ => {
+            return Error::new_spanned(&input, "IntoPluginArgs can only be derived for structs")
+                .to_compile_error()
+                .into();
+        }
+    };
+
+    let idents: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let names: Vec<_> = idents.iter().map(|ident| ident.to_string()).collect();
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::host::IntoPluginArgs for #name #ty_generics #where_clause {
+            const ARG_NAMES: &'static [&'static str] = &[#(#names),*];
+
+            fn into_plugin_args(
+                self,
+            ) -> ::std::result::Result<::std::vec::Vec<::host::OwnedPluginValue>, ::host::PluginError>
+            {
+                ::std::result::Result::Ok(::std::vec![
+                    #(::host::IntoPluginArg::into_plugin_arg(self.#idents)?),*
+                ])
+            }
+        }
+    }
+    .into()
+}
+
+// Registers a function with the plugin's function registry, so that it's described by
+// `plugin_describe_function` and called by `plugin_call`. The plugin crate must define (at its root)
+// the `PluginFunction` struct that's registered through `inventory`, and the `ExportValue` trait,
+// which each argument and return type must implement; the `arith` plugin shows both. The function
+// is exported under its own name, and keeps working as a plain Rust function. Arguments whose
+// `ExportValue` is `OPTIONAL` (e.g. `Option<T>`) are registered as optional, and get the value of
+// `ExportValue::from_missing` when the host omits them.
+#[proc_macro_attribute]
+pub fn plugin_export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(
+            proc_macro::Span::call_site().into(),
+            "plugin_export doesn't take arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let function = parse_macro_input!(item as ItemFn);
+
+    let mut arg_types = vec![];
+    let mut arg_names = vec![];
+    for input in &function.sig.inputs {
+        let arg = match input {
+            FnArg::Typed(arg) => match &*arg.pat {
+                Pat::Ident(pat) => Some((&arg.ty, &pat.ident)),
+                _ => None,
+            },
+            FnArg::Receiver(_) => None,
+        };
+        let Some((ty, name)) = arg else {
+            return Error::new_spanned(
+                input,
+                "plugin_export arguments must be plain `name: Type` arguments",
+            )
+            .to_compile_error()
+            .into();
+        };
+        arg_types.push(ty);
+        arg_names.push(c_str(name));
+    }
+    let return_type = match &function.sig.output {
+        ReturnType::Type(_, ty) => ty,
+        ReturnType::Default => {
+            return Error::new_spanned(
+                &function.sig,
+                "plugin_export functions must return a value",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+    if !function.sig.generics.params.is_empty() || function.sig.asyncness.is_some() {
+        return Error::new_spanned(
+            &function.sig,
+            "plugin_export functions can't be generic or async",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let ident = &function.sig.ident;
+    let wrapper = format_ident!("__plugin_export_{}", ident);
+    let name = c_str(ident);
+    let indices = 0..arg_types.len();
+
+    quote! {
+        #function
+
+        // converts the arguments, whose count the registry has already checked (though trailing
+        // optional ones may be missing), and the result
+        #[doc(hidden)]
+        fn #wrapper(
+            args: &[crate::PluginValue],
+        ) -> ::std::result::Result<crate::PluginValue, ::std::string::String> {
+            ::std::result::Result::Ok(crate::ExportValue::into_value(#ident(#(
+                match args.get(#indices) {
+                    ::std::option::Option::Some(arg) => {
+                        <#arg_types as crate::ExportValue>::from_arg(arg).ok_or_else(|| {
+                            ::std::format!("arg{} is invalid; expected {:?}", #indices, <#arg_types as crate::ExportValue>::TYPE)
+                        })?
+                    }
+                    ::std::option::Option::None => {
+                        <#arg_types as crate::ExportValue>::from_missing().ok_or_else(|| {
+                            ::std::format!("arg{} is missing", #indices)
+                        })?
+                    }
+                }
+            ),*)))
+        }
+
+        ::inventory::submit! {
+            crate::PluginFunction {
+                name: #name,
+                arg_names: &[#(#arg_names),*],
+                arg_types: &[#(<#arg_types as crate::ExportValue>::TYPE),*],
+                optional: &[#(<#arg_types as crate::ExportValue>::OPTIONAL),*],
+                return_type: <#return_type as crate::ExportValue>::TYPE,
+                call: #wrapper,
+            }
+        }
+    }
+    .into()
+}
+
+// The name of an identifier as a C string literal
+fn c_str(ident: &syn::Ident) -> syn::LitCStr {
+    syn::LitCStr::new(
+        &std::ffi::CString::new(ident.to_string()).unwrap(),
+        ident.span(),
+    )
+}
@@ -0,0 +1,107 @@
+// Audits every plugin in a directory, for CI that ships many plugins. Each plugin is inspected
+// rather than loaded, so that auditing never runs its `plugin_init` (see `Plugin::inspect`); reading
+// its metadata already rejects null or unterminated names and unknown types. It's then checked for
+// inconsistencies the host would otherwise only trip over when calling it:
+//
+// - its argument descriptions must agree with its declared argument types, and describe all of them
+// - optional arguments must come after the required ones, as only trailing ones can be omitted
+//
+// With `--audit-probe`, each plugin is also loaded (running its `plugin_init`, with the host's
+// config) and called with no arguments, to check that its entrypoint rejects a call with too few
+// arguments rather than reading past the end of them. That's opt-in, as calling a plugin needs it
+// initialized, with whatever side effects that has.
+//
+// A line is printed for each plugin, followed by its problems, e.g.:
+//
+// ok    libplugin.so
+// FAIL  libbroken.so
+//       arg1 is described as String, but declared as UInt
+use host::{OptionalSymbol, OwnedMetadata, Plugin, PluginError};
+use std::collections::HashMap;
+use std::env::consts::DLL_EXTENSION;
+use std::ffi::CString;
+use std::path::Path;
+
+// Audits the plugins in `dir` (the files with the platform's library extension), returning how many
+// of them failed to load or had a problem, and how many were audited. With `probe`, each plugin is
+// also initialized with `config` and called.
+pub fn audit(
+    dir: &Path,
+    config: &HashMap<CString, CString>,
+    probe: bool,
+) -> Result<(usize, usize), PluginError> {
+    let entries = std::fs::read_dir(dir).map_err(|e| {
+        PluginError::Usage(format!("Could not read directory {}: {}", dir.display(), e))
+    })?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && path.extension() == Some(DLL_EXTENSION.as_ref()))
+        .collect();
+    paths.sort();
+
+    let mut failed = 0;
+    for path in &paths {
+        let problems = match Plugin::inspect_exports(path) {
+            Ok((metadata, exports)) => {
+                let mut problems = problems(&metadata, &exports);
+                if probe {
+                    if let Err(e) = Plugin::load(path, config.clone())
+                        .and_then(|plugin| plugin.check_enforces_arg_count())
+                    {
+                        problems.push(e.to_string());
+                    }
+                }
+                problems
+            }
+            Err(e) => vec![e.to_string()],
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if problems.is_empty() {
+            println!("ok    {}", name);
+            continue;
+        }
+        failed += 1;
+        println!("FAIL  {}", name);
+        for problem in problems {
+            println!("      {}", problem);
+        }
+    }
+
+    Ok((failed, paths.len()))
+}
+
+// Describes everything wrong with a plugin's metadata, given the optional symbols it exports
+fn problems(metadata: &OwnedMetadata, exports: &[OptionalSymbol]) -> Vec<String> {
+    let mut problems = vec![];
+
+    match &metadata.args {
+        Some(args) => {
+            for (i, (arg, arg_type)) in args.iter().zip(&metadata.arg_types).enumerate() {
+                if arg.arg_type != *arg_type {
+                    problems.push(format!(
+                        "arg{} is described as {}, but declared as {}",
+                        i, arg.arg_type, arg_type
+                    ));
+                }
+            }
+            let required = metadata.required_args();
+            if let Some(i) = args.iter().skip(required).position(|arg| !arg.optional) {
+                problems.push(format!(
+                    "arg{} is required, but follows an optional argument",
+                    required + i
+                ));
+            }
+        }
+        // the host ignores descriptions that stop short of the declared arguments
+        None if exports.contains(&OptionalSymbol::DescribeArg)
+            && !metadata.arg_types.is_empty() =>
+        {
+            problems.push(format!(
+                "describes fewer arguments than the {} it declares",
+                metadata.arg_types.len()
+            ));
+        }
+        None => {}
+    }
+    problems
+}
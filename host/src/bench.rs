@@ -0,0 +1,142 @@
+// Measures the overhead of calling a plugin, and guards it against regressions by comparing the
+// measurement against a baseline file. Each line of a baseline file holds a function name and its
+// median call time in nanoseconds, e.g.:
+//
+// repeat 152
+//
+// Timings depend on the machine and build profile, so a baseline is only meaningful when compared
+// against measurements taken the same way (e.g., a release build on the same CI runner).
+use host::{Plugin, PluginError, PreparedArgs};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// How much slower than the baseline a call may be before it counts as a regression
+pub const THRESHOLD: f64 = 0.2;
+
+// The number of timed batches, whose median is reported so that outliers (e.g., from the process
+// being descheduled) don't affect the result
+const BATCHES: usize = 21;
+
+// Returns the median time of a call to the plugin, measured over batches of `iterations` calls.
+// The arguments are marshalled once up front, so this measures the overhead of the call itself.
+pub fn measure(
+    plugin: &Plugin,
+    args: &PreparedArgs,
+    iterations: u32,
+) -> Result<Duration, PluginError> {
+    let batch = || -> Result<Duration, PluginError> {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            // each result is dropped (and freed) within the batch, as part of the call's cost
+            plugin.call_prepared(args)?;
+        }
+        Ok(start.elapsed() / iterations)
+    };
+
+    // the first batch warms up caches and the allocator, so it isn't counted
+    batch()?;
+    let mut times = (0..BATCHES)
+        .map(|_| batch())
+        .collect::<Result<Vec<_>, _>>()?;
+    times.sort();
+    Ok(times[BATCHES / 2])
+}
+
+// Reads the baseline for `function` from the file, returning None if it has no entry for it
+pub fn read_baseline(path: &Path, function: &str) -> Result<Option<Duration>, PluginError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| PluginError::Usage(format!("Could not read {}: {}", path.display(), e)))?;
+    for (i, line) in contents.lines().enumerate() {
+        let Some((name, nanos)) = line.trim().split_once(' ') else {
+            continue;
+        };
+        if name != function {
+            continue;
+        }
+        let nanos = nanos.trim().parse().map_err(|_| {
+            PluginError::Usage(format!(
+                "{}:{}: invalid baseline {:?}",
+                path.display(),
+                i + 1,
+                line
+            ))
+        })?;
+        return Ok(Some(Duration::from_nanos(nanos)));
+    }
+    Ok(None)
+}
+
+// Sets the baseline for `function` in the file, keeping the entries for any other functions
+pub fn write_baseline(path: &Path, function: &str, time: Duration) -> Result<(), PluginError> {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let mut lines: Vec<String> = contents
+        .lines()
+        .filter(|line| line.split(' ').next() != Some(function))
+        .map(String::from)
+        .collect();
+    lines.push(format!("{} {}", function, time.as_nanos()));
+    fs::write(path, lines.join("\n") + "\n")
+        .map_err(|e| PluginError::Usage(format!("Could not write {}: {}", path.display(), e)))
+}
+
+// Describes how much slower `time` is than `baseline`, if it's beyond the threshold
+pub fn regression(time: Duration, baseline: Duration) -> Option<String> {
+    let change = time.as_secs_f64() / baseline.as_secs_f64() - 1.0;
+    (change > THRESHOLD).then(|| {
+        format!(
+            "Call time regressed: {} ns against a baseline of {} ns (+{:.0}%, over the {:.0}% \
+             threshold)",
+            time.as_nanos(),
+            baseline.as_nanos(),
+            change * 100.0,
+            THRESHOLD * 100.0
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_regressions_beyond_threshold() {
+        let baseline = Duration::from_nanos(100);
+        assert_eq!(regression(Duration::from_nanos(50), baseline), None);
+        assert_eq!(regression(Duration::from_nanos(120), baseline), None);
+        assert_eq!(
+            regression(Duration::from_nanos(150), baseline).as_deref(),
+            Some(
+                "Call time regressed: 150 ns against a baseline of 100 ns (+50%, over the 20% \
+                 threshold)"
+            )
+        );
+    }
+
+    #[test]
+    fn updates_baseline_keeping_other_functions() {
+        let path = std::env::temp_dir().join(format!("host-bench-{}.txt", std::process::id()));
+        fs::write(&path, "negate 80\nrepeat 250\n").unwrap();
+
+        write_baseline(&path, "repeat", Duration::from_nanos(240)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "negate 80\nrepeat 240\n"
+        );
+        assert_eq!(
+            read_baseline(&path, "repeat").unwrap(),
+            Some(Duration::from_nanos(240))
+        );
+        assert_eq!(read_baseline(&path, "scale").unwrap(), None);
+
+        fs::write(&path, "repeat fast\n").unwrap();
+        let e = read_baseline(&path, "repeat").unwrap_err();
+        assert!(
+            e.to_string()
+                .ends_with(":1: invalid baseline \"repeat fast\""),
+            "{}",
+            e
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
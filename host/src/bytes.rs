@@ -0,0 +1,149 @@
+use crate::plugin::Library;
+use crate::PluginError;
+use memmap2::Mmap;
+use std::alloc::Layout;
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+// A byte buffer, which is either allocated by the host (e.g., to pass as an argument), mapped from
+// a file by the host, or returned by the plugin. Returned buffers are used in place rather than
+// copied, and are freed through the plugin's `plugin_free_bytes` export when dropped (or by the host,
+// if the plugin allocated them with `HostServices::alloc`), so that memory is always freed by the
+// allocator that allocated it.
+pub struct Bytes(Storage);
+
+enum Storage {
+    Host(Vec<u8>),
+    Mapped(Mmap),
+    Plugin(PluginBuffer),
+}
+
+struct PluginBuffer {
+    ptr: *const u8,
+    len: usize,
+    // keeps the library loaded until the buffer has been freed
+    library: Arc<Library>,
+}
+
+// The buffer is owned exclusively by us until it's freed, and plugins must allow it to be freed
+// from any thread (see the notes on `Library`)
+unsafe impl Send for PluginBuffer {}
+unsafe impl Sync for PluginBuffer {}
+
+impl Drop for PluginBuffer {
+    fn drop(&mut self) {
+        if self.library.host_allocated {
+            // allocated by `host_alloc` with an alignment of 1; empty buffers weren't allocated
+            if self.len != 0 {
+                let layout = Layout::array::<u8>(self.len).expect("the buffer was allocated");
+                unsafe { std::alloc::dealloc(self.ptr as *mut u8, layout) };
+            }
+            return;
+        }
+        unsafe {
+            self.library
+                .container
+                .plugin_free_bytes(self.ptr as *mut u8, self.len)
+        };
+    }
+}
+
+impl Bytes {
+    // Maps the file at `path` into memory read-only, so that large inputs can be passed to the
+    // plugin without reading them in, let alone copying them. Plugins run in the host's process, so
+    // the mapping is passed like any other buffer (as a pointer and length), and the plugin borrows
+    // it for the duration of the call; it's unmapped when this is dropped. The pages are read-only,
+    // so a plugin that writes to them crashes the process, and the file must not be truncated while
+    // it's mapped, as reading pages past its new end raises SIGBUS on unix.
+    pub fn map(path: impl AsRef<Path>) -> Result<Bytes, PluginError> {
+        let path = path.as_ref();
+        let error = |e: std::io::Error| {
+            PluginError::Argument(format!("Could not map {}: {}", path.display(), e))
+        };
+        let file = File::open(path).map_err(error)?;
+        // empty files can't be mapped on every platform, and there's nothing to map anyway
+        if file.metadata().map_err(error)?.len() == 0 {
+            return Ok(Bytes(Storage::Host(vec![])));
+        }
+        let map = unsafe { Mmap::map(&file) }.map_err(error)?;
+        Ok(Bytes(Storage::Mapped(map)))
+    }
+
+    // Takes ownership of a buffer returned by the plugin, which must export `plugin_free_bytes`
+    // to be able to return bytes unless it allocates them with the host's allocator. Buffers longer
+    // than `max_len` are freed and rejected.
+    pub(crate) fn from_plugin(
+        ptr: *const u8,
+        len: usize,
+        library: &Arc<Library>,
+        max_len: usize,
+    ) -> Result<Bytes, PluginError> {
+        if !library.host_allocated && !library.container.has_plugin_free_bytes() {
+            return Err(PluginError::Plugin(
+                "Plugin returned bytes, but doesn't export plugin_free_bytes".to_string(),
+            ));
+        }
+        if ptr.is_null() {
+            if len != 0 {
+                return Err(PluginError::Plugin(
+                    "Plugin returned a null byte buffer".to_string(),
+                ));
+            }
+            return Ok(Bytes(Storage::Host(vec![])));
+        }
+
+        let buffer = PluginBuffer {
+            ptr,
+            len,
+            library: library.clone(),
+        };
+        if len > max_len {
+            return Err(PluginError::Plugin(format!(
+                "Plugin returned bytes longer than the limit of {} bytes",
+                max_len
+            )));
+        }
+        Ok(Bytes(Storage::Plugin(buffer)))
+    }
+}
+
+impl Bytes {
+    // Whether the buffer is mapped from a file (see `Bytes::map`), rather than held in memory
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.0, Storage::Mapped(_))
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Storage::Host(bytes) => bytes,
+            Storage::Mapped(map) => map,
+            Storage::Plugin(buffer) => unsafe {
+                std::slice::from_raw_parts(buffer.ptr, buffer.len)
+            },
+        }
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Bytes(Storage::Host(bytes))
+    }
+}
+
+// Mapped buffers are shown by their length, as they're typically too large to print (and reading
+// them would page in the whole file)
+impl Debug for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_mapped() {
+            return write!(f, "<mapped, {} bytes>", self.len());
+        }
+        write!(f, "b\"{}\"", self.escape_ascii())
+    }
+}
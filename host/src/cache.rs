@@ -0,0 +1,251 @@
+// A cache of plugins' metadata, persisted to a file so that a host managing many plugins can list
+// them without loading every one. Entries are keyed by the plugin's canonical path and store its
+// modification time; a plugin whose file has changed since it was cached is loaded again to refresh
+// its entry. The file is JSON, e.g.:
+//
+// {"plugins":{"/plugins/libplugin.so":{"modified":[1700000000,0],"metadata":{"name":"repeat",...}}},"version":5}
+//
+// The cache is only an optimization, so a missing or unreadable cache file is treated as empty, as
+// is one written in a different version of the format; rather than being migrated, its entries are
+// simply refreshed.
+use crate::ffi::{PluginType, RawPluginType};
+use crate::{ArgInfo, Bound, OwnedMetadata, Plugin, PluginError};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// The version of the cache format, which should be bumped whenever the format changes
+const CACHE_VERSION: u64 = 5;
+
+struct CacheEntry {
+    modified: SystemTime,
+    metadata: OwnedMetadata,
+}
+
+pub struct MetadataCache {
+    path: PathBuf,
+    entries: HashMap<String, CacheEntry>,
+    // Whether any entries have changed since the cache was opened
+    dirty: bool,
+}
+
+impl MetadataCache {
+    // Opens the cache stored at `path`, which doesn't need to exist yet
+    pub fn open(path: impl AsRef<Path>) -> MetadataCache {
+        let path = path.as_ref().to_path_buf();
+        let entries = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<Value>(&s).ok())
+            .filter(|json| json["version"] == CACHE_VERSION)
+            .and_then(|json| {
+                json["plugins"].as_object().map(|plugins| {
+                    plugins
+                        .iter()
+                        .filter_map(|(key, entry)| Some((key.clone(), entry_from_json(entry)?)))
+                        .collect()
+                })
+            })
+            .unwrap_or_default();
+        MetadataCache {
+            path,
+            entries,
+            dirty: false,
+        }
+    }
+
+    // Returns the metadata of the plugin at `plugin_path`, inspecting the plugin (without
+    // initializing it; see `Plugin::inspect`) only if it isn't cached or its file has been modified
+    // since it was
+    pub fn metadata(
+        &mut self,
+        plugin_path: impl AsRef<Path>,
+    ) -> Result<OwnedMetadata, PluginError> {
+        let plugin_path = plugin_path.as_ref();
+        let not_found = |e: std::io::Error| {
+            PluginError::Load(format!(
+                "Could not load plugin {}: {}",
+                plugin_path.display(),
+                e
+            ))
+        };
+        let canonical = plugin_path.canonicalize().map_err(not_found)?;
+        let modified = canonical
+            .metadata()
+            .and_then(|m| m.modified())
+            .map_err(not_found)?;
+        let key = canonical.to_string_lossy().to_string();
+
+        let fresh = self
+            .entries
+            .get(&key)
+            .is_some_and(|entry| entry.modified == modified);
+        if !fresh {
+            let metadata = Plugin::inspect(&canonical)?;
+            self.entries
+                .insert(key.clone(), CacheEntry { modified, metadata });
+            self.dirty = true;
+        }
+        Ok(self.entries[&key].metadata.clone())
+    }
+
+    // Writes the cache back to its file, if any entries have changed
+    pub fn save(&mut self) -> Result<(), PluginError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let plugins: Map<String, Value> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry_to_json(entry)))
+            .collect();
+        std::fs::write(
+            &self.path,
+            json!({ "plugins": plugins, "version": CACHE_VERSION }).to_string(),
+        )
+        .map_err(|e| {
+            PluginError::Usage(format!(
+                "Could not write cache {}: {}",
+                self.path.display(),
+                e
+            ))
+        })?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+// Types are stored as their raw FFI representation, so they're read back with the same validation
+// as types read from a plugin. Null can't be declared by a plugin, so it's never part of its
+// metadata.
+fn type_to_json(plugin_type: PluginType) -> Value {
+    json!(plugin_type.to_raw())
+}
+
+fn type_from_json(value: &Value) -> Option<PluginType> {
+    let raw = RawPluginType::try_from(value.as_i64()?).ok()?;
+    PluginType::try_from(raw).ok()
+}
+
+// Bounds are stored like values in recordings, e.g. {"UInt":10}
+fn bound_to_json(bound: Option<Bound>) -> Value {
+    match bound {
+        Some(Bound::Int(i)) => json!({ "Int": i }),
+        Some(Bound::UInt(u)) => json!({ "UInt": u }),
+        Some(Bound::Double(d)) => json!({ "Double": d }),
+        None => Value::Null,
+    }
+}
+
+// Reads a bound written by `bound_to_json`, returning Some(None) if there isn't one and None if
+// it's invalid
+fn bound_from_json(value: &Value) -> Option<Option<Bound>> {
+    if value.is_null() {
+        return Some(None);
+    }
+    let (kind, value) = value.as_object()?.iter().next()?;
+    Some(Some(match kind.as_str() {
+        "Int" => Bound::Int(value.as_i64()?),
+        "UInt" => Bound::UInt(value.as_u64()?),
+        "Double" => Bound::Double(value.as_f64()?),
+        _ => return None,
+    }))
+}
+
+fn entry_to_json(entry: &CacheEntry) -> Value {
+    let modified = entry
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    json!({
+        "modified": [modified.as_secs(), modified.subsec_nanos()],
+        "metadata": metadata_to_json(&entry.metadata),
+    })
+}
+
+// Reads an entry written by `entry_to_json`, returning None if it's invalid (in which case the
+// plugin is simply loaded again)
+fn entry_from_json(value: &Value) -> Option<CacheEntry> {
+    let modified = UNIX_EPOCH
+        + Duration::new(
+            value["modified"][0].as_u64()?,
+            u32::try_from(value["modified"][1].as_u64()?).ok()?,
+        );
+    Some(CacheEntry {
+        modified,
+        metadata: metadata_from_json(&value["metadata"])?,
+    })
+}
+
+// Encodes a plugin's metadata as JSON, which is also how isolated plugins send it to the host
+pub(crate) fn metadata_to_json(metadata: &OwnedMetadata) -> Value {
+    let args = metadata.args.as_ref().map(|args| {
+        args.iter()
+            .map(|arg| {
+                json!({
+                    "name": arg.name,
+                    "arg_type": type_to_json(arg.arg_type),
+                    "optional": arg.optional,
+                    "doc": arg.doc,
+                    "secret": arg.secret,
+                    "min": bound_to_json(arg.min),
+                    "max": bound_to_json(arg.max),
+                    "max_len": arg.max_len,
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    json!({
+        "name": metadata.name,
+        "arg_types": metadata.arg_types.iter().map(|t| type_to_json(*t)).collect::<Vec<_>>(),
+        "return_type": type_to_json(metadata.return_type),
+        "required_host_capabilities": metadata.required_host_capabilities,
+        "capabilities": metadata.capabilities,
+        "args": args,
+        "tags": metadata.tags,
+    })
+}
+
+// Reads metadata written by `metadata_to_json`, returning None if it's invalid
+pub(crate) fn metadata_from_json(metadata: &Value) -> Option<OwnedMetadata> {
+    let args = match &metadata["args"] {
+        Value::Null => None,
+        args => Some(
+            args.as_array()?
+                .iter()
+                .map(|arg| {
+                    Some(ArgInfo {
+                        name: arg["name"].as_str()?.to_string(),
+                        arg_type: type_from_json(&arg["arg_type"])?,
+                        optional: arg["optional"].as_bool()?,
+                        doc: arg["doc"].as_str().map(String::from),
+                        secret: arg["secret"].as_bool()?,
+                        min: bound_from_json(&arg["min"])?,
+                        max: bound_from_json(&arg["max"])?,
+                        max_len: match &arg["max_len"] {
+                            Value::Null => None,
+                            max_len => Some(usize::try_from(max_len.as_u64()?).ok()?),
+                        },
+                    })
+                })
+                .collect::<Option<_>>()?,
+        ),
+    };
+    Some(OwnedMetadata {
+        name: metadata["name"].as_str()?.to_string(),
+        arg_types: metadata["arg_types"]
+            .as_array()?
+            .iter()
+            .map(type_from_json)
+            .collect::<Option<_>>()?,
+        return_type: type_from_json(&metadata["return_type"])?,
+        required_host_capabilities: metadata["required_host_capabilities"].as_u64()?,
+        capabilities: metadata["capabilities"].as_u64()?,
+        args,
+        tags: metadata["tags"]
+            .as_array()?
+            .iter()
+            .map(|tag| tag.as_str().map(String::from))
+            .collect::<Option<_>>()?,
+    })
+}
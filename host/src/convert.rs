@@ -0,0 +1,206 @@
+// Conversions between OwnedPluginValue and Rust types, so that hosts can call plugins with their
+// own types (see `Plugin::call_typed`). Converting a value of the wrong type fails with
+// `PluginError::Plugin`, as it means the plugin returned something other than what was expected.
+use crate::{
+    ArgError, ArgErrorKind, Bytes, OwnedPluginValue, Plugin, PluginError, PluginHandle, PluginType,
+};
+use std::convert::Infallible;
+use std::ffi::CString;
+use std::fs::File;
+
+// Converts a value into a plugin's positional arguments; usually derived for a struct, with one
+// field per argument (see `host-derive`). `ARG_NAMES` are checked against the plugin's argument
+// names by `Plugin::call_named`.
+pub trait IntoPluginArgs {
+    const ARG_NAMES: &'static [&'static str];
+
+    fn into_plugin_args(self) -> Result<Vec<OwnedPluginValue>, PluginError>;
+}
+
+fn wrong_type(expected: PluginType, value: &OwnedPluginValue) -> PluginError {
+    PluginError::Plugin(format!(
+        "Expected a {} value, but got {}",
+        expected,
+        value.plugin_type()
+    ))
+}
+
+macro_rules! impl_conversions {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for OwnedPluginValue {
+                fn from(value: $ty) -> Self {
+                    OwnedPluginValue::$variant(value)
+                }
+            }
+
+            impl TryFrom<OwnedPluginValue> for $ty {
+                type Error = PluginError;
+
+                fn try_from(value: OwnedPluginValue) -> Result<Self, Self::Error> {
+                    match value {
+                        OwnedPluginValue::$variant(v) => Ok(v),
+                        other => Err(wrong_type(PluginType::$variant, &other)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_conversions! {
+    bool => Bool,
+    i64 => Int,
+    u64 => UInt,
+    f64 => Double,
+    CString => String,
+    PluginHandle => Handle,
+    Vec<OwnedPluginValue> => List,
+    Bytes => Bytes,
+    File => Fd,
+    Result<Box<OwnedPluginValue>, Box<OwnedPluginValue>> => Result,
+}
+
+#[cfg(feature = "serde")]
+impl_conversions! {
+    serde_json::Value => Json,
+}
+
+// Byte strings become String values, which needn't be valid UTF-8
+impl TryFrom<&[u8]> for OwnedPluginValue {
+    type Error = PluginError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        CString::new(value)
+            .map(OwnedPluginValue::String)
+            .map_err(|_| {
+                ArgError::invalid(
+                    ArgErrorKind::Malformed,
+                    PluginType::String,
+                    String::from_utf8_lossy(value),
+                    format!(
+                        "String contains a NUL byte: {:?}",
+                        String::from_utf8_lossy(value)
+                    ),
+                )
+            })
+    }
+}
+
+// Rust strings may contain NUL bytes, which can't be passed to the plugin
+impl TryFrom<&str> for OwnedPluginValue {
+    type Error = PluginError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        OwnedPluginValue::try_from(value.as_bytes())
+    }
+}
+
+impl TryFrom<String> for OwnedPluginValue {
+    type Error = PluginError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        OwnedPluginValue::try_from(value.as_str())
+    }
+}
+
+impl TryFrom<OwnedPluginValue> for String {
+    type Error = PluginError;
+
+    fn try_from(value: OwnedPluginValue) -> Result<Self, Self::Error> {
+        CString::try_from(value)?
+            .into_string()
+            .map_err(|e| PluginError::Plugin(format!("Plugin returned invalid UTF-8: {}", e)))
+    }
+}
+
+// Allows `call_typed` to return an OwnedPluginValue directly, which converts infallibly
+impl From<Infallible> for PluginError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
+}
+
+// Converts a single argument of `call!`. It's implemented for each type with a conversion to
+// OwnedPluginValue (rather than for any `T: TryInto<OwnedPluginValue>`) so that passing an
+// unsupported type fails with the message below, instead of one about a missing `From` impl.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be passed as a plugin argument",
+    label = "unsupported argument type",
+    note = "plugin arguments can be bool, i64, u64, f64, &str, String, &[u8], CString, Bytes, \
+            File, PluginHandle, Vec<OwnedPluginValue> or OwnedPluginValue, or an Option of one of \
+            these for an optional argument"
+)]
+pub trait IntoPluginArg {
+    fn into_plugin_arg(self) -> Result<OwnedPluginValue, PluginError>;
+}
+
+macro_rules! impl_into_plugin_arg {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl IntoPluginArg for $ty {
+                fn into_plugin_arg(self) -> Result<OwnedPluginValue, PluginError> {
+                    OwnedPluginValue::try_from(self).map_err(Into::into)
+                }
+            }
+        )*
+    };
+}
+
+impl_into_plugin_arg! {
+    bool,
+    i64,
+    u64,
+    f64,
+    &str,
+    String,
+    &[u8],
+    CString,
+    PluginHandle,
+    Vec<OwnedPluginValue>,
+    Bytes,
+    File,
+}
+
+#[cfg(feature = "serde")]
+impl_into_plugin_arg! {
+    serde_json::Value,
+}
+
+impl IntoPluginArg for OwnedPluginValue {
+    fn into_plugin_arg(self) -> Result<OwnedPluginValue, PluginError> {
+        Ok(self)
+    }
+}
+
+// Optional arguments are passed as Null when they're None (see `OwnedMetadata::is_optional`)
+impl<T: IntoPluginArg> IntoPluginArg for Option<T> {
+    fn into_plugin_arg(self) -> Result<OwnedPluginValue, PluginError> {
+        self.map_or(Ok(OwnedPluginValue::Null), T::into_plugin_arg)
+    }
+}
+
+// Calls a plugin's function with arguments of any types that convert to OwnedPluginValues, e.g.
+// `call!(plugin, "repeat", "hi", 3u64)`, returning a `Result<OwnedPluginValue, PluginError>`.
+// Fails with `PluginError::Usage` if the plugin doesn't export the named function.
+#[macro_export]
+macro_rules! call {
+    ($plugin:expr, $function:expr $(, $arg:expr)* $(,)?) => {
+        $crate::call_function(
+            &$plugin,
+            $function,
+            [$($crate::IntoPluginArg::into_plugin_arg($arg)),*],
+        )
+    };
+}
+
+// The implementation of `call!`, which takes the arguments once they've been converted
+#[doc(hidden)]
+pub fn call_function<const N: usize>(
+    plugin: &Plugin,
+    function: &str,
+    args: [Result<OwnedPluginValue, PluginError>; N],
+) -> Result<OwnedPluginValue, PluginError> {
+    let args = args.into_iter().collect::<Result<Vec<_>, _>>()?;
+    plugin.call_function(function, &args)
+}
@@ -0,0 +1,52 @@
+// Support for custom value types, which let plugins and hosts exchange domain-specific values
+// without changes to this crate. The host treats a custom value as an opaque byte payload tagged
+// with its type id; a handler registered for the id gives it a name and a textual form.
+use crate::{Bytes, OwnedPluginValue, PluginError};
+use std::collections::HashMap;
+
+// Interprets the payload of a custom type
+pub trait CustomType: Send + Sync {
+    // A human-readable name for the type
+    fn name(&self) -> &str;
+
+    // Renders a payload for display
+    fn format(&self, payload: &[u8]) -> String;
+
+    // Encodes a payload from its textual form, e.g. from a CLI argument
+    fn parse(&self, s: &str) -> Result<Vec<u8>, String> {
+        let _ = s;
+        Err(format!("{} values can't be parsed", self.name()))
+    }
+}
+
+// A registry of handlers for custom types, keyed by type id. Pass it to `FormatOptions` to render
+// custom values with their handlers.
+#[derive(Default)]
+pub struct CustomTypes {
+    types: HashMap<u32, Box<dyn CustomType>>,
+}
+
+impl CustomTypes {
+    // Registers the handler for `type_id`, replacing any previous one
+    pub fn register(&mut self, type_id: u32, handler: impl CustomType + 'static) {
+        self.types.insert(type_id, Box::new(handler));
+    }
+
+    pub fn get(&self, type_id: u32) -> Option<&dyn CustomType> {
+        self.types.get(&type_id).map(|handler| &**handler)
+    }
+
+    // Parses a value of the custom type from its textual form using the registered handler
+    pub fn parse(&self, type_id: u32, s: &str) -> Result<OwnedPluginValue, PluginError> {
+        let handler = self.get(type_id).ok_or_else(|| {
+            PluginError::Argument(format!("No handler is registered for Custom({})", type_id))
+        })?;
+        let payload = handler
+            .parse(s)
+            .map_err(|e| PluginError::Argument(format!("Invalid {}: {}", handler.name(), e)))?;
+        Ok(OwnedPluginValue::Custom {
+            type_id,
+            payload: Bytes::from(payload),
+        })
+    }
+}
@@ -0,0 +1,265 @@
+// Debug impls for the FFI types, which render what their pointers point to (rather than the
+// addresses themselves) so that values are readable in test failures and traces. Formatting only
+// reads through the pointers, never taking ownership of or freeing what they point to, and bounds
+// every read: strings are cut off after `MAX_STRING_LEN` bytes, buffers after `MAX_BYTES`, and
+// lists (and argument types) after `MAX_ITEMS`.
+//
+// As with any other use of these values, non-null pointers are trusted to be valid.
+use crate::ffi::{PluginMetadata, PluginResult, PluginType, PluginValue};
+use crate::raw::RawPluginValue;
+use crate::value::bounded_strlen;
+use std::ffi::c_char;
+use std::fmt::{Debug, Formatter};
+
+const MAX_STRING_LEN: usize = 256;
+const MAX_BYTES: usize = 32;
+const MAX_ITEMS: usize = 32;
+
+// A string behind a pointer, shown quoted (or as `null`)
+struct CStrPtr(*const c_char);
+
+impl Debug for CStrPtr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_null() {
+            return write!(f, "null");
+        }
+        let (len, truncated) = match unsafe { bounded_strlen(self.0, MAX_STRING_LEN) } {
+            Some(len) => (len, false),
+            None => (MAX_STRING_LEN, true),
+        };
+        let bytes = unsafe { std::slice::from_raw_parts(self.0 as *const u8, len) };
+        write!(f, "{:?}", String::from_utf8_lossy(bytes))?;
+        if truncated {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+// A buffer behind a pointer, shown as its length and leading bytes
+struct BytesPtr(*const u8, usize);
+
+impl Debug for BytesPtr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_null() {
+            return write!(f, "null ({} bytes)", self.1);
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(self.0, self.1.min(MAX_BYTES)) };
+        write!(f, "{:?}", bytes)?;
+        if self.1 > MAX_BYTES {
+            write!(f, "... ({} bytes)", self.1)?;
+        }
+        Ok(())
+    }
+}
+
+// The items of a list. They're read through their raw view, as each one's discriminant must be
+// validated before it can be interpreted.
+struct ListPtr(*const PluginValue, usize);
+
+impl Debug for ListPtr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_null() {
+            return write!(f, "null ({} items)", self.1);
+        }
+        let items = unsafe {
+            std::slice::from_raw_parts(self.0 as *const RawPluginValue, self.1.min(MAX_ITEMS))
+        };
+        let mut list = f.debug_list();
+        for item in items {
+            match item.validate() {
+                Ok(value) => list.entry(&value),
+                Err(e) => list.entry(&format_args!("<{}>", e)),
+            };
+        }
+        list.finish()?;
+        if self.1 > MAX_ITEMS {
+            write!(f, "... ({} items)", self.1)?;
+        }
+        Ok(())
+    }
+}
+
+// The value inside a Result, read through its raw view like a list's items
+struct ValuePtr(*const PluginValue);
+
+impl Debug for ValuePtr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_null() {
+            return write!(f, "null");
+        }
+        match unsafe { *(self.0 as *const RawPluginValue) }.validate() {
+            Ok(value) => write!(f, "{:?}", value),
+            Err(e) => write!(f, "<{}>", e),
+        }
+    }
+}
+
+impl Debug for PluginValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginValue::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            PluginValue::Int(i) => f.debug_tuple("Int").field(i).finish(),
+            PluginValue::UInt(u) => f.debug_tuple("UInt").field(u).finish(),
+            PluginValue::Double(d) => f.debug_tuple("Double").field(d).finish(),
+            PluginValue::String(s) => f.debug_tuple("String").field(&CStrPtr(*s)).finish(),
+            PluginValue::Json(s) => f.debug_tuple("Json").field(&CStrPtr(*s)).finish(),
+            PluginValue::Handle(token) => f.debug_tuple("Handle").field(token).finish(),
+            PluginValue::List { items, len } => {
+                f.debug_tuple("List").field(&ListPtr(*items, *len)).finish()
+            }
+            PluginValue::Bytes { ptr, len } => {
+                f.debug_tuple("Bytes").field(&BytesPtr(*ptr, *len)).finish()
+            }
+            PluginValue::Custom { type_id, ptr, len } => f
+                .debug_struct("Custom")
+                .field("type_id", type_id)
+                .field("payload", &BytesPtr(*ptr, *len))
+                .finish(),
+            PluginValue::Null => write!(f, "Null"),
+            PluginValue::Fd(fd) => f.debug_tuple("Fd").field(fd).finish(),
+            PluginValue::ResultOk(value) => {
+                f.debug_tuple("ResultOk").field(&ValuePtr(*value)).finish()
+            }
+            PluginValue::ResultErr(value) => {
+                f.debug_tuple("ResultErr").field(&ValuePtr(*value)).finish()
+            }
+        }
+    }
+}
+
+impl Debug for PluginResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginResult::Ok(value) => f.debug_tuple("Ok").field(value).finish(),
+            PluginResult::PartialOk { value, warning } => f
+                .debug_struct("PartialOk")
+                .field("value", value)
+                .field("warning", &CStrPtr(*warning))
+                .finish(),
+            PluginResult::Err {
+                code,
+                message,
+                payload,
+            } => f
+                .debug_struct("Err")
+                .field("code", code)
+                .field("message", &CStrPtr(*message))
+                .field("payload", &CStrPtr(*payload))
+                .finish(),
+            PluginResult::Arg(index) => f.debug_tuple("Arg").field(index).finish(),
+        }
+    }
+}
+
+// Shows the types by name, and any the host doesn't know about as `unknown(n)`
+impl Debug for PluginMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let format_type = |raw| {
+            PluginType::try_from(raw)
+                .map(|t| t.to_string())
+                .unwrap_or_else(|raw| format!("unknown({})", raw))
+        };
+        let arg_types: Box<dyn Debug> = if self.arg_types.is_null() {
+            Box::new(format_args!("null"))
+        } else if !self.arg_types.is_aligned() {
+            Box::new(format!("misaligned ({:p})", self.arg_types))
+        } else {
+            let len = self.arg_types_len.min(MAX_ITEMS);
+            let types = unsafe { std::slice::from_raw_parts(self.arg_types, len) };
+            Box::new(
+                types
+                    .iter()
+                    .map(|raw| format_type(*raw))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        f.debug_struct("PluginMetadata")
+            .field("name", &CStrPtr(self.name))
+            .field("arg_types", &arg_types)
+            .field("arg_types_len", &self.arg_types_len)
+            .field("return_type", &format_type(self.return_type))
+            .field(
+                "required_host_capabilities",
+                &format_args!("{:#x}", self.required_host_capabilities),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::PluginErrorCode;
+
+    fn raw(plugin_type: PluginType) -> crate::ffi::RawPluginType {
+        plugin_type.to_raw().unwrap()
+    }
+
+    #[test]
+    fn formats_metadata_like_repeats() {
+        let arg_types = [raw(PluginType::String), raw(PluginType::UInt)];
+        let metadata = PluginMetadata {
+            name: c"repeat".as_ptr(),
+            arg_types: arg_types.as_ptr(),
+            arg_types_len: 2,
+            return_type: raw(PluginType::String),
+            required_host_capabilities: 0x1,
+        };
+        assert_eq!(
+            format!("{:?}", metadata),
+            "PluginMetadata { name: \"repeat\", arg_types: [\"String\", \"UInt\"], \
+             arg_types_len: 2, return_type: \"String\", required_host_capabilities: 0x1 }"
+        );
+
+        let metadata = PluginMetadata {
+            name: std::ptr::null(),
+            arg_types: std::ptr::null(),
+            arg_types_len: 1,
+            return_type: 99,
+            required_host_capabilities: 0,
+        };
+        assert_eq!(
+            format!("{:?}", metadata),
+            "PluginMetadata { name: null, arg_types: null, arg_types_len: 1, \
+             return_type: \"unknown(99)\", required_host_capabilities: 0x0 }"
+        );
+    }
+
+    #[test]
+    fn formats_values_through_their_pointers() {
+        let items = [
+            PluginValue::String(c"ab".as_ptr()),
+            PluginValue::UInt(3),
+            PluginValue::Null,
+        ];
+        let list = PluginValue::List {
+            items: items.as_ptr(),
+            len: items.len(),
+        };
+        assert_eq!(
+            format!("{:?}", list),
+            "List([String(\"ab\"), UInt(3), Null])"
+        );
+
+        let bytes = [7u8; 40];
+        let value = PluginValue::Bytes {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        };
+        assert_eq!(
+            format!("{:?}", value),
+            format!("Bytes({:?}... (40 bytes))", [7u8; MAX_BYTES])
+        );
+
+        let result = PluginResult::Err {
+            code: PluginErrorCode::InvalidArgument,
+            message: c"bad".as_ptr() as *mut c_char,
+            payload: std::ptr::null_mut(),
+        };
+        assert_eq!(
+            format!("{:?}", result),
+            "Err { code: InvalidArgument, message: \"bad\", payload: null }"
+        );
+    }
+}
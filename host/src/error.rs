@@ -0,0 +1,301 @@
+use crate::ffi::{PluginErrorCode, PluginType};
+use crate::REDACTED;
+use std::ffi::{c_char, CString};
+use std::fmt::{Display, Formatter};
+
+// Errors that can occur while loading or calling a plugin. Each kind maps to a distinct exit code
+// for the host binary, so that scripts invoking it can tell failures apart:
+//
+// | Code | Meaning                                                                  |
+// |------|--------------------------------------------------------------------------|
+// | 0    | success                                                                  |
+// | 1    | `--diff` found differences, `--replay` found a mismatched result, or     |
+// |      | `--bench` found a regression against its baseline                        |
+// | 2    | usage error: invalid host options, or no plugin path given               |
+// | 3    | the plugin couldn't be loaded or failed to initialize                    |
+// | 4    | the arguments were invalid for the plugin (wrong count, failed to parse, |
+// |      | or outside the constraints the plugin declares)                          |
+// | 5    | the plugin returned an error, or a result the host couldn't handle       |
+// | 6    | the plugin panicked, or its worker process crashed (see `--isolation`)   |
+// | 7    | the call was cancelled or timed out                                      |
+#[derive(Debug)]
+pub enum PluginError {
+    Usage(String),
+    Load(String),
+    Argument(String),
+    // An argument that couldn't be converted to its type, or that doesn't satisfy its constraints
+    InvalidArg(ArgError),
+    Plugin(String),
+    Panicked(String),
+    Cancelled(String),
+    // The plugin was called from within one of its own calls on the same thread, e.g. by a closure
+    // the host runs during the call, which plugins don't support
+    Reentrant(String),
+    // An error returned by the plugin along with a JSON payload describing it, which the host can
+    // inspect to react to the error programmatically
+    WithPayload(Box<PluginError>, String),
+}
+
+impl PluginError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // calling a plugin reentrantly is a mistake in the host, like invalid usage
+            PluginError::Usage(_) | PluginError::Reentrant(_) => 2,
+            PluginError::Load(_) => 3,
+            PluginError::Argument(_) | PluginError::InvalidArg(_) => 4,
+            PluginError::Plugin(_) => 5,
+            PluginError::Panicked(_) => 6,
+            PluginError::Cancelled(_) => 7,
+            PluginError::WithPayload(error, _) => error.exit_code(),
+        }
+    }
+
+    // The JSON payload the plugin returned with the error, if any
+    pub fn payload(&self) -> Option<&str> {
+        match self {
+            PluginError::WithPayload(_, payload) => Some(payload),
+            _ => None,
+        }
+    }
+
+    // The payload parsed as JSON
+    #[cfg(feature = "serde")]
+    pub fn payload_json(&self) -> Option<Result<serde_json::Value, serde_json::Error>> {
+        self.payload().map(serde_json::from_str)
+    }
+
+    // Encodes the error as JSON, e.g. for `--format json`: `{"code":4,"message":"...",
+    // "payload":null,"arg":{...}}`, where `code` is its exit code, `payload` is the plugin's payload
+    // (as a string) and `arg` describes an `InvalidArg` error (see `ArgError::to_json`)
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Value {
+        let (error, payload) = match self {
+            PluginError::WithPayload(error, payload) => (&**error, Some(payload)),
+            error => (error, None),
+        };
+        let arg = match error {
+            PluginError::InvalidArg(arg) => arg.to_json(),
+            _ => serde_json::Value::Null,
+        };
+        serde_json::json!({
+            "code": error.exit_code(),
+            "message": error.to_string(),
+            "payload": payload,
+            "arg": arg,
+        })
+    }
+
+    // The inverse of `to_json`, recovering the kind of error from its exit code
+    #[cfg(feature = "serde")]
+    pub fn from_json(error: &serde_json::Value) -> PluginError {
+        let message = error["message"].as_str().unwrap_or_default().to_string();
+        let kind = match (
+            error["code"].as_i64(),
+            ArgError::from_json(&error["arg"], &message),
+        ) {
+            (Some(4), Some(arg)) => PluginError::InvalidArg(arg),
+            (Some(2), _) => PluginError::Usage(message),
+            (Some(3), _) => PluginError::Load(message),
+            (Some(4), None) => PluginError::Argument(message),
+            (Some(6), _) => PluginError::Panicked(message),
+            (Some(7), _) => PluginError::Cancelled(message),
+            _ => PluginError::Plugin(message),
+        };
+        match error["payload"].as_str() {
+            Some(payload) => PluginError::WithPayload(Box::new(kind), payload.to_string()),
+            None => kind,
+        }
+    }
+
+    // Converts an error returned by the plugin, taking ownership of (and freeing) the message and
+    // payload, which are converted with `message_from_ffi`
+    pub(crate) fn from_ffi(
+        code: PluginErrorCode,
+        message: *mut c_char,
+        payload: *mut c_char,
+        strict_utf8: bool,
+    ) -> PluginError {
+        let message = if message.is_null() {
+            "Plugin returned an error without a message".to_string()
+        } else {
+            message_from_ffi(unsafe { CString::from_raw(message) }, strict_utf8)
+        };
+        let payload = (!payload.is_null())
+            .then(|| message_from_ffi(unsafe { CString::from_raw(payload) }, strict_utf8));
+        let error = match code {
+            PluginErrorCode::Failed => PluginError::Plugin(message),
+            PluginErrorCode::InvalidArgument => PluginError::Argument(message),
+            PluginErrorCode::Panicked => PluginError::Panicked(message),
+            PluginErrorCode::Cancelled => PluginError::Cancelled(message),
+        };
+        match payload {
+            Some(payload) => PluginError::WithPayload(Box::new(error), payload),
+            None => error,
+        }
+    }
+}
+
+// Converts a message from the plugin (an error message or payload, or a warning) to a String.
+// Unlike returned strings, messages with invalid UTF-8 are never rejected, as that would hide the
+// error they describe; the invalid bytes are replaced with `�`. In strict mode, the message is also
+// marked as invalid, so that the replacement doesn't silently hide corruption.
+pub(crate) fn message_from_ffi(message: CString, strict_utf8: bool) -> String {
+    match message.into_string() {
+        Ok(message) => message,
+        Err(e) => {
+            let error = e.utf8_error();
+            let lossy = e.into_cstring().to_string_lossy().to_string();
+            if strict_utf8 {
+                format!("{} (invalid UTF-8: {})", lossy, error)
+            } else {
+                lossy
+            }
+        }
+    }
+}
+
+impl Display for PluginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginError::Usage(message)
+            | PluginError::Load(message)
+            | PluginError::Argument(message)
+            | PluginError::Plugin(message)
+            | PluginError::Panicked(message)
+            | PluginError::Cancelled(message)
+            | PluginError::Reentrant(message) => write!(f, "{}", message),
+            PluginError::InvalidArg(error) => write!(f, "{}", error.message),
+            PluginError::WithPayload(error, payload) => {
+                write!(f, "{}\npayload: {}", error, payload)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+// An argument the host rejected before calling the plugin, described in enough detail for tools
+// driving the host to report it themselves
+#[derive(Clone, Debug)]
+pub struct ArgError {
+    // The position of the argument, if the value was converted as one of a call's arguments
+    pub index: Option<usize>,
+    // The type the argument should have had
+    pub expected: PluginType,
+    // The argument as it was given, or None if it's secret
+    pub value: Option<String>,
+    pub kind: ArgErrorKind,
+    // A human-readable description of the problem, which is how the error is displayed
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgErrorKind {
+    // The value isn't of the expected type (e.g. `abc` for an Int)
+    Malformed,
+    // The value is a number too large or small for the expected type (e.g. `-1` for a UInt)
+    OutOfRange,
+    // Values of the expected type can't be given as text (e.g. Handles)
+    Unsupported,
+    // The value is of the expected type, but doesn't satisfy the constraints the plugin declares
+    // for the argument (see `ArgInfo`)
+    Constraint,
+}
+
+impl ArgErrorKind {
+    pub const ALL: [ArgErrorKind; 4] = [
+        ArgErrorKind::Malformed,
+        ArgErrorKind::OutOfRange,
+        ArgErrorKind::Unsupported,
+        ArgErrorKind::Constraint,
+    ];
+
+    // The name of the kind in JSON
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArgErrorKind::Malformed => "malformed",
+            ArgErrorKind::OutOfRange => "out_of_range",
+            ArgErrorKind::Unsupported => "unsupported",
+            ArgErrorKind::Constraint => "constraint",
+        }
+    }
+}
+
+impl ArgError {
+    // An error converting `value` to `expected`, not yet attributed to an argument
+    pub(crate) fn invalid(
+        kind: ArgErrorKind,
+        expected: PluginType,
+        value: impl Into<String>,
+        message: String,
+    ) -> PluginError {
+        PluginError::InvalidArg(ArgError {
+            index: None,
+            expected,
+            value: Some(value.into()),
+            kind,
+            message,
+        })
+    }
+
+    // Attributes the error to the argument at `index`, prefixing its message with `arg<index>: `.
+    // The value of a secret argument is left out, as is the message describing it.
+    pub fn at(mut self, index: usize, secret: bool) -> ArgError {
+        if secret {
+            self.value = None;
+            self.message = format!("Invalid {}: {}", self.expected, REDACTED);
+        }
+        self.index = Some(index);
+        self.message = format!("arg{}: {}", index, self.message);
+        self
+    }
+
+    // Encodes the error as JSON, e.g. `{"index":1,"expected":"UInt","value":"abc",
+    // "kind":"malformed"}`; its message is part of the enclosing `PluginError`'s JSON
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "index": self.index,
+            "expected": self.expected.to_string(),
+            "value": self.value,
+            "kind": self.kind.name(),
+        })
+    }
+
+    // The inverse of `to_json`, returning None if `arg` isn't an encoded `ArgError`
+    #[cfg(feature = "serde")]
+    fn from_json(arg: &serde_json::Value, message: &str) -> Option<ArgError> {
+        let index = match &arg["index"] {
+            serde_json::Value::Null => None,
+            index => Some(usize::try_from(index.as_u64()?).ok()?),
+        };
+        Some(ArgError {
+            index,
+            expected: arg["expected"].as_str()?.parse().ok()?,
+            value: arg["value"].as_str().map(String::from),
+            kind: ArgErrorKind::ALL
+                .into_iter()
+                .find(|kind| arg["kind"] == kind.name())?,
+            message: message.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            PluginError::Usage(String::new()),
+            PluginError::Load(String::new()),
+            PluginError::Argument(String::new()),
+            PluginError::Plugin(String::new()),
+            PluginError::Panicked(String::new()),
+        ];
+        let mut codes: Vec<_> = errors.iter().map(PluginError::exit_code).collect();
+        codes.dedup();
+        assert_eq!(codes, [2, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,262 @@
+// Evaluates expressions of nested plugin calls, e.g. `repeat(upper("hi"), 2)`, which generalize
+// `--pipe` to functions of several arguments. Each function name is resolved to the loaded plugin
+// of that name, and inner calls are evaluated first, their results passed to the call around them.
+//
+// The grammar is:
+//
+// expr    = call | literal
+// call    = name "(" [expr ("," expr)*] ")"
+// literal = string | number
+//
+// where names are identifiers, strings are double-quoted (with `\"` and `\\` escapes) and numbers
+// are written as on the command line. Literals are converted to the type of the argument they're
+// passed as, like command-line arguments, while a call's result must already be of that type.
+use host::{OwnedPluginValue, Plugin, PluginError, PluginType, REDACTED};
+use std::collections::HashMap;
+
+pub enum Expr {
+    Call { name: String, args: Vec<Expr> },
+    Literal(String),
+}
+
+// Parses an expression, which must consist of a single call
+pub fn parse(s: &str) -> Result<Expr, PluginError> {
+    let mut parser = Parser { s, pos: 0 };
+    let expr = parser.expr()?;
+    parser.skip_whitespace();
+    if parser.pos < s.len() {
+        return Err(parser.error("expected the end of the expression"));
+    }
+    if let Expr::Literal(_) = expr {
+        return Err(PluginError::Usage(
+            "--eval expects a function call".to_string(),
+        ));
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    // the byte offset of the next character to read
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> PluginError {
+        PluginError::Usage(format!(
+            "Invalid expression at column {}: {}",
+            self.s[..self.pos].chars().count() + 1,
+            message
+        ))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    // Consumes the characters matching `pred`, returning them
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &str {
+        let start = self.pos;
+        while let Some(c) = self.peek().filter(|c| pred(*c)) {
+            self.pos += c.len_utf8();
+        }
+        &self.s[start..self.pos]
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), PluginError> {
+        self.skip_whitespace();
+        if self.peek() != Some(expected) {
+            return Err(self.error(&format!("expected '{}'", expected)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Expr, PluginError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') => self.string(),
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let number = self.take_while(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+                Ok(Expr::Literal(number.to_string()))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => self.call(),
+            Some(_) => Err(self.error("expected a function call, string or number")),
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+
+    fn string(&mut self) -> Result<Expr, PluginError> {
+        // skip the opening quote
+        self.pos += 1;
+        let mut string = String::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(Expr::Literal(string)),
+                '\\' => match self.peek() {
+                    Some(c @ ('"' | '\\')) => {
+                        self.pos += 1;
+                        string.push(c);
+                    }
+                    _ => return Err(self.error("expected '\"' or '\\' after '\\'")),
+                },
+                c => string.push(c),
+            }
+        }
+    }
+
+    fn call(&mut self) -> Result<Expr, PluginError> {
+        let name = self
+            .take_while(|c| c.is_alphanumeric() || c == '_')
+            .to_string();
+        self.expect('(')?;
+        let mut args = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(')') {
+            self.pos += 1;
+            return Ok(Expr::Call { name, args });
+        }
+        loop {
+            args.push(self.expr()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(')') => {
+                    self.pos += 1;
+                    return Ok(Expr::Call { name, args });
+                }
+                _ => return Err(self.error("expected ',' or ')'")),
+            }
+        }
+    }
+}
+
+// Checks that every function in the expression is one of the plugins, called with an acceptable
+// number of arguments, and that each call's result is of the type its caller expects, before
+// anything is called. Returns the type of a call's result, or None for a literal.
+pub fn check(
+    expr: &Expr,
+    plugins: &HashMap<String, Plugin>,
+) -> Result<Option<PluginType>, PluginError> {
+    let Expr::Call { name, args } = expr else {
+        return Ok(None);
+    };
+    let metadata = plugins
+        .get(name)
+        .ok_or_else(|| PluginError::Usage(format!("No plugin provides {}", name)))?
+        .metadata();
+    metadata
+        .check_arg_count(args.len())
+        .map_err(|e| PluginError::Argument(format!("{}: {}", name, e)))?;
+
+    for (i, (arg, arg_type)) in args.iter().zip(&metadata.arg_types).enumerate() {
+        if let Some(result_type) = check(arg, plugins)? {
+            if result_type != *arg_type {
+                return Err(PluginError::Argument(format!(
+                    "{} expects arg{} to be {}, but it's passed a call returning {}",
+                    name, i, arg_type, result_type
+                )));
+            }
+        }
+    }
+    Ok(Some(metadata.return_type))
+}
+
+// Evaluates a checked expression, calling its innermost functions first
+pub fn eval(
+    expr: &Expr,
+    plugins: &HashMap<String, Plugin>,
+) -> Result<OwnedPluginValue, PluginError> {
+    let Expr::Call { name, args } = expr else {
+        unreachable!("literals are converted by the call they're passed to");
+    };
+    let plugin = &plugins[name];
+    let values = args
+        .iter()
+        .zip(&plugin.metadata().arg_types)
+        .enumerate()
+        .map(|(i, (arg, arg_type))| match arg {
+            Expr::Literal(text) => OwnedPluginValue::parse(*arg_type, text).map_err(|e| {
+                // as with command-line arguments, the values of secret arguments aren't shown
+                let secret = plugin.metadata().is_secret(i);
+                match e {
+                    PluginError::InvalidArg(e) => {
+                        let mut e = e.at(i, secret);
+                        e.message = format!("{} {}", name, e.message);
+                        PluginError::InvalidArg(e)
+                    }
+                    _ if secret => PluginError::Argument(format!(
+                        "{} arg{}: Invalid {}: {}",
+                        name, i, arg_type, REDACTED
+                    )),
+                    e => PluginError::Argument(format!("{} arg{}: {}", name, i, e)),
+                }
+            }),
+            call => eval(call, plugins),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    plugin.call(&values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes an expression back out, with its literals quoted, to compare parses against
+    fn unparse(expr: &Expr) -> String {
+        match expr {
+            Expr::Call { name, args } => format!(
+                "{}({})",
+                name,
+                args.iter().map(unparse).collect::<Vec<_>>().join(", ")
+            ),
+            Expr::Literal(literal) => format!("{:?}", literal),
+        }
+    }
+
+    #[test]
+    fn parses_nested_calls() {
+        let expr = parse(r#" repeat( upper("say \"hi\""),2 ) "#).unwrap();
+        assert_eq!(unparse(&expr), r#"repeat(upper("say \"hi\""), "2")"#);
+
+        let expr = parse("outer(inner(), -1.5e3, x_2(\"\"))").unwrap();
+        assert_eq!(unparse(&expr), r#"outer(inner(), "-1.5e3", x_2(""))"#);
+    }
+
+    #[test]
+    fn reports_column_of_syntax_errors() {
+        for (expr, message) in [
+            (
+                "repeat(\"hi\" 2)",
+                "Invalid expression at column 13: expected ',' or ')'",
+            ),
+            (
+                "repeat(\"hi",
+                "Invalid expression at column 11: unterminated string",
+            ),
+            (
+                "repeat(\"hi\")x",
+                "Invalid expression at column 13: expected the end of the expression",
+            ),
+            (
+                "repeat(@)",
+                "Invalid expression at column 8: expected a function call, string or number",
+            ),
+            ("repeat", "Invalid expression at column 7: expected '('"),
+            ("\"hi\"", "--eval expects a function call"),
+        ] {
+            let Err(e) = parse(expr) else {
+                panic!("{} parsed", expr);
+            };
+            assert_eq!(e.to_string(), message, "{}", expr);
+        }
+    }
+}
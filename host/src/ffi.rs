@@ -0,0 +1,645 @@
+// The FFI types shared with plugins. These must be kept in sync with the definitions in the plugin
+// crates (and the template used by `host --new-plugin`), as they define the ABI between the host
+// and the plugin.
+use crate::raw::RawPluginResult;
+use dlopen2::wrapper::WrapperApi;
+use std::ffi::{c_char, c_int, c_void};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+// An FFI-safe value enum to support various input/output types
+#[repr(C)]
+pub enum PluginValue {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    // Strings are represented as a pointer to a null-terminated string; all strings are owned
+    // by the host. Returned strings must be freed by the host. The pointer is never null: an
+    // empty string is a pointer to a lone null byte.
+    String(*const c_char),
+    // JSON is passed as a null-terminated UTF-8 string, with the same ownership rules as String
+    Json(*const c_char),
+    // An opaque token for a resource owned by the plugin. Each handle the plugin returns must be
+    // passed to `plugin_release` exactly once, after which the host must not use it again.
+    Handle(u64),
+    // A list of values. Lists passed as arguments are borrowed by the plugin; returned lists must
+    // be allocated as a boxed slice (`Box<[PluginValue]>`), which is freed by the host along with
+    // the values it contains.
+    List {
+        items: *const PluginValue,
+        len: usize,
+    },
+    // A buffer of arbitrary bytes. Bytes passed as arguments are borrowed by the plugin; returned
+    // buffers remain owned by the plugin's allocator, and the host passes each one back to
+    // `plugin_free_bytes` exactly once when it's done with it (unless the plugin allocated it with
+    // `HostServices::alloc`, in which case the host frees it itself).
+    Bytes {
+        ptr: *const u8,
+        len: usize,
+    },
+    // A value of a type defined outside of this crate, identified by `type_id`. The payload is
+    // opaque to the host, which leaves its interpretation to any handler registered for the type
+    // (see `CustomTypes`), and has the same ownership rules as Bytes.
+    Custom {
+        type_id: u32,
+        ptr: *const u8,
+        len: usize,
+    },
+    // No value, returned by a plugin that succeeded but has nothing to return (e.g., a lookup that
+    // found nothing). This is distinct from an error, and may be returned whatever the plugin's
+    // declared return type. The host also passes it in place of an optional argument it omits.
+    Null,
+    // A file descriptor (on Windows, a HANDLE) for a file opened by the host, which controls which
+    // files the plugin can access. It's only borrowed by the plugin, which must not close it; the
+    // host closes it after the call. File descriptors can be passed as arguments, but not returned.
+    Fd(i64),
+    // The outcome of an operation within a successful call (e.g. parsing one item of a list),
+    // like a Rust `Result`, so that a plugin can report a failure that's part of its answer rather
+    // than failing the whole call. The inner value is passed by pointer: Results passed as
+    // arguments are borrowed by the plugin, while returned ones must point to a boxed value
+    // (`Box<PluginValue>`), which is freed by the host along with the value it contains.
+    ResultOk(*const PluginValue),
+    ResultErr(*const PluginValue),
+}
+
+impl PluginValue {
+    pub fn plugin_type(&self) -> PluginType {
+        match self {
+            PluginValue::Bool(_) => PluginType::Bool,
+            PluginValue::Int(_) => PluginType::Int,
+            PluginValue::UInt(_) => PluginType::UInt,
+            PluginValue::Double(_) => PluginType::Double,
+            PluginValue::String(_) => PluginType::String,
+            PluginValue::Json(_) => PluginType::Json,
+            PluginValue::Handle(_) => PluginType::Handle,
+            PluginValue::List { .. } => PluginType::List,
+            PluginValue::Bytes { .. } => PluginType::Bytes,
+            PluginValue::Custom { type_id, .. } => PluginType::Custom(*type_id),
+            PluginValue::Null => PluginType::Null,
+            PluginValue::Fd(_) => PluginType::Fd,
+            PluginValue::ResultOk(_) | PluginValue::ResultErr(_) => PluginType::Result,
+        }
+    }
+}
+
+// An FFI-safe result type
+#[repr(C)]
+pub enum PluginResult {
+    Ok(PluginValue),
+    // A successful result accompanied by a non-fatal warning (e.g., only some of the input could
+    // be processed). The host is responsible for freeing the warning message.
+    PartialOk {
+        value: PluginValue,
+        warning: *mut c_char,
+    },
+    // The host is responsible for freeing the error message
+    Err {
+        code: PluginErrorCode,
+        message: *mut c_char,
+        // An optional JSON payload with structured details of the error (e.g., which argument was
+        // invalid), or null; the host is also responsible for freeing it
+        payload: *mut c_char,
+    },
+    // The call's argument at this index, handed back as its result rather than copied (e.g. by a
+    // plugin that returns its input unchanged). Only `plugin_entrypoint_owned` may return it, as
+    // the host only gives up its arguments there: it then moves the argument into the result, so
+    // nothing is allocated for it. The plugin still only borrows the argument, and must not keep
+    // any pointer into it once it returns.
+    Arg(usize),
+}
+
+// Categorizes the errors a plugin can return, so the host can react to them differently
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub enum PluginErrorCode {
+    // A general failure in the plugin function
+    Failed,
+    // The arguments passed by the host were invalid
+    InvalidArgument,
+    // The plugin function panicked
+    Panicked,
+    // The plugin stopped early because the host cancelled the call
+    Cancelled,
+}
+
+// The type of a value. This isn't passed across the FFI boundary directly; see `RawPluginType`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PluginType {
+    Bool,
+    Int,
+    UInt,
+    Double,
+    String,
+    Json,
+    Handle,
+    List,
+    Bytes,
+    Fd,
+    // Either an Ok or an Err value, each of which may be of any type
+    Result,
+    // A custom type, identified by an id chosen by the plugin author
+    Custom(u32),
+    // The type of a Null value. Plugins can't declare it, so it isn't one of `PluginType::ALL`.
+    Null,
+}
+
+// A PluginType as read from the plugin. A plugin built against a different version of these
+// definitions may send discriminants we don't know about, and reading one of those as a PluginType
+// would be undefined behavior, so types are read as raw integers (the size of a `repr(C)` enum)
+// and validated with `PluginType::try_from`.
+//
+// The built-in types are numbered from 0 in the order of `PluginType::ALL` (as the discriminants
+// of a `repr(C)` enum in the plugin), while custom types are sent as `CUSTOM_TYPE_BASE + type_id`.
+pub type RawPluginType = c_int;
+
+pub const CUSTOM_TYPE_BASE: RawPluginType = 0x4000_0000;
+
+impl PluginType {
+    // The built-in types, in the order of their discriminants
+    pub const ALL: [PluginType; 11] = [
+        PluginType::Bool,
+        PluginType::Int,
+        PluginType::UInt,
+        PluginType::Double,
+        PluginType::String,
+        PluginType::Json,
+        PluginType::Handle,
+        PluginType::List,
+        PluginType::Bytes,
+        PluginType::Fd,
+        PluginType::Result,
+    ];
+
+    // The raw representation of the type, the inverse of `PluginType::try_from`, or None if it has
+    // none: Null can't be declared by plugins, and a custom type's id must be small enough to be
+    // sent (at most `RawPluginType::MAX - CUSTOM_TYPE_BASE`), as every type read from a plugin is
+    pub fn to_raw(self) -> Option<RawPluginType> {
+        match self {
+            PluginType::Custom(type_id) => RawPluginType::try_from(type_id)
+                .ok()
+                .and_then(|id| CUSTOM_TYPE_BASE.checked_add(id)),
+            _ => PluginType::ALL
+                .iter()
+                .position(|t| *t == self)
+                .map(|i| i as RawPluginType),
+        }
+    }
+
+    // The Rust type that values of this type are marshalled to on the host (the payload of the
+    // matching `OwnedPluginValue` variant), e.g. for help text and errors. Custom values carry
+    // their payload as `Bytes`, alongside their type id.
+    pub fn rust_name(self) -> &'static str {
+        match self {
+            PluginType::Bool => "bool",
+            PluginType::Int => "i64",
+            PluginType::UInt => "u64",
+            PluginType::Double => "f64",
+            PluginType::String => "CString",
+            PluginType::Json => "serde_json::Value",
+            PluginType::Handle => "PluginHandle",
+            PluginType::List => "Vec<OwnedPluginValue>",
+            PluginType::Bytes | PluginType::Custom(_) => "Bytes",
+            PluginType::Fd => "File",
+            PluginType::Result => "Result<Box<OwnedPluginValue>, Box<OwnedPluginValue>>",
+            PluginType::Null => "()",
+        }
+    }
+
+    // The type whose values are marshalled to the Rust type named `name` (as returned by
+    // `rust_name`), or None if there isn't one. As custom types share `Bytes` with Bytes, that name
+    // maps to Bytes.
+    pub fn from_rust_name(name: &str) -> Option<PluginType> {
+        PluginType::ALL
+            .into_iter()
+            .chain([PluginType::Null])
+            .find(|t| t.rust_name() == name)
+    }
+}
+
+impl TryFrom<RawPluginType> for PluginType {
+    // the unknown discriminant
+    type Error = RawPluginType;
+
+    fn try_from(raw: RawPluginType) -> Result<Self, Self::Error> {
+        if raw >= CUSTOM_TYPE_BASE {
+            return Ok(PluginType::Custom((raw - CUSTOM_TYPE_BASE) as u32));
+        }
+        usize::try_from(raw)
+            .ok()
+            .and_then(|i| PluginType::ALL.get(i).copied())
+            .ok_or(raw)
+    }
+}
+
+impl Display for PluginType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            PluginType::Custom(type_id) => return write!(f, "Custom({})", type_id),
+            PluginType::Bool => "Bool",
+            PluginType::Int => "Int",
+            PluginType::UInt => "UInt",
+            PluginType::Double => "Double",
+            PluginType::String => "String",
+            PluginType::Json => "Json",
+            PluginType::Handle => "Handle",
+            PluginType::List => "List",
+            PluginType::Bytes => "Bytes",
+            PluginType::Fd => "Fd",
+            PluginType::Result => "Result",
+            PluginType::Null => "Null",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for PluginType {
+    type Err = String;
+
+    // Parses a type by the name it's displayed with (e.g. `UInt` or `Custom(3)`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(type_id) = s.strip_prefix("Custom(").and_then(|s| s.strip_suffix(')')) {
+            return type_id
+                .parse()
+                .map(PluginType::Custom)
+                .map_err(|_| format!("Invalid custom type {}", s));
+        }
+        PluginType::ALL
+            .into_iter()
+            .chain([PluginType::Null])
+            .find(|plugin_type| plugin_type.to_string() == s)
+            .ok_or_else(|| format!("Unknown type {}", s))
+    }
+}
+
+#[repr(C)]
+pub struct PluginMetadata {
+    pub name: *const c_char,
+    pub arg_types: *const RawPluginType,
+    pub arg_types_len: usize,
+    pub return_type: RawPluginType,
+    // The `CAPABILITY_*` bits for the host services the plugin can't run without
+    pub required_host_capabilities: u64,
+}
+
+// Capabilities a host may provide, which plugins can require through their metadata
+pub const CAPABILITY_LOG: u64 = 1 << 0;
+pub const CAPABILITY_CONFIG: u64 = 1 << 1;
+pub const CAPABILITY_CANCELLATION: u64 = 1 << 2;
+pub const CAPABILITY_JSON: u64 = 1 << 3;
+pub const CAPABILITY_PROGRESS: u64 = 1 << 4;
+pub const CAPABILITY_EMIT: u64 = 1 << 5;
+pub const CAPABILITY_DEADLINE: u64 = 1 << 6;
+pub const CAPABILITY_ALLOC: u64 = 1 << 7;
+pub const CAPABILITY_LOG_FIELDS: u64 = 1 << 8;
+
+pub const CAPABILITY_NAMES: [(u64, &str); 9] = [
+    (CAPABILITY_LOG, "log"),
+    (CAPABILITY_CONFIG, "config"),
+    (CAPABILITY_CANCELLATION, "cancellation"),
+    (CAPABILITY_JSON, "json"),
+    (CAPABILITY_PROGRESS, "progress"),
+    (CAPABILITY_EMIT, "emit"),
+    (CAPABILITY_DEADLINE, "deadline"),
+    (CAPABILITY_ALLOC, "alloc"),
+    (CAPABILITY_LOG_FIELDS, "log_fields"),
+];
+
+// The capabilities this host provides; Json values need the serde feature
+pub const HOST_CAPABILITIES: u64 = CAPABILITY_LOG
+    | CAPABILITY_CONFIG
+    | CAPABILITY_CANCELLATION
+    | CAPABILITY_PROGRESS
+    | CAPABILITY_EMIT
+    | CAPABILITY_DEADLINE
+    | CAPABILITY_ALLOC
+    | CAPABILITY_LOG_FIELDS
+    | if cfg!(feature = "serde") {
+        CAPABILITY_JSON
+    } else {
+        0
+    };
+
+// Capabilities a plugin may advertise through its optional `plugin_capabilities` export, which
+// tell the host how it may call the plugin rather than what the plugin needs from the host
+pub const PLUGIN_THREAD_SAFE: u64 = 1 << 0;
+pub const PLUGIN_PURE: u64 = 1 << 1;
+
+pub const PLUGIN_CAPABILITY_NAMES: [(u64, &str); 2] =
+    [(PLUGIN_THREAD_SAFE, "THREAD_SAFE"), (PLUGIN_PURE, "PURE")];
+
+// Names each capability set in `capabilities`, including any this host doesn't know about
+pub fn capability_names(capabilities: u64) -> Vec<String> {
+    bit_names(capabilities, &CAPABILITY_NAMES)
+}
+
+// Names each capability set in `capabilities` that a plugin advertises (`PLUGIN_*` bits)
+pub fn plugin_capability_names(capabilities: u64) -> Vec<String> {
+    bit_names(capabilities, &PLUGIN_CAPABILITY_NAMES)
+}
+
+fn bit_names(bits: u64, names: &[(u64, &str)]) -> Vec<String> {
+    (0..64)
+        .map(|bit| 1 << bit)
+        .filter(|capability| bits & capability != 0)
+        .map(|capability| {
+            names
+                .iter()
+                .find(|(c, _)| *c == capability)
+                .map(|(_, name)| name.to_string())
+                .unwrap_or_else(|| format!("unknown({:#x})", capability))
+        })
+        .collect()
+}
+
+// Describes a single argument, as returned by version 1 of the optional `plugin_describe_arg`
+// export (the unversioned `plugin_describe_arg`)
+#[repr(C)]
+pub struct ArgDescriptorV1 {
+    pub name: *const c_char,
+    pub arg_type: RawPluginType,
+    pub optional: bool,
+    pub doc: *const c_char,
+}
+
+// Describes a single argument, as returned by version 2 of the optional `plugin_describe_arg` export
+// (`plugin_describe_arg_v2`), which adds the secret flag and bounds to version 1
+#[repr(C)]
+pub struct ArgDescriptor {
+    // The name of the argument, or null if the index is out of range
+    pub name: *const c_char,
+    pub arg_type: RawPluginType,
+    // Optional arguments must come after all required arguments, and may be omitted by the host,
+    // either by passing fewer arguments or by passing Null in their place (e.g. to omit one but not
+    // the next), so plugins must treat both the same
+    pub optional: bool,
+    // A human-readable description of the argument; may be null
+    pub doc: *const c_char,
+    // Whether the argument holds a secret (e.g. a credential), whose value the host redacts
+    // wherever it would otherwise show it, such as in traces and error messages
+    pub secret: bool,
+    // Inclusive bounds on a numeric argument: each is null, or points to a value of the argument's
+    // type that outlives the plugin (`Int`, `UInt` or `Double`)
+    pub min: *const PluginValue,
+    pub max: *const PluginValue,
+    // The maximum length in bytes of a String or Bytes argument; 0 means unlimited
+    pub max_len: usize,
+}
+
+// Levels are ordered from most to least severe, so a level passes a threshold if it's no greater than
+// it
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 4] = [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+    ];
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    // Parses a level by name, ignoring case (e.g. `warn`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LogLevel::ALL
+            .into_iter()
+            .find(|level| level.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                format!(
+                    "Unknown log level {}; expected error, warn, info or debug",
+                    s
+                )
+            })
+    }
+}
+
+// A field attached to a structured log message (see `HostServices::log_fields`), e.g. `count=3`.
+// Both the key and the value are only borrowed by the host.
+#[repr(C)]
+pub struct KeyValue {
+    pub key: *const c_char,
+    pub value: PluginValue,
+}
+
+// Services the host provides to the plugin, passed to the optional `plugin_init` export. The
+// struct and the context it points to remain valid until the plugin is unloaded, so the plugin may
+// store the pointer and use it from later calls. Strings passed into the callbacks are only
+// borrowed for the duration of the call.
+#[repr(C)]
+pub struct HostServices {
+    // Opaque host state, which must be passed back to each of the callbacks
+    pub context: *const c_void,
+    // Logs a message at the given level
+    pub log: extern "C" fn(context: *const c_void, level: LogLevel, message: *const c_char),
+    // Looks up a config value by key, returning null if it's not set. The returned string is owned
+    // by the host and lives as long as the services themselves.
+    pub get_config: extern "C" fn(context: *const c_void, key: *const c_char) -> *const c_char,
+    // Returns true once the host has cancelled the current call, either on its own (e.g. because
+    // it timed out) or along with all of the plugin's calls. Long-running plugins should check this
+    // periodically and, when it's set, stop and return a `Cancelled` error. Must be called on the
+    // thread making the call, like `time_remaining`.
+    pub is_cancelled: extern "C" fn(context: *const c_void) -> bool,
+    // Reports how far through the current call the plugin is, as a fraction from 0 to 1. The host
+    // keeps the most recent report, e.g. to show how far a call got before it timed out; reports
+    // are only attributed to the call if they're made on the thread making it.
+    pub report_progress: extern "C" fn(context: *const c_void, fraction: f64),
+    // Passes the host a value produced by the current call before it returns, for plugins that
+    // stream their output. Must be called on the thread making the call. If it returns true, the
+    // host has taken ownership of the value (which is freed as if it had been returned); if it
+    // returns false (e.g., the host isn't streaming the call's output), the value remains the
+    // plugin's to free.
+    pub emit: extern "C" fn(context: *const c_void, value: *const PluginValue) -> bool,
+    // Returns the number of seconds left until the current call's deadline, 0 once it has passed,
+    // or infinity if the call doesn't have one, so that plugins can budget their work (e.g. return
+    // a partial result rather than be cut off). Each call has its own deadline, so this must be
+    // called on the thread making the call.
+    pub time_remaining: extern "C" fn(context: *const c_void) -> f64,
+    // Allocates `size` bytes aligned to `align` (a power of two) with the host's allocator, returning
+    // null if it can't (or a dangling, aligned pointer if `size` is 0). The data the host frees
+    // (returned strings, lists and messages) can be allocated with this, so that it's freed by the
+    // allocator that allocated it whatever allocator the plugin uses: strings as their length plus
+    // one with an alignment of 1, and lists as an array of `PluginValue`s. A plugin that requires
+    // `CAPABILITY_ALLOC` allocates everything it returns this way, including bytes and custom
+    // payloads (with an alignment of 1), which the host then frees itself rather than passing them
+    // to `plugin_free_bytes`.
+    pub alloc: extern "C" fn(context: *const c_void, size: usize, align: usize) -> *mut u8,
+    // Frees memory allocated by `alloc`, given the same size and alignment, e.g. a value the plugin
+    // allocated but isn't going to return after all
+    pub free: extern "C" fn(context: *const c_void, ptr: *mut u8, size: usize, align: usize),
+    // Logs a message at the given level like `log`, along with `fields_len` key-value fields (e.g.
+    // the item being processed), so that the plugin's logs can be filtered and queried by them.
+    // Plugins that use it should require `CAPABILITY_LOG_FIELDS`, as older hosts don't provide it.
+    pub log_fields: extern "C" fn(
+        context: *const c_void,
+        level: LogLevel,
+        message: *const c_char,
+        fields: *const KeyValue,
+        fields_len: usize,
+    ),
+}
+
+// The symbols we load from the plugin library. A symbol whose signature changes gets a new version,
+// exported as `<name>_v<version>` (the unversioned name being version 1), so that a library can
+// support older hosts by exporting its older versions as well. The host uses the newest version it
+// understands (see `OptionalSymbol::versions`), and ignores versions newer than that.
+#[derive(WrapperApi)]
+pub(crate) struct PluginApi {
+    plugin_metadata: unsafe extern "C" fn() -> PluginMetadata,
+    // returns a `PluginResult`, which is read through its raw view so that it can be validated
+    plugin_entrypoint:
+        unsafe extern "C" fn(args: *const PluginValue, args_len: usize) -> RawPluginResult,
+    // Describe and call each of the plugin's functions, for plugins that export more than one.
+    // `plugin_describe_function` returns the metadata of the function at `index`, or metadata with
+    // a null name past the last one, and `plugin_call` calls that function as `plugin_entrypoint`
+    // does; a plugin exports both or neither. `plugin_metadata` and `plugin_entrypoint` are still
+    // required, and describe and call the plugin's default function, which is all that hosts that
+    // don't know about these see. The capabilities in `plugin_metadata` apply to every function.
+    plugin_describe_function: Option<unsafe extern "C" fn(index: usize) -> PluginMetadata>,
+    plugin_call: Option<
+        unsafe extern "C" fn(
+            index: usize,
+            args: *const PluginValue,
+            args_len: usize,
+        ) -> RawPluginResult,
+    >,
+    // Describes argument `index` of the function at `function`, as `plugin_describe_arg_v2` does
+    // for the default function; optional for plugins that export several functions
+    plugin_describe_function_arg:
+        Option<unsafe extern "C" fn(function: usize, index: usize) -> ArgDescriptor>,
+    plugin_describe_arg: Option<unsafe extern "C" fn(index: usize) -> ArgDescriptorV1>,
+    plugin_describe_arg_v2: Option<unsafe extern "C" fn(index: usize) -> ArgDescriptor>,
+    // Returns the plugin's tag at `index`, or null past the last one. Tags are static strings (e.g.
+    // `category=text` or `experimental`) that hosts can use to categorize and filter plugins.
+    plugin_describe_tag: Option<unsafe extern "C" fn(index: usize) -> *const c_char>,
+    // Called once after loading, before any other function but `plugin_metadata` (which the host
+    // reads first to check the capabilities the plugin requires); returns null on success or an
+    // error message, which the host is responsible for freeing
+    plugin_init: Option<unsafe extern "C" fn(services: *const HostServices) -> *mut c_char>,
+    // Releases a handle returned by the plugin; required for plugins that return handles
+    plugin_release: Option<unsafe extern "C" fn(token: u64)>,
+    // Frees a byte buffer returned by the plugin; required for plugins that return bytes, unless
+    // they allocate them with `HostServices::alloc` (see `CAPABILITY_ALLOC`)
+    plugin_free_bytes: Option<unsafe extern "C" fn(ptr: *mut u8, len: usize)>,
+    // A variant of the entrypoint that the host calls when it's giving up ownership of the
+    // arguments (`Plugin::call_owned`), which may return `PluginResult::Arg` to hand one of them
+    // back instead of a copy. Plugins that export it must still export `plugin_entrypoint`, which
+    // the host's other calls use.
+    plugin_entrypoint_owned:
+        Option<unsafe extern "C" fn(args: *const PluginValue, args_len: usize) -> RawPluginResult>,
+    // Returns the plugin to the state it was in after `plugin_init`, e.g. clearing caches or
+    // counters, without the cost of reloading it. Returns `Ok(Null)` on success; the host never
+    // calls it during a call.
+    plugin_reset: Option<unsafe extern "C" fn() -> RawPluginResult>,
+    // Returns the `PLUGIN_*` capabilities the plugin advertises, e.g. that it's `PLUGIN_PURE`;
+    // plugins that don't export it advertise none
+    plugin_capabilities: Option<unsafe extern "C" fn() -> u64>,
+    // A variant of the entrypoint that returns any number of values through an array owned by the
+    // host, which has room for `out_capacity` values. On success, the plugin writes its values to
+    // the start of `out`, stores how many it wrote in `out_len` and returns `Ok(Null)`; the host
+    // then owns the values (though not the array, which it always owns) and frees them as it does
+    // returned values. On error, the host ignores `out`, so the plugin must free anything it wrote
+    // there before returning the error.
+    plugin_entrypoint_out: Option<
+        unsafe extern "C" fn(
+            args: *const PluginValue,
+            args_len: usize,
+            out: *mut PluginValue,
+            out_capacity: usize,
+            out_len: *mut usize,
+        ) -> RawPluginResult,
+    >,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_raw_types() {
+        for (i, plugin_type) in PluginType::ALL.into_iter().enumerate() {
+            assert!(PluginType::try_from(i as RawPluginType) == Ok(plugin_type));
+            assert_eq!(plugin_type.to_raw(), Some(i as RawPluginType));
+        }
+        assert!(PluginType::try_from(CUSTOM_TYPE_BASE + 3) == Ok(PluginType::Custom(3)));
+        assert_eq!(PluginType::Custom(3).to_raw(), Some(CUSTOM_TYPE_BASE + 3));
+        assert_eq!(PluginType::Null.to_raw(), None);
+    }
+
+    #[test]
+    fn converts_custom_types_only_if_their_id_fits() {
+        let max_id = (RawPluginType::MAX - CUSTOM_TYPE_BASE) as u32;
+        assert_eq!(
+            PluginType::Custom(max_id).to_raw(),
+            Some(RawPluginType::MAX)
+        );
+        assert!(PluginType::try_from(RawPluginType::MAX) == Ok(PluginType::Custom(max_id)));
+        for type_id in [max_id + 1, i32::MAX as u32 + 1, u32::MAX] {
+            assert_eq!(PluginType::Custom(type_id).to_raw(), None);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_raw_types() {
+        let len = PluginType::ALL.len() as RawPluginType;
+        for raw in [len, 99, CUSTOM_TYPE_BASE - 1, -1, RawPluginType::MIN] {
+            assert!(PluginType::try_from(raw) == Err(raw), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn maps_types_to_rust_names_and_back() {
+        let names = [
+            (PluginType::Bool, "bool"),
+            (PluginType::Int, "i64"),
+            (PluginType::UInt, "u64"),
+            (PluginType::Double, "f64"),
+            (PluginType::String, "CString"),
+            (PluginType::Json, "serde_json::Value"),
+            (PluginType::Handle, "PluginHandle"),
+            (PluginType::List, "Vec<OwnedPluginValue>"),
+            (PluginType::Bytes, "Bytes"),
+            (PluginType::Fd, "File"),
+            (
+                PluginType::Result,
+                "Result<Box<OwnedPluginValue>, Box<OwnedPluginValue>>",
+            ),
+            (PluginType::Null, "()"),
+        ];
+        // every type is covered
+        assert_eq!(names.len(), PluginType::ALL.len() + 1);
+        for (plugin_type, name) in names {
+            assert_eq!(plugin_type.rust_name(), name);
+            assert!(
+                PluginType::from_rust_name(name) == Some(plugin_type),
+                "{}",
+                name
+            );
+        }
+
+        // custom types share Bytes' name, which maps back to Bytes
+        assert_eq!(PluginType::Custom(7).rust_name(), "Bytes");
+        assert!(PluginType::from_rust_name("u8").is_none());
+    }
+}
@@ -0,0 +1,168 @@
+// Running plugins in a separate worker process, so that a plugin that aborts or corrupts memory
+// can't take the host down with it (`catch_unwind` only protects the host from panics). The worker
+// loads the plugin and serves calls over its stdin and stdout, exchanging one line of JSON per
+// message, with values encoded as they are in recordings (see `record.rs`):
+//
+// worker -> host  {"metadata":{"name":"repeat",...}}, or {"error":{...}} if loading failed
+// host -> worker  {"args":[{"String":"cool"},{"UInt":3}]}
+// worker -> host  {"ok":{"String":"coolcoolcool"},"warning":null}, or {"error":{...}}
+//
+// where errors are encoded by `PluginError::to_json`, e.g. {"code":5,"message":"...",...}. Every
+// call costs a round trip and a copy of its arguments and result, and values that only make sense
+// within a process (handles and file descriptors) can't be sent at all.
+use crate::cache::{metadata_from_json, metadata_to_json};
+use crate::record::{from_json, to_json};
+use crate::{OwnedMetadata, OwnedPluginValue, Plugin, PluginError};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+// A plugin loaded by a worker process, which is killed when this is dropped
+pub struct IsolatedPlugin {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    metadata: OwnedMetadata,
+}
+
+impl IsolatedPlugin {
+    // Starts the worker, a command that loads the plugin and passes it to `serve` (the host binary
+    // does so when run with `--worker`), and waits for the plugin's metadata. The worker's stdin and
+    // stdout are used to talk to it, while its stderr is inherited, so that the plugin's log
+    // messages are shown as usual.
+    pub fn spawn(mut command: Command) -> Result<IsolatedPlugin, PluginError> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| PluginError::Load(format!("Could not start the plugin worker: {}", e)))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+        let reply = receive(&mut stdout).ok_or_else(|| PluginError::Load(exited(&mut child)))?;
+        if let Some(error) = reply.get("error") {
+            return Err(PluginError::from_json(error));
+        }
+        let metadata = metadata_from_json(&reply["metadata"]).ok_or_else(|| {
+            PluginError::Load(format!("Plugin worker sent invalid metadata: {}", reply))
+        })?;
+        Ok(IsolatedPlugin {
+            child,
+            stdin,
+            stdout,
+            metadata,
+        })
+    }
+
+    pub fn metadata(&self) -> &OwnedMetadata {
+        &self.metadata
+    }
+
+    // Calls the plugin in the worker, like `Plugin::call_partial`. If the worker dies during the
+    // call (e.g. because the plugin aborted), this fails with a `Panicked` error describing how it
+    // exited, and the plugin can't be called again.
+    pub fn call(
+        &mut self,
+        args: &[OwnedPluginValue],
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        let args = args.iter().map(to_json).collect::<Result<Vec<_>, _>>()?;
+        // if the worker has exited, writing fails, but that's reported by reading its reply
+        let _ =
+            writeln!(self.stdin, "{}", json!({ "args": args })).and_then(|_| self.stdin.flush());
+
+        let reply = receive(&mut self.stdout)
+            .ok_or_else(|| PluginError::Panicked(exited(&mut self.child)))?;
+        if let Some(error) = reply.get("error") {
+            return Err(PluginError::from_json(error));
+        }
+        let invalid =
+            |e: String| PluginError::Plugin(format!("Plugin worker sent an invalid reply: {}", e));
+        let value = from_json(&reply["ok"]).map_err(|e| invalid(e.to_string()))?;
+        let warning = match &reply["warning"] {
+            Value::Null => None,
+            Value::String(warning) => Some(warning.clone()),
+            warning => return Err(invalid(format!("invalid warning {}", warning))),
+        };
+        Ok((value, warning))
+    }
+}
+
+impl Drop for IsolatedPlugin {
+    fn drop(&mut self) {
+        // the worker holds no state worth shutting down for, and may be stuck in a call
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Reads the worker's next message, or None if it has exited (or sent something that isn't JSON,
+// which only happens if the plugin wrote to stdout)
+fn receive(stdout: &mut BufReader<ChildStdout>) -> Option<Value> {
+    let mut line = String::new();
+    match stdout.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => serde_json::from_str(&line).ok(),
+    }
+}
+
+// Describes how the worker exited, once it has stopped replying
+fn exited(child: &mut Child) -> String {
+    match child.wait() {
+        Ok(status) => format!("Plugin worker exited unexpectedly ({})", status),
+        Err(e) => format!("Plugin worker stopped replying: {}", e),
+    }
+}
+
+fn send(output: &mut impl Write, message: Value) -> Result<(), PluginError> {
+    writeln!(output, "{}", message)
+        .and_then(|_| output.flush())
+        .map_err(|e| PluginError::Usage(format!("Could not reply to the host: {}", e)))
+}
+
+// Runs a worker for `IsolatedPlugin`: sends the plugin's metadata (or the error it failed to load
+// with) to `output`, then calls it with each set of arguments read from `input` until it ends. The
+// plugin must not write to `output` itself, so it shouldn't be the stdout of a plugin that does.
+pub fn serve(
+    plugin: Result<Plugin, PluginError>,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(), PluginError> {
+    let plugin = match plugin {
+        Ok(plugin) => plugin,
+        Err(e) => return send(&mut output, json!({ "error": e.to_json() })),
+    };
+    send(
+        &mut output,
+        json!({ "metadata": metadata_to_json(plugin.metadata()) }),
+    )?;
+
+    for line in input.lines() {
+        let line =
+            line.map_err(|e| PluginError::Usage(format!("Could not read from the host: {}", e)))?;
+        let result = serde_json::from_str::<Value>(&line)
+            .map_err(|e| PluginError::Usage(format!("Invalid request from the host: {}", e)))
+            .and_then(|request| {
+                request["args"]
+                    .as_array()
+                    .ok_or_else(|| {
+                        PluginError::Usage(format!("Invalid request from the host: {}", request))
+                    })?
+                    .iter()
+                    .map(from_json)
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .and_then(|args| plugin.call_partial(&args))
+            .and_then(|(value, warning)| {
+                let value = to_json(&value).map_err(|e| {
+                    PluginError::Plugin(format!(
+                        "Plugin returned a value that can't be sent: {}",
+                        e
+                    ))
+                })?;
+                Ok(json!({ "ok": value, "warning": warning }))
+            });
+        let reply = result.unwrap_or_else(|e| json!({ "error": e.to_json() }));
+        send(&mut output, reply)?;
+    }
+    Ok(())
+}
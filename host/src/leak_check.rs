@@ -0,0 +1,112 @@
+// A global allocator that tracks the host's live allocations, used by the `leak-check` feature to
+// verify that every argument and result is freed exactly once after a call.
+//
+// Some of the memory the host frees was allocated by the plugin (e.g., returned strings, which the
+// host takes ownership of), so rather than counting allocations and frees, which wouldn't balance,
+// we keep a table of the addresses the host has allocated and ignore frees of any others. The
+// table has a fixed size so that tracking doesn't itself allocate; allocations made while it's full
+// aren't tracked, and are counted in `untracked_allocations`.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const SLOTS: usize = 1 << 16;
+const EMPTY: usize = 0;
+// marks a slot whose allocation was freed, so that lookups continue past it
+const FREED: usize = 1;
+
+static TABLE: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(EMPTY) }; SLOTS];
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static UNTRACKED: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+struct LeakCheckAllocator;
+
+#[global_allocator]
+static ALLOCATOR: LeakCheckAllocator = LeakCheckAllocator;
+
+fn slot(ptr: usize) -> usize {
+    // allocations are aligned, so the low bits carry little information
+    (ptr >> 4).wrapping_mul(0x9E37_79B9_7F4A_7C15) % SLOTS
+}
+
+fn track(ptr: usize) {
+    let start = slot(ptr);
+    for i in 0..SLOTS {
+        let entry = &TABLE[(start + i) % SLOTS];
+        let current = entry.load(Ordering::Relaxed);
+        if (current == EMPTY || current == FREED)
+            && entry
+                .compare_exchange(current, ptr, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            LIVE.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    }
+    UNTRACKED.fetch_add(1, Ordering::Relaxed);
+}
+
+fn untrack(ptr: usize) {
+    let start = slot(ptr);
+    for i in 0..SLOTS {
+        let entry = &TABLE[(start + i) % SLOTS];
+        match entry.load(Ordering::Relaxed) {
+            // not allocated by the host
+            EMPTY => return,
+            current if current == ptr => {
+                if entry
+                    .compare_exchange(ptr, FREED, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    LIVE.fetch_sub(1, Ordering::Relaxed);
+                }
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for LeakCheckAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            track(ptr as usize);
+            ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        untrack(ptr as usize);
+        System.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            untrack(ptr as usize);
+            track(new_ptr as usize);
+            ALLOCATED_BYTES.fetch_add(new_size.saturating_sub(layout.size()), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+// The number of allocations made by the host that haven't been freed. Compare the counts before
+// and after a call to find what the call leaked.
+pub fn live_allocations() -> usize {
+    LIVE.load(Ordering::Relaxed)
+}
+
+// The number of allocations that couldn't be tracked because the table was full
+pub fn untracked_allocations() -> usize {
+    UNTRACKED.load(Ordering::Relaxed)
+}
+
+// The total number of bytes the host has allocated, including those since freed (growing an
+// allocation counts the bytes it grew by). Compare the totals before and after a call to find how
+// much it allocated.
+pub fn allocated_bytes() -> usize {
+    ALLOCATED_BYTES.load(Ordering::Relaxed)
+}
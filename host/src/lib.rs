@@ -0,0 +1,68 @@
+// A library for loading and calling plugins, which confines all of the unsafe FFI details to this
+// crate. The host binary (in main.rs) is a thin CLI over this library, and other applications can
+// embed it directly.
+mod bytes;
+#[cfg(feature = "serde")]
+mod cache;
+mod convert;
+mod custom;
+mod debug;
+mod error;
+pub mod ffi;
+#[cfg(feature = "serde")]
+mod isolation;
+#[cfg(feature = "leak-check")]
+mod leak_check;
+mod limits;
+#[cfg(feature = "serde")]
+mod memo;
+mod metadata;
+#[cfg(feature = "mock")]
+mod mock;
+mod plugin;
+mod raw;
+#[cfg(feature = "serde")]
+mod record;
+#[cfg(feature = "signing")]
+mod signature;
+mod telemetry;
+mod value;
+
+pub use bytes::Bytes;
+#[cfg(feature = "serde")]
+pub use cache::MetadataCache;
+#[doc(hidden)]
+pub use convert::call_function;
+pub use convert::{IntoPluginArg, IntoPluginArgs};
+pub use custom::{CustomType, CustomTypes};
+pub use error::{ArgError, ArgErrorKind, PluginError};
+pub use ffi::{PluginType, PluginValue};
+#[cfg(feature = "derive")]
+pub use host_derive::IntoPluginArgs;
+#[cfg(feature = "serde")]
+pub use isolation::{serve, IsolatedPlugin};
+#[cfg(feature = "leak-check")]
+pub use leak_check::{allocated_bytes, live_allocations, untracked_allocations};
+pub use limits::ResourceLimits;
+#[cfg(feature = "serde")]
+pub use memo::MemoStats;
+pub use metadata::{
+    capabilities_table, format_raw_metadata, functions_table, plugins_table, ArgInfo, Bound,
+    OwnedMetadata, REDACTED,
+};
+#[cfg(feature = "mock")]
+pub use mock::MockPlugin;
+pub use plugin::{
+    BorrowedPluginValue, CallStats, CancellationToken, OptionalSymbol, Plugin, PluginHandle,
+    PreparedArgs, SymbolVisibility, DEFAULT_LOG_LEVEL,
+};
+#[cfg(feature = "tokio")]
+pub use plugin::{PluginStream, STREAM_BUFFER};
+#[cfg(feature = "serde")]
+pub use record::{replay, Recorder};
+#[cfg(feature = "signing")]
+pub use signature::{signature_path, TrustedKey};
+pub use value::{
+    FloatFormat, FormatOptions, OwnedPluginValue, ParseOptions, DEFAULT_MAX_ITEMS,
+    DEFAULT_MAX_LIST_LEN, DEFAULT_MAX_RESULT_BYTES, DEFAULT_MAX_STRING_LEN,
+};
@@ -0,0 +1,66 @@
+// Resource limits for running untrusted plugins. As plugins run in the host's process, the limits
+// apply to the whole process rather than to a single call, so they are a coarse safety net rather
+// than a sandbox: a plugin that exceeds the memory limit will see its allocations fail (which
+// usually aborts the process), and one that exceeds the CPU limit is killed by the OS. They're only
+// supported on unix, where they're set with `setrlimit`.
+use crate::PluginError;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceLimits {
+    // The maximum size of the process's address space, in bytes
+    pub memory: Option<u64>,
+    // The maximum CPU time the process may use, in seconds
+    pub cpu_seconds: Option<u64>,
+}
+
+impl ResourceLimits {
+    // Applies the limits to the current process. Limits can only be lowered, so this should be
+    // called once, before loading any plugins.
+    #[cfg(unix)]
+    pub fn apply(&self) -> Result<(), PluginError> {
+        if let Some(memory) = self.memory {
+            check(
+                unsafe { libc::setrlimit(libc::RLIMIT_AS, &rlimit(memory)) },
+                "memory",
+            )?;
+        }
+        if let Some(cpu_seconds) = self.cpu_seconds {
+            check(
+                unsafe { libc::setrlimit(libc::RLIMIT_CPU, &rlimit(cpu_seconds)) },
+                "CPU",
+            )?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self) -> Result<(), PluginError> {
+        if self.memory.is_some() || self.cpu_seconds.is_some() {
+            return Err(PluginError::Usage(
+                "Resource limits are not supported on this platform".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Sets both the soft and hard limits, so that the plugin can't raise them again
+#[cfg(unix)]
+fn rlimit(value: u64) -> libc::rlimit {
+    libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    }
+}
+
+#[cfg(unix)]
+fn check(result: libc::c_int, what: &str) -> Result<(), PluginError> {
+    if result != 0 {
+        return Err(PluginError::Usage(format!(
+            "Failed to set the {} limit: {}",
+            what,
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
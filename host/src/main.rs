@@ -1,143 +1,1377 @@
-use dlopen2::wrapper::{Container, WrapperApi};
-use std::env::args;
-use std::ffi::{CStr, CString};
-use std::fmt::{Display, Formatter};
+use host::ffi::{LogLevel, PluginType};
+use host::{
+    capabilities_table, functions_table, plugins_table, Bytes, FloatFormat, FormatOptions,
+    OptionalSymbol, OwnedMetadata, OwnedPluginValue, ParseOptions, Plugin, PluginError,
+    PreparedArgs, ResourceLimits, SymbolVisibility, DEFAULT_LOG_LEVEL, DEFAULT_MAX_ITEMS,
+    DEFAULT_MAX_LIST_LEN, DEFAULT_MAX_RESULT_BYTES, DEFAULT_MAX_STRING_LEN, REDACTED,
+};
+use std::collections::HashMap;
+use std::env::args_os;
+use std::ffi::{CString, OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-// An FFI-safe value enum to support various input/output types
-#[repr(C)]
-pub enum PluginValue {
-    Bool(bool),
-    Int(i64),
-    UInt(u64),
-    Double(f64),
-    // Strings are represented as a pointer to a null-terminated string; all strings are owned
-    // by the host. Returned strings must be freed by the host.
-    String(*const i8),
+mod audit;
+mod bench;
+mod eval;
+mod scaffold;
+
+// Environment variables with this prefix are passed to the plugin as config values, e.g.
+// `PLUGIN_CFG_SEPARATOR=-` sets `separator`
+const CONFIG_ENV_PREFIX: &str = "PLUGIN_CFG_";
+
+// The config key through which `--plugin-cwd` passes the directory plugins should resolve relative
+// paths against
+const BASE_DIR_KEY: &str = "base_dir";
+
+// How many more times `--check-stable-metadata` reads each plugin's metadata after loading it
+const STABLE_METADATA_READS: usize = 10;
+
+// Options controlling the host's behavior, passed as flags before the plugin path
+#[derive(Default)]
+struct Options {
+    // Fail if the plugin returns a string that is not valid UTF-8, rather than lossily replacing
+    // the invalid bytes
+    strict_utf8: bool,
+    // Trace the arguments and result of each call to stderr
+    trace: bool,
+    // Make each plugin's symbols visible to the libraries loaded after it
+    global_symbols: bool,
+    // Read each plugin's metadata several times after loading it, failing if it changes
+    check_stable_metadata: bool,
+    // The least severe level of the plugin's log messages to print
+    log_level: Option<LogLevel>,
+    // The maximum length of a string the plugin may return
+    max_string_len: Option<usize>,
+    // The maximum number of items in a list the plugin may return
+    max_list_len: Option<usize>,
+    // The maximum total size of a result the plugin may return
+    max_result_bytes: Option<usize>,
+    // The number of list items to print before eliding the rest
+    max_items: Option<usize>,
+    // Print lists in full, regardless of `max_items`
+    full: bool,
+    // How to print Doubles
+    float_format: FloatFormat,
+    // How to print results (and, for JSON, errors)
+    format: OutputFormat,
+    // Read Bytes arguments as the paths of files to map into memory, rather than as their contents
+    map_bytes: bool,
+    // The character between the items of List arguments, if not `,`
+    list_separator: Option<char>,
+    // Call the plugin in a worker process, so that it can't crash the host
+    subprocess: bool,
+    // Run as the worker for a host calling the plugin in a subprocess
+    worker: bool,
+    // Print the signatures of the plugin's functions instead of calling it
+    functions: bool,
+    // List the plugins, with their tags, instead of calling one
+    list: bool,
+    // Only list plugins with all of these tags
+    tags: Vec<String>,
+    // Print the host capabilities the plugin requires, and which the host provides, instead of
+    // calling it
+    caps: bool,
+    // Call a pipeline of plugins, passing each one's result to the next
+    pipe: bool,
+    // Compare the metadata of two plugins instead of calling one
+    diff: bool,
+    // Call the plugin once per line of stdin, passing the line as the first argument
+    stdin: bool,
+    // Cache up to this many results of a pure plugin, answering repeated calls from the cache
+    memoize: Option<usize>,
+    // Generate a new plugin crate instead of calling a plugin
+    new_plugin: bool,
+    // Audit every plugin in this directory instead of calling a plugin
+    audit: Option<PathBuf>,
+    // With --audit, also initialize and call each plugin to check that it enforces its argument
+    // count
+    audit_probe: bool,
+    // Append each call and its result to this file
+    record: Option<String>,
+    // Replay the calls recorded in this file instead of calling the plugin with arguments
+    replay: Option<String>,
+    // Evaluate this expression of nested calls to the plugins, instead of calling a single plugin
+    eval: Option<String>,
+    // Read the metadata printed by `--functions` from this cache file, refreshing it as needed
+    cache: Option<String>,
+    // Refuse to load plugins that aren't signed by the public key in this file
+    verify_key: Option<String>,
+    // Measure the call time over batches of this many calls instead of printing the result
+    bench: Option<u32>,
+    // Compare the measured call time against the baseline in this file
+    baseline: Option<String>,
+    // Write the measured call time to the baseline file instead of comparing against it
+    update_baseline: bool,
+    // Config values made available to the plugin through `HostServices::get_config`
+    config: HashMap<CString, CString>,
+    // Directories to look for plugins in, if they aren't found relative to the working directory
+    plugin_path: Vec<PathBuf>,
+    // Limits on the memory and CPU time the process (and so the plugin) may use
+    limits: ResourceLimits,
+    // Give up on a call that takes longer than this
+    timeout: Option<Duration>,
+    // Give up on a call that hasn't completed by this time
+    deadline: Option<Instant>,
 }
 
-impl PluginValue {
-    pub fn to_owned(self) -> OwnedPluginValue {
-        match self {
-            PluginValue::Bool(b) => OwnedPluginValue::Bool(b),
-            PluginValue::Int(i) => OwnedPluginValue::Int(i),
-            PluginValue::UInt(u) => OwnedPluginValue::UInt(u),
-            PluginValue::Double(d) => OwnedPluginValue::Double(d),
-            PluginValue::String(s) => {
-                OwnedPluginValue::String(unsafe { CString::from_raw(s as *mut i8) })
-            }
-        }
+// The formats results can be printed in, chosen with `--format`. In the machine-readable formats,
+// stdout only holds results, one line per call.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum OutputFormat {
+    // `Plugin returned: <value>`, formatted as configured by the other options
+    #[default]
+    Text,
+    // A JSON object with the result or error, values being encoded as in recordings (requires
+    // serde)
+    Json,
+    // The result as a single CSV field (see `OwnedPluginValue::format_csv`)
+    Csv,
+}
+
+fn print_usage(program: &str) {
+    eprintln!("Usage: {} [options] <plugin> [function] [args...]", program);
+    eprintln!("       {} --diff <old plugin> <new plugin>", program);
+    eprintln!(
+        "       {} --pipe <plugin> <plugin>... [-- args...]",
+        program
+    );
+    eprintln!("       {} --eval <expr> <plugin>...", program);
+    eprintln!("       {} --replay <file> <plugin>", program);
+    eprintln!("       {} --functions <plugin>", program);
+    eprintln!("       {} --functions --cache <file> <plugin>...", program);
+    eprintln!(
+        "       {} --list [--cache <file>] [--tag <tag>]... <plugin>...",
+        program
+    );
+    eprintln!("       {} --caps <plugin>", program);
+    eprintln!("       {} --stdin <plugin> [function] [args...]", program);
+    eprintln!(
+        "       {} --bench <n> [--baseline <file> [--update-baseline]] <plugin> [function] \
+         [args...]",
+        program
+    );
+    eprintln!("       {} --new-plugin <name> <dir>", program);
+    eprintln!("       {} [--audit-probe] --audit <dir>", program);
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --strict-utf8         fail if the plugin returns a string that isn't valid UTF-8");
+    eprintln!("  --trace               trace raw metadata, and the arguments and result of calls");
+    eprintln!(
+        "  --caps                list the host capabilities the plugin requires and the host"
+    );
+    eprintln!("                        provides, and the capabilities the plugin advertises");
+    eprintln!("  --global-symbols      load plugins with their symbols visible to later plugins");
+    eprintln!("  --check-stable-metadata");
+    eprintln!(
+        "                        fail if a plugin's metadata changes over {} more reads",
+        STABLE_METADATA_READS
+    );
+    eprintln!("  --config <key=value>  set a config value for the plugin; may be repeated");
+    eprintln!(
+        "                        (values are also read from {}<KEY> variables)",
+        CONFIG_ENV_PREFIX
+    );
+    eprintln!("  --plugin-path <dir>   search dir for plugins not found in the working directory;");
+    eprintln!("                        may be repeated");
+    eprintln!(
+        "  --plugin-cwd <dir>    have the plugin resolve relative paths against dir (sets {})",
+        BASE_DIR_KEY
+    );
+    eprintln!(
+        "  --log-level <level>   print plugin log messages at or above level (default {})",
+        DEFAULT_LOG_LEVEL
+    );
+    eprintln!(
+        "  --max-string-len <n>  reject returned strings longer than n bytes (default {})",
+        DEFAULT_MAX_STRING_LEN
+    );
+    eprintln!(
+        "  --max-list-len <n>    reject returned lists longer than n items (default {})",
+        DEFAULT_MAX_LIST_LEN
+    );
+    eprintln!("  --max-result-bytes <n>");
+    eprintln!(
+        "                        reject results larger than n bytes in total (default {})",
+        DEFAULT_MAX_RESULT_BYTES
+    );
+    eprintln!(
+        "  --max-items <n>       print at most n items of each list (default {})",
+        DEFAULT_MAX_ITEMS
+    );
+    eprintln!("  --full                print lists in full");
+    eprintln!("  --float-format <fmt>  print doubles as plain (default), fixed:<digits>,");
+    eprintln!("                        scientific or roundtrip");
+    eprintln!("  --format <format>     print results as text (default), csv or json (with errors;");
+    eprintln!("                        requires serde)");
+    eprintln!("  --isolation <mode>    inprocess (default) or subprocess, to call the plugin in a");
+    eprintln!("                        separate process that can crash without the host (requires");
+    eprintln!("                        serde)");
+    eprintln!("  --map-bytes           pass Bytes arguments by mapping the files they name");
+    eprintln!("  --list-separator <c>  separate the items of List arguments with c (default ,)");
+    eprintln!("  --record <file>       record the call and its result to file (requires serde)");
+    eprintln!(
+        "  --cache <file>        cache the metadata read by --functions in file (requires serde)"
+    );
+    eprintln!("  --list                list the plugins and their tags instead of calling one");
+    eprintln!("  --tag <tag>           with --list, only list plugins with tag; may be repeated");
+    eprintln!("  --memoize <n>         cache up to n results of a pure plugin (requires serde)");
+    eprintln!("  --eval <expr>         evaluate nested calls, e.g. 'repeat(upper(\"hi\"), 2)'");
+    eprintln!(
+        "  --verify-key <file>   only load plugins signed by the key in file (requires signing)"
+    );
+    eprintln!("  --bench <n>           measure the median call time over batches of n calls");
+    eprintln!("  --baseline <file>     with --bench, fail if slower than the baseline in file");
+    eprintln!("  --update-baseline     with --baseline, write the measured time to file instead");
+    eprintln!(
+        "  --audit-probe         with --audit, also initialize each plugin and call it with no"
+    );
+    eprintln!("                        arguments, to check that it rejects too few");
+    eprintln!("  --timeout <seconds>   give up on a call that takes longer than this");
+    eprintln!(
+        "  --deadline <time>     give up on a call that hasn't completed by time, in seconds"
+    );
+    eprintln!("                        since the Unix epoch");
+    eprintln!("  --limit-memory <n>    limit the process's address space to n bytes (unix only)");
+    eprintln!("  --limit-cpu <n>       limit the process's CPU time to n seconds (unix only)");
+}
+
+// Formats a result the way it's printed, separately from printing it so that the output can be
+// captured (e.g., to compare against a snapshot). A Null result means the plugin had nothing to
+// return, which isn't an error.
+fn format_result(value: &OwnedPluginValue, options: &Options) -> Result<String, PluginError> {
+    let invalid = |e| PluginError::Plugin(format!("Plugin returned invalid UTF-8: {}", e));
+    if options.format == OutputFormat::Csv {
+        let format = FormatOptions {
+            strict_utf8: options.strict_utf8,
+            float_format: options.float_format,
+            ..FormatOptions::default()
+        };
+        return value.format_csv(&format).map_err(invalid);
     }
+    if let OwnedPluginValue::Null = value {
+        return Ok("Plugin returned: (none)".to_string());
+    }
+    let format = FormatOptions {
+        strict_utf8: options.strict_utf8,
+        max_items: if options.full {
+            None
+        } else {
+            Some(options.max_items.unwrap_or(DEFAULT_MAX_ITEMS))
+        },
+        float_format: options.float_format,
+        ..FormatOptions::default()
+    };
+    let output = value.format(&format).map_err(invalid)?;
+    Ok(format!("Plugin returned: {}", output))
 }
 
-// An owned version of PluginValue that owns all dynamically allocated resources,
-// such that memory will be freed when the value is dropped.
-pub enum OwnedPluginValue {
-    Bool(bool),
-    Int(i64),
-    UInt(u64),
-    Double(f64),
-    String(CString),
+fn print_result(value: OwnedPluginValue, options: &Options) -> Result<(), PluginError> {
+    print_result_and_warning(value, None, options)
 }
 
-impl Display for OwnedPluginValue {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            OwnedPluginValue::Bool(b) => write!(f, "{}", b),
-            OwnedPluginValue::Int(i) => write!(f, "{}", i),
-            OwnedPluginValue::UInt(u) => write!(f, "{}", u),
-            OwnedPluginValue::Double(d) => write!(f, "{}", d),
-            OwnedPluginValue::String(s) => write!(f, "{}", s.to_string_lossy()),
+fn main() {
+    let mut args = args_os();
+    let program = args
+        .next()
+        .map(|program| program.to_string_lossy().to_string())
+        .unwrap_or_else(|| "host".to_string());
+
+    let mut json = false;
+    if let Err(e) = run(args, &mut json) {
+        #[cfg(feature = "serde")]
+        if json {
+            println!("{}", serde_json::json!({ "error": e.to_json() }));
+            std::process::exit(e.exit_code());
         }
+        eprintln!("{}", e);
+        if let PluginError::Usage(_) = e {
+            print_usage(&program);
+        }
+        std::process::exit(e.exit_code());
     }
 }
 
-// An FFI-safe result type
-#[repr(C)]
-pub enum PluginResult {
-    Ok(PluginValue),
-    // The host is responsible for freeing the error message
-    Err(*mut i8),
+// Collects the environment variables whose names start with `prefix` as config values, stripping the
+// prefix and lowercasing the rest of the name. Variables that aren't valid UTF-8 or contain a NUL
+// byte can't be passed to the plugin, so they're skipped.
+fn env_config(prefix: &str) -> HashMap<CString, CString> {
+    std::env::vars_os()
+        .filter_map(|(key, value)| {
+            let key = key.to_str()?.strip_prefix(prefix)?.to_lowercase();
+            let value = value.into_string().ok()?;
+            Some((CString::new(key).ok()?, CString::new(value).ok()?))
+        })
+        .collect()
 }
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub enum PluginType {
-    Bool,
-    Int,
-    UInt,
-    Double,
-    String,
+// Returns the next argument, if it's valid UTF-8
+fn next_str(args: &mut impl Iterator<Item = OsString>) -> Option<String> {
+    args.next()?.into_string().ok()
 }
 
-#[repr(C)]
-pub struct PluginMetadata {
-    pub name: *const i8,
-    pub arg_types: *const PluginType,
-    pub arg_types_len: usize,
-    pub return_type: PluginType,
+// Runs the host with the given arguments; `json` is set once `--format json` is parsed, so that
+// errors after that point are printed as JSON
+fn run(args: impl Iterator<Item = OsString>, json: &mut bool) -> Result<(), PluginError> {
+    // the arguments are kept to pass them on to a worker process (see `call_isolated`)
+    let argv: Vec<OsString> = args.collect();
+    let mut args = argv.iter().cloned();
+    let mut options = Options::default();
+    let mut positional = vec![];
+    while let Some(arg) = args.next() {
+        // options must be valid UTF-8, but the arguments passed to the plugin can be arbitrary
+        // bytes (e.g., to pass a string that isn't valid UTF-8)
+        let Some(option) = arg.to_str().filter(|arg| arg.starts_with("--")) else {
+            positional.push(arg);
+            break;
+        };
+        match option {
+            "--strict-utf8" => options.strict_utf8 = true,
+            "--trace" => options.trace = true,
+            "--global-symbols" => options.global_symbols = true,
+            "--check-stable-metadata" => options.check_stable_metadata = true,
+            "--diff" => options.diff = true,
+            "--full" => options.full = true,
+            "--pipe" => options.pipe = true,
+            "--functions" => options.functions = true,
+            "--list" => options.list = true,
+            "--caps" => options.caps = true,
+            "--map-bytes" => options.map_bytes = true,
+            // not listed in the usage, as it's only run by the host itself
+            "--worker" => options.worker = true,
+            "--format" => {
+                options.format = match next_str(&mut args).as_deref() {
+                    Some("text") => OutputFormat::Text,
+                    Some("json") => OutputFormat::Json,
+                    Some("csv") => OutputFormat::Csv,
+                    _ => {
+                        return Err(PluginError::Usage(
+                            "--format expects text, csv or json".to_string(),
+                        ))
+                    }
+                };
+                *json = options.format == OutputFormat::Json;
+            }
+            "--isolation" => {
+                options.subprocess = match next_str(&mut args).as_deref() {
+                    Some("inprocess") => false,
+                    Some("subprocess") => true,
+                    _ => {
+                        return Err(PluginError::Usage(
+                            "--isolation expects inprocess or subprocess".to_string(),
+                        ))
+                    }
+                }
+            }
+            "--stdin" => options.stdin = true,
+            "--new-plugin" => options.new_plugin = true,
+            "--update-baseline" => options.update_baseline = true,
+            "--memoize" => {
+                let n = next_str(&mut args)
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| {
+                        PluginError::Usage("--memoize expects a number of results".to_string())
+                    })?;
+                options.memoize = Some(n);
+            }
+            "--bench" => {
+                let n = next_str(&mut args)
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| {
+                        PluginError::Usage("--bench expects a number of calls".to_string())
+                    })?;
+                options.bench = Some(n);
+            }
+            "--audit" => {
+                let dir = next_str(&mut args)
+                    .ok_or_else(|| PluginError::Usage("--audit expects a directory".to_string()))?;
+                options.audit = Some(PathBuf::from(dir));
+            }
+            "--audit-probe" => options.audit_probe = true,
+            "--eval" => {
+                let expr = next_str(&mut args).ok_or_else(|| {
+                    PluginError::Usage("--eval expects an expression".to_string())
+                })?;
+                options.eval = Some(expr);
+            }
+            "--config" => {
+                let (key, value) = next_str(&mut args)
+                    .and_then(|kv| {
+                        let (key, value) = kv.split_once('=')?;
+                        Some((CString::new(key).ok()?, CString::new(value).ok()?))
+                    })
+                    .ok_or_else(|| {
+                        PluginError::Usage(
+                            "--config expects an argument of the form key=value".to_string(),
+                        )
+                    })?;
+                options.config.insert(key, value);
+            }
+            "--tag" => {
+                let tag = next_str(&mut args)
+                    .ok_or_else(|| PluginError::Usage("--tag expects a tag".to_string()))?;
+                options.tags.push(tag);
+            }
+            "--plugin-path" => {
+                let dir = args.next().ok_or_else(|| {
+                    PluginError::Usage("--plugin-path expects a directory".to_string())
+                })?;
+                options.plugin_path.push(PathBuf::from(dir));
+            }
+            "--plugin-cwd" => {
+                // the working directory is process-wide, so rather than changing it, the plugin is
+                // given a base directory through its config
+                let dir = next_str(&mut args)
+                    .ok_or_else(|| PluginError::Usage("--plugin-cwd expects a directory".into()))?;
+                let dir = Path::new(&dir)
+                    .canonicalize()
+                    .ok()
+                    .filter(|dir| dir.is_dir())
+                    .and_then(|dir| CString::new(dir.to_string_lossy().as_bytes()).ok())
+                    .ok_or_else(|| {
+                        PluginError::Usage(format!("--plugin-cwd: {} isn't a directory", dir))
+                    })?;
+                options
+                    .config
+                    .insert(CString::new(BASE_DIR_KEY).unwrap(), dir);
+            }
+            "--max-string-len" => {
+                let len = next_str(&mut args)
+                    .and_then(|len| len.parse().ok())
+                    .ok_or_else(|| {
+                        PluginError::Usage("--max-string-len expects a number of bytes".to_string())
+                    })?;
+                options.max_string_len = Some(len);
+            }
+            "--max-list-len" => {
+                let len = next_str(&mut args)
+                    .and_then(|len| len.parse().ok())
+                    .ok_or_else(|| {
+                        PluginError::Usage("--max-list-len expects a number of items".to_string())
+                    })?;
+                options.max_list_len = Some(len);
+            }
+            "--max-result-bytes" => {
+                let bytes = next_str(&mut args)
+                    .and_then(|bytes| bytes.parse().ok())
+                    .ok_or_else(|| {
+                        PluginError::Usage(
+                            "--max-result-bytes expects a number of bytes".to_string(),
+                        )
+                    })?;
+                options.max_result_bytes = Some(bytes);
+            }
+            "--float-format" => {
+                options.float_format =
+                    next_str(&mut args)
+                        .unwrap_or_default()
+                        .parse()
+                        .map_err(|e| {
+                            PluginError::Usage(format!("--float-format expects a format: {}", e))
+                        })?;
+            }
+            "--list-separator" => {
+                let separator = next_str(&mut args).unwrap_or_default();
+                let mut chars = separator.chars();
+                options.list_separator = match (chars.next(), chars.next()) {
+                    (Some(c), None) if c != '"' => Some(c),
+                    _ => {
+                        return Err(PluginError::Usage(
+                            "--list-separator expects a single character other than '\"'"
+                                .to_string(),
+                        ))
+                    }
+                };
+            }
+            "--log-level" => {
+                let level = next_str(&mut args)
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(|e| {
+                        PluginError::Usage(format!("--log-level expects a level: {}", e))
+                    })?;
+                options.log_level = Some(level);
+            }
+            "--timeout" => {
+                let timeout = next_str(&mut args)
+                    .and_then(|seconds| seconds.parse().ok())
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .filter(|timeout| !timeout.is_zero())
+                    .ok_or_else(|| {
+                        PluginError::Usage("--timeout expects a number of seconds".to_string())
+                    })?;
+                options.timeout = Some(timeout);
+            }
+            "--deadline" => {
+                let deadline = next_str(&mut args)
+                    .and_then(|seconds| seconds.parse().ok())
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+                    .ok_or_else(|| {
+                        PluginError::Usage(
+                            "--deadline expects a time in seconds since the Unix epoch".to_string(),
+                        )
+                    })?;
+                // a deadline that has already passed is kept as one, so that the call fails
+                let now = Instant::now();
+                let deadline = match deadline.duration_since(SystemTime::now()) {
+                    Ok(remaining) => now.checked_add(remaining).ok_or_else(|| {
+                        PluginError::Usage("--deadline is too far in the future".to_string())
+                    })?,
+                    Err(_) => now,
+                };
+                options.deadline = Some(deadline);
+            }
+            "--max-items" => {
+                let n = next_str(&mut args)
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| {
+                        PluginError::Usage("--max-items expects a number of items".to_string())
+                    })?;
+                options.max_items = Some(n);
+            }
+            "--limit-memory" => {
+                let bytes = next_str(&mut args)
+                    .and_then(|bytes| bytes.parse().ok())
+                    .ok_or_else(|| {
+                        PluginError::Usage("--limit-memory expects a number of bytes".to_string())
+                    })?;
+                options.limits.memory = Some(bytes);
+            }
+            "--limit-cpu" => {
+                let seconds = next_str(&mut args)
+                    .and_then(|seconds| seconds.parse().ok())
+                    .ok_or_else(|| {
+                        PluginError::Usage("--limit-cpu expects a number of seconds".to_string())
+                    })?;
+                options.limits.cpu_seconds = Some(seconds);
+            }
+            "--record" | "--replay" | "--cache" | "--verify-key" | "--baseline" => {
+                let path = next_str(&mut args)
+                    .ok_or_else(|| PluginError::Usage(format!("{} expects a file", option)))?;
+                match option {
+                    "--record" => options.record = Some(path),
+                    "--replay" => options.replay = Some(path),
+                    "--cache" => options.cache = Some(path),
+                    "--verify-key" => options.verify_key = Some(path),
+                    _ => options.baseline = Some(path),
+                }
+            }
+            "--" => break,
+            _ => {
+                return Err(PluginError::Usage(format!("Unknown option {}", option)));
+            }
+        }
+    }
+    // everything after the plugin path (or `--`) is passed to the plugin
+    positional.extend(args);
+
+    // values given with --config take precedence over those from the environment
+    for (key, value) in env_config(CONFIG_ENV_PREFIX) {
+        options.config.entry(key).or_insert(value);
+    }
+
+    if options.timeout.is_some() && options.deadline.is_some() {
+        return Err(PluginError::Usage(
+            "--timeout can't be combined with --deadline".to_string(),
+        ));
+    }
+    if (options.timeout.is_some() || options.deadline.is_some()) && options.record.is_some() {
+        return Err(PluginError::Usage(
+            "--timeout and --deadline can't be combined with --record".to_string(),
+        ));
+    }
+
+    #[cfg(not(feature = "serde"))]
+    if options.record.is_some() || options.replay.is_some() || options.cache.is_some() {
+        return Err(PluginError::Usage(
+            "--record, --replay and --cache require the host to be built with the serde feature"
+                .to_string(),
+        ));
+    }
+
+    #[cfg(not(feature = "serde"))]
+    if options.memoize.is_some() {
+        return Err(PluginError::Usage(
+            "--memoize requires the host to be built with the serde feature".to_string(),
+        ));
+    }
+    if options.memoize.is_some() && (options.timeout.is_some() || options.deadline.is_some()) {
+        return Err(PluginError::Usage(
+            "--memoize can't be combined with --timeout or --deadline".to_string(),
+        ));
+    }
+
+    #[cfg(not(feature = "serde"))]
+    if options.subprocess || options.worker {
+        return Err(PluginError::Usage(
+            "--isolation subprocess requires the host to be built with the serde feature"
+                .to_string(),
+        ));
+    }
+    if options.subprocess
+        && (options.diff
+            || options.pipe
+            || options.eval.is_some()
+            || options.functions
+            || options.list
+            || options.caps
+            || options.stdin
+            || options.bench.is_some()
+            || options.record.is_some()
+            || options.replay.is_some()
+            || options.timeout.is_some()
+            || options.deadline.is_some())
+    {
+        return Err(PluginError::Usage(
+            "--isolation subprocess only supports calling a plugin once".to_string(),
+        ));
+    }
+
+    #[cfg(not(feature = "serde"))]
+    if options.format == OutputFormat::Json {
+        return Err(PluginError::Usage(
+            "--format json requires the host to be built with the serde feature".to_string(),
+        ));
+    }
+    if options.format != OutputFormat::Text
+        && (options.diff
+            || options.functions
+            || options.list
+            || options.caps
+            || options.bench.is_some()
+            || options.replay.is_some()
+            || options.new_plugin)
+    {
+        return Err(PluginError::Usage(
+            "--format only applies to the results of calls".to_string(),
+        ));
+    }
+
+    #[cfg(not(feature = "signing"))]
+    if options.verify_key.is_some() {
+        return Err(PluginError::Usage(
+            "--verify-key requires the host to be built with the signing feature".to_string(),
+        ));
+    }
+
+    if !options.tags.is_empty() && !options.list {
+        return Err(PluginError::Usage(
+            "--tag can only be used with --list".to_string(),
+        ));
+    }
+
+    // applied before loading any plugins, as the limits cover the whole process
+    options.limits.apply()?;
+
+    if options.new_plugin {
+        let [name, dir] = positional.as_slice() else {
+            return Err(PluginError::Usage(
+                "--new-plugin expects a name and a directory".to_string(),
+            ));
+        };
+        let name = name
+            .to_str()
+            .ok_or_else(|| PluginError::Usage("Plugin names must be valid UTF-8".to_string()))?;
+        scaffold::new_plugin(name, Path::new(dir))?;
+        println!("Created plugin {} in {}", name, Path::new(dir).display());
+        return Ok(());
+    }
+    if options.audit_probe && options.audit.is_none() {
+        return Err(PluginError::Usage(
+            "--audit-probe can only be used with --audit".to_string(),
+        ));
+    }
+    if let Some(dir) = &options.audit {
+        if !positional.is_empty() {
+            return Err(PluginError::Usage(
+                "--audit doesn't take a plugin or arguments".to_string(),
+            ));
+        }
+        return audit(dir, &options);
+    }
+    #[cfg(feature = "serde")]
+    if options.worker {
+        let Some(plugin_path) = positional.first() else {
+            return Err(PluginError::Usage("No plugin specified".to_string()));
+        };
+        return host::serve(
+            load(plugin_path, &options),
+            std::io::stdin().lock(),
+            std::io::stdout().lock(),
+        );
+    }
+    if options.diff {
+        return diff(&positional, &options);
+    }
+    if options.pipe {
+        return pipe(&positional, &options);
+    }
+    if let Some(expr) = &options.eval {
+        return evaluate(expr, &positional, &options);
+    }
+    #[cfg(feature = "serde")]
+    if let Some(cache) = &options.cache {
+        if !options.functions && !options.list {
+            return Err(PluginError::Usage(
+                "--cache can only be used with --functions or --list".to_string(),
+            ));
+        }
+        return cached_functions(cache, &positional, &options);
+    }
+
+    if options.list {
+        if positional.is_empty() {
+            return Err(PluginError::Usage("No plugin specified".to_string()));
+        }
+        // listing plugins only needs their metadata, so they aren't initialized
+        let plugins = positional
+            .iter()
+            .map(|path| Plugin::inspect(locate(path, &options)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        print_plugins(&positional, &plugins, &options);
+        return Ok(());
+    }
+
+    let Some((plugin_path, plugin_args)) = positional.split_first() else {
+        return Err(PluginError::Usage("No plugin specified".to_string()));
+    };
+
+    if options.functions {
+        if !plugin_args.is_empty() {
+            return Err(PluginError::Usage(
+                "--functions doesn't take plugin arguments".to_string(),
+            ));
+        }
+        // listing functions only needs the metadata, so the plugin isn't initialized
+        let path = locate(plugin_path, &options)?;
+        let functions = Plugin::inspect_functions(path)?;
+        print!("{}", functions_table(&functions.iter().collect::<Vec<_>>()));
+        return Ok(());
+    }
+
+    if options.caps {
+        if !plugin_args.is_empty() {
+            return Err(PluginError::Usage(
+                "--caps doesn't take plugin arguments".to_string(),
+            ));
+        }
+        // as with --functions, the plugin isn't initialized
+        let path = locate(plugin_path, &options)?;
+        print!("{}", capabilities_table(&Plugin::inspect(path)?));
+        return Ok(());
+    }
+
+    #[cfg(feature = "serde")]
+    if options.subprocess {
+        return call_isolated(&argv, plugin_args, &options);
+    }
+
+    let plugin = Arc::new(load(plugin_path, &options)?);
+
+    #[cfg(feature = "serde")]
+    if let Some(path) = &options.replay {
+        if !plugin_args.is_empty() {
+            return Err(PluginError::Usage(
+                "--replay doesn't take plugin arguments".to_string(),
+            ));
+        }
+        return replay(&plugin, path);
+    }
+
+    let functions = plugin.functions();
+    let (metadata, plugin_args) = select_function(&functions, plugin_args)?;
+
+    if options.stdin {
+        return stdin(&plugin, metadata, plugin_args, &options);
+    }
+    if let Some(iterations) = options.bench {
+        return bench(&plugin, metadata, plugin_args, iterations, &options);
+    }
+
+    #[cfg(feature = "leak-check")]
+    let live = host::live_allocations();
+
+    call(&plugin, metadata, plugin_args, &options)?;
+
+    // everything allocated for the call (its arguments, result and output) has been dropped by
+    // now, so any allocations that are still live were leaked
+    #[cfg(feature = "leak-check")]
+    {
+        let leaked = host::live_allocations().saturating_sub(live);
+        eprintln!("[leak-check] {} allocations leaked by the call", leaked);
+        if host::untracked_allocations() > 0 {
+            eprintln!(
+                "[leak-check] {} allocations couldn't be tracked",
+                host::untracked_allocations()
+            );
+        }
+    }
+    Ok(())
 }
 
-#[derive(WrapperApi)]
-struct PluginApi {
-    plugin_metadata: unsafe extern "C" fn() -> PluginMetadata,
-    plugin_entrypoint:
-        unsafe extern "C" fn(args: *const PluginValue, args_len: usize) -> PluginResult,
+// Calls the plugin with the arguments given on the command line and prints the result
+fn call(
+    plugin: &Arc<Plugin>,
+    metadata: &OwnedMetadata,
+    plugin_args: &[OsString],
+    options: &Options,
+) -> Result<(), PluginError> {
+    // secret arguments must never be written to disk
+    if options.record.is_some() && metadata.has_secret_args() {
+        return Err(PluginError::Usage(format!(
+            "{} has secret arguments, so its calls can't be recorded",
+            metadata.name
+        )));
+    }
+
+    let call_args = parse_args(metadata, plugin_args, options)?;
+    if let Some(timeout) = options.timeout {
+        // the arguments are moved to the thread making the call, so they can't be recorded
+        let (value, warning) = plugin.clone().call_with_timeout(call_args, timeout)?;
+        return print_result_and_warning(value, warning, options);
+    }
+    if let Some(deadline) = options.deadline {
+        let (value, warning) = plugin.clone().call_with_deadline(call_args, deadline)?;
+        return print_result_and_warning(value, warning, options);
+    }
+
+    let result = plugin.call_function_partial(&metadata.name, &call_args);
+    #[cfg(feature = "serde")]
+    if let Some(path) = &options.record {
+        host::Recorder::open(path)?.record(
+            &metadata.name,
+            &call_args,
+            result.as_ref().map(|(value, _)| value),
+        )?;
+    }
+
+    let (value, warning) = result?;
+    print_result_and_warning(value, warning, options)
 }
 
-fn main() {
-    let args: Vec<_> = args().into_iter().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <plugin>", args[0]);
-        std::process::exit(1);
+// Calls the plugin in a worker process, which is the host itself run with `--worker` and the same
+// arguments, so that a plugin that aborts or crashes only takes the worker down with it
+#[cfg(feature = "serde")]
+fn call_isolated(
+    argv: &[OsString],
+    plugin_args: &[OsString],
+    options: &Options,
+) -> Result<(), PluginError> {
+    let exe = std::env::current_exe()
+        .map_err(|e| PluginError::Load(format!("Could not find the host executable: {}", e)))?;
+    let mut command = std::process::Command::new(exe);
+    command.arg("--worker").args(argv);
+    let mut plugin = host::IsolatedPlugin::spawn(command)?;
+    if options.format == OutputFormat::Text {
+        println!("Loaded plugin {}", plugin.metadata().name);
     }
 
-    let container: Container<PluginApi> =
-        unsafe { Container::load(&args[1]) }.expect("Could not load plugin");
+    // plugins currently export a single function
+    let (metadata, plugin_args) = select_function(&[plugin.metadata()], plugin_args)?;
+    let call_args = parse_args(metadata, plugin_args, options)?;
+    let (value, warning) = plugin.call(&call_args)?;
+    print_result_and_warning(value, warning, options)
+}
+
+fn print_result_and_warning(
+    value: OwnedPluginValue,
+    warning: Option<String>,
+    options: &Options,
+) -> Result<(), PluginError> {
+    // the warning is part of the result's JSON, so that stdout holds one object per call
+    #[cfg(feature = "serde")]
+    if options.format == OutputFormat::Json {
+        let value = value.to_json()?;
+        println!("{}", serde_json::json!({ "ok": value, "warning": warning }));
+        return Ok(());
+    }
+    println!("{}", format_result(&value, options)?);
+    if let Some(warning) = warning {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(())
+}
 
-    let metadata: PluginMetadata = unsafe { container.plugin_metadata() };
-    println!("Loaded plugin {}", unsafe {
-        CStr::from_ptr(metadata.name).to_string_lossy()
-    });
+// Calls the plugin once for each line read from stdin, passing the line as the first argument
+// followed by `fixed_args`, and prints each result. Stops at the first error.
+fn stdin(
+    plugin: &Arc<Plugin>,
+    metadata: &OwnedMetadata,
+    fixed_args: &[OsString],
+    options: &Options,
+) -> Result<(), PluginError> {
+    for line in std::io::stdin().lines() {
+        let line = line.map_err(|e| {
+            PluginError::Argument(format!("Could not read a line from stdin: {}", e))
+        })?;
+        let mut plugin_args = vec![OsString::from(line)];
+        plugin_args.extend_from_slice(fixed_args);
+        call(plugin, metadata, &plugin_args, options)?;
+    }
 
-    if metadata.arg_types_len != args.len() - 2 {
+    #[cfg(feature = "serde")]
+    if let Some(stats) = plugin.memo_stats().filter(|_| options.trace) {
         eprintln!(
-            "Expected {} arguments, got {}",
-            metadata.arg_types_len,
-            args.len() - 2
+            "[trace] answered {} of {} calls from the cache",
+            stats.hits,
+            stats.hits + stats.misses
         );
+    }
+    Ok(())
+}
+
+// Measures the time of a call to the plugin with the given arguments. With a baseline, exits with
+// code 1 if the call is slower than the baseline by more than `bench::THRESHOLD`, or updates the
+// baseline if `--update-baseline` was given.
+fn bench(
+    plugin: &Plugin,
+    metadata: &OwnedMetadata,
+    plugin_args: &[OsString],
+    iterations: u32,
+    options: &Options,
+) -> Result<(), PluginError> {
+    let args = PreparedArgs::new(parse_args(metadata, plugin_args, options)?);
+    let time = bench::measure(plugin, &args, iterations)?;
+    println!("Median call time: {} ns", time.as_nanos());
+
+    let Some(path) = &options.baseline else {
+        return Ok(());
+    };
+    let path = Path::new(path);
+    if options.update_baseline {
+        bench::write_baseline(path, &metadata.name, time)?;
+        println!("Updated the baseline in {}", path.display());
+        return Ok(());
+    }
+
+    let baseline = bench::read_baseline(path, &metadata.name)?.ok_or_else(|| {
+        PluginError::Usage(format!(
+            "{} has no baseline for {}; create one with --update-baseline",
+            path.display(),
+            metadata.name
+        ))
+    })?;
+    println!("Baseline call time: {} ns", baseline.as_nanos());
+    if let Some(regression) = bench::regression(time, baseline) {
+        println!("{}", regression);
         std::process::exit(1);
     }
+    Ok(())
+}
 
-    let mut call_args: Vec<PluginValue> = vec![];
-    for (i, arg) in args[2..].iter().enumerate() {
-        match unsafe { *metadata.arg_types.add(i) } {
-            PluginType::Bool => {
-                call_args.push(PluginValue::Bool(arg.parse().expect("Invalid bool")))
-            }
-            PluginType::Int => call_args.push(PluginValue::Int(arg.parse().expect("Invalid int"))),
-            PluginType::UInt => {
-                call_args.push(PluginValue::UInt(arg.parse().expect("Invalid uint")))
+// Splits the function to call off the front of the plugin arguments, following the grammar
+// `host <plugin> [function] [args...]`. A plugin that exports multiple functions must be given the
+// name of one of them. For a plugin with a single function the name is optional, so a first
+// argument matching it is only taken as the name if the function can't be called with all of the
+// arguments but can without it; `host libplugin.so repeat 3` still repeats "repeat" three times.
+fn select_function<'a, 'b>(
+    functions: &[&'a OwnedMetadata],
+    args: &'b [OsString],
+) -> Result<(&'a OwnedMetadata, &'b [OsString]), PluginError> {
+    let named = |function: &OwnedMetadata| {
+        args.first()
+            .is_some_and(|name| name.to_str() == Some(&function.name))
+    };
+    match functions {
+        [function] => {
+            if named(function)
+                && function.check_arg_count(args.len()).is_err()
+                && function.check_arg_count(args.len() - 1).is_ok()
+            {
+                return Ok((function, &args[1..]));
             }
-            PluginType::Double => {
-                call_args.push(PluginValue::Double(arg.parse().expect("Invalid double")))
+            Ok((function, args))
+        }
+        _ => match functions.iter().find(|f| named(f)) {
+            Some(function) => Ok((function, &args[1..])),
+            None => {
+                let names: Vec<_> = functions.iter().map(|f| f.name.as_str()).collect();
+                Err(PluginError::Usage(format!(
+                    "The plugin exports multiple functions; specify one of: {}",
+                    names.join(", ")
+                )))
             }
-            PluginType::String => call_args.push(PluginValue::String(arg.as_ptr() as *const i8)),
+        },
+    }
+}
+
+// Finds the plugin given as `path` and checks that it's signed by the key given with
+// `--verify-key`, if any. An absolute path is used as it is, while a relative one is looked for in
+// the working directory and then in each `--plugin-path` directory, in order. If there are search
+// directories and the plugin isn't in any of them, this fails with the paths it tried; otherwise a
+// missing plugin is left for the loader to report.
+fn locate(path: &OsStr, options: &Options) -> Result<PathBuf, PluginError> {
+    let path = Path::new(path);
+    // the loader searches the library path for a bare file name, rather than the working directory
+    let mut candidates = vec![match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new(".").join(path),
+        _ => path.to_path_buf(),
+    }];
+    if path.is_relative() {
+        candidates.extend(options.plugin_path.iter().map(|dir| dir.join(path)));
+    }
+    let found = match candidates.iter().find(|candidate| candidate.is_file()) {
+        Some(found) => found.clone(),
+        None if candidates.len() > 1 => {
+            let tried: Vec<_> = candidates
+                .iter()
+                .map(|candidate| format!("  {}", candidate.display()))
+                .collect();
+            return Err(PluginError::Load(format!(
+                "Could not find plugin {}; tried:\n{}",
+                path.display(),
+                tried.join("\n")
+            )));
         }
+        None => path.to_path_buf(),
+    };
+
+    #[cfg(feature = "signing")]
+    if let Some(key) = &options.verify_key {
+        host::TrustedKey::from_file(key)?.verify(&found)?;
     }
+    Ok(found)
+}
 
-    let result = unsafe { container.plugin_entrypoint(call_args.as_ptr(), call_args.len()) };
+// Loads the plugin at `path`, applying the host options
+fn load(path: &OsStr, options: &Options) -> Result<Plugin, PluginError> {
+    let path = locate(path, options)?;
+    let visibility = if options.global_symbols {
+        SymbolVisibility::Global
+    } else {
+        SymbolVisibility::Local
+    };
+    let mut plugin = Plugin::load_with_visibility(path, options.config.clone(), visibility)?;
+    if options.check_stable_metadata {
+        plugin.check_stable_metadata(STABLE_METADATA_READS)?;
+    }
+    if let Some(len) = options.max_string_len {
+        plugin.set_max_string_len(len);
+    }
+    if let Some(len) = options.max_list_len {
+        plugin.set_max_list_len(len);
+    }
+    if let Some(bytes) = options.max_result_bytes {
+        plugin.set_max_result_bytes(bytes);
+    }
+    plugin.set_strict_utf8(options.strict_utf8);
+    plugin.set_trace(options.trace);
+    #[cfg(feature = "serde")]
+    if let Some(capacity) = options.memoize {
+        plugin.set_memoize(capacity)?;
+    }
+    if let Some(level) = options.log_level {
+        plugin.set_log_level(level);
+    }
+    // a worker's stdout carries its replies, and the host it's working for prints this itself; in
+    // JSON mode, stdout only holds results
+    if !options.worker && options.format == OutputFormat::Text {
+        println!("Loaded plugin {}", plugin.metadata().name);
+    }
+    if options.trace {
+        for line in plugin.raw_metadata().lines() {
+            eprintln!("[trace] metadata {}", line);
+        }
+        let exports: Vec<_> = OptionalSymbol::ALL
+            .iter()
+            .filter_map(|symbol| Some(symbol.versioned_name(plugin.symbol_version(*symbol)?)))
+            .collect();
+        if exports.is_empty() {
+            eprintln!("[trace] optional exports: none");
+        } else {
+            eprintln!("[trace] optional exports: {}", exports.join(", "));
+        }
+    }
+    Ok(plugin)
+}
+
+// Parses CLI arguments into values of the types the plugin expects
+fn parse_args(
+    metadata: &OwnedMetadata,
+    args: &[OsString],
+    options: &Options,
+) -> Result<Vec<OwnedPluginValue>, PluginError> {
+    // check the count before parsing, as we need each argument's type to parse it
+    metadata.check_arg_count(args.len())?;
+    let parse_options = ParseOptions {
+        list_separator: options.list_separator.unwrap_or(','),
+    };
+
+    args.iter()
+        .zip(&metadata.arg_types)
+        .enumerate()
+        .map(|(i, (arg, arg_type))| {
+            let value = match arg_type {
+                PluginType::Bytes if options.map_bytes => {
+                    Bytes::map(Path::new(arg)).map(OwnedPluginValue::Bytes)
+                }
+                _ => OwnedPluginValue::parse_os_with(*arg_type, arg, &parse_options),
+            };
+            value.map_err(|e| match e {
+                PluginError::InvalidArg(e) => {
+                    PluginError::InvalidArg(e.at(i, metadata.is_secret(i)))
+                }
+                // other errors may quote the argument, so they're replaced for secret arguments
+                _ if metadata.is_secret(i) => {
+                    PluginError::Argument(format!("arg{}: Invalid {}: {}", i, arg_type, REDACTED))
+                }
+                e => PluginError::Argument(format!("arg{}: {}", i, e)),
+            })
+        })
+        .collect()
+}
+
+// Calls a pipeline of plugins, where the first is called with the CLI arguments (given after
+// `--`) and each of the others is called with the previous one's result as its only argument
+fn pipe(positional: &[OsString], options: &Options) -> Result<(), PluginError> {
+    let (paths, plugin_args) = match positional.iter().position(|arg| *arg == "--") {
+        Some(i) => (&positional[..i], &positional[i + 1..]),
+        None => (positional, &[][..]),
+    };
+    if paths.len() < 2 {
+        return Err(PluginError::Usage(
+            "--pipe expects at least two plugins".to_string(),
+        ));
+    }
 
-    // take ownership of the plugin arguments and drop them, ensuring that any dynamically allocated
-    // memory is freed
-    drop(call_args.into_iter().map(|t| t.to_owned()));
+    let plugins = paths
+        .iter()
+        .map(|path| load(path, options))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    match result {
-        PluginResult::Ok(value) => {
-            println!("Plugin returned: {}", value.to_owned());
+    // check that the stages fit together before calling any of them
+    for (prev, next) in plugins.iter().zip(&plugins[1..]) {
+        let (prev, next) = (prev.metadata(), next.metadata());
+        next.check_arg_count(1).map_err(|_| {
+            PluginError::Argument(format!(
+                "{} can't be called with a single argument, so it can't follow {}",
+                next.name, prev.name
+            ))
+        })?;
+        if next.arg_types[0] != prev.return_type {
+            return Err(PluginError::Argument(format!(
+                "{} returns {}, but {} expects {}",
+                prev.name, prev.return_type, next.name, next.arg_types[0]
+            )));
         }
-        PluginResult::Err(err) => {
-            eprintln!("{}", unsafe { CString::from_raw(err) }.to_string_lossy());
-            std::process::exit(1);
+    }
+
+    let mut args = parse_args(plugins[0].metadata(), plugin_args, options)?;
+    for plugin in &plugins {
+        let (value, warning) = plugin.call_partial(&args)?;
+        if let Some(warning) = warning {
+            eprintln!("Warning from {}: {}", plugin.metadata().name, warning);
+        }
+        // a stage with nothing to return ends the pipeline early, as there's nothing to pass on
+        if let OwnedPluginValue::Null = value {
+            return print_result(value, options);
+        }
+        // the previous arguments, including the previous stage's result, are freed here
+        args = vec![value];
+    }
+
+    print_result(args.pop().unwrap(), options)
+}
+
+// Evaluates an expression of nested calls to the plugins (see eval.rs), each of which is called by
+// its name, and prints the result
+fn evaluate(expr: &str, paths: &[OsString], options: &Options) -> Result<(), PluginError> {
+    // parsed first, so that a typo doesn't need any plugins to be loaded to be reported
+    let expr = eval::parse(expr)?;
+    if paths.is_empty() {
+        return Err(PluginError::Usage("No plugin specified".to_string()));
+    }
+
+    let mut plugins = HashMap::new();
+    for path in paths {
+        let plugin = load(path, options)?;
+        let name = plugin.metadata().name.clone();
+        if plugins.insert(name.clone(), plugin).is_some() {
+            return Err(PluginError::Usage(format!(
+                "More than one plugin provides {}",
+                name
+            )));
+        }
+    }
+
+    eval::check(&expr, &plugins)?;
+    print_result(eval::eval(&expr, &plugins)?, options)
+}
+
+// Prints the functions of each of the plugins (or with --list, the plugins themselves), reading their
+// metadata from the cache so that only plugins that are new or have changed since they were cached
+// need to be loaded
+#[cfg(feature = "serde")]
+fn cached_functions(cache: &str, paths: &[OsString], options: &Options) -> Result<(), PluginError> {
+    if paths.is_empty() {
+        return Err(PluginError::Usage("No plugin specified".to_string()));
+    }
+
+    let mut cache = host::MetadataCache::open(cache);
+    let functions = paths
+        .iter()
+        .map(|path| {
+            // plugins missing from the cache are loaded, so they're verified first
+            cache.metadata(locate(path, options)?)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if options.list {
+        print_plugins(paths, &functions, options);
+    } else {
+        print!("{}", functions_table(&functions.iter().collect::<Vec<_>>()));
+    }
+    cache.save()
+}
+
+// Prints a table of the plugins at `paths`, whose metadata is `plugins`, leaving out those without
+// every tag given by `--tag`
+fn print_plugins(paths: &[OsString], plugins: &[OwnedMetadata], options: &Options) {
+    let plugins: Vec<_> = paths
+        .iter()
+        .map(Path::new)
+        .zip(plugins)
+        .filter(|(_, plugin)| options.tags.iter().all(|tag| plugin.has_tag(tag)))
+        .collect();
+    print!("{}", plugins_table(&plugins));
+}
+
+// Reads the metadata of two versions of a plugin (without initializing them) and prints the
+// differences between their interfaces, exiting with code 1 if there are any
+fn diff(paths: &[OsString], options: &Options) -> Result<(), PluginError> {
+    let [old_path, new_path] = paths else {
+        return Err(PluginError::Usage(
+            "--diff expects exactly two plugins".to_string(),
+        ));
+    };
+
+    let old = Plugin::inspect(locate(old_path, options)?)?;
+    let new = Plugin::inspect(locate(new_path, options)?)?;
+
+    let diffs = old.diff(&new);
+    if diffs.is_empty() {
+        println!("No differences");
+        return Ok(());
+    }
+
+    for diff in &diffs {
+        println!("{}", diff);
+    }
+    std::process::exit(1);
+}
+
+// Audits every plugin in a directory, exiting with code 1 if any of them failed
+fn audit(dir: &Path, options: &Options) -> Result<(), PluginError> {
+    let (failed, total) = audit::audit(dir, &options.config, options.audit_probe)?;
+    if failed == 0 {
+        println!("All {} plugins passed the audit", total);
+        return Ok(());
+    }
+    println!("{} of {} plugins failed the audit", failed, total);
+    std::process::exit(1);
+}
+
+// Replays the calls recorded in a file and prints any results that differ, exiting with code 1 if
+// there are any
+#[cfg(feature = "serde")]
+fn replay(plugin: &Plugin, path: &str) -> Result<(), PluginError> {
+    let mismatches = host::replay(plugin, path)?;
+    if mismatches.is_empty() {
+        println!("All recorded calls matched");
+        return Ok(());
+    }
+
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use host::PluginType;
+
+    fn function(name: &str) -> OwnedMetadata {
+        OwnedMetadata {
+            name: name.to_string(),
+            arg_types: vec![PluginType::String],
+            return_type: PluginType::String,
+            required_host_capabilities: 0,
+            capabilities: 0,
+            args: None,
+            tags: vec![],
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn selects_single_function_with_or_without_name() {
+        let repeat = function("repeat");
+        let functions = [&repeat];
+
+        let given = args(&["repeat", "ab"]);
+        let (selected, rest) = select_function(&functions, &given).unwrap();
+        assert_eq!(selected.name, "repeat");
+        assert_eq!(rest, &args(&["ab"])[..]);
+
+        let given = args(&["ab"]);
+        let (selected, rest) = select_function(&functions, &given).unwrap();
+        assert_eq!(selected.name, "repeat");
+        assert_eq!(rest, &given[..]);
+
+        // the name given twice passes it as the first argument
+        let given = args(&["repeat", "repeat"]);
+        let (_, rest) = select_function(&functions, &given).unwrap();
+        assert_eq!(rest, &args(&["repeat"])[..]);
+    }
+
+    #[test]
+    fn keeps_name_of_single_function_that_fits_arity() {
+        let mut repeat = function("repeat");
+        repeat.arg_types.push(PluginType::UInt);
+        let functions = [&repeat];
+
+        // `repeat 3` is a call with two arguments, not a call named `repeat` with one
+        let given = args(&["repeat", "3"]);
+        let (_, rest) = select_function(&functions, &given).unwrap();
+        assert_eq!(rest, &given[..]);
+
+        let given = args(&["repeat", "ab", "3"]);
+        let (_, rest) = select_function(&functions, &given).unwrap();
+        assert_eq!(rest, &args(&["ab", "3"])[..]);
+
+        // with a single argument, it's passed as the string
+        let given = args(&["repeat"]);
+        let (_, rest) = select_function(&functions, &given).unwrap();
+        assert_eq!(rest, &given[..]);
+    }
+
+    #[test]
+    fn requires_name_of_one_of_several_functions() {
+        let (upper, lower) = (function("upper"), function("lower"));
+        let functions = [&upper, &lower];
+
+        let given = args(&["lower", "AB"]);
+        let (selected, rest) = select_function(&functions, &given).unwrap();
+        assert_eq!(selected.name, "lower");
+        assert_eq!(rest, &args(&["AB"])[..]);
+
+        for given in [args(&["AB"]), args(&[])] {
+            let Err(e) = select_function(&functions, &given) else {
+                panic!("expected an error");
+            };
+            assert!(matches!(e, PluginError::Usage(_)));
+            assert_eq!(
+                e.to_string(),
+                "The plugin exports multiple functions; specify one of: upper, lower"
+            );
         }
     }
 }
@@ -0,0 +1,136 @@
+// Memoization of calls to pure plugins, which return the same result whenever they're called with
+// the same arguments and have no side effects the caller relies on, so that a repeated call can be
+// answered without calling the plugin again. Plugins declare that they're pure by advertising the
+// `PLUGIN_PURE` capability, and only those can be memoized (see `Plugin::set_memoize`).
+//
+// Calls are keyed on the function's name and its arguments encoded as JSON, as in recordings (see
+// `record.rs`), and each result is kept in the same encoding, so that a cached result can be handed
+// out any number of times. Calls whose arguments or result can't be encoded (e.g. handles, which
+// are tied to the plugin's state) are simply not cached, and neither are errors, which may be
+// transient (e.g. a cancelled call). The cache holds a bounded number of results, evicting the
+// least recently used.
+use crate::record::{from_json, to_json};
+use crate::{OwnedPluginValue, PluginError};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// How many of a memoized plugin's calls were answered from its cache
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct CachedResult {
+    value: Value,
+    warning: Option<String>,
+    // The value of `ResultCache::clock` when the result was last used
+    last_used: u64,
+}
+
+struct ResultCache {
+    capacity: usize,
+    results: HashMap<String, CachedResult>,
+    // Counts lookups, to order the results by when they were last used
+    clock: u64,
+    stats: MemoStats,
+}
+
+impl ResultCache {
+    fn get(&mut self, key: &str) -> Option<(Value, Option<String>)> {
+        self.clock += 1;
+        let Some(result) = self.results.get_mut(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        result.last_used = self.clock;
+        self.stats.hits += 1;
+        Some((result.value.clone(), result.warning.clone()))
+    }
+
+    // Caches a result, first evicting the least recently used one if the cache is full. Finding it
+    // takes a scan of the cache, which is cheap next to the call whose result is being cached.
+    fn insert(&mut self, key: String, value: Value, warning: Option<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.results.len() >= self.capacity && !self.results.contains_key(&key) {
+            let oldest = self
+                .results
+                .iter()
+                .min_by_key(|(_, result)| result.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.results.remove(&oldest);
+            }
+        }
+        let last_used = self.clock;
+        self.results.insert(
+            key,
+            CachedResult {
+                value,
+                warning,
+                last_used,
+            },
+        );
+    }
+}
+
+// The cached results of a plugin's calls, holding at most `capacity` of them
+pub(crate) struct Memo {
+    cache: Mutex<ResultCache>,
+}
+
+impl Memo {
+    pub(crate) fn new(capacity: usize) -> Memo {
+        Memo {
+            cache: Mutex::new(ResultCache {
+                capacity,
+                results: HashMap::new(),
+                clock: 0,
+                stats: MemoStats::default(),
+            }),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> MemoStats {
+        self.cache.lock().unwrap().stats
+    }
+
+    // Answers a call to `function` with the same arguments as a cached one from the cache, or else
+    // makes it with `call` and caches its result. The cache isn't locked during the call, so
+    // concurrent calls with the same arguments may each call the plugin.
+    pub(crate) fn call(
+        &self,
+        function: &str,
+        args: &[OwnedPluginValue],
+        call: impl FnOnce() -> Result<(OwnedPluginValue, Option<String>), PluginError>,
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        let Some(key) = cache_key(function, args) else {
+            return call();
+        };
+        if let Some((value, warning)) = self.cache.lock().unwrap().get(&key) {
+            return Ok((from_json(&value)?, warning));
+        }
+
+        let (value, warning) = call()?;
+        if let Ok(encoded) = to_json(&value) {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(key, encoded, warning.clone());
+        }
+        Ok((value, warning))
+    }
+}
+
+// The key of a call to `function` with these arguments, or None if any of them can't be encoded
+fn cache_key(function: &str, args: &[OwnedPluginValue]) -> Option<String> {
+    let args = args
+        .iter()
+        .map(to_json)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    Some(Value::Array(vec![Value::from(function), Value::Array(args)]).to_string())
+}
@@ -0,0 +1,1017 @@
+use crate::ffi::{
+    capability_names, plugin_capability_names, ArgDescriptor, PluginApi, PluginMetadata,
+    PluginType, PluginValue, RawPluginType, CAPABILITY_NAMES, HOST_CAPABILITIES,
+    PLUGIN_CAPABILITY_NAMES, PLUGIN_PURE,
+};
+use crate::raw::RawPluginValue;
+use crate::value::bounded_strlen;
+use crate::{ArgError, ArgErrorKind, OwnedPluginValue, PluginError};
+use dlopen2::wrapper::Container;
+use std::cmp::Ordering;
+use std::ffi::c_char;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+// Shown in place of the value of a secret argument
+pub const REDACTED: &str = "****";
+
+// An owned copy of an `ArgDescriptor`
+#[derive(Clone, PartialEq)]
+pub struct ArgInfo {
+    pub name: String,
+    pub arg_type: PluginType,
+    pub optional: bool,
+    pub doc: Option<String>,
+    pub secret: bool,
+    // Inclusive bounds on the value of an Int, UInt or Double argument, of the argument's type
+    pub min: Option<Bound>,
+    pub max: Option<Bound>,
+    // The maximum length in bytes of a String or Bytes argument
+    pub max_len: Option<usize>,
+}
+
+// A bound on the value of a numeric argument. Bounds are only compared with values of the same
+// type (and are never NaN), so the derived ordering is the numeric one.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum Bound {
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+}
+
+impl Display for Bound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bound::Int(i) => write!(f, "{}", i),
+            Bound::UInt(u) => write!(f, "{}", u),
+            Bound::Double(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+impl ArgInfo {
+    // Checks that `value` satisfies the argument's constraints, returning a description of the
+    // problem if not. The values of secret arguments are left out of it.
+    pub fn check(&self, value: &OwnedPluginValue) -> Result<(), String> {
+        let shown = |value: &dyn Display| {
+            if self.secret {
+                REDACTED.to_string()
+            } else {
+                value.to_string()
+            }
+        };
+
+        if let Some(max_len) = self.max_len {
+            let len = match value {
+                OwnedPluginValue::String(s) => s.as_bytes().len(),
+                OwnedPluginValue::Bytes(bytes) => bytes.len(),
+                _ => 0,
+            };
+            if len > max_len {
+                return Err(format!(
+                    "{} must be at most {} bytes long, got {} bytes",
+                    self.name,
+                    max_len,
+                    shown(&len)
+                ));
+            }
+        }
+
+        let value = match *value {
+            OwnedPluginValue::Int(i) => Bound::Int(i),
+            OwnedPluginValue::UInt(u) => Bound::UInt(u),
+            OwnedPluginValue::Double(d) => Bound::Double(d),
+            _ => return Ok(()),
+        };
+        // NaN is incomparable, so it's outside any bound
+        if let Some(min) = self.min {
+            if matches!(value.partial_cmp(&min), None | Some(Ordering::Less)) {
+                return Err(format!(
+                    "{} must be at least {}, got {}",
+                    self.name,
+                    min,
+                    shown(&value)
+                ));
+            }
+        }
+        if let Some(max) = self.max {
+            if matches!(value.partial_cmp(&max), None | Some(Ordering::Greater)) {
+                return Err(format!(
+                    "{} must be at most {}, got {}",
+                    self.name,
+                    max,
+                    shown(&value)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // A description of the argument's constraints, e.g. `1 to 10` or `at most 64 bytes`, if it has
+    // any
+    pub fn constraints(&self) -> Option<String> {
+        let range = match (self.min, self.max) {
+            (Some(min), Some(max)) => Some(format!("{} to {}", min, max)),
+            (Some(min), None) => Some(format!("at least {}", min)),
+            (None, Some(max)) => Some(format!("at most {}", max)),
+            (None, None) => None,
+        };
+        range.or_else(|| Some(format!("at most {} bytes", self.max_len?)))
+    }
+}
+
+impl Display for ArgInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.optional {
+            write!(f, "[{}: {}]", self.name, self.arg_type)
+        } else {
+            write!(f, "<{}: {}>", self.name, self.arg_type)
+        }
+    }
+}
+
+// An owned copy of the plugin's metadata, which is safe to use after the plugin has been unloaded
+#[derive(Clone)]
+pub struct OwnedMetadata {
+    pub name: String,
+    pub arg_types: Vec<PluginType>,
+    pub return_type: PluginType,
+    // The host capabilities the plugin requires, as `CAPABILITY_*` bits
+    pub required_host_capabilities: u64,
+    // The capabilities the plugin advertises through `plugin_capabilities`, as `PLUGIN_*` bits.
+    // Only the default function has them; the plugin's other functions share them.
+    pub capabilities: u64,
+    // Descriptions of each argument, if the plugin exports `plugin_describe_arg`
+    pub args: Option<Vec<ArgInfo>>,
+    // The plugin's tags, if it exports `plugin_describe_tag`
+    pub tags: Vec<String>,
+}
+
+// The most functions a plugin may describe, which guards against a plugin that never returns
+// metadata with a null name
+const MAX_FUNCTIONS: usize = 1024;
+
+// The maximum length of the names in a plugin's metadata (of the plugin and its arguments), and of
+// argument docs. Strings in metadata are static data in the plugin, so one that's missing its
+// terminator would otherwise be read until a zero byte happens to follow it.
+const MAX_NAME_LEN: usize = 1024;
+const MAX_DOC_LEN: usize = 64 * 1024;
+// The most arguments a plugin may declare. Far more than any real function takes, this guards
+// against a corrupt `arg_types_len` being used to read past the end of `arg_types`.
+const MAX_ARGS: usize = 256;
+// The most tags a plugin may have, which likewise guards against a plugin that never returns null
+const MAX_TAGS: usize = 256;
+
+// Reads a string from the plugin's metadata, reading at most `max_len + 1` bytes so that a missing
+// terminator can't cause an unbounded overread
+fn read_str(ptr: *const c_char, max_len: usize, what: &str) -> Result<String, PluginError> {
+    if ptr.is_null() {
+        return Err(PluginError::Load(format!(
+            "Plugin declared a null {}",
+            what
+        )));
+    }
+    let Some(len) = (unsafe { bounded_strlen(ptr, max_len) }) else {
+        return Err(PluginError::Load(format!(
+            "Plugin declared a {} that isn't terminated within {} bytes",
+            what, max_len
+        )));
+    };
+    let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len) };
+    Ok(String::from_utf8_lossy(bytes).to_string())
+}
+
+// Validates a type read from the plugin, which may not be one we know about
+fn read_type(raw: RawPluginType, what: &str) -> Result<PluginType, PluginError> {
+    PluginType::try_from(raw).map_err(|raw| {
+        PluginError::Load(format!(
+            "Plugin declared an unknown type {} for {}; it may have been built for a different \
+             version of the host",
+            raw, what
+        ))
+    })
+}
+
+// Reads a bound on the argument `arg` of type `arg_type`, which must be a value of that type if it
+// isn't null
+fn read_bound(
+    ptr: *const PluginValue,
+    arg_type: PluginType,
+    what: &str,
+) -> Result<Option<Bound>, PluginError> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    let invalid =
+        |e: String| PluginError::Load(format!("Plugin declared an invalid {}: {}", what, e));
+    let value = unsafe { *(ptr as *const RawPluginValue) }
+        .validate()
+        .map_err(invalid)?;
+    match (arg_type, value) {
+        (PluginType::Int, PluginValue::Int(i)) => Ok(Some(Bound::Int(i))),
+        (PluginType::UInt, PluginValue::UInt(u)) => Ok(Some(Bound::UInt(u))),
+        (PluginType::Double, PluginValue::Double(d)) if d.is_nan() => {
+            Err(invalid("NaN".to_string()))
+        }
+        (PluginType::Double, PluginValue::Double(d)) => Ok(Some(Bound::Double(d))),
+        (PluginType::Int | PluginType::UInt | PluginType::Double, _) => Err(invalid(format!(
+            "expected a value of the argument's type {}",
+            arg_type
+        ))),
+        _ => Err(invalid(format!("{} arguments can't be bounded", arg_type))),
+    }
+}
+
+// Formats a plugin's raw metadata for debugging, one field per line, e.g. to sanity-check what
+// `plugin_metadata` returns. Unlike `OwnedMetadata::read` this never fails: null pointers and
+// unknown types are shown as they are rather than rejected.
+//
+// Safety: any non-null pointers in `metadata` must be valid, i.e. `name` must be readable up to its
+// terminator (of which at most `MAX_NAME_LEN + 1` bytes are read) and `arg_types` must point to
+// `arg_types_len` types (of which at most `MAX_ARGS` are read, and only if it's aligned)
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn format_raw_metadata(metadata: &PluginMetadata) -> String {
+    let format_type = |raw: RawPluginType| {
+        PluginType::try_from(raw)
+            .map(|t| t.to_string())
+            .unwrap_or_else(|raw| format!("unknown({})", raw))
+    };
+
+    let name = if metadata.name.is_null() {
+        "null".to_string()
+    } else {
+        match bounded_strlen(metadata.name, MAX_NAME_LEN) {
+            Some(len) => format!(
+                "{:?}",
+                String::from_utf8_lossy(std::slice::from_raw_parts(
+                    metadata.name as *const u8,
+                    len
+                ))
+            ),
+            None => format!("unterminated within {} bytes", MAX_NAME_LEN),
+        }
+    };
+    let arg_types = if metadata.arg_types.is_null() {
+        "null".to_string()
+    } else if !metadata.arg_types.is_aligned() {
+        format!("misaligned ({:p})", metadata.arg_types)
+    } else if metadata.arg_types_len > MAX_ARGS {
+        format!("more than the limit of {}", MAX_ARGS)
+    } else {
+        let types: Vec<_> = std::slice::from_raw_parts(metadata.arg_types, metadata.arg_types_len)
+            .iter()
+            .map(|raw| format_type(*raw))
+            .collect();
+        format!("[{}]", types.join(", "))
+    };
+    let capabilities = capability_names(metadata.required_host_capabilities);
+
+    format!(
+        "name: {}\narg_types: {}\narg_types_len: {}\nreturn_type: {}\n\
+         required_host_capabilities: {:#x} ({})\n",
+        name,
+        arg_types,
+        metadata.arg_types_len,
+        format_type(metadata.return_type),
+        metadata.required_host_capabilities,
+        if capabilities.is_empty() {
+            "none".to_string()
+        } else {
+            capabilities.join(", ")
+        }
+    )
+}
+
+impl OwnedMetadata {
+    // Reads the plugin's metadata (and its argument descriptions and tags, if it exports them) into
+    // an owned copy. This is the only place the host reads through the pointers the plugin returns
+    // as its metadata, each of which is checked first: nulls, lengths over the limits above,
+    // misaligned arrays, unterminated strings and unknown types are rejected with a `Load` error.
+    pub(crate) fn read(container: &Container<PluginApi>) -> Result<OwnedMetadata, PluginError> {
+        let metadata: PluginMetadata = unsafe { container.plugin_metadata() };
+        let mut owned = OwnedMetadata::read_raw(&metadata)?;
+        owned.args = describe_args(metadata.arg_types_len, |i| describe_arg(container, i))?;
+        owned.capabilities = unsafe { container.plugin_capabilities() }.unwrap_or(0);
+        owned.tags = describe_tags(container)?;
+        Ok(owned)
+    }
+
+    // Reads the metadata of each of the plugin's functions, if it exports several (see
+    // `PluginApi::plugin_describe_function`), or returns an empty list if it doesn't. Functions
+    // only have argument descriptions if the plugin exports `plugin_describe_function_arg`.
+    pub(crate) fn read_functions(
+        container: &Container<PluginApi>,
+    ) -> Result<Vec<OwnedMetadata>, PluginError> {
+        if container.has_plugin_describe_function() != container.has_plugin_call() {
+            return Err(PluginError::Load(
+                "Plugin must export both plugin_describe_function and plugin_call, or neither"
+                    .to_string(),
+            ));
+        }
+
+        let mut functions: Vec<OwnedMetadata> = vec![];
+        for i in 0.. {
+            let Some(metadata) = (unsafe { container.plugin_describe_function(i) }) else {
+                break;
+            };
+            if metadata.name.is_null() {
+                break;
+            }
+            if i == MAX_FUNCTIONS {
+                return Err(PluginError::Load(format!(
+                    "Plugin describes more than {} functions",
+                    MAX_FUNCTIONS
+                )));
+            }
+            let mut function = OwnedMetadata::read_raw(&metadata)?;
+            function.args = describe_args(metadata.arg_types_len, |arg| unsafe {
+                container.plugin_describe_function_arg(i, arg)
+            })?;
+            if functions.iter().any(|f| f.name == function.name) {
+                return Err(PluginError::Load(format!(
+                    "Plugin describes more than one function named {}",
+                    function.name
+                )));
+            }
+            functions.push(function);
+        }
+        Ok(functions)
+    }
+
+    // Reads metadata returned by the plugin, without argument descriptions
+    fn read_raw(metadata: &PluginMetadata) -> Result<OwnedMetadata, PluginError> {
+        if metadata.arg_types_len > MAX_ARGS {
+            return Err(PluginError::Load(format!(
+                "Plugin declares {} arguments, more than the limit of {}",
+                metadata.arg_types_len, MAX_ARGS
+            )));
+        }
+        let arg_types = if metadata.arg_types_len == 0 {
+            &[]
+        } else if metadata.arg_types.is_null() {
+            return Err(PluginError::Load(format!(
+                "Plugin declares {} arguments, but its arg_types are null",
+                metadata.arg_types_len
+            )));
+        } else if !metadata.arg_types.is_aligned() {
+            return Err(PluginError::Load(format!(
+                "Plugin declares its arg_types at a misaligned address ({:p})",
+                metadata.arg_types
+            )));
+        } else {
+            unsafe { std::slice::from_raw_parts(metadata.arg_types, metadata.arg_types_len) }
+        };
+
+        Ok(OwnedMetadata {
+            name: read_str(metadata.name, MAX_NAME_LEN, "name")?,
+            arg_types: arg_types
+                .iter()
+                .enumerate()
+                .map(|(i, raw)| read_type(*raw, &format!("arg{}", i)))
+                .collect::<Result<_, _>>()?,
+            return_type: read_type(metadata.return_type, "the return value")?,
+            required_host_capabilities: metadata.required_host_capabilities,
+            capabilities: 0,
+            args: None,
+            tags: vec![],
+        })
+    }
+
+    // The name of the first field in which this differs from `other`, another read of the same
+    // plugin's metadata, or None if they're identical
+    pub(crate) fn changed_field(&self, other: &OwnedMetadata) -> Option<&'static str> {
+        if self.name != other.name {
+            Some("name")
+        } else if self.arg_types != other.arg_types {
+            Some("arg_types")
+        } else if self.return_type != other.return_type {
+            Some("return_type")
+        } else if self.required_host_capabilities != other.required_host_capabilities {
+            Some("required_host_capabilities")
+        } else if self.capabilities != other.capabilities {
+            Some("capabilities")
+        } else if self.args != other.args {
+            Some("argument descriptions")
+        } else if self.tags != other.tags {
+            Some("tags")
+        } else {
+            None
+        }
+    }
+
+    // Whether the plugin has `tag`, which must match one of its tags exactly
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    // Whether the plugin advertises `PLUGIN_PURE`: it returns the same result whenever it's called
+    // with the same arguments, so its results can be cached (see `Plugin::set_memoize`)
+    pub fn is_pure(&self) -> bool {
+        self.capabilities & PLUGIN_PURE != 0
+    }
+
+    // Whether the argument at `index` is secret, i.e. its value must never be shown. Only plugins
+    // that describe their arguments can mark them as secret.
+    pub fn is_secret(&self, index: usize) -> bool {
+        self.args
+            .as_ref()
+            .and_then(|args| args.get(index))
+            .is_some_and(|arg| arg.secret)
+    }
+
+    // Checks that `value` satisfies the constraints the plugin declares for the argument at
+    // `index`, if it describes its arguments
+    pub fn check_arg_value(
+        &self,
+        index: usize,
+        value: &OwnedPluginValue,
+    ) -> Result<(), PluginError> {
+        let Some(arg) = self.args.as_ref().and_then(|args| args.get(index)) else {
+            return Ok(());
+        };
+        arg.check(value).map_err(|message| {
+            let shown = match value {
+                _ if arg.secret => None,
+                OwnedPluginValue::Int(i) => Some(i.to_string()),
+                OwnedPluginValue::UInt(u) => Some(u.to_string()),
+                OwnedPluginValue::Double(d) => Some(d.to_string()),
+                OwnedPluginValue::String(s) => Some(s.to_string_lossy().to_string()),
+                _ => None,
+            };
+            PluginError::InvalidArg(ArgError {
+                index: Some(index),
+                expected: arg.arg_type,
+                value: shown,
+                kind: ArgErrorKind::Constraint,
+                message,
+            })
+        })
+    }
+
+    pub fn has_secret_args(&self) -> bool {
+        (0..self.arg_types.len()).any(|i| self.is_secret(i))
+    }
+
+    // The number of arguments that must be passed; all arguments are required, unless the plugin
+    // describes some of them as optional
+    pub fn required_args(&self) -> usize {
+        self.args
+            .as_ref()
+            .map(|args| args.iter().take_while(|arg| !arg.optional).count())
+            .unwrap_or(self.arg_types.len())
+    }
+
+    // Whether the argument at `index` is optional, in which case it may be omitted (if no argument
+    // after it is passed) or passed as Null
+    pub fn is_optional(&self, index: usize) -> bool {
+        index >= self.required_args() && index < self.arg_types.len()
+    }
+
+    // Checks that `len` arguments is an acceptable number to pass to the plugin, returning an error
+    // that describes the plugin's arguments if not
+    pub fn check_arg_count(&self, len: usize) -> Result<(), PluginError> {
+        let required_args = self.required_args();
+        if len >= required_args && len <= self.arg_types.len() {
+            return Ok(());
+        }
+
+        let mut message = if required_args == self.arg_types.len() {
+            format!("Expected {} arguments, got {}", self.arg_types.len(), len)
+        } else {
+            format!(
+                "Expected {} to {} arguments, got {}",
+                required_args,
+                self.arg_types.len(),
+                len
+            )
+        };
+
+        if let Some(usage) = self.usage() {
+            message.push('\n');
+            message.push_str(&usage);
+        }
+        Err(PluginError::Argument(message))
+    }
+
+    // Checks that the given names match the names of the plugin's arguments, in order. Plugins
+    // that don't describe their arguments can't be checked, so any names are accepted.
+    pub fn check_arg_names(&self, names: &[&str]) -> Result<(), PluginError> {
+        let Some(args) = &self.args else {
+            return Ok(());
+        };
+        for (i, (name, arg)) in names.iter().zip(args).enumerate() {
+            if *name != arg.name {
+                return Err(PluginError::Argument(format!(
+                    "arg{} is named {}, but the plugin expects {}",
+                    i, name, arg.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Compares this metadata against that of another version of the plugin, returning a
+    // description of each difference in its interface
+    pub fn diff(&self, new: &OwnedMetadata) -> Vec<String> {
+        let mut diffs = vec![];
+        if self.name != new.name {
+            diffs.push(format!("name: {} -> {}", self.name, new.name));
+        }
+
+        if self.arg_types.len() != new.arg_types.len() {
+            diffs.push(format!(
+                "argument count: {} -> {}",
+                self.arg_types.len(),
+                new.arg_types.len()
+            ));
+        }
+
+        for (i, (old_type, new_type)) in self.arg_types.iter().zip(&new.arg_types).enumerate() {
+            if old_type != new_type {
+                diffs.push(format!("arg{} type: {} -> {}", i, old_type, new_type));
+            }
+        }
+
+        match (&self.args, &new.args) {
+            (Some(old_args), Some(new_args)) => {
+                for (i, (old_arg, new_arg)) in old_args.iter().zip(new_args).enumerate() {
+                    if old_arg.name != new_arg.name {
+                        diffs.push(format!(
+                            "arg{} name: {} -> {}",
+                            i, old_arg.name, new_arg.name
+                        ));
+                    }
+                    if old_arg.optional != new_arg.optional {
+                        diffs.push(format!(
+                            "arg{} optional: {} -> {}",
+                            i, old_arg.optional, new_arg.optional
+                        ));
+                    }
+                    let constraints =
+                        |arg: &ArgInfo| arg.constraints().unwrap_or_else(|| "none".to_string());
+                    if (old_arg.min, old_arg.max, old_arg.max_len)
+                        != (new_arg.min, new_arg.max, new_arg.max_len)
+                    {
+                        diffs.push(format!(
+                            "arg{} constraints: {} -> {}",
+                            i,
+                            constraints(old_arg),
+                            constraints(new_arg)
+                        ));
+                    }
+                }
+            }
+            (Some(_), None) => diffs.push("argument descriptions removed".to_string()),
+            (None, Some(_)) => diffs.push("argument descriptions added".to_string()),
+            (None, None) => {}
+        }
+
+        if self.return_type != new.return_type {
+            diffs.push(format!(
+                "return type: {} -> {}",
+                self.return_type, new.return_type
+            ));
+        }
+
+        if self.required_host_capabilities != new.required_host_capabilities {
+            diffs.push(format!(
+                "required host capabilities: [{}] -> [{}]",
+                capability_names(self.required_host_capabilities).join(", "),
+                capability_names(new.required_host_capabilities).join(", ")
+            ));
+        }
+
+        if self.capabilities != new.capabilities {
+            diffs.push(format!(
+                "capabilities: [{}] -> [{}]",
+                plugin_capability_names(self.capabilities).join(", "),
+                plugin_capability_names(new.capabilities).join(", ")
+            ));
+        }
+
+        diffs
+    }
+
+    // The function's arguments as `name: Type`, using positional names if the plugin doesn't
+    // describe its arguments; optional arguments are shown in brackets
+    pub fn signature(&self) -> Vec<String> {
+        match &self.args {
+            Some(args) => args
+                .iter()
+                .map(|arg| {
+                    if arg.optional {
+                        format!("[{}: {}]", arg.name, arg.arg_type)
+                    } else {
+                        format!("{}: {}", arg.name, arg.arg_type)
+                    }
+                })
+                .collect(),
+            None => self
+                .arg_types
+                .iter()
+                .enumerate()
+                .map(|(i, arg_type)| format!("arg{}: {}", i, arg_type))
+                .collect(),
+        }
+    }
+
+    // A human-readable description of how to call the plugin, if it describes its arguments
+    pub fn usage(&self) -> Option<String> {
+        let args = self.args.as_ref()?;
+        let signature: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut usage = format!("Usage: {} {}", self.name, signature.join(" "));
+        for arg in args {
+            usage.push_str(&format!(
+                "\n  {:<12} {}",
+                arg.name,
+                arg.doc.as_deref().unwrap_or("")
+            ));
+            if let Some(constraints) = arg.constraints() {
+                usage.push_str(&format!(" ({})", constraints));
+            }
+        }
+        Some(usage)
+    }
+}
+
+// Asks the plugin to describe an argument, through the newest version of `plugin_describe_arg` it
+// exports; version 1 has neither the secret flag nor bounds, so its arguments aren't secret and
+// are unbounded. Returns None if it exports neither.
+fn describe_arg(container: &Container<PluginApi>, index: usize) -> Option<ArgDescriptor> {
+    if let Some(descriptor) = unsafe { container.plugin_describe_arg_v2(index) } {
+        return Some(descriptor);
+    }
+    let v1 = unsafe { container.plugin_describe_arg(index) }?;
+    Some(ArgDescriptor {
+        name: v1.name,
+        arg_type: v1.arg_type,
+        optional: v1.optional,
+        doc: v1.doc,
+        secret: false,
+        min: std::ptr::null(),
+        max: std::ptr::null(),
+        max_len: 0,
+    })
+}
+
+// Reads the plugin's tags, if it exports `plugin_describe_tag`
+fn describe_tags(container: &Container<PluginApi>) -> Result<Vec<String>, PluginError> {
+    let mut tags = vec![];
+    for i in 0.. {
+        let Some(tag) = (unsafe { container.plugin_describe_tag(i) }) else {
+            break;
+        };
+        if tag.is_null() {
+            break;
+        }
+        if i == MAX_TAGS {
+            return Err(PluginError::Load(format!(
+                "Plugin describes more than {} tags",
+                MAX_TAGS
+            )));
+        }
+        tags.push(read_str(tag, MAX_NAME_LEN, &format!("tag{}", i))?);
+    }
+    Ok(tags)
+}
+
+// Queries the plugin for a description of each of its `len` arguments through `describe` (which
+// returns None if the plugin doesn't export the function describing them), returning None if it
+// doesn't describe them all
+fn describe_args(
+    len: usize,
+    describe: impl Fn(usize) -> Option<ArgDescriptor>,
+) -> Result<Option<Vec<ArgInfo>>, PluginError> {
+    let mut args = vec![];
+    for i in 0..len {
+        let Some(descriptor) = describe(i) else {
+            return Ok(None);
+        };
+        if descriptor.name.is_null() {
+            // the plugin describes fewer arguments than its metadata declares
+            return Ok(None);
+        }
+
+        let arg_type = read_type(descriptor.arg_type, &format!("arg{}", i))?;
+        let min = read_bound(descriptor.min, arg_type, &format!("min for arg{}", i))?;
+        let max = read_bound(descriptor.max, arg_type, &format!("max for arg{}", i))?;
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                return Err(PluginError::Load(format!(
+                    "Plugin declared a min for arg{} ({}) greater than its max ({})",
+                    i, min, max
+                )));
+            }
+        }
+        let max_len = match descriptor.max_len {
+            0 => None,
+            _ if !matches!(arg_type, PluginType::String | PluginType::Bytes) => {
+                return Err(PluginError::Load(format!(
+                    "Plugin declared a max_len for arg{}, but {} arguments don't have a length",
+                    i, arg_type
+                )));
+            }
+            max_len => Some(max_len),
+        };
+
+        args.push(ArgInfo {
+            name: read_str(descriptor.name, MAX_NAME_LEN, &format!("name for arg{}", i))?,
+            arg_type,
+            optional: descriptor.optional,
+            secret: descriptor.secret,
+            min,
+            max,
+            max_len,
+            doc: if descriptor.doc.is_null() {
+                None
+            } else {
+                Some(read_str(
+                    descriptor.doc,
+                    MAX_DOC_LEN,
+                    &format!("doc for arg{}", i),
+                )?)
+            },
+        });
+    }
+
+    Ok(Some(args))
+}
+
+// Renders a table of function signatures with aligned columns, one row per function
+pub fn functions_table(functions: &[&OwnedMetadata]) -> String {
+    let rows: Vec<[String; 3]> = functions
+        .iter()
+        .map(|f| {
+            [
+                f.name.clone(),
+                f.signature().join(", "),
+                f.return_type.to_string(),
+            ]
+        })
+        .collect();
+    table(["Function", "Arguments", "Returns"], &rows)
+}
+
+// Renders a table of plugins (with the metadata read from each path) and their tags, one row per
+// plugin
+pub fn plugins_table(plugins: &[(&Path, &OwnedMetadata)]) -> String {
+    let rows: Vec<[String; 3]> = plugins
+        .iter()
+        .map(|(path, plugin)| {
+            [
+                plugin.name.clone(),
+                plugin.tags.join(", "),
+                path.display().to_string(),
+            ]
+        })
+        .collect();
+    table(["Plugin", "Tags", "Path"], &rows)
+}
+
+// Renders a table of the host capabilities, showing which the plugin requires and which this host
+// provides. Every capability the host knows of is listed, followed by any unknown ones the plugin
+// requires (which the host can't provide). A second table shows which of the plugin capabilities
+// (`PLUGIN_*`) the plugin advertises, again followed by any unknown ones.
+pub fn capabilities_table(metadata: &OwnedMetadata) -> String {
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+    let known = CAPABILITY_NAMES.iter().fold(0, |known, (c, _)| known | c);
+    let rows: Vec<[String; 3]> = CAPABILITY_NAMES
+        .iter()
+        .map(|(c, _)| *c)
+        .chain(
+            (0..64)
+                .map(|bit| 1 << bit)
+                .filter(|c| metadata.required_host_capabilities & !known & c != 0),
+        )
+        .map(|c| {
+            [
+                capability_names(c).join(""),
+                yes_no(metadata.required_host_capabilities & c != 0),
+                yes_no(HOST_CAPABILITIES & c != 0),
+            ]
+        })
+        .collect();
+
+    let known = PLUGIN_CAPABILITY_NAMES
+        .iter()
+        .fold(0, |known, (c, _)| known | c);
+    let advertised: Vec<[String; 2]> = PLUGIN_CAPABILITY_NAMES
+        .iter()
+        .map(|(c, _)| *c)
+        .chain(
+            (0..64)
+                .map(|bit| 1 << bit)
+                .filter(|c| metadata.capabilities & !known & c != 0),
+        )
+        .map(|c| {
+            [
+                plugin_capability_names(c).join(""),
+                yes_no(metadata.capabilities & c != 0),
+            ]
+        })
+        .collect();
+    format!(
+        "{}\n{}",
+        table(["Capability", "Required", "Provided"], &rows),
+        table(["Plugin capability", "Advertised"], &advertised)
+    )
+}
+
+// Renders rows under a header, with each column padded to its widest cell
+fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let header = header.map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let line: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{
+        CAPABILITY_CONFIG, CAPABILITY_EMIT, CAPABILITY_LOG, CAPABILITY_NAMES, PLUGIN_PURE,
+    };
+
+    // The metadata of the repeat plugin, without its argument descriptions
+    fn repeat() -> OwnedMetadata {
+        OwnedMetadata {
+            name: "repeat".to_string(),
+            arg_types: vec![PluginType::String, PluginType::UInt],
+            return_type: PluginType::String,
+            args: None,
+            required_host_capabilities: 0,
+            capabilities: 0,
+            tags: vec![],
+        }
+    }
+
+    #[test]
+    fn diff_reports_changed_arg_type() {
+        let old = repeat();
+        assert!(old.diff(&old).is_empty());
+
+        let mut new = repeat();
+        new.arg_types[1] = PluginType::Int;
+        assert_eq!(old.diff(&new), ["arg1 type: UInt -> Int"]);
+
+        new.arg_types.pop();
+        new.return_type = PluginType::Json;
+        assert_eq!(
+            old.diff(&new),
+            ["argument count: 2 -> 1", "return type: String -> Json"]
+        );
+    }
+
+    #[test]
+    fn diff_reports_required_capabilities() {
+        let old = repeat();
+        let mut new = repeat();
+        new.required_host_capabilities = CAPABILITY_CONFIG | 1 << 63;
+        new.capabilities = PLUGIN_PURE;
+        assert_eq!(
+            old.diff(&new),
+            [
+                "required host capabilities: [] -> [config, unknown(0x8000000000000000)]",
+                "capabilities: [] -> [PURE]"
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_table_aligns_columns() {
+        let scale = OwnedMetadata {
+            name: "scale".to_string(),
+            arg_types: vec![PluginType::Double, PluginType::Int, PluginType::Bool],
+            return_type: PluginType::Double,
+            ..repeat()
+        };
+        assert_eq!(
+            functions_table(&[&repeat(), &scale]),
+            "Function  Arguments                            Returns\n\
+             repeat    arg0: String, arg1: UInt             String\n\
+             scale     arg0: Double, arg1: Int, arg2: Bool  Double\n"
+        );
+    }
+
+    #[test]
+    fn checks_values_against_bounds() {
+        let count = ArgInfo {
+            name: "count".to_string(),
+            arg_type: PluginType::UInt,
+            optional: false,
+            doc: None,
+            secret: false,
+            min: Some(Bound::UInt(2)),
+            max: Some(Bound::UInt(10)),
+            max_len: None,
+        };
+        assert_eq!(count.constraints().as_deref(), Some("2 to 10"));
+        for in_range in [2, 5, 10] {
+            assert_eq!(count.check(&OwnedPluginValue::UInt(in_range)), Ok(()));
+        }
+        assert_eq!(
+            count.check(&OwnedPluginValue::UInt(1)),
+            Err("count must be at least 2, got 1".to_string())
+        );
+        assert_eq!(
+            count.check(&OwnedPluginValue::UInt(11)),
+            Err("count must be at most 10, got 11".to_string())
+        );
+
+        // NaN is outside any bound, and secret values aren't shown
+        let ratio = ArgInfo {
+            name: "ratio".to_string(),
+            arg_type: PluginType::Double,
+            secret: true,
+            min: Some(Bound::Double(0.0)),
+            max: None,
+            ..count
+        };
+        assert_eq!(
+            ratio.check(&OwnedPluginValue::Double(f64::NAN)),
+            Err("ratio must be at least 0, got ****".to_string())
+        );
+        assert_eq!(ratio.check(&OwnedPluginValue::Double(1e9)), Ok(()));
+    }
+
+    #[test]
+    fn plugins_table_lists_tags() {
+        let mut repeat = repeat();
+        repeat.tags = vec!["category=text".to_string(), "example".to_string()];
+        let negate = OwnedMetadata {
+            name: "negate".to_string(),
+            ..self::repeat()
+        };
+        assert_eq!(
+            plugins_table(&[
+                (Path::new("libplugin.so"), &repeat),
+                (Path::new("libnegate.so"), &negate)
+            ]),
+            "Plugin  Tags                    Path\n\
+             repeat  category=text, example  libplugin.so\n\
+             negate                          libnegate.so\n"
+        );
+    }
+
+    #[test]
+    fn capabilities_table_lists_required_and_provided() {
+        let mut metadata = repeat();
+        metadata.required_host_capabilities = CAPABILITY_LOG | CAPABILITY_EMIT | 1 << 40;
+        metadata.capabilities = PLUGIN_PURE | 1 << 10;
+        let output = capabilities_table(&metadata);
+        let (host, plugin) = output.split_once("\n\n").unwrap();
+        let rows: Vec<Vec<_>> = host
+            .lines()
+            .map(|row| row.split_whitespace().collect())
+            .collect();
+        assert_eq!(rows[0], ["Capability", "Required", "Provided"]);
+        assert_eq!(rows[1], ["log", "yes", "yes"]);
+        assert_eq!(rows[2], ["config", "no", "yes"]);
+        assert_eq!(rows[6], ["emit", "yes", "yes"]);
+        // every known capability is listed, then the unknown ones the plugin requires
+        assert_eq!(rows.len(), 1 + CAPABILITY_NAMES.len() + 1);
+        assert_eq!(
+            rows.last().unwrap(),
+            &["unknown(0x10000000000)", "yes", "no"]
+        );
+
+        assert_eq!(
+            plugin,
+            "Plugin capability  Advertised\n\
+             THREAD_SAFE        no\n\
+             PURE               yes\n\
+             unknown(0x400)     yes\n"
+        );
+    }
+
+    #[test]
+    fn formats_null_raw_metadata() {
+        let metadata = PluginMetadata {
+            name: std::ptr::null(),
+            arg_types: std::ptr::null(),
+            arg_types_len: 2,
+            return_type: 99,
+            required_host_capabilities: CAPABILITY_LOG,
+        };
+        assert_eq!(
+            unsafe { format_raw_metadata(&metadata) },
+            "name: null\narg_types: null\narg_types_len: 2\nreturn_type: unknown(99)\n\
+             required_host_capabilities: 0x1 (log)\n"
+        );
+    }
+}
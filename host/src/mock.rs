@@ -0,0 +1,88 @@
+// Builds small plugins from inline source, for tests that need a plugin with some particular
+// (often pathological) behavior, such as returning a null string or the wrong type, without
+// committing a crate for each. The source is compiled by `rustc` as a cdylib, after the FFI types
+// that `--new-plugin` generates, so it only needs to define the plugin's exports:
+//
+// let mock = MockPlugin::build(r#"
+//     #[no_mangle]
+//     pub extern "C" fn plugin_metadata() -> PluginMetadata { ... }
+//
+//     #[no_mangle]
+//     pub extern "C" fn plugin_entrypoint(args: *const PluginValue, len: usize) -> PluginResult {
+//         PluginResult::Ok(PluginValue::String(std::ptr::null()))
+//     }
+// "#)?;
+// let result = Plugin::load(mock.path(), HashMap::new())?.call(&[]);
+//
+// `rustc` is run from the RUSTC variable if it's set, and from the PATH otherwise.
+use crate::PluginError;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const FFI_TYPES: &str = include_str!("../templates/ffi.rs.tmpl");
+
+// Distinguishes the directories of mock plugins built by the same process
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// A plugin library built from inline source, in a temporary directory that's removed when this is
+// dropped. A plugin loaded from it must be dropped first, as the library stays mapped until then.
+pub struct MockPlugin {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl MockPlugin {
+    // Compiles `source` (preceded by the FFI types) into a plugin library, failing with the
+    // compiler's errors if it doesn't build
+    pub fn build(source: &str) -> Result<MockPlugin, PluginError> {
+        let dir = std::env::temp_dir().join(format!(
+            "mock-plugin-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let failed = |e: String| PluginError::Load(format!("Could not build mock plugin: {}", e));
+        std::fs::create_dir_all(&dir).map_err(|e| failed(e.to_string()))?;
+        // from here on the directory is removed on failure, by dropping the mock
+        let mock = MockPlugin {
+            path: dir.join(format!("{}mock{}", DLL_PREFIX, DLL_SUFFIX)),
+            dir,
+        };
+
+        let src = mock.dir.join("lib.rs");
+        std::fs::write(&src, format!("{}\n{}", FFI_TYPES, source))
+            .map_err(|e| failed(e.to_string()))?;
+        let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let output = Command::new(&rustc)
+            .args([
+                "--crate-type",
+                "cdylib",
+                "--crate-name",
+                "mock",
+                "--edition",
+                "2021",
+            ])
+            // the FFI types are included whether or not the source uses them
+            .args(["-A", "dead_code", "-o"])
+            .arg(&mock.path)
+            .arg(&src)
+            .output()
+            .map_err(|e| failed(format!("could not run {}: {}", rustc.to_string_lossy(), e)))?;
+        if !output.status.success() {
+            return Err(failed(String::from_utf8_lossy(&output.stderr).into_owned()));
+        }
+        Ok(mock)
+    }
+
+    // The path of the built library, to load the plugin from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for MockPlugin {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,1734 @@
+use crate::error::message_from_ffi;
+use crate::ffi::{
+    capability_names, HostServices, KeyValue, LogLevel, PluginApi, PluginResult, PluginValue,
+    CAPABILITY_ALLOC, HOST_CAPABILITIES,
+};
+use crate::raw::{RawPluginResult, RawPluginValue};
+use crate::telemetry::CallSpan;
+use crate::value::{raw_fd, ResultLimits};
+use crate::{
+    format_raw_metadata, IntoPluginArgs, OwnedMetadata, OwnedPluginValue, PluginError, REDACTED,
+};
+use dlopen2::wrapper::Container;
+use std::alloc::Layout;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString, OsStr, OsString};
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+// The least severe level of plugin log messages printed by default
+pub const DEFAULT_LOG_LEVEL: LogLevel = LogLevel::Info;
+
+// The host state behind `HostServices::context`
+struct HostContext {
+    config: HashMap<CString, CString>,
+    cancelled: CancellationToken,
+    // The least severe level that's logged, as a `LogLevel` discriminant
+    log_level: Arc<AtomicU8>,
+    progress: Progress,
+}
+
+extern "C" fn host_log(context: *const c_void, level: LogLevel, message: *const c_char) {
+    let context = unsafe { &*(context as *const HostContext) };
+    if level as u8 > context.log_level.load(Ordering::Relaxed) {
+        return;
+    }
+    let message = unsafe { CStr::from_ptr(message) };
+    eprintln!("[plugin {}] {}", level, message.to_string_lossy());
+}
+
+// Logs a message with its fields appended as `key=value` pairs, e.g. `processed item="a" count=3`
+extern "C" fn host_log_fields(
+    context: *const c_void,
+    level: LogLevel,
+    message: *const c_char,
+    fields: *const KeyValue,
+    fields_len: usize,
+) {
+    let context = unsafe { &*(context as *const HostContext) };
+    if level as u8 > context.log_level.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut line = unsafe { CStr::from_ptr(message) }
+        .to_string_lossy()
+        .to_string();
+    let fields = if fields.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(fields, fields_len) }
+    };
+    for field in fields {
+        let key = if field.key.is_null() {
+            "?".into()
+        } else {
+            unsafe { CStr::from_ptr(field.key) }.to_string_lossy()
+        };
+        // the value is read through its raw view, as its discriminant must be validated before it
+        // can be interpreted
+        let value = unsafe { *(&field.value as *const PluginValue as *const RawPluginValue) };
+        line.push_str(&format!(" {}={}", key, format_log_field(value)));
+    }
+    eprintln!("[plugin {}] {}", level, line);
+}
+
+// Formats a borrowed log field: scalars as they're printed, strings quoted, and anything else as
+// its Debug representation, which reads through its pointers without taking ownership
+fn format_log_field(value: RawPluginValue) -> String {
+    match value.validate() {
+        Ok(PluginValue::Bool(b)) => b.to_string(),
+        Ok(PluginValue::Int(i)) => i.to_string(),
+        Ok(PluginValue::UInt(u)) => u.to_string(),
+        Ok(PluginValue::Double(d)) => d.to_string(),
+        Ok(PluginValue::String(s)) if !s.is_null() => {
+            format!("{:?}", unsafe { CStr::from_ptr(s) }.to_string_lossy())
+        }
+        Ok(value) => format!("{:?}", value),
+        Err(e) => format!("<{}>", e),
+    }
+}
+
+extern "C" fn host_get_config(context: *const c_void, key: *const c_char) -> *const c_char {
+    let context = unsafe { &*(context as *const HostContext) };
+    let key = unsafe { CStr::from_ptr(key) };
+    context
+        .config
+        .get(key)
+        .map(|value| value.as_ptr())
+        .unwrap_or(std::ptr::null())
+}
+
+extern "C" fn host_is_cancelled(context: *const c_void) -> bool {
+    let context = unsafe { &*(context as *const HostContext) };
+    context.cancelled.is_cancelled()
+        || CALL_LIMITS.with(|limits| {
+            limits
+                .borrow()
+                .as_ref()
+                .is_some_and(|limits| limits.cancelled.is_cancelled())
+        })
+}
+
+extern "C" fn host_report_progress(context: *const c_void, fraction: f64) {
+    let context = unsafe { &*(context as *const HostContext) };
+    context.progress.set(fraction);
+    CALL_LIMITS.with(|limits| {
+        if let Some(limits) = limits.borrow().as_ref() {
+            limits.progress.set(fraction);
+        }
+    });
+}
+
+extern "C" fn host_time_remaining(_context: *const c_void) -> f64 {
+    CALL_LIMITS.with(|limits| match limits.borrow().as_ref() {
+        Some(limits) => limits
+            .deadline
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64(),
+        None => f64::INFINITY,
+    })
+}
+
+// The host's allocator, for plugins that allocate the data they return with it. The layouts are
+// checked, as an invalid one would be undefined behaviour, and zero-sized allocations (which the
+// global allocator doesn't support) are handled as `Box` and `Vec` handle them.
+extern "C" fn host_alloc(_context: *const c_void, size: usize, align: usize) -> *mut u8 {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) if size == 0 => std::ptr::null_mut::<u8>().wrapping_add(layout.align()),
+        Ok(layout) => unsafe { std::alloc::alloc(layout) },
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+extern "C" fn host_free(_context: *const c_void, ptr: *mut u8, size: usize, align: usize) {
+    if ptr.is_null() || size == 0 {
+        return;
+    }
+    if let Ok(layout) = Layout::from_size_align(size, align) {
+        unsafe { std::alloc::dealloc(ptr, layout) };
+    }
+}
+
+// Plugins emit values on the thread making the call, so the sink for a streaming call is set for
+// the duration of the call on that thread (see `Plugin::call_streaming`). Outside of one, there's
+// no sink and emitted values are refused.
+type EmitSink = Box<dyn FnMut(RawPluginValue)>;
+
+thread_local! {
+    static EMIT_SINK: RefCell<Option<EmitSink>> = const { RefCell::new(None) };
+}
+
+extern "C" fn host_emit(_context: *const c_void, value: *const PluginValue) -> bool {
+    if value.is_null() {
+        return false;
+    }
+    // the sink is taken out of the cell while it runs, so that it can make a streaming call of its
+    // own (into another plugin), which sets a sink for that call's duration
+    let Some(mut sink) = EMIT_SINK.with(|sink| sink.borrow_mut().take()) else {
+        return false;
+    };
+    // the value is read through its raw view, as its discriminant must be validated before it can
+    // be interpreted
+    sink(unsafe { *(value as *const RawPluginValue) });
+    EMIT_SINK.with(|cell| *cell.borrow_mut() = Some(sink));
+    true
+}
+
+// The plugins being called on this thread, identified by their libraries. Plugins may be called
+// from several threads at once, but not from within one of their own calls on the same thread
+// (e.g. by the closure passed to `call_streaming`, which runs while the plugin is emitting), where
+// the plugin's state may be half-updated; such calls fail with a `Reentrant` error instead.
+thread_local! {
+    static ACTIVE_CALLS: RefCell<Vec<*const Library>> = const { RefCell::new(Vec::new()) };
+}
+
+// Marks a plugin as being called on this thread until it's dropped
+struct ActiveCall(*const Library);
+
+impl ActiveCall {
+    fn enter(library: &Arc<Library>, name: &str) -> Result<ActiveCall, PluginError> {
+        let library = Arc::as_ptr(library);
+        ACTIVE_CALLS.with(|calls| {
+            let mut calls = calls.borrow_mut();
+            if calls.contains(&library) {
+                return Err(PluginError::Reentrant(format!(
+                    "Plugin {} was called again from within one of its own calls",
+                    name
+                )));
+            }
+            calls.push(library);
+            Ok(ActiveCall(library))
+        })
+    }
+}
+
+impl Drop for ActiveCall {
+    fn drop(&mut self) {
+        ACTIVE_CALLS.with(|calls| {
+            let mut calls = calls.borrow_mut();
+            calls.retain(|library| *library != self.0);
+            // free the list once the outermost call returns, so that a call leaves nothing
+            // allocated behind (which the leak-check feature would report as a leak)
+            if calls.is_empty() {
+                calls.shrink_to_fit();
+            }
+        });
+    }
+}
+
+// Restores the emit sink that was set before a streaming call when the call ends, even if it
+// panics: that of the streaming call this one was made from, if any
+struct EmitSinkGuard(Option<EmitSink>);
+
+impl Drop for EmitSinkGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        EMIT_SINK.with(|sink| *sink.borrow_mut() = previous);
+    }
+}
+
+// The most recent progress reported by a plugin through `HostServices::report_progress`, shared
+// between the host services and the `Plugin`. It's stored as the bits of an f64, with NaN meaning
+// that nothing has been reported.
+#[derive(Clone)]
+struct Progress(Arc<AtomicU64>);
+
+impl Default for Progress {
+    fn default() -> Self {
+        Progress(Arc::new(AtomicU64::new(f64::NAN.to_bits())))
+    }
+}
+
+impl Progress {
+    fn set(&self, fraction: f64) {
+        // out-of-range reports are clamped rather than rejected, as they're only informational
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        self.0.store(fraction.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Option<f64> {
+        let fraction = f64::from_bits(self.0.load(Ordering::Relaxed));
+        (!fraction.is_nan()).then_some(fraction)
+    }
+}
+
+// The limits of a call made by `Plugin::call_until`: its deadline, a cancellation flag that's set
+// if it misses it, and the progress it has reported. They're set on the thread making the call for
+// its duration, which the host services read them from, so that they only apply to that call
+// rather than to the plugin's other calls, and are gone once it returns.
+struct CallLimits {
+    deadline: Instant,
+    cancelled: CancellationToken,
+    progress: Progress,
+}
+
+thread_local! {
+    static CALL_LIMITS: RefCell<Option<CallLimits>> = const { RefCell::new(None) };
+}
+
+// Cooperatively cancels a plugin's calls. Cancelling sets a flag that the plugin can poll through
+// `HostServices::is_cancelled`; a plugin that supports cancellation then returns early with a
+// `Cancelled` error. Plugins that never check the flag simply run to completion. The flag stays set,
+// cancelling any later calls as well, until it's reset. (A call that times out is cancelled on its
+// own, without setting this flag; see `Plugin::call_with_timeout`.)
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+// Which of a plugin's functions a call is for: its default function, which `plugin_entrypoint`
+// calls, or one of those a plugin that exports several describes, by index
+#[derive(Clone, Copy)]
+enum Entry {
+    Default,
+    Function(usize),
+}
+
+// Whether `arg` is a Null passed in place of the optional argument at `index`, omitting it
+fn omits(metadata: &OwnedMetadata, index: usize, arg: &OwnedPluginValue) -> bool {
+    matches!(arg, OwnedPluginValue::Null) && metadata.is_optional(index)
+}
+
+// Fails if the plugin requires any capabilities this host doesn't provide, naming them
+fn check_capabilities(required: u64) -> Result<(), PluginError> {
+    let missing = required & !HOST_CAPABILITIES;
+    if missing != 0 {
+        return Err(PluginError::Load(format!(
+            "Plugin requires host capabilities that aren't supported: {}",
+            capability_names(missing).join(", ")
+        )));
+    }
+    Ok(())
+}
+
+// The optional symbols a plugin may export, which can be checked with `Plugin::has`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptionalSymbol {
+    DescribeArg,
+    DescribeTag,
+    Init,
+    Release,
+    FreeBytes,
+    // `plugin_describe_function` and `plugin_call`, which a plugin exports together
+    Functions,
+    EntrypointOwned,
+    EntrypointOut,
+    Reset,
+    Capabilities,
+}
+
+impl OptionalSymbol {
+    pub const ALL: [OptionalSymbol; 10] = [
+        OptionalSymbol::DescribeArg,
+        OptionalSymbol::DescribeTag,
+        OptionalSymbol::Init,
+        OptionalSymbol::Release,
+        OptionalSymbol::FreeBytes,
+        OptionalSymbol::Functions,
+        OptionalSymbol::EntrypointOwned,
+        OptionalSymbol::EntrypointOut,
+        OptionalSymbol::Reset,
+        OptionalSymbol::Capabilities,
+    ];
+
+    // The versions of the symbol the host understands, oldest first (see `PluginApi`)
+    pub fn versions(&self) -> &'static [u32] {
+        match self {
+            OptionalSymbol::DescribeArg => &[1, 2],
+            _ => &[1],
+        }
+    }
+
+    // The name the plugin exports a version of the symbol as, e.g. `plugin_describe_arg_v2`
+    pub fn versioned_name(&self, version: u32) -> String {
+        match version {
+            1 => self.symbol_name().to_string(),
+            version => format!("{}_v{}", self.symbol_name(), version),
+        }
+    }
+
+    // The name of the symbol the plugin exports (version 1, if it's versioned)
+    pub fn symbol_name(&self) -> &'static str {
+        match self {
+            OptionalSymbol::DescribeArg => "plugin_describe_arg",
+            OptionalSymbol::DescribeTag => "plugin_describe_tag",
+            OptionalSymbol::Init => "plugin_init",
+            OptionalSymbol::Release => "plugin_release",
+            OptionalSymbol::FreeBytes => "plugin_free_bytes",
+            OptionalSymbol::Functions => "plugin_describe_function",
+            OptionalSymbol::EntrypointOwned => "plugin_entrypoint_owned",
+            OptionalSymbol::EntrypointOut => "plugin_entrypoint_out",
+            OptionalSymbol::Reset => "plugin_reset",
+            OptionalSymbol::Capabilities => "plugin_capabilities",
+        }
+    }
+}
+
+// Whether the library exports the given version of an optional symbol
+fn exports(container: &Container<PluginApi>, symbol: OptionalSymbol, version: u32) -> bool {
+    match (symbol, version) {
+        (OptionalSymbol::DescribeArg, 1) => container.has_plugin_describe_arg(),
+        (OptionalSymbol::DescribeArg, 2) => container.has_plugin_describe_arg_v2(),
+        (OptionalSymbol::DescribeTag, 1) => container.has_plugin_describe_tag(),
+        (OptionalSymbol::Init, 1) => container.has_plugin_init(),
+        (OptionalSymbol::Release, 1) => container.has_plugin_release(),
+        (OptionalSymbol::FreeBytes, 1) => container.has_plugin_free_bytes(),
+        (OptionalSymbol::EntrypointOut, 1) => container.has_plugin_entrypoint_out(),
+        (OptionalSymbol::Reset, 1) => container.has_plugin_reset(),
+        (OptionalSymbol::Capabilities, 1) => container.has_plugin_capabilities(),
+        (OptionalSymbol::Functions, 1) => container.has_plugin_describe_function(),
+        (OptionalSymbol::EntrypointOwned, 1) => container.has_plugin_entrypoint_owned(),
+        _ => false,
+    }
+}
+
+// Loads the library at `path` to inspect it, without initializing the plugin
+fn open_uninitialized(path: &OsStr) -> Result<Container<PluginApi>, PluginError> {
+    unsafe { Container::load_with_flags(path, SymbolVisibility::default().dlopen_flags()) }
+        .map_err(|e| open_error(path, e))
+}
+
+// The variables the dynamic loader searches for a library's dependencies, named in load errors
+#[cfg(target_os = "macos")]
+const LIBRARY_PATH_VAR: &str = "DYLD_LIBRARY_PATH";
+#[cfg(windows)]
+const LIBRARY_PATH_VAR: &str = "PATH";
+#[cfg(not(any(target_os = "macos", windows)))]
+const LIBRARY_PATH_VAR: &str = "LD_LIBRARY_PATH";
+
+// Describes why the library at `path` couldn't be opened, keeping the OS's error verbatim, as it
+// names the file it couldn't find. If the plugin itself exists, a missing file is a library it
+// depends on, which the loader only looks for on the library search path, so we say so.
+fn open_error(path: &OsStr, e: dlopen2::Error) -> PluginError {
+    let mut message = format!("Could not load plugin: {}", e);
+    let missing = match &e {
+        dlopen2::Error::OpeningLibraryError(e) => {
+            let detail = e.to_string();
+            let not_found = detail.contains("cannot open shared object file")
+                || detail.contains("Library not loaded")
+                || e.raw_os_error() == Some(126);
+            // the OS names the file it failed to open first, which may be the plugin itself (e.g.
+            // if it can't be read)
+            not_found && !detail.starts_with(&*path.to_string_lossy())
+        }
+        _ => false,
+    };
+    if missing && std::path::Path::new(path).is_file() {
+        message.push_str(&format!(
+            "\nThe plugin exists, so this is a library it depends on; check that it's installed, \
+             and that its directory is on {} or in the plugin's rpath",
+            LIBRARY_PATH_VAR
+        ));
+    }
+    PluginError::Load(message)
+}
+
+// Whether the symbols a plugin defines are made available to libraries loaded after it (RTLD_GLOBAL),
+// or kept to the plugin itself (RTLD_LOCAL). Plugins are loaded locally by default, so that two
+// plugins that happen to define symbols with the same name (e.g., internal functions of a shared
+// dependency) can't resolve to each other's definitions. Only unix makes the distinction; on other
+// platforms the visibility is ignored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymbolVisibility {
+    #[default]
+    Local,
+    Global,
+}
+
+impl SymbolVisibility {
+    // The flags to pass to dlopen, if the platform takes any
+    #[cfg(unix)]
+    fn dlopen_flags(self) -> Option<i32> {
+        let visibility = match self {
+            SymbolVisibility::Local => libc::RTLD_LOCAL,
+            SymbolVisibility::Global => libc::RTLD_GLOBAL,
+        };
+        Some(libc::RTLD_LAZY | visibility)
+    }
+
+    #[cfg(not(unix))]
+    fn dlopen_flags(self) -> Option<i32> {
+        None
+    }
+}
+
+// The loaded library, along with the host services it may hold pointers to. This is shared with
+// any handles the plugin returns, so that the library isn't unloaded while they're still alive.
+pub(crate) struct Library {
+    // Fields are dropped in declaration order, so the library is unloaded before the services it
+    // may hold pointers to are freed. The services and context are boxed so that their addresses
+    // stay stable as the `Library` is moved.
+    pub(crate) container: Container<PluginApi>,
+    // Whether the plugin allocates the data it returns with `HostServices::alloc` (see
+    // `CAPABILITY_ALLOC`), so that the host frees returned bytes itself
+    pub(crate) host_allocated: bool,
+    _services: Box<HostServices>,
+    _context: Box<HostContext>,
+}
+
+// Plugins may be called from any thread (for example, from a blocking thread pool by
+// `call_async`), and from multiple threads at once. Plugins must therefore be written to be
+// thread-safe, which for a plugin without mutable global state requires no extra work.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+// The number of emitted values `call_stream` buffers before blocking the plugin
+#[cfg(feature = "tokio")]
+pub const STREAM_BUFFER: usize = 16;
+
+// The values emitted by a call, returned by `call_stream`. Its `poll_next` has the signature of
+// `futures::Stream::poll_next`, so it can be adapted to a `Stream` (e.g., with
+// `futures::stream::poll_fn`) without this crate depending on one.
+#[cfg(feature = "tokio")]
+pub struct PluginStream {
+    receiver: tokio::sync::mpsc::Receiver<Result<OwnedPluginValue, PluginError>>,
+}
+
+#[cfg(feature = "tokio")]
+impl PluginStream {
+    // Waits for the next item, returning None once the call has completed
+    pub async fn next(&mut self) -> Option<Result<OwnedPluginValue, PluginError>> {
+        self.receiver.recv().await
+    }
+
+    pub fn poll_next(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<OwnedPluginValue, PluginError>>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+// A loaded plugin, which confines the unsafe details of calling across the FFI boundary
+pub struct Plugin {
+    library: Arc<Library>,
+    metadata: OwnedMetadata,
+    // The plugin's functions, if it exports several (see `PluginApi::plugin_describe_function`)
+    functions: Vec<OwnedMetadata>,
+    limits: ResultLimits,
+    strict_utf8: bool,
+    cancelled: CancellationToken,
+    log_level: Arc<AtomicU8>,
+    progress: Progress,
+    trace: bool,
+    // What the plugin was loaded with, so that `reset_or_reload` can load it again
+    path: OsString,
+    config: HashMap<CString, CString>,
+    visibility: SymbolVisibility,
+    #[cfg(feature = "signing")]
+    key: Option<crate::TrustedKey>,
+    // The cached results of the plugin's calls, if they're memoized (see `set_memoize`)
+    #[cfg(feature = "serde")]
+    memo: Option<crate::memo::Memo>,
+}
+
+// A resource owned by the plugin (such as an open file), returned to the host as an opaque token.
+// The host must release each handle exactly once when it's done with it, which happens when this
+// is dropped; it's deliberately not `Clone` to preserve that guarantee. Until then, it can be passed
+// back to the plugin that returned it as an argument (but not to any other plugin).
+pub struct PluginHandle {
+    token: u64,
+    library: Arc<Library>,
+}
+
+impl PluginHandle {
+    // Takes ownership of a handle returned by the plugin, which must export `plugin_release` to
+    // be able to return handles
+    pub(crate) fn new(token: u64, library: &Arc<Library>) -> Result<PluginHandle, PluginError> {
+        if !library.container.has_plugin_release() {
+            return Err(PluginError::Plugin(
+                "Plugin returned a handle, but doesn't export plugin_release".to_string(),
+            ));
+        }
+
+        Ok(PluginHandle {
+            token,
+            library: library.clone(),
+        })
+    }
+
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+}
+
+impl Drop for PluginHandle {
+    fn drop(&mut self) {
+        unsafe { self.library.container.plugin_release(self.token) };
+    }
+}
+
+impl Debug for PluginHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PluginHandle({})", self.token)
+    }
+}
+
+// Owns any memory we need to allocate to pass the arguments, which must remain alive until the call
+// completes
+#[derive(Default)]
+struct ArgStorage {
+    #[cfg(feature = "serde")]
+    json: Vec<CString>,
+    lists: Vec<Vec<PluginValue>>,
+}
+
+impl ArgStorage {
+    // Frees everything allocated for the previous arguments, keeping the storage for reuse
+    fn clear(&mut self) {
+        #[cfg(feature = "serde")]
+        self.json.clear();
+        self.lists.clear();
+    }
+
+    // Converts an argument to its FFI representation, which borrows from both the argument and
+    // the storage. Moving a Vec doesn't move its contents, so earlier pointers remain valid as the
+    // storage grows.
+    fn borrow(&mut self, arg: &OwnedPluginValue) -> PluginValue {
+        match arg {
+            OwnedPluginValue::Bool(b) => PluginValue::Bool(*b),
+            OwnedPluginValue::Int(i) => PluginValue::Int(*i),
+            OwnedPluginValue::UInt(u) => PluginValue::UInt(*u),
+            OwnedPluginValue::Double(d) => PluginValue::Double(*d),
+            // a CString always ends in its terminator, so even an empty one points at a readable
+            // null byte (unlike an empty String, whose pointer dangles)
+            OwnedPluginValue::String(s) => PluginValue::String(s.as_ptr()),
+            OwnedPluginValue::Handle(handle) => PluginValue::Handle(handle.token),
+            OwnedPluginValue::Bytes(bytes) => PluginValue::Bytes {
+                ptr: bytes.as_ptr(),
+                len: bytes.len(),
+            },
+            OwnedPluginValue::Custom { type_id, payload } => PluginValue::Custom {
+                type_id: *type_id,
+                ptr: payload.as_ptr(),
+                len: payload.len(),
+            },
+            OwnedPluginValue::Null => PluginValue::Null,
+            OwnedPluginValue::Fd(file) => PluginValue::Fd(raw_fd(file)),
+            OwnedPluginValue::List(items) => {
+                let items: Vec<PluginValue> = items.iter().map(|item| self.borrow(item)).collect();
+                let value = PluginValue::List {
+                    items: items.as_ptr(),
+                    len: items.len(),
+                };
+                self.lists.push(items);
+                value
+            }
+            // the inner value is kept like a list of one item
+            OwnedPluginValue::Result(result) => {
+                let (Ok(inner) | Err(inner)) = result;
+                let inner = vec![self.borrow(inner)];
+                let value = match result {
+                    Ok(_) => PluginValue::ResultOk(inner.as_ptr()),
+                    Err(_) => PluginValue::ResultErr(inner.as_ptr()),
+                };
+                self.lists.push(inner);
+                value
+            }
+            #[cfg(feature = "serde")]
+            OwnedPluginValue::Json(json) => {
+                // serialized JSON never contains a raw NUL byte, as it's escaped within strings
+                let json = CString::new(json.to_string()).unwrap();
+                let value = PluginValue::Json(json.as_ptr());
+                self.json.push(json);
+                value
+            }
+        }
+    }
+}
+
+// The number of bytes allocated to marshal `args`: the FFI array of arguments, and the buffers
+// `ArgStorage` allocates for lists and JSON values (strings and bytes are borrowed from the
+// arguments themselves, so they aren't counted)
+fn marshalled_bytes(args: &[OwnedPluginValue]) -> usize {
+    let buffers: usize = args
+        .iter()
+        .map(|arg| match arg {
+            OwnedPluginValue::List(items) => marshalled_bytes(items),
+            OwnedPluginValue::Result(Ok(inner) | Err(inner)) => {
+                marshalled_bytes(std::slice::from_ref(inner))
+            }
+            #[cfg(feature = "serde")]
+            OwnedPluginValue::Json(json) => json.to_string().len() + 1,
+            _ => 0,
+        })
+        .sum();
+    args.len() * size_of::<PluginValue>() + buffers
+}
+
+// How much memory the host allocated for a call: to marshal its arguments, and to hold the value
+// it returned (see `OwnedPluginValue::heap_bytes`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallStats {
+    pub arg_bytes: usize,
+    pub result_bytes: usize,
+}
+
+impl CallStats {
+    fn new(args: &[OwnedPluginValue], result: &OwnedPluginValue) -> CallStats {
+        CallStats {
+            arg_bytes: marshalled_bytes(args),
+            result_bytes: result.heap_bytes(),
+        }
+    }
+}
+
+// Arguments that have been marshalled once and can be passed to any number of calls with
+// `Plugin::call_prepared`. The FFI representation borrows from the arguments and from the buffers
+// allocated for them, all of which are owned here and only freed when this is dropped, so the
+// pointers passed to the plugin stay valid across calls.
+pub struct PreparedArgs {
+    // declared first so that it's dropped before the memory it points into
+    call_args: Vec<PluginValue>,
+    _storage: ArgStorage,
+    args: Vec<OwnedPluginValue>,
+}
+
+impl PreparedArgs {
+    pub fn new(args: Vec<OwnedPluginValue>) -> PreparedArgs {
+        let mut storage = ArgStorage::default();
+        // the arguments' own buffers (e.g., the bytes of a CString) are heap-allocated, so they
+        // don't move when `args` is moved into the struct
+        let call_args = args.iter().map(|arg| storage.borrow(arg)).collect();
+        PreparedArgs {
+            call_args,
+            _storage: storage,
+            args,
+        }
+    }
+
+    pub fn args(&self) -> &[OwnedPluginValue] {
+        &self.args
+    }
+}
+
+// The FFI representation of a single OwnedPluginValue (see `OwnedPluginValue::to_ffi`), e.g. for
+// passing a value to a plugin through a lower-level interface. The PluginValue borrows from the
+// owned value and from buffers allocated for it, which this guard keeps alive, so its pointers are
+// only valid for as long as the guard is.
+pub struct BorrowedPluginValue<'a> {
+    value: PluginValue,
+    _storage: ArgStorage,
+    _owned: PhantomData<&'a OwnedPluginValue>,
+}
+
+impl BorrowedPluginValue<'_> {
+    pub fn value(&self) -> &PluginValue {
+        &self.value
+    }
+}
+
+impl OwnedPluginValue {
+    // Converts the value back into its FFI representation, the inverse of taking ownership of a
+    // value returned by a plugin
+    pub fn to_ffi(&self) -> BorrowedPluginValue<'_> {
+        let mut storage = ArgStorage::default();
+        let value = storage.borrow(self);
+        BorrowedPluginValue {
+            value,
+            _storage: storage,
+            _owned: PhantomData,
+        }
+    }
+}
+
+impl Plugin {
+    // Loads the plugin at `path` and initializes it, making `config` available to it through the
+    // host services. Its symbols are kept local to it; see `SymbolVisibility`.
+    pub fn load(
+        path: impl AsRef<OsStr>,
+        config: HashMap<CString, CString>,
+    ) -> Result<Plugin, PluginError> {
+        Plugin::load_with_visibility(path, config, SymbolVisibility::default())
+    }
+
+    // Reads the metadata of the plugin at `path` without initializing it: `plugin_init` is never
+    // called, so plugins can be listed or audited without triggering whatever side effects it has
+    // (e.g. opening connections). A plugin that builds its metadata from its config sees none, and
+    // so reports its unconfigured metadata. Note that the library is still loaded to read it, which
+    // runs any constructors it has, and is unloaded again before this returns.
+    pub fn inspect(path: impl AsRef<OsStr>) -> Result<OwnedMetadata, PluginError> {
+        OwnedMetadata::read(&open_uninitialized(path.as_ref())?)
+    }
+
+    // Like `inspect`, but returns the metadata of each function the plugin exports: those it
+    // describes through `plugin_describe_function`, or just its default function if it doesn't
+    pub fn inspect_functions(path: impl AsRef<OsStr>) -> Result<Vec<OwnedMetadata>, PluginError> {
+        let container = open_uninitialized(path.as_ref())?;
+        let functions = OwnedMetadata::read_functions(&container)?;
+        if functions.is_empty() {
+            Ok(vec![OwnedMetadata::read(&container)?])
+        } else {
+            Ok(functions)
+        }
+    }
+
+    // Like `inspect`, but also returns the optional symbols the plugin exports, e.g. to check its
+    // metadata against them
+    pub fn inspect_exports(
+        path: impl AsRef<OsStr>,
+    ) -> Result<(OwnedMetadata, Vec<OptionalSymbol>), PluginError> {
+        let container = open_uninitialized(path.as_ref())?;
+        let metadata = OwnedMetadata::read(&container)?;
+        let symbols = OptionalSymbol::ALL
+            .into_iter()
+            .filter(|symbol| {
+                symbol
+                    .versions()
+                    .iter()
+                    .any(|version| exports(&container, *symbol, *version))
+            })
+            .collect();
+        Ok((metadata, symbols))
+    }
+
+    // Like `load_with_visibility`, but first checks that the library is signed by `key`, refusing
+    // to load it (without running any of its code) if it isn't
+    #[cfg(feature = "signing")]
+    pub fn load_verified(
+        path: impl AsRef<OsStr>,
+        config: HashMap<CString, CString>,
+        visibility: SymbolVisibility,
+        key: &crate::TrustedKey,
+    ) -> Result<Plugin, PluginError> {
+        key.verify(std::path::Path::new(path.as_ref()))?;
+        let mut plugin = Plugin::load_with_visibility(path, config, visibility)?;
+        plugin.key = Some(key.clone());
+        Ok(plugin)
+    }
+
+    // Like `load`, but with control over whether the plugin's symbols are visible to libraries
+    // loaded after it
+    pub fn load_with_visibility(
+        path: impl AsRef<OsStr>,
+        config: HashMap<CString, CString>,
+        visibility: SymbolVisibility,
+    ) -> Result<Plugin, PluginError> {
+        let cancelled = CancellationToken::default();
+        let log_level = Arc::new(AtomicU8::new(DEFAULT_LOG_LEVEL as u8));
+        let progress = Progress::default();
+        let path = path.as_ref().to_os_string();
+        let context = Box::new(HostContext {
+            config: config.clone(),
+            cancelled: cancelled.clone(),
+            log_level: log_level.clone(),
+            progress: progress.clone(),
+        });
+        let services = Box::new(HostServices {
+            context: &*context as *const HostContext as *const c_void,
+            log: host_log,
+            get_config: host_get_config,
+            is_cancelled: host_is_cancelled,
+            report_progress: host_report_progress,
+            emit: host_emit,
+            time_remaining: host_time_remaining,
+            alloc: host_alloc,
+            free: host_free,
+            log_fields: host_log_fields,
+        });
+
+        let container: Container<PluginApi> =
+            unsafe { Container::load_with_flags(&path, visibility.dlopen_flags()) }
+                .map_err(|e| open_error(&path, e))?;
+
+        // Check the capabilities before initializing the plugin, so that a plugin we can't support
+        // never runs its `plugin_init`. They're checked again in the metadata read after it.
+        let required = unsafe { container.plugin_metadata() }.required_host_capabilities;
+        check_capabilities(required)?;
+
+        if let Some(err) = unsafe { container.plugin_init(&*services) } {
+            if !err.is_null() {
+                let err = unsafe { CString::from_raw(err) };
+                return Err(PluginError::Load(format!(
+                    "Plugin failed to initialize: {}",
+                    err.to_string_lossy()
+                )));
+            }
+        }
+
+        let metadata = OwnedMetadata::read(&container)?;
+
+        check_capabilities(metadata.required_host_capabilities)?;
+        let functions = OwnedMetadata::read_functions(&container)?;
+
+        Ok(Plugin {
+            library: Arc::new(Library {
+                container,
+                host_allocated: metadata.required_host_capabilities & CAPABILITY_ALLOC != 0,
+                _services: services,
+                _context: context,
+            }),
+            metadata,
+            functions,
+            limits: ResultLimits::default(),
+            strict_utf8: false,
+            cancelled,
+            log_level,
+            progress,
+            trace: false,
+            path,
+            config,
+            visibility,
+            #[cfg(feature = "signing")]
+            key: None,
+            #[cfg(feature = "serde")]
+            memo: None,
+        })
+    }
+
+    pub fn metadata(&self) -> &OwnedMetadata {
+        &self.metadata
+    }
+
+    // The metadata of each of the plugin's functions: those it describes if it exports several, or
+    // otherwise its only function
+    pub fn functions(&self) -> Vec<&OwnedMetadata> {
+        if self.functions.is_empty() {
+            vec![&self.metadata]
+        } else {
+            self.functions.iter().collect()
+        }
+    }
+
+    // The entry for the function named `name`
+    fn entry(&self, name: &str) -> Result<Entry, PluginError> {
+        match self.functions.iter().position(|f| f.name == name) {
+            Some(index) => Ok(Entry::Function(index)),
+            None if self.functions.is_empty() && self.metadata.name == name => Ok(Entry::Default),
+            None => Err(PluginError::Usage(format!(
+                "Plugin {} has no function named {}",
+                self.metadata.name, name
+            ))),
+        }
+    }
+
+    fn entry_metadata(&self, entry: Entry) -> &OwnedMetadata {
+        match entry {
+            Entry::Default => &self.metadata,
+            Entry::Function(index) => &self.functions[index],
+        }
+    }
+
+    // Whether the plugin exports any version of the given optional symbol
+    pub fn has(&self, symbol: OptionalSymbol) -> bool {
+        self.symbol_version(symbol).is_some()
+    }
+
+    // The newest version of the given optional symbol that the plugin exports and the host
+    // understands, which is the one the host uses, or None if it exports none of them
+    pub fn symbol_version(&self, symbol: OptionalSymbol) -> Option<u32> {
+        symbol
+            .versions()
+            .iter()
+            .rev()
+            .copied()
+            .find(|version| self.exports(symbol, *version))
+    }
+
+    fn exports(&self, symbol: OptionalSymbol, version: u32) -> bool {
+        exports(&self.library.container, symbol, version)
+    }
+
+    // Reads the plugin's metadata `reads` more times, checking that each read is identical to the
+    // metadata read when it was loaded. Metadata that changes between reads is a sign of a bug in
+    // the plugin, such as building it in memory that's freed (or reused) once `plugin_metadata`
+    // returns, which fails with a `Load` error naming the field that changed.
+    pub fn check_stable_metadata(&self, reads: usize) -> Result<(), PluginError> {
+        for read in 1..=reads {
+            let metadata = OwnedMetadata::read(&self.library.container)?;
+            if let Some(field) = self.metadata.changed_field(&metadata) {
+                return Err(PluginError::Load(format!(
+                    "Plugin metadata isn't stable: its {} changed on read {} of {}",
+                    field,
+                    read + 1,
+                    reads + 1
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Formats the metadata exactly as the plugin returns it, for debugging (see
+    // `format_raw_metadata`)
+    pub fn raw_metadata(&self) -> String {
+        unsafe { format_raw_metadata(&self.library.container.plugin_metadata()) }
+    }
+
+    // Returns a token that can be used to cancel the plugin's calls, e.g. from another thread
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancelled.clone()
+    }
+
+    // The most recent progress reported by any of the plugin's calls, as a fraction from 0 to 1, or
+    // None if it hasn't reported any
+    pub fn last_progress(&self) -> Option<f64> {
+        self.progress.get()
+    }
+
+    // Sets the least severe level of the plugin's log messages that are printed; defaults to
+    // `DEFAULT_LOG_LEVEL`. Messages logged while the plugin initializes always use the default.
+    pub fn set_log_level(&self, level: LogLevel) {
+        self.log_level.store(level as u8, Ordering::Relaxed);
+    }
+
+    // Enables tracing of what crosses the FFI boundary on each call: the type and value of each
+    // argument, and the raw discriminants of the result. The trace is written to stderr.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    // Sets the maximum length in bytes of strings (and byte buffers) the plugin may return; longer
+    // results are rejected with an error. Defaults to `DEFAULT_MAX_STRING_LEN`.
+    pub fn set_max_string_len(&mut self, max_string_len: usize) {
+        self.limits.max_string_len = max_string_len;
+    }
+
+    // Sets whether error messages and warnings from the plugin that aren't valid UTF-8 are marked as
+    // such (see `message_from_ffi`), rather than only having their invalid bytes replaced
+    pub fn set_strict_utf8(&mut self, strict_utf8: bool) {
+        self.strict_utf8 = strict_utf8;
+    }
+
+    // Sets the maximum number of items in lists the plugin may return (including lists nested in
+    // them); longer lists are rejected with an error. Defaults to `DEFAULT_MAX_LIST_LEN`.
+    pub fn set_max_list_len(&mut self, max_list_len: usize) {
+        self.limits.max_list_len = max_list_len;
+    }
+
+    // Sets the maximum total size in bytes of a result the plugin may return, counting its strings,
+    // buffers and list items (see `ResultLimits`); larger results are rejected with an error, and
+    // values written by `call_out` share a single limit. Defaults to `DEFAULT_MAX_RESULT_BYTES`.
+    pub fn set_max_result_bytes(&mut self, max_result_bytes: usize) {
+        self.limits.max_result_bytes = max_result_bytes;
+    }
+
+    // Caches up to `capacity` of the plugin's results, so that a call to one of its functions with
+    // the same arguments as a cached call is answered without calling the plugin (see memo.rs).
+    // This covers `call`, `call_function` and the calls built on them, but not calls with a timeout
+    // or deadline, batches or `call_out`. Fails with a usage error if the plugin doesn't advertise
+    // `PLUGIN_PURE`, as caching the results of any other plugin could change what its callers see.
+    #[cfg(feature = "serde")]
+    pub fn set_memoize(&mut self, capacity: usize) -> Result<(), PluginError> {
+        if !self.metadata.is_pure() {
+            return Err(PluginError::Usage(format!(
+                "{} can't be memoized, as it doesn't advertise PLUGIN_PURE",
+                self.metadata.name
+            )));
+        }
+        self.memo = Some(crate::memo::Memo::new(capacity));
+        Ok(())
+    }
+
+    // How many of the plugin's calls were answered from its cache, if they're memoized
+    #[cfg(feature = "serde")]
+    pub fn memo_stats(&self) -> Option<crate::MemoStats> {
+        self.memo.as_ref().map(|memo| memo.stats())
+    }
+
+    // Resets the plugin's state through its `plugin_reset` export, which must not be called while
+    // any of its calls are running. Fails with a usage error if the plugin doesn't export it; see
+    // `reset_or_reload` for a fallback.
+    pub fn reset(&self) -> Result<(), PluginError> {
+        if !self.has(OptionalSymbol::Reset) {
+            return Err(PluginError::Usage(format!(
+                "Plugin {} doesn't export plugin_reset",
+                self.metadata.name
+            )));
+        }
+        let active = ActiveCall::enter(&self.library, &self.metadata.name)?;
+        let result = unsafe { self.library.container.plugin_reset() }
+            .expect("checked that the plugin exports plugin_reset")
+            .interpret();
+        drop(active);
+        self.take_result(result?).map(|_| ())
+    }
+
+    // Resets the plugin's state, through `plugin_reset` if it exports it, or else by unloading the
+    // library and loading it again with the same config (and settings, such as limits). A reloaded
+    // plugin is initialized again, and has a new cancellation token. Reloading fails if handles the
+    // plugin returned are still alive, as they keep the old library loaded, and it only resets the
+    // plugin if unloading it really unloads it: libraries that can't be unloaded (e.g. ones that
+    // registered thread-local destructors) are returned as they were by the OS.
+    pub fn reset_or_reload(self) -> Result<Plugin, PluginError> {
+        if self.has(OptionalSymbol::Reset) {
+            self.reset()?;
+            return Ok(self);
+        }
+
+        // the old library must be unloaded first, or loading it would return the same one
+        let library = Arc::try_unwrap(self.library).map_err(|_| {
+            PluginError::Usage(format!(
+                "Plugin {} can't be reloaded while handles it returned are still alive",
+                self.metadata.name
+            ))
+        })?;
+        drop(library);
+
+        #[cfg(feature = "signing")]
+        let mut plugin = match self.key {
+            Some(key) => Plugin::load_verified(self.path, self.config, self.visibility, &key)?,
+            None => Plugin::load_with_visibility(self.path, self.config, self.visibility)?,
+        };
+        #[cfg(not(feature = "signing"))]
+        let mut plugin = Plugin::load_with_visibility(self.path, self.config, self.visibility)?;
+        plugin.limits = self.limits;
+        plugin.strict_utf8 = self.strict_utf8;
+        plugin.trace = self.trace;
+        #[cfg(feature = "serde")]
+        {
+            plugin.memo = self.memo;
+        }
+        plugin
+            .log_level
+            .store(self.log_level.load(Ordering::Relaxed), Ordering::Relaxed);
+        Ok(plugin)
+    }
+
+    // Calls the plugin with the given arguments, returning its result
+    pub fn call(&self, args: &[OwnedPluginValue]) -> Result<OwnedPluginValue, PluginError> {
+        self.call_partial(args).map(|(value, _)| value)
+    }
+
+    // Like `call`, but also returns how much memory the host allocated for the call
+    pub fn call_with_stats(
+        &self,
+        args: &[OwnedPluginValue],
+    ) -> Result<(OwnedPluginValue, CallStats), PluginError> {
+        let value = self.call(args)?;
+        let stats = CallStats::new(args, &value);
+        Ok((value, stats))
+    }
+
+    // Calls the plugin with arguments and a result of the caller's own types, which are converted
+    // to and from OwnedPluginValues (e.g., `call_typed::<_, String>(...)`). Integer arguments are
+    // coerced to the signedness the plugin expects, where they fit (see
+    // `OwnedPluginValue::coerce`). Fails if the result can't be converted to `R`.
+    pub fn call_typed<A, R>(&self, args: A) -> Result<R, PluginError>
+    where
+        A: Into<Vec<OwnedPluginValue>>,
+        R: TryFrom<OwnedPluginValue>,
+        R::Error: Into<PluginError>,
+    {
+        let value = self.call(&self.coerce_args(args.into())?)?;
+        R::try_from(value).map_err(Into::into)
+    }
+
+    // Like `call_typed`, but first checks that the names of the arguments (e.g., the fields of a
+    // struct deriving `IntoPluginArgs`) match the names the plugin gives them
+    pub fn call_named<A, R>(&self, args: A) -> Result<R, PluginError>
+    where
+        A: IntoPluginArgs,
+        R: TryFrom<OwnedPluginValue>,
+        R::Error: Into<PluginError>,
+    {
+        self.metadata.check_arg_names(A::ARG_NAMES)?;
+        let value = self.call(&self.coerce_args(args.into_plugin_args()?)?)?;
+        R::try_from(value).map_err(Into::into)
+    }
+
+    // Like `call`, but also returns the warning if the plugin produced a partial result
+    pub fn call_partial(
+        &self,
+        args: &[OwnedPluginValue],
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        self.call_entry(Entry::Default, args)
+    }
+
+    // Calls the plugin's function named `name`, one of its `functions`, returning its result
+    pub fn call_function(
+        &self,
+        name: &str,
+        args: &[OwnedPluginValue],
+    ) -> Result<OwnedPluginValue, PluginError> {
+        self.call_function_partial(name, args)
+            .map(|(value, _)| value)
+    }
+
+    // Like `call_function`, but also returns the warning if the function produced a partial result
+    pub fn call_function_partial(
+        &self,
+        name: &str,
+        args: &[OwnedPluginValue],
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        self.call_entry(self.entry(name)?, args)
+    }
+
+    fn call_entry(
+        &self,
+        entry: Entry,
+        args: &[OwnedPluginValue],
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        #[cfg(feature = "serde")]
+        if let Some(memo) = &self.memo {
+            let name = &self.entry_metadata(entry).name;
+            return memo.call(name, args, || self.call_uncached(entry, args));
+        }
+        self.call_uncached(entry, args)
+    }
+
+    fn call_uncached(
+        &self,
+        entry: Entry,
+        args: &[OwnedPluginValue],
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        self.check_args_of(self.entry_metadata(entry), args)?;
+
+        let mut storage = ArgStorage::default();
+        let call_args: Vec<PluginValue> = args.iter().map(|arg| storage.borrow(arg)).collect();
+        let result = self.invoke(entry, args, &call_args);
+
+        // the plugin only borrows its arguments, so we can now free them
+        drop(call_args);
+        drop(storage);
+        result
+    }
+
+    // Calls the plugin through `plugin_entrypoint_out`, for plugins that return several values
+    // through an array of up to `capacity` out-parameters rather than a single value (see
+    // `PluginApi`). Returns the values the plugin wrote, in order. Fails with a usage error if the
+    // plugin doesn't export `plugin_entrypoint_out`.
+    pub fn call_out(
+        &self,
+        args: &[OwnedPluginValue],
+        capacity: usize,
+    ) -> Result<Vec<OwnedPluginValue>, PluginError> {
+        if !self.has(OptionalSymbol::EntrypointOut) {
+            return Err(PluginError::Usage(format!(
+                "Plugin {} doesn't export plugin_entrypoint_out",
+                self.metadata.name
+            )));
+        }
+        self.check_args(args)?;
+
+        let span = CallSpan::start(&self.path, &self.metadata, args);
+        let values = span.in_scope(|| self.call_out_traced(args, capacity));
+        span.finish(values.as_ref().map(|_| false));
+        values
+    }
+
+    // The body of `call_out`, within its span
+    fn call_out_traced(
+        &self,
+        args: &[OwnedPluginValue],
+        capacity: usize,
+    ) -> Result<Vec<OwnedPluginValue>, PluginError> {
+        let mut storage = ArgStorage::default();
+        let call_args: Vec<PluginValue> = args.iter().map(|arg| storage.borrow(arg)).collect();
+        // Null holds no pointers, so the slots the plugin doesn't fill need no freeing
+        let mut out: Vec<PluginValue> = (0..capacity).map(|_| PluginValue::Null).collect();
+        let mut out_len = 0;
+        let container = &self.library.container;
+        let result = self.call_raw(args, || unsafe {
+            container
+                .plugin_entrypoint_out(
+                    call_args.as_ptr(),
+                    call_args.len(),
+                    out.as_mut_ptr(),
+                    capacity,
+                    &mut out_len,
+                )
+                .expect("checked that the plugin exports plugin_entrypoint_out")
+        })?;
+
+        // any value returned alongside the out-parameters is freed here
+        self.take_result(result)?;
+        if out_len > capacity {
+            // we can't know which values are real, so none of them are freed
+            return Err(PluginError::Plugin(format!(
+                "Plugin wrote {} values, but there was only room for {}",
+                out_len, capacity
+            )));
+        }
+
+        // as with list items, each value is read through its raw view to validate it, and every
+        // value is converted before checking for errors, so that all of them are freed
+        let mut remaining = self.limits.max_result_bytes;
+        let values: Vec<_> = out[..out_len]
+            .iter()
+            .map(|value| {
+                unsafe { *(value as *const PluginValue as *const RawPluginValue) }
+                    .validate()
+                    .map_err(|e| {
+                        PluginError::Plugin(format!("Plugin wrote an invalid value: {}", e))
+                    })?
+                    .into_owned(&self.library, &self.limits, &mut remaining)
+            })
+            .collect();
+        values.into_iter().collect()
+    }
+
+    // Calls the plugin once for each set of arguments, returning the results in the same order.
+    // The buffers used to marshal the arguments are reused from one call to the next, so this is
+    // cheaper than calling `call` in a loop. As with `call`, warnings are discarded.
+    pub fn call_batch(
+        &self,
+        batch: &[Vec<OwnedPluginValue>],
+    ) -> Vec<Result<OwnedPluginValue, PluginError>> {
+        let mut storage = ArgStorage::default();
+        let mut call_args: Vec<PluginValue> = vec![];
+        batch
+            .iter()
+            .map(|args| {
+                self.check_args(args)?;
+                // the previous call's arguments are no longer borrowed by the plugin
+                call_args.clear();
+                storage.clear();
+                call_args.extend(args.iter().map(|arg| storage.borrow(arg)));
+                self.invoke(Entry::Default, args, &call_args)
+                    .map(|(value, _)| value)
+            })
+            .collect()
+    }
+
+    // Like `call_batch`, but splits the batch between up to `threads` threads, which call the
+    // plugin concurrently. This relies on the plugin being thread-safe, as all plugins must be.
+    pub fn call_batch_parallel(
+        &self,
+        batch: &[Vec<OwnedPluginValue>],
+        threads: usize,
+    ) -> Vec<Result<OwnedPluginValue, PluginError>> {
+        let chunk_size = batch.len().div_ceil(threads.max(1)).max(1);
+        std::thread::scope(|scope| {
+            let chunks: Vec<_> = batch
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(|| self.call_batch(chunk)))
+                .collect();
+            chunks
+                .into_iter()
+                .flat_map(|chunk| {
+                    // panics in the plugin are caught by the plugin, so this is a bug in the host
+                    chunk
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect()
+        })
+    }
+
+    // Calls the plugin with arguments that were marshalled ahead of time, so that repeated calls
+    // with the same arguments don't need to reallocate them. Returns the warning if the plugin
+    // produced a partial result, like `call_partial`.
+    pub fn call_prepared(
+        &self,
+        args: &PreparedArgs,
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        // the arguments may have been prepared without reference to this plugin, so we check them
+        // on every call
+        self.check_args(&args.args)?;
+        self.invoke(Entry::Default, &args.args, &args.call_args)
+    }
+
+    // Calls the plugin with no arguments, skipping the host's own check of their number, to check
+    // that a plugin with required arguments rejects a call without them (with an InvalidArgument
+    // error) rather than reading past the end of its arguments. Plugins without required arguments
+    // pass trivially. Meant for auditing plugins, as a plugin that doesn't check will likely crash.
+    pub fn check_enforces_arg_count(&self) -> Result<(), PluginError> {
+        let required = self.metadata.required_args();
+        if required == 0 {
+            return Ok(());
+        }
+        match self.invoke(Entry::Default, &[], &[]) {
+            Err(PluginError::Argument(_)) => Ok(()),
+            Err(PluginError::WithPayload(e, _)) if matches!(*e, PluginError::Argument(_)) => Ok(()),
+            Ok(_) => Err(PluginError::Plugin(format!(
+                "accepted a call with no arguments, though it requires {}",
+                required
+            ))),
+            Err(e) => Err(PluginError::Plugin(format!(
+                "failed a call with no arguments with the wrong error: {}",
+                e
+            ))),
+        }
+    }
+
+    // Calls the entry's function with arguments that have already been checked and marshalled
+    // (`args` are only used for tracing), taking ownership of the result
+    fn invoke(
+        &self,
+        entry: Entry,
+        args: &[OwnedPluginValue],
+        call_args: &[PluginValue],
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        let span = CallSpan::start(&self.path, self.entry_metadata(entry), args);
+        let result = span.in_scope(|| self.invoke_traced(entry, args, call_args));
+        span.finish(result.as_ref().map(|(_, warning)| warning.is_some()));
+        result
+    }
+
+    // The body of `invoke`, within its span
+    fn invoke_traced(
+        &self,
+        entry: Entry,
+        args: &[OwnedPluginValue],
+        call_args: &[PluginValue],
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        let container = &self.library.container;
+        let result = self.call_raw(args, || unsafe {
+            match entry {
+                Entry::Default => container.plugin_entrypoint(call_args.as_ptr(), call_args.len()),
+                Entry::Function(index) => container
+                    .plugin_call(index, call_args.as_ptr(), call_args.len())
+                    .expect("functions are only read from plugins that export plugin_call"),
+            }
+        })?;
+
+        let owned = self.take_result(result);
+
+        if self.trace {
+            if let Ok((value, _)) = &owned {
+                let stats = CallStats::new(args, value);
+                eprintln!(
+                    "[trace] allocated: {} bytes for arguments, {} bytes for the result",
+                    stats.arg_bytes, stats.result_bytes
+                );
+            }
+        }
+        owned
+    }
+
+    // Makes a call to the plugin through `call`, tracing the arguments it's passed (`args`) and the
+    // result's discriminants, and returns the result, which the caller must take ownership of. Fails
+    // with a `Reentrant` error if the plugin is already being called on this thread.
+    fn call_raw(
+        &self,
+        args: &[OwnedPluginValue],
+        call: impl FnOnce() -> RawPluginResult,
+    ) -> Result<PluginResult, PluginError> {
+        if self.trace {
+            for (i, arg) in args.iter().enumerate() {
+                if self.metadata.is_secret(i) {
+                    eprintln!("[trace] arg{}: {} = {}", i, arg.plugin_type(), REDACTED);
+                } else {
+                    eprintln!("[trace] arg{}: {} = {}", i, arg.plugin_type(), arg);
+                }
+            }
+        }
+
+        let active = ActiveCall::enter(&self.library, &self.metadata.name)?;
+        let result = call().interpret();
+        // the plugin has returned, so converting the result may call it again (e.g. in a custom
+        // type's handler)
+        drop(active);
+        let result = result?;
+
+        if self.trace {
+            let discriminant = match &result {
+                PluginResult::Ok(value) => format!("Ok({})", value.plugin_type()),
+                PluginResult::PartialOk { value, .. } => {
+                    format!("PartialOk({})", value.plugin_type())
+                }
+                PluginResult::Err { code, .. } => format!("Err({:?})", code),
+                PluginResult::Arg(index) => format!("Arg({})", index),
+            };
+            eprintln!("[trace] result: {}", discriminant);
+        }
+        Ok(result)
+    }
+
+    // Takes ownership of what the plugin returned, converting the value (and any warning or error)
+    fn take_result(
+        &self,
+        result: PluginResult,
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        let mut remaining = self.limits.max_result_bytes;
+        match result {
+            PluginResult::Ok(value) => Ok((
+                value.into_owned(&self.library, &self.limits, &mut remaining)?,
+                None,
+            )),
+            PluginResult::PartialOk { value, warning } => {
+                // take ownership of the warning first, so that it's freed even if the value is
+                // invalid
+                let warning = (!warning.is_null()).then(|| unsafe { CString::from_raw(warning) });
+                let value = value.into_owned(&self.library, &self.limits, &mut remaining)?;
+                let Some(warning) = warning else {
+                    return Err(PluginError::Plugin(
+                        "Plugin returned a null warning".to_string(),
+                    ));
+                };
+                Ok((value, Some(message_from_ffi(warning, self.strict_utf8))))
+            }
+            PluginResult::Err {
+                code,
+                message,
+                payload,
+            } => Err(PluginError::from_ffi(
+                code,
+                message,
+                payload,
+                self.strict_utf8,
+            )),
+            PluginResult::Arg(index) => Err(PluginError::Plugin(format!(
+                "Plugin handed back arg{}, but it was only lent its arguments",
+                index
+            ))),
+        }
+    }
+
+    // Like `call`, but gives up ownership of the arguments, so that a plugin that exports
+    // `plugin_entrypoint_owned` can hand one of them back as its result without copying it (see
+    // `PluginResult::Arg`). Plugins that don't export it are called as by `call`.
+    pub fn call_owned(
+        &self,
+        mut args: Vec<OwnedPluginValue>,
+    ) -> Result<OwnedPluginValue, PluginError> {
+        if !self.has(OptionalSymbol::EntrypointOwned) {
+            return self.call(&args);
+        }
+        self.check_args(&args)?;
+
+        let span = CallSpan::start(&self.path, &self.metadata, &args);
+        let result = span.in_scope(|| {
+            let mut storage = ArgStorage::default();
+            let call_args: Vec<PluginValue> = args.iter().map(|arg| storage.borrow(arg)).collect();
+            let container = &self.library.container;
+            self.call_raw(&args, || unsafe {
+                container
+                    .plugin_entrypoint_owned(call_args.as_ptr(), call_args.len())
+                    .expect("checked that the plugin exports plugin_entrypoint_owned")
+            })
+        });
+        let value = match result {
+            // the plugin no longer borrows the arguments, so the one it named can be moved out
+            Ok(PluginResult::Arg(index)) if index < args.len() => Ok(args.swap_remove(index)),
+            Ok(PluginResult::Arg(index)) => Err(PluginError::Plugin(format!(
+                "Plugin handed back arg{}, but was only passed {} arguments",
+                index,
+                args.len()
+            ))),
+            Ok(result) => self.take_result(result).map(|(value, _)| value),
+            Err(e) => Err(e),
+        };
+        span.finish(value.as_ref().map(|_| false));
+        value
+    }
+
+    // Like `call_partial`, but gives up on the call if it doesn't complete within `timeout`, failing
+    // with a `Cancelled` error that includes the last progress the call reported. The call runs on
+    // its own thread, which owns the arguments; when it times out, it's cancelled so that a plugin
+    // that supports cancellation stops early. Only this call is cancelled: the plugin's
+    // cancellation token is left alone, so its other calls, including later ones, are unaffected. A
+    // plugin that doesn't support cancellation keeps running in the background until it completes,
+    // and its result is then freed.
+    pub fn call_with_timeout(
+        self: Arc<Self>,
+        args: Vec<OwnedPluginValue>,
+        timeout: Duration,
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        let deadline = Instant::now()
+            .checked_add(timeout)
+            .ok_or_else(|| PluginError::Usage(format!("Timeout {:?} is too long", timeout)))?;
+        self.call_until(args, deadline, &format!("timed out after {:?}", timeout))
+    }
+
+    // Like `call_with_timeout`, but gives up on the call once `deadline` passes, e.g. to honor the
+    // deadline of a request the call is part of. The plugin can ask how much time it has left
+    // through `HostServices::time_remaining`. If the deadline has already passed, the plugin isn't
+    // called at all.
+    pub fn call_with_deadline(
+        self: Arc<Self>,
+        args: Vec<OwnedPluginValue>,
+        deadline: Instant,
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        self.call_until(args, deadline, "missed its deadline")
+    }
+
+    // Makes a call that gives up at `deadline`, describing the failure as `Call <missed>`
+    fn call_until(
+        self: Arc<Self>,
+        args: Vec<OwnedPluginValue>,
+        deadline: Instant,
+        missed: &str,
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        let now = Instant::now();
+        if deadline <= now {
+            return Err(PluginError::Cancelled(format!(
+                "Call {} (it wasn't started)",
+                missed
+            )));
+        }
+        let limits = CallLimits {
+            deadline,
+            cancelled: CancellationToken::default(),
+            progress: Progress::default(),
+        };
+        let (cancelled, progress) = (limits.cancelled.clone(), limits.progress.clone());
+        let (sender, receiver) = mpsc::channel();
+        let plugin = self.clone();
+        std::thread::spawn(move || {
+            // the limits are dropped with the thread once the call returns
+            CALL_LIMITS.with(|call_limits| *call_limits.borrow_mut() = Some(limits));
+            // the receiver is gone if the call timed out, in which case the result is dropped
+            let _ = sender.send(plugin.call_partial(&args));
+        });
+
+        let missed_deadline = || {
+            let progress = match progress.get() {
+                Some(fraction) => format!("{:.0}% complete", fraction * 100.0),
+                None => "no progress reported".to_string(),
+            };
+            PluginError::Cancelled(format!("Call {} ({})", missed, progress))
+        };
+        match receiver.recv_timeout(deadline - now) {
+            // a plugin that watches `time_remaining` may give up just before the host does
+            Ok(Err(PluginError::Cancelled(_))) if Instant::now() >= deadline => {
+                Err(missed_deadline())
+            }
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                cancelled.cancel();
+                Err(missed_deadline())
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(PluginError::Panicked(
+                "Plugin call failed to complete".to_string(),
+            )),
+        }
+    }
+
+    // Like `call_partial`, but passes each value the plugin emits before returning (through
+    // `HostServices::emit`) to `on_item`, in the order they're emitted. Emitted values are checked
+    // and converted like results, so an invalid one is passed as an error without ending the call.
+    pub fn call_streaming(
+        &self,
+        args: &[OwnedPluginValue],
+        mut on_item: impl FnMut(Result<OwnedPluginValue, PluginError>) + 'static,
+    ) -> Result<(OwnedPluginValue, Option<String>), PluginError> {
+        let library = self.library.clone();
+        let limits = self.limits;
+        // each emitted value is limited separately, as if it were a result
+        let sink = move |raw: RawPluginValue| {
+            let mut remaining = limits.max_result_bytes;
+            let item = raw
+                .validate()
+                .map_err(|e| PluginError::Plugin(format!("Plugin emitted an invalid value: {}", e)))
+                .and_then(|value| value.into_owned(&library, &limits, &mut remaining));
+            on_item(item);
+        };
+
+        let previous = EMIT_SINK.with(|emit_sink| emit_sink.replace(Some(Box::new(sink))));
+        let _guard = EmitSinkGuard(previous);
+        self.call_partial(args)
+    }
+
+    // Calls the plugin on tokio's blocking thread pool, so that a long-running plugin doesn't block
+    // the async runtime. The arguments are moved into the blocking task, which owns them until the
+    // call completes.
+    #[cfg(feature = "tokio")]
+    pub async fn call_async(
+        self: std::sync::Arc<Self>,
+        args: Vec<OwnedPluginValue>,
+    ) -> Result<OwnedPluginValue, PluginError> {
+        tokio::task::spawn_blocking(move || self.call(&args))
+            .await
+            .map_err(|e| PluginError::Panicked(format!("Plugin call failed to complete: {}", e)))?
+    }
+
+    // Calls the plugin on tokio's blocking thread pool, returning a stream of the values it emits
+    // as they arrive (see `call_streaming`). The stream ends once the call returns: a result other
+    // than Null is yielded as its last item, as is the error if the call fails. At most
+    // `STREAM_BUFFER` items are buffered; once the buffer is full, the plugin blocks in `emit` until
+    // the consumer catches up. If the stream is dropped early, the call still runs to completion,
+    // freeing the values it emits. Must be called from within a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn call_stream(self: Arc<Self>, args: Vec<OwnedPluginValue>) -> PluginStream {
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        tokio::task::spawn_blocking(move || {
+            let items = sender.clone();
+            let result = self.call_streaming(&args, move |item| {
+                // fails only if the stream was dropped, in which case the item is freed
+                let _ = items.blocking_send(item);
+            });
+            let last = match result {
+                Ok((OwnedPluginValue::Null, _)) => None,
+                Ok((value, _)) => Some(Ok(value)),
+                Err(e) => Some(Err(e)),
+            };
+            if let Some(last) = last {
+                let _ = sender.blocking_send(last);
+            }
+            // the senders are dropped as the task completes, which ends the stream
+        });
+        PluginStream { receiver }
+    }
+
+    // Coerces each argument to the type the plugin declares for it. Arguments beyond those it
+    // declares are left for `check_args` to reject.
+    fn coerce_args(
+        &self,
+        args: Vec<OwnedPluginValue>,
+    ) -> Result<Vec<OwnedPluginValue>, PluginError> {
+        let arg_types = &self.metadata.arg_types;
+        args.into_iter()
+            .enumerate()
+            .map(|(i, arg)| match arg_types.get(i) {
+                Some(_) if omits(&self.metadata, i, &arg) => Ok(arg),
+                Some(arg_type) => arg.coerce(*arg_type).map_err(|e| match e {
+                    PluginError::InvalidArg(e) => {
+                        PluginError::InvalidArg(e.at(i, self.metadata.is_secret(i)))
+                    }
+                    e => PluginError::Argument(format!("arg{}: {}", i, e)),
+                }),
+                None => Ok(arg),
+            })
+            .collect()
+    }
+
+    // Verifies that the arguments match the plugin's declared argument types
+    fn check_args(&self, args: &[OwnedPluginValue]) -> Result<(), PluginError> {
+        self.check_args_of(&self.metadata, args)
+    }
+
+    // Like `check_args`, for the function described by `metadata`
+    fn check_args_of(
+        &self,
+        metadata: &OwnedMetadata,
+        args: &[OwnedPluginValue],
+    ) -> Result<(), PluginError> {
+        metadata.check_arg_count(args.len())?;
+
+        for (i, (arg, arg_type)) in args.iter().zip(&metadata.arg_types).enumerate() {
+            if omits(metadata, i, arg) {
+                continue;
+            }
+            if arg.plugin_type() != *arg_type {
+                return Err(PluginError::Argument(format!(
+                    "arg{} has type {}, but the plugin expects {}",
+                    i,
+                    arg.plugin_type(),
+                    arg_type
+                )));
+            }
+            // a handle's token only means something to the plugin that returned it
+            if let OwnedPluginValue::Handle(handle) = arg {
+                if !Arc::ptr_eq(&handle.library, &self.library) {
+                    return Err(PluginError::Argument(format!(
+                        "arg{} is a handle returned by a different plugin",
+                        i
+                    )));
+                }
+            }
+            self.metadata.check_arg_value(i, arg)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+// Raw views of the FFI enums that carry data, used to validate what the plugin returns before we
+// interpret it. Reading a `PluginResult` or `PluginValue` with a corrupt discriminant (e.g., from a
+// plugin built against different definitions) would be undefined behavior, so results are read
+// through these types and converted by the functions here, which are the only place the host
+// interprets raw results.
+//
+// A `repr(C)` enum with fields has the layout of a `repr(C)` struct holding its discriminant
+// followed by a union of its variants' fields, which is what these mirror.
+use crate::ffi::{PluginErrorCode, PluginResult, PluginValue};
+use crate::PluginError;
+use std::ffi::{c_char, c_int};
+use std::mem::{align_of, size_of};
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub(crate) struct RawPluginValue {
+    tag: c_int,
+    fields: RawPluginValueFields,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+union RawPluginValueFields {
+    // read as an integer, as a bool with any value other than 0 or 1 is invalid
+    bool: u8,
+    int: i64,
+    uint: u64,
+    double: f64,
+    string: *const c_char,
+    handle: u64,
+    fd: i64,
+    list: RawList,
+    bytes: RawBytes,
+    custom: RawCustom,
+    result: *const PluginValue,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawList {
+    items: *const PluginValue,
+    len: usize,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawBytes {
+    ptr: *const u8,
+    len: usize,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawCustom {
+    type_id: u32,
+    ptr: *const u8,
+    len: usize,
+}
+
+#[repr(C)]
+pub(crate) struct RawPluginResult {
+    tag: c_int,
+    fields: RawPluginResultFields,
+}
+
+#[repr(C)]
+union RawPluginResultFields {
+    ok: RawPluginValue,
+    partial_ok: RawPartialOk,
+    err: RawErr,
+    arg: usize,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawPartialOk {
+    value: RawPluginValue,
+    warning: *mut c_char,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawErr {
+    code: c_int,
+    message: *mut c_char,
+    payload: *mut c_char,
+}
+
+// the raw views must have exactly the layout of the types they stand in for
+const _: () = assert!(size_of::<RawPluginValue>() == size_of::<PluginValue>());
+const _: () = assert!(size_of::<RawPluginResult>() == size_of::<PluginResult>());
+const _: () = assert!(align_of::<RawPluginValue>() == align_of::<PluginValue>());
+
+impl RawPluginValue {
+    // Validates the value's discriminant (and, for Bool, its payload), returning a description of
+    // the problem if it's invalid
+    pub(crate) fn validate(self) -> Result<PluginValue, String> {
+        let fields = self.fields;
+        // Safety: each arm only reads the field of the variant named by the tag, which the plugin
+        // must have initialized for it
+        Ok(unsafe {
+            match self.tag {
+                0 => match fields.bool {
+                    0 => PluginValue::Bool(false),
+                    1 => PluginValue::Bool(true),
+                    b => return Err(format!("invalid Bool value {}", b)),
+                },
+                1 => PluginValue::Int(fields.int),
+                2 => PluginValue::UInt(fields.uint),
+                3 => PluginValue::Double(fields.double),
+                4 => PluginValue::String(fields.string),
+                5 => PluginValue::Json(fields.string),
+                6 => PluginValue::Handle(fields.handle),
+                7 => PluginValue::List {
+                    items: fields.list.items,
+                    len: fields.list.len,
+                },
+                8 => PluginValue::Bytes {
+                    ptr: fields.bytes.ptr,
+                    len: fields.bytes.len,
+                },
+                9 => PluginValue::Custom {
+                    type_id: fields.custom.type_id,
+                    ptr: fields.custom.ptr,
+                    len: fields.custom.len,
+                },
+                10 => PluginValue::Null,
+                11 => PluginValue::Fd(fields.fd),
+                12 => PluginValue::ResultOk(fields.result),
+                13 => PluginValue::ResultErr(fields.result),
+                tag => return Err(format!("unknown value discriminant {}", tag)),
+            }
+        })
+    }
+}
+
+impl RawPluginResult {
+    // Interprets the result returned by the plugin. If any discriminant is invalid, we can't know
+    // which of the pointers it contains are real, so the call fails without touching (or freeing)
+    // any of them.
+    pub(crate) fn interpret(self) -> Result<PluginResult, PluginError> {
+        let invalid =
+            |e: String| PluginError::Plugin(format!("Plugin returned an invalid result: {}", e));
+        // Safety: as in `RawPluginValue::validate`
+        Ok(unsafe {
+            match self.tag {
+                0 => PluginResult::Ok(self.fields.ok.validate().map_err(invalid)?),
+                1 => PluginResult::PartialOk {
+                    value: self.fields.partial_ok.value.validate().map_err(invalid)?,
+                    warning: self.fields.partial_ok.warning,
+                },
+                2 => PluginResult::Err {
+                    code: match self.fields.err.code {
+                        0 => PluginErrorCode::Failed,
+                        1 => PluginErrorCode::InvalidArgument,
+                        2 => PluginErrorCode::Panicked,
+                        3 => PluginErrorCode::Cancelled,
+                        code => return Err(invalid(format!("unknown error code {}", code))),
+                    },
+                    message: self.fields.err.message,
+                    payload: self.fields.err.payload,
+                },
+                3 => PluginResult::Arg(self.fields.arg),
+                tag => return Err(invalid(format!("unknown result discriminant {}", tag))),
+            }
+        })
+    }
+}
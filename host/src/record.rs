@@ -0,0 +1,238 @@
+// Recording and replaying plugin calls, for catching changes in a plugin's behavior. Each call is
+// written as a line of JSON containing the plugin name, the arguments and the result, e.g.:
+//
+// {"args":[{"String":"cool"},{"UInt":3}],"function":"repeat","result":{"ok":{"String":"coolcoolcool"}},"version":2}
+//
+// Replaying a recording repeats each call against a (possibly updated) plugin and reports any
+// results that differ.
+//
+// Each line records the version of the format it was written in, so that recordings made by older
+// hosts can still be replayed: they're upgraded to the current format by `migrate` as they're read.
+// Lines without a version were written before versions were recorded, and are version 1.
+use crate::{OwnedPluginValue, Plugin, PluginError};
+use serde_json::{json, Map, Number, Value};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// The version of the format written by `Recorder`, which should be bumped (with a migration from the
+// previous version added to `migrate`) whenever the format changes
+const RECORDING_VERSION: u64 = 2;
+
+// Upgrades a recorded call to the current version of the format, one version at a time
+fn migrate(mut record: Value) -> Result<Value, String> {
+    let mut version = match &record["version"] {
+        Value::Null => 1,
+        version => version
+            .as_u64()
+            .ok_or_else(|| format!("invalid version {}", version))?,
+    };
+    if version > RECORDING_VERSION {
+        return Err(format!(
+            "recorded in version {} of the format, but this host only supports up to version {}",
+            version, RECORDING_VERSION
+        ));
+    }
+
+    while version < RECORDING_VERSION {
+        match version {
+            // version 2 only added the version itself
+            1 => {}
+            _ => unreachable!("no migration from version {}", version),
+        }
+        version += 1;
+    }
+    record["version"] = json!(version);
+    Ok(record)
+}
+
+// Appends recorded calls to a file
+pub struct Recorder {
+    file: BufWriter<File>,
+}
+
+impl Recorder {
+    // Opens the file for recording, appending to any calls that were already recorded in it
+    pub fn open(path: impl AsRef<Path>) -> Result<Recorder, PluginError> {
+        let path = path.as_ref();
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| PluginError::Usage(format!("Could not open {}: {}", path.display(), e)))?;
+        Ok(Recorder {
+            file: BufWriter::new(file),
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        function: &str,
+        args: &[OwnedPluginValue],
+        result: Result<&OwnedPluginValue, &PluginError>,
+    ) -> Result<(), PluginError> {
+        let args = args.iter().map(to_json).collect::<Result<Vec<_>, _>>()?;
+        let line = json!({
+            "function": function,
+            "args": args,
+            "result": result_to_json(result)?,
+            "version": RECORDING_VERSION,
+        });
+        writeln!(self.file, "{}", line)
+            .and_then(|_| self.file.flush())
+            .map_err(|e| PluginError::Usage(format!("Could not write recording: {}", e)))
+    }
+}
+
+// Replays the calls recorded in the file against the plugin, returning a description of each call
+// whose result differs from the recorded one
+pub fn replay(plugin: &Plugin, path: impl AsRef<Path>) -> Result<Vec<String>, PluginError> {
+    let path = path.as_ref();
+    let file = File::open(path)
+        .map_err(|e| PluginError::Usage(format!("Could not open {}: {}", path.display(), e)))?;
+
+    let mut mismatches = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let invalid = |e: String| {
+            PluginError::Usage(format!(
+                "{}:{}: invalid recording: {}",
+                path.display(),
+                i + 1,
+                e
+            ))
+        };
+        let line = line.map_err(|e| invalid(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(&line).map_err(|e| invalid(e.to_string()))?;
+        let record = migrate(record).map_err(invalid)?;
+
+        let function = record["function"].as_str().unwrap_or_default();
+        if function != plugin.metadata().name {
+            mismatches.push(format!(
+                "line {}: recorded a call to {}, but the plugin is {}",
+                i + 1,
+                function,
+                plugin.metadata().name
+            ));
+            continue;
+        }
+
+        let args = record["args"]
+            .as_array()
+            .ok_or_else(|| invalid("missing args".to_string()))?
+            .iter()
+            .map(from_json)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(e.to_string()))?;
+
+        let result = result_to_json(plugin.call(&args).as_ref())?;
+        if result != record["result"] {
+            mismatches.push(format!(
+                "line {}: expected {}, got {}",
+                i + 1,
+                record["result"],
+                result
+            ));
+        }
+    }
+    Ok(mismatches)
+}
+
+fn result_to_json(result: Result<&OwnedPluginValue, &PluginError>) -> Result<Value, PluginError> {
+    Ok(match result {
+        Ok(value) => json!({ "ok": to_json(value)? }),
+        Err(e) => json!({ "error": { "code": e.exit_code(), "message": e.to_string() } }),
+    })
+}
+
+// Encodes a value as a JSON object tagged with its type, e.g. `{"UInt": 3}`. This is also how
+// values are sent to and from isolated plugins.
+pub(crate) fn to_json(value: &OwnedPluginValue) -> Result<Value, PluginError> {
+    let unsupported = |what: &str| PluginError::Usage(format!("{} can't be encoded as JSON", what));
+    let (tag, value) = match value {
+        OwnedPluginValue::Bool(b) => ("Bool", json!(b)),
+        OwnedPluginValue::Int(i) => ("Int", json!(i)),
+        OwnedPluginValue::UInt(u) => ("UInt", json!(u)),
+        OwnedPluginValue::Double(d) => (
+            "Double",
+            Value::Number(Number::from_f64(*d).ok_or_else(|| unsupported("Non-finite doubles"))?),
+        ),
+        OwnedPluginValue::String(s) => (
+            "String",
+            json!(s
+                .to_str()
+                .map_err(|_| unsupported("Strings that aren't valid UTF-8"))?),
+        ),
+        OwnedPluginValue::Handle(_) => return Err(unsupported("Handles")),
+        OwnedPluginValue::Fd(_) => return Err(unsupported("File descriptors")),
+        OwnedPluginValue::Bytes(bytes) => ("Bytes", json!(&bytes[..])),
+        OwnedPluginValue::Custom { type_id, payload } => (
+            "Custom",
+            json!({ "type_id": type_id, "payload": &payload[..] }),
+        ),
+        OwnedPluginValue::List(items) => (
+            "List",
+            Value::Array(items.iter().map(to_json).collect::<Result<_, _>>()?),
+        ),
+        OwnedPluginValue::Json(json) => ("Json", json.clone()),
+        OwnedPluginValue::Null => ("Null", Value::Null),
+        OwnedPluginValue::Result(Ok(value)) => ("Ok", to_json(value)?),
+        OwnedPluginValue::Result(Err(value)) => ("Err", to_json(value)?),
+    };
+    let mut object = Map::new();
+    object.insert(tag.to_string(), value);
+    Ok(Value::Object(object))
+}
+
+impl OwnedPluginValue {
+    // Encodes the value as JSON tagged with its type, as in recordings (e.g. `{"UInt": 3}`)
+    pub fn to_json(&self) -> Result<Value, PluginError> {
+        to_json(self)
+    }
+}
+
+pub(crate) fn from_json(value: &Value) -> Result<OwnedPluginValue, PluginError> {
+    let invalid = || PluginError::Usage(format!("Invalid encoded value: {}", value));
+    let Some((tag, value)) = value.as_object().and_then(|o| o.iter().next()) else {
+        return Err(invalid());
+    };
+    Ok(match tag.as_str() {
+        "Bool" => OwnedPluginValue::Bool(value.as_bool().ok_or_else(invalid)?),
+        "Int" => OwnedPluginValue::Int(value.as_i64().ok_or_else(invalid)?),
+        "UInt" => OwnedPluginValue::UInt(value.as_u64().ok_or_else(invalid)?),
+        "Double" => OwnedPluginValue::Double(value.as_f64().ok_or_else(invalid)?),
+        "String" => OwnedPluginValue::String(
+            CString::new(value.as_str().ok_or_else(invalid)?).map_err(|_| invalid())?,
+        ),
+        "Bytes" => OwnedPluginValue::Bytes(
+            serde_json::from_value::<Vec<u8>>(value.clone())
+                .map_err(|_| invalid())?
+                .into(),
+        ),
+        "Custom" => OwnedPluginValue::Custom {
+            type_id: value["type_id"]
+                .as_u64()
+                .and_then(|id| u32::try_from(id).ok())
+                .ok_or_else(invalid)?,
+            payload: serde_json::from_value::<Vec<u8>>(value["payload"].clone())
+                .map_err(|_| invalid())?
+                .into(),
+        },
+        "List" => OwnedPluginValue::List(
+            value
+                .as_array()
+                .ok_or_else(invalid)?
+                .iter()
+                .map(from_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        "Json" => OwnedPluginValue::Json(value.clone()),
+        "Null" => OwnedPluginValue::Null,
+        "Ok" => OwnedPluginValue::Result(Ok(Box::new(from_json(value)?))),
+        "Err" => OwnedPluginValue::Result(Err(Box::new(from_json(value)?))),
+        _ => return Err(invalid()),
+    })
+}
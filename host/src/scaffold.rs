@@ -0,0 +1,52 @@
+// Generates a new plugin crate from the template in `templates/`, which follows the same
+// conventions as the example plugins
+use host::PluginError;
+use std::fs;
+use std::path::Path;
+
+const CARGO_TOML: &str = include_str!("../templates/Cargo.toml.tmpl");
+const LIB_RS: &str = include_str!("../templates/lib.rs.tmpl");
+// The FFI types, which are shared with mock plugins (see `host::MockPlugin`)
+const FFI_TYPES: &str = include_str!("../templates/ffi.rs.tmpl");
+
+// Writes a plugin crate named `name` to `dir`, which must not already contain a crate
+pub fn new_plugin(name: &str, dir: &Path) -> Result<(), PluginError> {
+    // the name is used for the crate, the exported function and a Rust identifier, so it's
+    // limited to what's valid for all of them
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        return Err(PluginError::Usage(format!(
+            "Invalid plugin name {}; names must start with a lowercase letter, and contain only \
+             lowercase letters, digits and underscores",
+            name
+        )));
+    }
+
+    let files = [("Cargo.toml", CARGO_TOML), ("src/lib.rs", LIB_RS)];
+    for (path, _) in &files {
+        if dir.join(path).exists() {
+            return Err(PluginError::Usage(format!(
+                "{} already exists",
+                dir.join(path).display()
+            )));
+        }
+    }
+
+    for (path, template) in &files {
+        let path = dir.join(path);
+        let write = || {
+            fs::create_dir_all(path.parent().unwrap())?;
+            let contents = template
+                .replace("__FFI_TYPES__", FFI_TYPES)
+                .replace("__NAME__", name);
+            fs::write(&path, contents)
+        };
+        write().map_err(|e| {
+            PluginError::Usage(format!("Could not write {}: {}", path.display(), e))
+        })?;
+    }
+    Ok(())
+}
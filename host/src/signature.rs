@@ -0,0 +1,81 @@
+// Verification of detached plugin signatures, so that a host can refuse to load plugins that
+// weren't signed by a key it trusts. A plugin's signature is stored next to it, in a file named
+// after the library with `.sig` appended (e.g. `libplugin.so.sig`), and holds the raw 64-byte
+// ed25519 signature of the library's contents. Keys are read as PEM-encoded public keys, so both
+// can be produced with openssl:
+//
+// openssl genpkey -algorithm ed25519 -out key.pem
+// openssl pkey -in key.pem -pubout -out key.pub.pem
+// openssl pkeyutl -sign -rawin -inkey key.pem -in libplugin.so -out libplugin.so.sig
+//
+// The library is verified by reading it before it's loaded, so the directory it's loaded from must
+// not be writable by anyone who could replace it in between.
+use crate::PluginError;
+use ed25519_dalek::pkcs8::DecodePublicKey;
+use ed25519_dalek::{Signature, VerifyingKey};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+// A public key whose signatures the host trusts
+#[derive(Clone)]
+pub struct TrustedKey(VerifyingKey);
+
+impl TrustedKey {
+    // Reads a PEM-encoded ed25519 public key from a file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<TrustedKey, PluginError> {
+        let path = path.as_ref();
+        let pem = std::fs::read_to_string(path)
+            .map_err(|e| PluginError::Usage(format!("Could not read {}: {}", path.display(), e)))?;
+        VerifyingKey::from_public_key_pem(&pem)
+            .map(TrustedKey)
+            .map_err(|e| {
+                PluginError::Usage(format!(
+                    "{} isn't an ed25519 public key: {}",
+                    path.display(),
+                    e
+                ))
+            })
+    }
+
+    // Checks that the library at `path` is signed by this key, failing if its signature file is
+    // missing or the signature doesn't match its contents
+    pub fn verify(&self, path: impl AsRef<Path>) -> Result<(), PluginError> {
+        let path = path.as_ref();
+        let sig_path = signature_path(path);
+        let refuse = |reason: String| {
+            PluginError::Load(format!(
+                "Refusing to load plugin {}: {}",
+                path.display(),
+                reason
+            ))
+        };
+
+        let signature = std::fs::read(&sig_path).map_err(|e| {
+            refuse(format!(
+                "could not read its signature {}: {}",
+                sig_path.display(),
+                e
+            ))
+        })?;
+        let signature = Signature::from_slice(&signature).map_err(|_| {
+            refuse(format!(
+                "{} isn't an ed25519 signature (expected {} bytes, got {})",
+                sig_path.display(),
+                Signature::BYTE_SIZE,
+                signature.len()
+            ))
+        })?;
+        let library = std::fs::read(path).map_err(|e| refuse(e.to_string()))?;
+
+        self.0
+            .verify_strict(&library, &signature)
+            .map_err(|_| refuse("it isn't signed by the trusted key".to_string()))
+    }
+}
+
+// The path of the signature of the library at `path`
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut sig_path = OsString::from(path.as_os_str());
+    sig_path.push(".sig");
+    PathBuf::from(sig_path)
+}
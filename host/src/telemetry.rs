@@ -0,0 +1,73 @@
+// Spans around plugin calls, for hosts that collect traces with the `tracing` crate (enabled by the
+// `tracing` feature). Each call is wrapped in an INFO-level `plugin_call` span recording the plugin
+// library, its function, the types of the arguments and, once the call returns, how long it took and
+// its outcome (`ok`, `partial` or `error`, with the error's message). Argument values aren't
+// recorded, as they may be secret. Without the feature, the spans compile to nothing.
+use crate::{OwnedMetadata, OwnedPluginValue, PluginError};
+use std::ffi::OsStr;
+
+pub(crate) struct CallSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    start: std::time::Instant,
+}
+
+impl CallSpan {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn start(path: &OsStr, metadata: &OwnedMetadata, args: &[OwnedPluginValue]) -> Self {
+        let arg_types: Vec<_> = args
+            .iter()
+            .map(|arg| arg.plugin_type().to_string())
+            .collect();
+        CallSpan {
+            span: tracing::info_span!(
+                "plugin_call",
+                plugin = %path.to_string_lossy(),
+                function = %metadata.name,
+                arg_types = %arg_types.join(", "),
+                duration_us = tracing::field::Empty,
+                outcome = tracing::field::Empty,
+                error = tracing::field::Empty,
+            ),
+            start: std::time::Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn start(
+        _path: &OsStr,
+        _metadata: &OwnedMetadata,
+        _args: &[OwnedPluginValue],
+    ) -> Self {
+        CallSpan {}
+    }
+
+    // Runs the call within the span, so that anything it logs is attributed to it
+    pub(crate) fn in_scope<R>(&self, call: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(call);
+        #[cfg(not(feature = "tracing"))]
+        call()
+    }
+
+    // Records how the call ended: whether its result was partial, or the error it failed with
+    pub(crate) fn finish(self, result: Result<bool, &PluginError>) {
+        #[cfg(feature = "tracing")]
+        {
+            let duration = u64::try_from(self.start.elapsed().as_micros()).unwrap_or(u64::MAX);
+            self.span.record("duration_us", duration);
+            let outcome = match result {
+                Ok(false) => "ok",
+                Ok(true) => "partial",
+                Err(e) => {
+                    self.span.record("error", tracing::field::display(e));
+                    "error"
+                }
+            };
+            self.span.record("outcome", outcome);
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = result;
+    }
+}
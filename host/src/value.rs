@@ -0,0 +1,1093 @@
+use crate::ffi::{PluginType, PluginValue};
+use crate::plugin::{Library, PluginHandle};
+use crate::raw::RawPluginValue;
+use crate::{ArgError, ArgErrorKind, Bytes, CustomTypes, PluginError};
+use std::ffi::{c_char, CString, OsStr};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::num::{IntErrorKind, ParseIntError};
+use std::path::Path;
+use std::str::{FromStr, Utf8Error};
+use std::sync::Arc;
+
+// The default limit on the length of strings returned by plugins
+pub const DEFAULT_MAX_STRING_LEN: usize = 64 * 1024 * 1024;
+
+// The default limit on the number of items in lists returned by plugins
+pub const DEFAULT_MAX_LIST_LEN: usize = 1024 * 1024;
+
+// The default limit on the total size of a result returned by plugins (see `ResultLimits`)
+pub const DEFAULT_MAX_RESULT_BYTES: usize = 1024 * 1024 * 1024;
+
+// The default number of list items shown before the rest are elided
+pub const DEFAULT_MAX_ITEMS: usize = 10;
+
+// Returns the length of the null-terminated string at `ptr`, or None if no terminator is found
+// within the first `max` bytes. Unlike `strlen`, this never reads more than `max + 1` bytes.
+//
+// Safety: `ptr` must be valid for reads up to its terminator, or `max + 1` bytes, whichever is
+// shorter
+pub(crate) unsafe fn bounded_strlen(ptr: *const c_char, max: usize) -> Option<usize> {
+    (0..=max).find(|&i| unsafe { *ptr.add(i) } == 0)
+}
+
+// The descriptor of a file as passed to the plugin: a file descriptor on unix, or a HANDLE on
+// Windows
+#[cfg(unix)]
+pub(crate) fn raw_fd(file: &File) -> i64 {
+    use std::os::unix::io::AsRawFd;
+    file.as_raw_fd() as i64
+}
+
+#[cfg(windows)]
+pub(crate) fn raw_fd(file: &File) -> i64 {
+    use std::os::windows::io::AsRawHandle;
+    file.as_raw_handle() as i64
+}
+
+// The limits on what a plugin may return. Besides the limits on individual strings and lists,
+// `max_result_bytes` bounds the total size of a result, estimated as it's read in the same way as
+// `OwnedPluginValue::heap_bytes`: the lengths of its strings (with their terminators) and buffers,
+// plus the space taken by the items of its lists. Reading stops as soon as the limit is reached, so
+// an oversized result is rejected without being materialized in full.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ResultLimits {
+    pub max_string_len: usize,
+    pub max_list_len: usize,
+    pub max_result_bytes: usize,
+}
+
+impl Default for ResultLimits {
+    fn default() -> Self {
+        ResultLimits {
+            max_string_len: DEFAULT_MAX_STRING_LEN,
+            max_list_len: DEFAULT_MAX_LIST_LEN,
+            max_result_bytes: DEFAULT_MAX_RESULT_BYTES,
+        }
+    }
+}
+
+impl ResultLimits {
+    // Deducts `bytes` from what remains of the result's budget, failing if it's exceeded (in which
+    // case nothing remains, so that any further reads fail early too)
+    fn charge(&self, remaining: &mut usize, bytes: usize) -> Result<(), PluginError> {
+        match remaining.checked_sub(bytes) {
+            Some(rest) => {
+                *remaining = rest;
+                Ok(())
+            }
+            None => {
+                *remaining = 0;
+                Err(self.too_large())
+            }
+        }
+    }
+
+    fn too_large(&self) -> PluginError {
+        PluginError::Plugin(format!(
+            "Plugin returned a result larger than the limit of {} bytes",
+            self.max_result_bytes
+        ))
+    }
+}
+
+// Takes ownership of a string returned by the plugin, checking its length (against both the limit
+// on strings and what remains of the result's budget) before materializing it. A string that isn't
+// terminated within `max_string_len` bytes is leaked rather than freed: the size of its allocation
+// can only be found by reading on until its terminator, which may be missing altogether.
+fn owned_string(
+    s: *const c_char,
+    limits: &ResultLimits,
+    remaining: &mut usize,
+) -> Result<CString, PluginError> {
+    if s.is_null() {
+        return Err(PluginError::Plugin(
+            "Plugin returned a null string".to_string(),
+        ));
+    }
+    let Some(len) = (unsafe { bounded_strlen(s, limits.max_string_len) }) else {
+        return Err(PluginError::Plugin(format!(
+            "Plugin returned a string longer than the limit of {} bytes",
+            limits.max_string_len
+        )));
+    };
+    // taken before it's charged for, so that it's freed if it doesn't fit in the result
+    let s = unsafe { take_string(s, len) };
+    // the terminator counts towards the result's size
+    limits.charge(remaining, len + 1)?;
+    Ok(s)
+}
+
+// Takes ownership of a string allocated as a `CString`, given the length of its contents (as found
+// by `bounded_strlen`). Unlike `CString::from_raw`, this doesn't scan the string for its terminator
+// again, so that the only read of it is the bounded one.
+//
+// Safety: `ptr` must have been returned by `CString::into_raw`, and `len` must be the position of
+// its terminator
+unsafe fn take_string(ptr: *const c_char, len: usize) -> CString {
+    let bytes =
+        unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr as *mut u8, len + 1)) };
+    unsafe { CString::from_vec_with_nul_unchecked(bytes.into_vec()) }
+}
+
+// Parses an integer argument of `arg_type`, whose range is `min..=max`. Integers are written in
+// decimal, optionally with a leading `+` or `-` and leading zeros, and surrounding whitespace is
+// ignored. Underscores (as in Rust literals) aren't accepted, so that `1_000` isn't silently read
+// as a different number by tools that don't support them.
+fn parse_integer<T>(arg_type: PluginType, s: &str, min: T, max: T) -> Result<T, PluginError>
+where
+    T: FromStr<Err = ParseIntError> + Display,
+{
+    let invalid = |kind: ArgErrorKind, reason: String| {
+        ArgError::invalid(
+            kind,
+            arg_type,
+            s,
+            format!("Invalid {} {:?}: {}", arg_type, s, reason),
+        )
+    };
+    let trimmed = s.trim();
+    if trimmed.contains('_') {
+        return Err(invalid(
+            ArgErrorKind::Malformed,
+            "underscores aren't allowed".to_string(),
+        ));
+    }
+    trimmed.parse().map_err(|e: ParseIntError| match e.kind() {
+        IntErrorKind::Empty => invalid(ArgErrorKind::Malformed, "expected a number".to_string()),
+        IntErrorKind::PosOverflow => {
+            invalid(ArgErrorKind::OutOfRange, format!("must be at most {}", max))
+        }
+        IntErrorKind::NegOverflow => invalid(
+            ArgErrorKind::OutOfRange,
+            format!("must be at least {}", min),
+        ),
+        // unsigned types reject a minus sign as an invalid digit
+        _ if trimmed.len() > 1
+            && trimmed.starts_with('-')
+            && trimmed[1..].bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            invalid(
+                ArgErrorKind::OutOfRange,
+                format!("must be at least {}", min),
+            )
+        }
+        _ => invalid(
+            ArgErrorKind::Malformed,
+            "expected a decimal integer".to_string(),
+        ),
+    })
+}
+
+impl PluginValue {
+    // Takes ownership of a value returned by the plugin, failing if it can't be represented on the
+    // host (in which case any memory it held is still freed). Strings longer than
+    // `max_string_len` bytes are rejected without reading them in full, protecting the host from
+    // plugins returning enormous results. Likewise, lists longer than `max_list_len` items are
+    // rejected before any of their items are read. The value's size is deducted from `remaining`,
+    // the bytes left of the result's `max_result_bytes`, which is shared by all of a result's
+    // values.
+    pub(crate) fn into_owned(
+        self,
+        library: &Arc<Library>,
+        limits: &ResultLimits,
+        remaining: &mut usize,
+    ) -> Result<OwnedPluginValue, PluginError> {
+        Ok(match self {
+            PluginValue::Bool(b) => OwnedPluginValue::Bool(b),
+            PluginValue::Int(i) => OwnedPluginValue::Int(i),
+            PluginValue::UInt(u) => OwnedPluginValue::UInt(u),
+            PluginValue::Double(d) => OwnedPluginValue::Double(d),
+            PluginValue::String(s) => OwnedPluginValue::String(owned_string(s, limits, remaining)?),
+            PluginValue::Handle(token) => {
+                OwnedPluginValue::Handle(PluginHandle::new(token, library)?)
+            }
+            // buffers are used in place, so they can be measured before anything is read from them
+            PluginValue::Bytes { ptr, len } => {
+                let bytes = Bytes::from_plugin(ptr, len, library, limits.max_string_len)?;
+                limits.charge(remaining, len)?;
+                OwnedPluginValue::Bytes(bytes)
+            }
+            PluginValue::Custom { type_id, ptr, len } => {
+                let payload = Bytes::from_plugin(ptr, len, library, limits.max_string_len)?;
+                limits.charge(remaining, len)?;
+                OwnedPluginValue::Custom { type_id, payload }
+            }
+            PluginValue::Null => OwnedPluginValue::Null,
+            PluginValue::Fd(_) => {
+                return Err(PluginError::Plugin(
+                    "Plugin returned a file descriptor, which can only be passed to it".to_string(),
+                ));
+            }
+            PluginValue::ResultOk(value) => {
+                OwnedPluginValue::Result(Ok(owned_result(value, library, limits, remaining)?))
+            }
+            PluginValue::ResultErr(value) => {
+                OwnedPluginValue::Result(Err(owned_result(value, library, limits, remaining)?))
+            }
+            PluginValue::List { items, len } => {
+                if items.is_null() {
+                    if len != 0 {
+                        return Err(PluginError::Plugin(
+                            "Plugin returned a null list".to_string(),
+                        ));
+                    }
+                    return Ok(OwnedPluginValue::List(vec![]));
+                }
+                if len > limits.max_list_len {
+                    // a length this large is more likely corrupt than real, so the list is leaked
+                    // rather than freed (which would read every item to drop it)
+                    return Err(PluginError::Plugin(format!(
+                        "Plugin returned a list of {} items, more than the limit of {}",
+                        len, limits.max_list_len
+                    )));
+                }
+                // the items are charged for up front, so that a list too large for the result is
+                // rejected before any of them are materialized; they're still converted to free
+                // them, but with nothing left of the budget, each fails without being read in full
+                let too_large = limits
+                    .charge(remaining, len.saturating_mul(size_of::<OwnedPluginValue>()))
+                    .err();
+                // the items are read through their raw view, as each one's discriminant must be
+                // validated before it can be interpreted
+                let items = unsafe {
+                    Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                        items as *mut RawPluginValue,
+                        len,
+                    ))
+                };
+                // convert every item before checking for errors, so that all of them are freed
+                let items: Vec<_> = items
+                    .iter()
+                    .map(|item| {
+                        item.validate()
+                            .map_err(|e| {
+                                PluginError::Plugin(format!(
+                                    "Plugin returned an invalid list item: {}",
+                                    e
+                                ))
+                            })?
+                            .into_owned(library, limits, remaining)
+                    })
+                    .collect();
+                if let Some(e) = too_large {
+                    return Err(e);
+                }
+                OwnedPluginValue::List(items.into_iter().collect::<Result<_, _>>()?)
+            }
+            PluginValue::Json(s) => {
+                let json = owned_string(s, limits, remaining)?;
+                #[cfg(feature = "serde")]
+                {
+                    let json = json.to_str().map_err(|e| {
+                        PluginError::Plugin(format!("Plugin returned invalid UTF-8 JSON: {}", e))
+                    })?;
+                    OwnedPluginValue::Json(serde_json::from_str(json).map_err(|e| {
+                        PluginError::Plugin(format!("Plugin returned invalid JSON: {}", e))
+                    })?)
+                }
+                #[cfg(not(feature = "serde"))]
+                {
+                    drop(json);
+                    return Err(PluginError::Plugin(
+                        "JSON values require the host to be built with the serde feature"
+                            .to_string(),
+                    ));
+                }
+            }
+        })
+    }
+}
+
+// Takes ownership of the boxed value inside a returned Result, which is read through its raw view
+// (like a list's items) and freed once it's been converted
+fn owned_result(
+    value: *const PluginValue,
+    library: &Arc<Library>,
+    limits: &ResultLimits,
+    remaining: &mut usize,
+) -> Result<Box<OwnedPluginValue>, PluginError> {
+    if value.is_null() {
+        return Err(PluginError::Plugin(
+            "Plugin returned a Result with a null value".to_string(),
+        ));
+    }
+    let value = unsafe { Box::from_raw(value as *mut RawPluginValue) };
+    let value = value
+        .validate()
+        .map_err(|e| PluginError::Plugin(format!("Plugin returned an invalid Result: {}", e)))?
+        .into_owned(library, limits, remaining)?;
+    limits.charge(remaining, size_of::<OwnedPluginValue>())?;
+    Ok(Box::new(value))
+}
+
+// An owned version of PluginValue that owns all dynamically allocated resources,
+// such that memory will be freed when the value is dropped.
+pub enum OwnedPluginValue {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    String(CString),
+    Handle(PluginHandle),
+    List(Vec<OwnedPluginValue>),
+    Bytes(Bytes),
+    Custom {
+        type_id: u32,
+        payload: Bytes,
+    },
+    // objects keep their keys in the order the plugin wrote them (serde_json's `preserve_order`), so
+    // that they're formatted and recorded deterministically
+    #[cfg(feature = "serde")]
+    Json(serde_json::Value),
+    // No value: the plugin succeeded, but had nothing to return
+    Null,
+    // A file opened by the host, whose descriptor is lent to the plugin for the duration of a call
+    Fd(File),
+    // The outcome of an operation within a successful call, which is distinct from the call
+    // failing
+    Result(Result<Box<OwnedPluginValue>, Box<OwnedPluginValue>>),
+}
+
+// Flattens a Json value for `OwnedPluginValue::format_csv`: strings are written without quotes,
+// arrays as their items joined by commas and objects as `key=value;` pairs
+#[cfg(feature = "serde")]
+fn write_json_csv(out: &mut String, json: &serde_json::Value) {
+    match json {
+        serde_json::Value::Null => {}
+        serde_json::Value::String(s) => out.push_str(s),
+        serde_json::Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_csv(out, item);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields {
+                out.push_str(key);
+                out.push('=');
+                write_json_csv(out, value);
+                out.push(';');
+            }
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+// Controls how values are formatted by `OwnedPluginValue::format`
+#[derive(Clone, Default)]
+pub struct FormatOptions {
+    // Fail on strings that are not valid UTF-8, instead of replacing the invalid bytes
+    pub strict_utf8: bool,
+    // The number of items to show from each list, followed by a count of the remaining ones; if
+    // None, lists are shown in full
+    pub max_items: Option<usize>,
+    // Handlers used to render custom values; values without a handler are shown as raw bytes
+    pub custom_types: Option<Arc<CustomTypes>>,
+    // How Doubles are written
+    pub float_format: FloatFormat,
+}
+
+// Controls how values are parsed by `OwnedPluginValue::parse_with`
+#[derive(Clone)]
+pub struct ParseOptions {
+    // The character between the items of a List; defaults to `,`. It can't be `"`, which quotes
+    // items containing it.
+    pub list_separator: char,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            list_separator: ',',
+        }
+    }
+}
+
+// Splits a List argument into its items at `separator`, ignoring one after the last item. An item
+// that starts with `"` is quoted: it ends at the next lone `"`, which must be followed by a
+// separator or the end, and may contain separators and doubled quotes (`""`), which stand for a
+// single one. Quotes elsewhere are taken literally. Fails with a description of a malformed quote.
+fn split_list(s: &str, separator: char) -> Result<Vec<String>, &'static str> {
+    let mut items = vec![];
+    let mut chars = s.chars().peekable();
+    while chars.peek().is_some() {
+        let mut item = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => item.push('"'),
+                    Some('"') => break,
+                    Some(c) => item.push(c),
+                    None => return Err("unterminated quote"),
+                }
+            }
+            if chars.next_if(|c| *c != separator).is_some() {
+                return Err("a quoted item must be followed by a separator");
+            }
+            chars.next();
+        } else {
+            item.extend(chars.by_ref().take_while(|c| *c != separator));
+        }
+        items.push(item);
+    }
+    Ok(items)
+}
+
+// How `OwnedPluginValue::format` writes Doubles, e.g. for 0.1:
+//
+// plain       0.1            the shortest decimal that reads back as the same value (`{}`)
+// fixed:<n>   0.100 (n = 3)  exactly n digits after the decimal point, rounded (`{:.n}`)
+// scientific  1e-1           the shortest mantissa, with an exponent (`{:e}`)
+// roundtrip   0.1            like plain, but always recognizably a float (1.0, not 1), and
+//                            with an exponent for very large or small values (`{:?}`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FloatFormat {
+    #[default]
+    Plain,
+    Fixed(usize),
+    Scientific,
+    RoundTrip,
+}
+
+impl FloatFormat {
+    fn format(self, d: f64) -> String {
+        match self {
+            FloatFormat::Plain => d.to_string(),
+            FloatFormat::Fixed(precision) => format!("{:.*}", precision, d),
+            FloatFormat::Scientific => format!("{:e}", d),
+            FloatFormat::RoundTrip => format!("{:?}", d),
+        }
+    }
+}
+
+impl FromStr for FloatFormat {
+    type Err = String;
+
+    // Parses a format by name, as listed on `FloatFormat`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(FloatFormat::Plain),
+            "scientific" => Ok(FloatFormat::Scientific),
+            "roundtrip" => Ok(FloatFormat::RoundTrip),
+            _ => s
+                .strip_prefix("fixed:")
+                .and_then(|precision| precision.parse().ok())
+                .map(FloatFormat::Fixed)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown float format {}; expected plain, fixed:<n>, scientific, roundtrip",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+// Shows the value in full, lossily replacing any invalid UTF-8
+impl Display for OwnedPluginValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // formatting can only fail in strict mode
+        let s = self.format(&FormatOptions::default()).unwrap();
+        write!(f, "{}", s)
+    }
+}
+
+impl OwnedPluginValue {
+    // Parses a value of the given type from its textual representation (e.g., a CLI argument)
+    pub fn parse(arg_type: PluginType, s: &str) -> Result<OwnedPluginValue, PluginError> {
+        OwnedPluginValue::parse_with(arg_type, s, &ParseOptions::default())
+    }
+
+    // Like `parse`, with control over how lists are split
+    pub fn parse_with(
+        arg_type: PluginType,
+        s: &str,
+        options: &ParseOptions,
+    ) -> Result<OwnedPluginValue, PluginError> {
+        let invalid = || {
+            ArgError::invalid(
+                ArgErrorKind::Malformed,
+                arg_type,
+                s,
+                format!("Invalid {}: {}", arg_type, s),
+            )
+        };
+        let unsupported = |message: &str| {
+            ArgError::invalid(ArgErrorKind::Unsupported, arg_type, s, message.to_string())
+        };
+        Ok(match arg_type {
+            PluginType::Bool => OwnedPluginValue::Bool(s.parse().map_err(|_| invalid())?),
+            PluginType::Int => {
+                OwnedPluginValue::Int(parse_integer(arg_type, s, i64::MIN, i64::MAX)?)
+            }
+            PluginType::UInt => OwnedPluginValue::UInt(parse_integer(arg_type, s, 0, u64::MAX)?),
+            PluginType::Double => OwnedPluginValue::Double(s.parse().map_err(|_| invalid())?),
+            PluginType::String => OwnedPluginValue::String(CString::new(s).map_err(|_| invalid())?),
+            // bytes are given as the argument's own bytes
+            PluginType::Bytes => OwnedPluginValue::Bytes(s.as_bytes().to_vec().into()),
+            // lists are given as separated strings of items, which may be quoted (see `split_list`)
+            PluginType::List => OwnedPluginValue::List(
+                split_list(s, options.list_separator)
+                    .map_err(|reason| {
+                        ArgError::invalid(
+                            ArgErrorKind::Malformed,
+                            arg_type,
+                            s,
+                            format!("Invalid List: {} ({})", s, reason),
+                        )
+                    })?
+                    .iter()
+                    .map(|item| OwnedPluginValue::parse(PluginType::String, item))
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid())?,
+            ),
+            PluginType::Custom(type_id) => {
+                return Err(unsupported(&format!(
+                    "Custom({}) values must be parsed with CustomTypes::parse",
+                    type_id
+                )));
+            }
+            PluginType::Handle => {
+                return Err(unsupported(
+                    "Handles can only be obtained from a plugin, not parsed",
+                ));
+            }
+            PluginType::Null => {
+                return Err(unsupported(
+                    "Null values can only be returned by a plugin, not parsed",
+                ));
+            }
+            PluginType::Result => {
+                return Err(unsupported(
+                    "Result values can only be returned by a plugin, not parsed",
+                ));
+            }
+            PluginType::Fd => OwnedPluginValue::open_fd(Path::new(s))?,
+            #[cfg(feature = "serde")]
+            PluginType::Json => OwnedPluginValue::Json(serde_json::from_str(s).map_err(|e| {
+                ArgError::invalid(
+                    ArgErrorKind::Malformed,
+                    arg_type,
+                    s,
+                    format!("Invalid Json: {}", e),
+                )
+            })?),
+            #[cfg(not(feature = "serde"))]
+            PluginType::Json => {
+                return Err(unsupported(
+                    "Json arguments require the host to be built with the serde feature",
+                ));
+            }
+        })
+    }
+
+    // Opens the file at `path` for the plugin to write to, creating it or truncating it
+    fn open_fd(path: &Path) -> Result<OwnedPluginValue, PluginError> {
+        File::create(path)
+            .map(OwnedPluginValue::Fd)
+            .map_err(|e| PluginError::Argument(format!("Could not open {}: {}", path.display(), e)))
+    }
+
+    // Like `parse`, but takes an OS string (e.g., from `std::env::args_os`). On Unix, String and
+    // Bytes values are taken as raw bytes, which need not be valid UTF-8. Fd values are opened from
+    // any path, on any platform.
+    pub fn parse_os(arg_type: PluginType, s: &OsStr) -> Result<OwnedPluginValue, PluginError> {
+        OwnedPluginValue::parse_os_with(arg_type, s, &ParseOptions::default())
+    }
+
+    // Like `parse_os`, with control over how lists are split
+    pub fn parse_os_with(
+        arg_type: PluginType,
+        s: &OsStr,
+        options: &ParseOptions,
+    ) -> Result<OwnedPluginValue, PluginError> {
+        if let PluginType::Fd = arg_type {
+            return OwnedPluginValue::open_fd(Path::new(s));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            match arg_type {
+                PluginType::String => return OwnedPluginValue::try_from(s.as_bytes()),
+                PluginType::Bytes => {
+                    return Ok(OwnedPluginValue::Bytes(s.as_bytes().to_vec().into()))
+                }
+                _ => {}
+            }
+        }
+
+        let s = s.to_str().ok_or_else(|| {
+            ArgError::invalid(
+                ArgErrorKind::Malformed,
+                arg_type,
+                s.to_string_lossy(),
+                format!(
+                    "Invalid {}: {} is not valid UTF-8",
+                    arg_type,
+                    s.to_string_lossy()
+                ),
+            )
+        })?;
+        OwnedPluginValue::parse_with(arg_type, s, options)
+    }
+
+    // The number of bytes of heap memory the value holds (including that of any items). JSON values
+    // are counted by their serialized length, which is the form they cross the FFI boundary in.
+    // Buffers mapped from files aren't on the heap, so they don't count.
+    pub fn heap_bytes(&self) -> usize {
+        match self {
+            OwnedPluginValue::String(s) => s.as_bytes_with_nul().len(),
+            OwnedPluginValue::Bytes(bytes) if bytes.is_mapped() => 0,
+            OwnedPluginValue::Bytes(bytes) => bytes.len(),
+            OwnedPluginValue::Custom { payload, .. } => payload.len(),
+            OwnedPluginValue::List(items) => {
+                items.capacity() * size_of::<OwnedPluginValue>()
+                    + items
+                        .iter()
+                        .map(OwnedPluginValue::heap_bytes)
+                        .sum::<usize>()
+            }
+            OwnedPluginValue::Result(Ok(value) | Err(value)) => {
+                size_of::<OwnedPluginValue>() + value.heap_bytes()
+            }
+            #[cfg(feature = "serde")]
+            OwnedPluginValue::Json(json) => json.to_string().len(),
+            OwnedPluginValue::Bool(_)
+            | OwnedPluginValue::Int(_)
+            | OwnedPluginValue::UInt(_)
+            | OwnedPluginValue::Double(_)
+            | OwnedPluginValue::Handle(_)
+            | OwnedPluginValue::Null
+            | OwnedPluginValue::Fd(_) => 0,
+        }
+    }
+
+    pub fn plugin_type(&self) -> PluginType {
+        match self {
+            OwnedPluginValue::Bool(_) => PluginType::Bool,
+            OwnedPluginValue::Int(_) => PluginType::Int,
+            OwnedPluginValue::UInt(_) => PluginType::UInt,
+            OwnedPluginValue::Double(_) => PluginType::Double,
+            OwnedPluginValue::String(_) => PluginType::String,
+            OwnedPluginValue::Handle(_) => PluginType::Handle,
+            OwnedPluginValue::List(_) => PluginType::List,
+            OwnedPluginValue::Bytes(_) => PluginType::Bytes,
+            OwnedPluginValue::Custom { type_id, .. } => PluginType::Custom(*type_id),
+            #[cfg(feature = "serde")]
+            OwnedPluginValue::Json(_) => PluginType::Json,
+            OwnedPluginValue::Null => PluginType::Null,
+            OwnedPluginValue::Fd(_) => PluginType::Fd,
+            OwnedPluginValue::Result(_) => PluginType::Result,
+        }
+    }
+
+    // Whether the value can be passed as a value of type `ty`, either because it is one or because
+    // `coerce` can convert it: an Int that isn't negative can be passed as a UInt, and a UInt that
+    // fits in an i64 as an Int. There are no other coercions; in particular, a Null only matches
+    // Null, even though plugins may return Null whatever their declared return type.
+    pub fn matches_type(&self, ty: PluginType) -> bool {
+        match (self, ty) {
+            (OwnedPluginValue::Int(i), PluginType::UInt) => *i >= 0,
+            (OwnedPluginValue::UInt(u), PluginType::Int) => i64::try_from(*u).is_ok(),
+            (value, ty) => value.plugin_type() == ty,
+        }
+    }
+
+    // Converts the value to type `ty`, where `matches_type` allows it, so that it can be passed to
+    // a plugin expecting that type (which only accepts values of exactly its declared types)
+    pub fn coerce(self, ty: PluginType) -> Result<OwnedPluginValue, PluginError> {
+        let out_of_range = |value: &dyn Display| {
+            ArgError::invalid(
+                ArgErrorKind::OutOfRange,
+                ty,
+                value.to_string(),
+                format!("{} is out of range for {}", value, ty),
+            )
+        };
+        match (self, ty) {
+            (OwnedPluginValue::Int(i), PluginType::UInt) => u64::try_from(i)
+                .map(OwnedPluginValue::UInt)
+                .map_err(|_| out_of_range(&i)),
+            (OwnedPluginValue::UInt(u), PluginType::Int) => i64::try_from(u)
+                .map(OwnedPluginValue::Int)
+                .map_err(|_| out_of_range(&u)),
+            (value, ty) if value.plugin_type() == ty => Ok(value),
+            (value, ty) => Err(PluginError::Argument(format!(
+                "Expected a {} value, but got {}",
+                ty,
+                value.plugin_type()
+            ))),
+        }
+    }
+
+    // Formats the value like `Display`, but returns an error for strings that are not valid UTF-8
+    // instead of replacing the invalid bytes
+    pub fn to_string_strict(&self) -> Result<String, Utf8Error> {
+        self.format(&FormatOptions {
+            strict_utf8: true,
+            ..FormatOptions::default()
+        })
+    }
+
+    // Formats the value according to the options; this can only fail if `strict_utf8` is set
+    pub fn format(&self, options: &FormatOptions) -> Result<String, Utf8Error> {
+        let mut out = String::new();
+        self.write_to(&mut out, options)?;
+        Ok(out)
+    }
+
+    // Formats the value as a CSV field, quoted if it contains a comma, quote or line break (with
+    // quotes doubled). Scalars are written as by `format`, and Null as an empty field. Aggregates
+    // are flattened into a single field: lists as their items joined by commas (in full, regardless
+    // of `max_items`), and Json objects as `key=value;` pairs.
+    pub fn format_csv(&self, options: &FormatOptions) -> Result<String, Utf8Error> {
+        let mut field = String::new();
+        self.write_csv_to(&mut field, options)?;
+        if field.contains([',', '"', '\n', '\r']) {
+            field = format!("\"{}\"", field.replace('"', "\"\""));
+        }
+        Ok(field)
+    }
+
+    fn write_csv_to(&self, out: &mut String, options: &FormatOptions) -> Result<(), Utf8Error> {
+        match self {
+            OwnedPluginValue::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write_csv_to(out, options)?;
+                }
+            }
+            #[cfg(feature = "serde")]
+            OwnedPluginValue::Json(json) => write_json_csv(out, json),
+            OwnedPluginValue::Null => {}
+            value => value.write_to(out, options)?,
+        }
+        Ok(())
+    }
+
+    fn write_to(&self, out: &mut String, options: &FormatOptions) -> Result<(), Utf8Error> {
+        match self {
+            OwnedPluginValue::Bool(b) => out.push_str(&b.to_string()),
+            OwnedPluginValue::Int(i) => out.push_str(&i.to_string()),
+            OwnedPluginValue::UInt(u) => out.push_str(&u.to_string()),
+            OwnedPluginValue::Double(d) => out.push_str(&options.float_format.format(*d)),
+            OwnedPluginValue::String(s) if options.strict_utf8 => out.push_str(s.to_str()?),
+            OwnedPluginValue::String(s) => out.push_str(&s.to_string_lossy()),
+            OwnedPluginValue::Handle(handle) => {
+                out.push_str(&format!("handle #{}", handle.token()))
+            }
+            OwnedPluginValue::Bytes(bytes) => out.push_str(&format!("{:?}", bytes)),
+            OwnedPluginValue::Custom { type_id, payload } => {
+                match options.custom_types.as_ref().and_then(|t| t.get(*type_id)) {
+                    Some(handler) => out.push_str(&handler.format(payload)),
+                    None => out.push_str(&format!("Custom({}) {:?}", type_id, payload)),
+                }
+            }
+            OwnedPluginValue::List(items) => {
+                let shown = options.max_items.unwrap_or(items.len()).min(items.len());
+                out.push('[');
+                for (i, item) in items[..shown].iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    item.write_to(out, options)?;
+                }
+                if shown < items.len() {
+                    if shown > 0 {
+                        out.push_str(", ");
+                    }
+                    out.push_str(&format!("... ({} more)", items.len() - shown));
+                }
+                out.push(']');
+            }
+            #[cfg(feature = "serde")]
+            OwnedPluginValue::Json(json) => out.push_str(&json.to_string()),
+            OwnedPluginValue::Null => out.push_str("null"),
+            OwnedPluginValue::Fd(file) => out.push_str(&format!("fd {}", raw_fd(file))),
+            OwnedPluginValue::Result(result) => {
+                let (label, value) = match result {
+                    Ok(value) => ("Ok", value),
+                    Err(value) => ("Err", value),
+                };
+                out.push_str(label);
+                out.push('(');
+                value.write_to(out, options)?;
+                out.push(')');
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owned_string_checks_limits() {
+        let limits = ResultLimits {
+            max_string_len: 5,
+            ..ResultLimits::default()
+        };
+        let string = |s: &str| CString::new(s).unwrap().into_raw() as *const c_char;
+
+        let mut remaining = 100;
+        let s = owned_string(string("hello"), &limits, &mut remaining).unwrap();
+        assert_eq!(s.to_str(), Ok("hello"));
+        assert_eq!(remaining, 94);
+
+        // too long for the limit on strings
+        let e = owned_string(string("hello!"), &limits, &mut remaining).unwrap_err();
+        assert!(e.to_string().contains("longer than the limit of 5 bytes"));
+
+        // within the limit, but too large for what remains of the result
+        let mut remaining = 4;
+        let e = owned_string(string("hello"), &limits, &mut remaining).unwrap_err();
+        assert!(e.to_string().contains("larger than the limit"));
+        assert_eq!(remaining, 0);
+
+        let e = owned_string(std::ptr::null(), &limits, &mut remaining).unwrap_err();
+        assert!(e.to_string().contains("null string"));
+    }
+
+    #[test]
+    fn truncates_long_lists() {
+        let list = OwnedPluginValue::List((1..=15).map(OwnedPluginValue::UInt).collect());
+        let format = |max_items| {
+            list.format(&FormatOptions {
+                max_items,
+                ..FormatOptions::default()
+            })
+            .unwrap()
+        };
+
+        assert_eq!(
+            format(Some(DEFAULT_MAX_ITEMS)),
+            "[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, ... (5 more)]"
+        );
+        assert_eq!(format(Some(0)), "[... (15 more)]");
+        assert_eq!(
+            format(None),
+            "[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]"
+        );
+        // a list no longer than the limit is shown in full
+        assert_eq!(format(Some(15)), format(None));
+    }
+
+    #[test]
+    fn parses_uint_edge_cases() {
+        let uint = |s: &str| match OwnedPluginValue::parse(PluginType::UInt, s) {
+            Ok(OwnedPluginValue::UInt(u)) => Ok(u),
+            Ok(value) => panic!("expected a UInt, got {}", value),
+            Err(e) => Err(e.to_string()),
+        };
+
+        assert_eq!(uint("42"), Ok(42));
+        assert_eq!(uint("0042"), Ok(42));
+        assert_eq!(uint("+42"), Ok(42));
+        assert_eq!(uint(" 42\t"), Ok(42));
+        assert_eq!(uint("18446744073709551615"), Ok(u64::MAX));
+        assert_eq!(
+            uint("18446744073709551616"),
+            Err(
+                "Invalid UInt \"18446744073709551616\": must be at most 18446744073709551615"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            uint("-1"),
+            Err("Invalid UInt \"-1\": must be at least 0".to_string())
+        );
+        assert_eq!(
+            uint("1_000"),
+            Err("Invalid UInt \"1_000\": underscores aren't allowed".to_string())
+        );
+        assert_eq!(
+            uint("  "),
+            Err("Invalid UInt \"  \": expected a number".to_string())
+        );
+        for s in ["4 2", "0x10", "1e3", "++1", "-"] {
+            assert_eq!(
+                uint(s),
+                Err(format!("Invalid UInt {:?}: expected a decimal integer", s))
+            );
+        }
+    }
+
+    #[test]
+    fn counts_heap_bytes_of_values() {
+        let string = || OwnedPluginValue::String(CString::new("abc").unwrap());
+        assert_eq!(string().heap_bytes(), 4);
+        assert_eq!(OwnedPluginValue::UInt(7).heap_bytes(), 0);
+
+        let mut items = Vec::with_capacity(3);
+        items.extend([string(), OwnedPluginValue::Null]);
+        assert_eq!(
+            OwnedPluginValue::List(items).heap_bytes(),
+            3 * size_of::<OwnedPluginValue>() + 4
+        );
+
+        // a Result's value is boxed
+        assert_eq!(
+            OwnedPluginValue::Result(Err(Box::new(string()))).heap_bytes(),
+            size_of::<OwnedPluginValue>() + 4
+        );
+    }
+
+    #[test]
+    fn formats_doubles_in_each_mode() {
+        let format = |value: OwnedPluginValue, float_format: &str| {
+            value
+                .format(&FormatOptions {
+                    float_format: float_format.parse().unwrap(),
+                    ..FormatOptions::default()
+                })
+                .unwrap()
+        };
+        let tenth = || OwnedPluginValue::Double(0.1);
+        assert_eq!(format(tenth(), "plain"), "0.1");
+        assert_eq!(format(tenth(), "fixed:3"), "0.100");
+        assert_eq!(format(tenth(), "fixed:0"), "0");
+        assert_eq!(format(tenth(), "scientific"), "1e-1");
+        assert_eq!(format(tenth(), "roundtrip"), "0.1");
+
+        // where plain and roundtrip differ
+        assert_eq!(format(OwnedPluginValue::Double(1.0), "plain"), "1");
+        assert_eq!(format(OwnedPluginValue::Double(1.0), "roundtrip"), "1.0");
+        assert_eq!(format(OwnedPluginValue::Double(1e-7), "plain"), "0.0000001");
+        assert_eq!(format(OwnedPluginValue::Double(1e-7), "roundtrip"), "1e-7");
+
+        // doubles in lists are formatted the same way
+        let list = OwnedPluginValue::List(vec![tenth(), OwnedPluginValue::Int(1)]);
+        assert_eq!(format(list, "fixed:2"), "[0.10, 1]");
+
+        for s in ["fixed", "fixed:", "fixed:-1", "exact"] {
+            assert!(s.parse::<FloatFormat>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn escapes_csv_fields() {
+        let csv = |value: OwnedPluginValue| value.format_csv(&FormatOptions::default()).unwrap();
+        let string = |s: &str| OwnedPluginValue::String(CString::new(s).unwrap());
+
+        assert_eq!(csv(string("plain")), "plain");
+        assert_eq!(csv(string(r#"a, "b""#)), r#""a, ""b""""#);
+        assert_eq!(csv(string("two\nlines")), "\"two\nlines\"");
+        assert_eq!(csv(OwnedPluginValue::Null), "");
+
+        // lists are flattened into a single field, and so quoted
+        let list = OwnedPluginValue::List(vec![string("x"), OwnedPluginValue::UInt(2)]);
+        assert_eq!(csv(list), "\"x,2\"");
+    }
+
+    #[test]
+    fn splits_lists_with_quoted_items() {
+        let items = |s: &str, list_separator: char| {
+            let options = ParseOptions { list_separator };
+            match OwnedPluginValue::parse_with(PluginType::List, s, &options) {
+                Ok(OwnedPluginValue::List(items)) => Ok(items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<_>>()),
+                Ok(value) => panic!("expected a List, got {}", value),
+                Err(e) => Err(e.to_string()),
+            }
+        };
+
+        assert_eq!(items("a,b", ','), Ok(vec!["a".into(), "b".into()]));
+        assert_eq!(items(r#""a,b",c"#, ','), Ok(vec!["a,b".into(), "c".into()]));
+        assert_eq!(
+            items(r#""say ""hi""",x"#, ','),
+            Ok(vec![r#"say "hi""#.into(), "x".into()])
+        );
+        // quotes that don't start an item are literal
+        assert_eq!(
+            items(r#"a"b,c"#, ','),
+            Ok(vec![r#"a"b"#.into(), "c".into()])
+        );
+        assert_eq!(
+            items("a,,b,", ','),
+            Ok(vec!["a".into(), "".into(), "b".into()])
+        );
+
+        assert_eq!(items("a,b;c", ';'), Ok(vec!["a,b".into(), "c".into()]));
+        assert_eq!(items(r#""a;b";c"#, ';'), Ok(vec!["a;b".into(), "c".into()]));
+
+        assert_eq!(
+            items(r#""a,b"#, ','),
+            Err(r#"Invalid List: "a,b (unterminated quote)"#.to_string())
+        );
+        assert_eq!(
+            items(r#""a"b,c"#, ','),
+            Err(
+                r#"Invalid List: "a"b,c (a quoted item must be followed by a separator)"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn matches_types_with_integer_coercions() {
+        use OwnedPluginValue as V;
+        use PluginType as T;
+
+        let string = || V::String(CString::new("s").unwrap());
+        let custom = |type_id| V::Custom {
+            type_id,
+            payload: vec![1].into(),
+        };
+        let cases = [
+            (V::Bool(true), T::Bool, true),
+            (V::Bool(true), T::Int, false),
+            (V::Int(-1), T::Int, true),
+            (V::Int(0), T::UInt, true),
+            (V::Int(i64::MAX), T::UInt, true),
+            (V::Int(-1), T::UInt, false),
+            (V::Int(1), T::Double, false),
+            (V::UInt(u64::MAX), T::UInt, true),
+            (V::UInt(i64::MAX as u64), T::Int, true),
+            (V::UInt(i64::MAX as u64 + 1), T::Int, false),
+            (V::Double(1.0), T::Double, true),
+            (V::Double(1.0), T::Int, false),
+            (string(), T::String, true),
+            (string(), T::Bytes, false),
+            (V::Bytes(vec![].into()), T::Bytes, true),
+            (V::List(vec![]), T::List, true),
+            (V::List(vec![]), T::String, false),
+            (custom(7), T::Custom(7), true),
+            (custom(7), T::Custom(8), false),
+            (V::Null, T::Null, true),
+            (V::Null, T::String, false),
+            (V::Result(Ok(Box::new(V::Null))), T::Result, true),
+        ];
+        for (value, ty, matches) in cases {
+            let shown = format!("{} as {}", value, ty);
+            assert_eq!(value.matches_type(ty), matches, "{}", shown);
+            // coercing succeeds exactly when the value matches, and gives a value of the type
+            match value.coerce(ty) {
+                Ok(value) => {
+                    assert!(matches, "{}", shown);
+                    assert_eq!(value.plugin_type(), ty, "{}", shown);
+                }
+                Err(_) => assert!(!matches, "{}", shown),
+            }
+        }
+
+        let Err(e) = V::Int(-1).coerce(T::UInt) else {
+            panic!("expected an error");
+        };
+        assert!(matches!(e, PluginError::InvalidArg(_)), "{}", e);
+        assert_eq!(e.to_string(), "-1 is out of range for UInt");
+        let Err(e) = V::Bool(true).coerce(T::Int) else {
+            panic!("expected an error");
+        };
+        assert!(matches!(e, PluginError::Argument(_)), "{}", e);
+    }
+}
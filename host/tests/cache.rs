@@ -0,0 +1,65 @@
+// Tests of the metadata cache, which requires the serde feature
+#![cfg(feature = "serde")]
+mod common;
+
+use common::{plugin_path, temp_path};
+use host::MetadataCache;
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// Replaces the file at `path` with a copy of `source`, giving it the modification time `modified`.
+// The copy is renamed into place, so that a library that's still loaded isn't modified under it.
+fn replace(path: &Path, source: &Path, modified: SystemTime) {
+    let staged = path.with_extension("staged");
+    std::fs::copy(source, &staged).unwrap();
+    File::options()
+        .write(true)
+        .open(&staged)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    std::fs::rename(&staged, path).unwrap();
+}
+
+#[test]
+fn refreshes_entries_of_modified_plugins() {
+    let cache_path = temp_path("cache.json");
+    let plugin = temp_path("cached-plugin.so");
+    let cached_at = SystemTime::now() - Duration::from_secs(60);
+    replace(&plugin, &plugin_path("negate"), cached_at);
+
+    let mut cache = MetadataCache::open(&cache_path);
+    assert_eq!(cache.metadata(&plugin).unwrap().name, "negate");
+    cache.save().unwrap();
+
+    // while the file's modification time is unchanged, its entry is read from the cache file
+    // without loading it, even though it's now a different plugin
+    replace(&plugin, &plugin_path("scale"), cached_at);
+    let mut cache = MetadataCache::open(&cache_path);
+    assert_eq!(cache.metadata(&plugin).unwrap().name, "negate");
+
+    // once it's modified, the entry is refreshed, and saved for the next time
+    replace(&plugin, &plugin_path("scale"), SystemTime::now());
+    assert_eq!(cache.metadata(&plugin).unwrap().name, "scale");
+    cache.save().unwrap();
+    let mut cache = MetadataCache::open(&cache_path);
+    assert_eq!(cache.metadata(&plugin).unwrap().name, "scale");
+
+    std::fs::remove_file(&plugin).unwrap();
+    std::fs::remove_file(&cache_path).unwrap();
+}
+
+#[test]
+fn caches_tags() {
+    let cache_path = temp_path("tags-cache.json");
+    let mut cache = MetadataCache::open(&cache_path);
+    let tags = cache.metadata(plugin_path("plugin")).unwrap().tags;
+    assert_eq!(tags, ["category=text", "example"]);
+    cache.save().unwrap();
+
+    let mut cache = MetadataCache::open(&cache_path);
+    assert_eq!(cache.metadata(plugin_path("plugin")).unwrap().tags, tags);
+
+    std::fs::remove_file(&cache_path).unwrap();
+}
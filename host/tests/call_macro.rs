@@ -0,0 +1,112 @@
+// Tests of calling plugins through the call! macro
+mod common;
+
+use common::{expect_err, load, mock};
+use host::{call, OwnedPluginValue, Plugin, PluginError};
+use std::collections::HashMap;
+
+#[test]
+fn calls_plugin_with_rust_values() {
+    let plugin = load("plugin");
+    let result = call!(plugin, "repeat", "hi", 3u64).unwrap();
+    assert!(matches!(result, OwnedPluginValue::String(s) if s.to_str() == Ok("hihihi")));
+
+    // a trailing comma is allowed, and arguments are converted before the plugin checks them
+    let e = expect_err(call!(plugin, "repeat", "hi", -1i64,));
+    assert!(matches!(e, PluginError::Argument(_)), "{}", e);
+
+    let e = expect_err(call!(plugin, "echo", "hi", 3u64));
+    assert_eq!(e.to_string(), "Plugin repeat has no function named echo");
+
+    // any of a plugin's functions can be called by name
+    let arith = load("arith");
+    let result = call!(arith, "halve", 3.0).unwrap();
+    assert_eq!(result.to_string(), "1.5");
+}
+
+#[test]
+fn rejects_unsupported_argument_types() {
+    let tests = trybuild::TestCases::new();
+    tests.compile_fail("tests/ui/*.rs");
+}
+
+#[test]
+fn passes_none_for_optional_arguments() {
+    // greets `name` with an optional greeting, which may be omitted or passed as Null
+    let greet = mock(
+        r#"
+        #[repr(C)]
+        pub struct ArgDescriptor {
+            pub name: *const i8,
+            pub arg_type: PluginType,
+            pub optional: bool,
+            pub doc: *const i8,
+            pub secret: bool,
+            pub min: *const PluginValue,
+            pub max: *const PluginValue,
+            pub max_len: usize,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"greet".as_ptr(),
+                arg_types: [PluginType::String, PluginType::String].as_ptr(),
+                arg_types_len: 2,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_describe_arg_v2(index: usize) -> ArgDescriptor {
+            let (name, optional) = match index {
+                0 => (c"name".as_ptr(), false),
+                1 => (c"greeting".as_ptr(), true),
+                _ => (std::ptr::null(), false),
+            };
+            ArgDescriptor {
+                name,
+                arg_type: PluginType::String,
+                optional,
+                doc: std::ptr::null(),
+                secret: false,
+                min: std::ptr::null(),
+                max: std::ptr::null(),
+                max_len: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, len: usize) -> PluginResult {
+            let args = unsafe { std::slice::from_raw_parts(args, len) };
+            let str = |value: &PluginValue| match value {
+                PluginValue::String(s) => unsafe { std::ffi::CStr::from_ptr(*s) }.to_str().unwrap(),
+                _ => unreachable!(),
+            };
+            let greeting = match args.get(1) {
+                None | Some(PluginValue::Null) => "hello",
+                Some(greeting) => str(greeting),
+            };
+            let greeted = format!("{} {}", greeting, str(&args[0]));
+            PluginResult::Ok(PluginValue::String(
+                std::ffi::CString::new(greeted).unwrap().into_raw(),
+            ))
+        }
+        "#,
+    );
+    let plugin = Plugin::load(greet.path(), HashMap::new()).unwrap();
+    let greeted = |value: OwnedPluginValue| value.to_string();
+
+    let value = call!(plugin, "greet", "bob", Some("hi")).unwrap();
+    assert_eq!(greeted(value), "hi bob");
+    let value = call!(plugin, "greet", "bob", None::<&str>).unwrap();
+    assert_eq!(greeted(value), "hello bob");
+    // the optional argument can also be left out
+    let value = call!(plugin, "greet", "bob").unwrap();
+    assert_eq!(greeted(value), "hello bob");
+
+    // but a required one can't be None
+    let e = expect_err(call!(plugin, "greet", None::<&str>, "hi"));
+    assert!(matches!(e, PluginError::Argument(_)), "{}", e);
+}
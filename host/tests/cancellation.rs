@@ -0,0 +1,151 @@
+// Tests of cancelling calls, and of calls with timeouts and deadlines
+mod common;
+
+use common::{expect_err, mock, HOST_SERVICES};
+use host::{OwnedPluginValue, Plugin, PluginError};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// A plugin that, when its call has a deadline, polls for cancellation until it's cancelled,
+// reporting that it's halfway done. Without a deadline it returns at once, with whether it was
+// cancelled.
+const POLLING: &str = r#"
+    #[no_mangle]
+    pub extern "C" fn plugin_metadata() -> PluginMetadata {
+        PluginMetadata {
+            name: c"poll".as_ptr(),
+            arg_types: std::ptr::null(),
+            arg_types_len: 0,
+            return_type: PluginType::Bool,
+            required_host_capabilities: 0,
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+        let s = services();
+        if (s.time_remaining)(s.context).is_infinite() {
+            return PluginResult::Ok(PluginValue::Bool((s.is_cancelled)(s.context)));
+        }
+        (s.report_progress)(s.context, 0.5);
+        while !(s.is_cancelled)(s.context) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        PluginResult::Err {
+            code: PluginErrorCode::Cancelled,
+            message: c"call was cancelled".to_owned().into_raw(),
+            payload: std::ptr::null_mut(),
+        }
+    }
+"#;
+
+fn polling() -> host::MockPlugin {
+    mock(&format!("{}{}", HOST_SERVICES, POLLING))
+}
+
+#[test]
+fn cancels_polling_plugin_on_timeout() {
+    let mock = polling();
+    let plugin = Arc::new(Plugin::load(mock.path(), HashMap::new()).unwrap());
+
+    let e = expect_err(
+        plugin
+            .clone()
+            .call_with_timeout(vec![], Duration::from_millis(50)),
+    );
+    assert!(matches!(e, PluginError::Cancelled(_)));
+    assert_eq!(e.to_string(), "Call timed out after 50ms (50% complete)");
+
+    // only the call that timed out was cancelled, and its deadline doesn't apply to later calls
+    assert!(!plugin.cancellation_token().is_cancelled());
+    assert_eq!(plugin.call(&[]).unwrap().to_string(), "false");
+}
+
+#[test]
+fn cancels_through_plugin_token() {
+    let mock = polling();
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let token = plugin.cancellation_token();
+    token.cancel();
+    assert_eq!(plugin.call(&[]).unwrap().to_string(), "true");
+    token.reset();
+    assert_eq!(plugin.call(&[]).unwrap().to_string(), "false");
+}
+
+#[test]
+fn deadlines_apply_to_their_own_call() {
+    let mock = polling();
+    let plugin = Arc::new(Plugin::load(mock.path(), HashMap::new()).unwrap());
+
+    // a call without a deadline, made while another call's deadline is pending, doesn't see it
+    let timed = {
+        let plugin = plugin.clone();
+        std::thread::spawn(move || {
+            plugin.call_with_deadline(vec![], Instant::now() + Duration::from_millis(200))
+        })
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(plugin.call(&[]).unwrap().to_string(), "false");
+
+    let e = expect_err(timed.join().unwrap());
+    assert_eq!(e.to_string(), "Call missed its deadline (50% complete)");
+}
+
+#[test]
+fn fails_at_once_past_deadline() {
+    let mock = polling();
+    let plugin = Arc::new(Plugin::load(mock.path(), HashMap::new()).unwrap());
+
+    // the plugin isn't called, or it would report its progress
+    let started = Instant::now();
+    let e = expect_err(plugin.clone().call_with_deadline(vec![], started));
+    assert!(matches!(e, PluginError::Cancelled(_)), "{}", e);
+    assert_eq!(
+        e.to_string(),
+        "Call missed its deadline (it wasn't started)"
+    );
+    assert!(started.elapsed() < Duration::from_millis(50));
+    assert!(!plugin.cancellation_token().is_cancelled());
+}
+
+#[test]
+fn tells_plugin_time_remaining() {
+    let mock = mock(&format!(
+        "{}{}",
+        HOST_SERVICES,
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"budget".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::Double,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            let s = services();
+            PluginResult::Ok(PluginValue::Double((s.time_remaining)(s.context)))
+        }
+        "#
+    ));
+    let plugin = Arc::new(Plugin::load(mock.path(), HashMap::new()).unwrap());
+    let remaining = |value: OwnedPluginValue| match value {
+        OwnedPluginValue::Double(seconds) => seconds,
+        value => panic!("expected a Double, got {}", value),
+    };
+
+    let seconds = remaining(
+        plugin
+            .clone()
+            .call_with_deadline(vec![], Instant::now() + Duration::from_secs(10))
+            .unwrap()
+            .0,
+    );
+    assert!(seconds > 9.0 && seconds <= 10.0, "{}", seconds);
+    assert_eq!(remaining(plugin.call(&[]).unwrap()), f64::INFINITY);
+}
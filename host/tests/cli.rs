@@ -0,0 +1,1037 @@
+// Tests of the host binary's command line, run against the sample plugins
+mod common;
+
+use common::{host, mock, plugin_path, stderr, stdout, HOST_SERVICES};
+
+fn repeat_path() -> String {
+    plugin_path("plugin").to_str().unwrap().to_string()
+}
+
+#[test]
+fn argument_and_load_errors_have_distinct_exit_codes() {
+    let output = host(&[&repeat_path(), "abc", "many"]);
+    assert_eq!(output.status.code(), Some(4));
+
+    let output = host(&["./no-such-plugin.so", "abc", "2"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = host(&[&repeat_path(), "abc", "2"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn prints_usage_from_arg_descriptors() {
+    let output = host(&[&repeat_path(), "abc"]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = stderr(&output);
+    assert!(stderr.contains("Expected 2 arguments, got 1"), "{}", stderr);
+    assert!(
+        stderr.contains("Usage: repeat <string: String> <count: UInt>"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("the string to repeat"), "{}", stderr);
+    assert!(
+        stderr.contains("the number of times to repeat the string"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn reads_config_through_host_services() {
+    let output = host(&["--config", "separator=-", &repeat_path(), "ab", "3"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: ab-ab-ab"));
+
+    // without the config value, there's no separator
+    let output = host(&[&repeat_path(), "ab", "3"]);
+    assert!(stdout(&output).contains("Plugin returned: ababab"));
+}
+
+#[test]
+fn diffs_against_variant_with_changed_arg_type() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"repeat".as_ptr(),
+                arg_types: [PluginType::String, PluginType::Int].as_ptr(),
+                arg_types_len: 2,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::UInt(0))
+        }
+        "#,
+    );
+
+    let output = host(&["--diff", &repeat_path(), mock.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let diffs = stdout(&output);
+    assert!(diffs.contains("arg1 type: UInt -> Int"), "{}", diffs);
+    assert!(diffs.contains("argument descriptions removed"), "{}", diffs);
+
+    let output = host(&["--diff", &repeat_path(), &repeat_path()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "No differences\n");
+}
+
+#[test]
+fn pipes_string_between_plugins() {
+    let greet = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"greet".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::String(c"hi".to_owned().into_raw()))
+        }
+        "#,
+    );
+    let shout = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"shout".as_ptr(),
+                arg_types: [PluginType::String].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {
+            let PluginValue::String(s) = *args else {
+                unreachable!()
+            };
+            let s = std::ffi::CStr::from_ptr(s).to_str().unwrap().to_uppercase();
+            PluginResult::Ok(PluginValue::String(std::ffi::CString::new(s).unwrap().into_raw()))
+        }
+        "#,
+    );
+    let (greet, shout) = (
+        greet.path().to_str().unwrap(),
+        shout.path().to_str().unwrap(),
+    );
+
+    let output = host(&["--pipe", greet, shout]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: HI"));
+
+    // repeat takes two arguments, so it can't follow another plugin
+    let output = host(&["--pipe", greet, &repeat_path()]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains("repeat can't be called with a single argument"));
+}
+
+#[test]
+fn lists_functions_of_each_plugin() {
+    let scale = plugin_path("scale");
+    for (path, row) in [
+        (
+            repeat_path().as_str(),
+            "repeat    string: String, count: UInt  String",
+        ),
+        (
+            scale.to_str().unwrap(),
+            "scale     arg0: Double, arg1: Int, arg2: Bool  Double",
+        ),
+    ] {
+        let output = host(&["--functions", path]);
+        assert!(output.status.success());
+        let table = stdout(&output);
+        let rows: Vec<_> = table
+            .lines()
+            .skip_while(|l| l.starts_with("Loaded"))
+            .collect();
+        assert_eq!(rows.len(), 2, "{}", table);
+        assert!(rows[0].starts_with("Function  Arguments"));
+        assert_eq!(rows[1], row);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn passes_non_utf8_argument_to_plugin() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let output = common::host_command(&[&repeat_path()])
+        .arg(OsStr::from_bytes(b"ab\xff"))
+        .arg("2")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains("expected valid UTF-8 string"));
+}
+
+#[test]
+fn traces_arguments_and_result() {
+    let output = host(&["--trace", &repeat_path(), "ab", "2"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let trace = stderr(&output);
+    assert!(trace.contains("[trace] arg0: String = ab\n"), "{}", trace);
+    assert!(trace.contains("[trace] arg1: UInt = 2\n"), "{}", trace);
+    assert!(trace.contains("[trace] result: Ok(String)\n"), "{}", trace);
+
+    // without it, nothing is traced
+    let output = host(&[&repeat_path(), "ab", "2"]);
+    assert!(!stderr(&output).contains("[trace]"));
+}
+
+// A plugin named `name` that takes no arguments and runs `body`, e.g. to use more resources than
+// a limit allows
+#[cfg(unix)]
+fn greedy(name: &str, body: &str) -> host::MockPlugin {
+    mock(&format!(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"{}".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            {}
+        }}
+        "#,
+        name, body
+    ))
+}
+
+#[cfg(unix)]
+#[test]
+fn terminates_plugin_over_resource_limits() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let spin = greedy(
+        "spin",
+        "let mut n = 0u64; loop { n = std::hint::black_box(n.wrapping_add(1)); }",
+    );
+    let output = host(&["--limit-cpu", "1", spin.path().to_str().unwrap()]);
+    // the soft and hard limits are the same, so depending on the OS the process may be killed
+    // outright rather than sent SIGXCPU first
+    assert!(
+        matches!(output.status.signal(), Some(libc::SIGXCPU | libc::SIGKILL)),
+        "{:?}",
+        output
+    );
+
+    let hog = greedy(
+        "hog",
+        "let v = vec![0u8; 1 << 32]; PluginResult::Ok(PluginValue::UInt(v.len() as u64))",
+    );
+    let output = host(&["--limit-memory", "1073741824", hog.path().to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert_eq!(output.status.signal(), Some(libc::SIGABRT), "{:?}", output);
+
+    // the same plugin runs without the limit
+    let output = host(&[hog.path().to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn calls_repeat_with_or_without_function_name() {
+    let output = host(&[&repeat_path(), "ab", "2"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: abab\n"));
+
+    let output = host(&[&repeat_path(), "repeat", "ab", "2"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: abab\n"));
+
+    // the name given twice is passed as the string to repeat
+    let output = host(&[&repeat_path(), "repeat", "repeat", "2"]);
+    assert!(stdout(&output).contains("Plugin returned: repeatrepeat\n"));
+
+    // as is the name given once, when the arguments fit repeat without it
+    let output = host(&[&repeat_path(), "repeat", "3"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: repeatrepeatrepeat\n"));
+}
+
+#[test]
+fn calls_plugin_once_per_stdin_line() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = common::host_command(&["--stdin", &repeat_path(), "3"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // an empty line is passed as an empty string, and the last line needn't end with a newline
+    child.stdin.take().unwrap().write_all(b"ab\n\nc d").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        "Loaded plugin repeat\n\
+         Plugin returned: ababab\n\
+         Plugin returned: \n\
+         Plugin returned: c dc dc d\n"
+    );
+}
+
+#[test]
+fn filters_plugin_logs_by_level() {
+    let chatty = mock(&format!(
+        r#"{}
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"chatty".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            // levels are passed by discriminant: Error, Warn, Info, then Debug
+            let services = services();
+            (services.log)(services.context, 3, c"at debug".as_ptr());
+            (services.log)(services.context, 2, c"at info".as_ptr());
+            (services.log)(services.context, 1, c"at warn".as_ptr());
+            PluginResult::Ok(PluginValue::UInt(0))
+        }}
+        "#,
+        HOST_SERVICES
+    ));
+    let path = chatty.path().to_str().unwrap();
+    // the plugin's lines of stderr, without anything else the host reports
+    let logs = |args: &[&str]| -> Vec<String> {
+        stderr(&host(args))
+            .lines()
+            .filter(|line| line.starts_with("[plugin "))
+            .map(String::from)
+            .collect()
+    };
+
+    assert_eq!(
+        logs(&[path]),
+        ["[plugin INFO] at info", "[plugin WARN] at warn"]
+    );
+    assert_eq!(
+        logs(&["--log-level", "debug", path]),
+        [
+            "[plugin DEBUG] at debug",
+            "[plugin INFO] at info",
+            "[plugin WARN] at warn"
+        ]
+    );
+    assert_eq!(
+        logs(&["--log-level", "WARN", path]),
+        ["[plugin WARN] at warn"]
+    );
+
+    let output = host(&["--log-level", "loud", path]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn renders_structured_log_fields() {
+    let parser = mock(&format!(
+        r#"{}
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"parser".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 1 << 8,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            let services = services();
+            let fields = [
+                KeyValue {{ key: c"count".as_ptr(), value: PluginValue::UInt(3) }},
+                KeyValue {{ key: c"item".as_ptr(), value: PluginValue::String(c"a b".as_ptr()) }},
+                KeyValue {{ key: c"ratio".as_ptr(), value: PluginValue::Double(0.5) }},
+            ];
+            (services.log_fields)(services.context, 2, c"parsed".as_ptr(), fields.as_ptr(), 3);
+            (services.log_fields)(services.context, 3, c"hidden".as_ptr(), fields.as_ptr(), 3);
+            (services.log_fields)(services.context, 1, c"bare".as_ptr(), std::ptr::null(), 0);
+            PluginResult::Ok(PluginValue::UInt(0))
+        }}
+        "#,
+        HOST_SERVICES
+    ));
+    let output = host(&[parser.path().to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    // the debug-level message is filtered out, as by default only info and above are logged
+    let logs: Vec<_> = stderr(&output)
+        .lines()
+        .filter(|line| line.starts_with("[plugin "))
+        .map(String::from)
+        .collect();
+    assert_eq!(
+        logs,
+        [
+            "[plugin INFO] parsed count=3 item=\"a b\" ratio=0.5",
+            "[plugin WARN] bare"
+        ]
+    );
+}
+
+#[test]
+fn prints_none_for_null_result() {
+    let lookup = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"lookup".as_ptr(),
+                arg_types: [PluginType::String].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        // finds nothing, which isn't an error
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::Null)
+        }
+        "#,
+    );
+    let output = host(&[lookup.path().to_str().unwrap(), "missing"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "Loaded plugin lookup\nPlugin returned: (none)\n"
+    );
+}
+
+#[test]
+fn reads_config_from_prefixed_environment_variables() {
+    let output = common::host_command(&[&repeat_path(), "ab", "3"])
+        .env("PLUGIN_CFG_SEPARATOR", "-")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: ab-ab-ab\n"));
+
+    // --config takes precedence over the environment
+    let output = common::host_command(&["--config", "separator=+", &repeat_path(), "ab", "3"])
+        .env("PLUGIN_CFG_SEPARATOR", "-")
+        .output()
+        .unwrap();
+    assert!(stdout(&output).contains("Plugin returned: ab+ab+ab\n"));
+}
+
+#[test]
+fn fails_bench_slower_than_baseline() {
+    let baseline = common::temp_path("baseline.txt");
+    let bench = |baseline: &std::path::Path, update: bool| {
+        let mut args = vec!["--bench", "10", "--baseline", baseline.to_str().unwrap()];
+        if update {
+            args.push("--update-baseline");
+        }
+        let repeat = repeat_path();
+        args.extend([repeat.as_str(), "ab", "2"]);
+        host(&args)
+    };
+
+    // no call takes a nanosecond
+    std::fs::write(&baseline, "repeat 1\n").unwrap();
+    let output = bench(&baseline, false);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Call time regressed"));
+
+    // an updated baseline holds the measurement, which a generous baseline passes
+    let output = bench(&baseline, true);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(std::fs::read_to_string(&baseline)
+        .unwrap()
+        .starts_with("repeat "));
+    std::fs::write(&baseline, "repeat 1000000000\n").unwrap();
+    let output = bench(&baseline, false);
+    assert!(output.status.success(), "{}", stderr(&output));
+    std::fs::remove_file(&baseline).unwrap();
+}
+
+#[test]
+fn audits_directory_of_plugins() {
+    use std::env::consts::DLL_EXTENSION;
+
+    // declares an argument, but doesn't check that it was passed; its plugin_init leaves a marker
+    // file, to tell whether it was initialized
+    let initialized = common::temp_path("audit-initialized");
+    let lenient = mock(&format!(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_init(_services: *const std::ffi::c_void) -> *mut i8 {{
+            std::fs::write({:?}, "").unwrap();
+            std::ptr::null_mut()
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"lenient".as_ptr(),
+                arg_types: [PluginType::Int].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::Int,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            PluginResult::Ok(PluginValue::Int(0))
+        }}
+        "#,
+        initialized
+    ));
+    let dir = common::temp_path("audit");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    let healthy = dir.join(format!("healthy.{}", DLL_EXTENSION));
+    let broken = dir.join(format!("lenient.{}", DLL_EXTENSION));
+    std::fs::copy(plugin_path("negate"), &healthy).unwrap();
+    std::fs::copy(lenient.path(), &broken).unwrap();
+    // files that aren't libraries are skipped
+    std::fs::write(dir.join("README"), "").unwrap();
+
+    // without probing, plugins are only inspected, so neither is initialized or called
+    let output = host(&["--audit", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    assert!(!initialized.exists());
+
+    let output = host(&["--audit-probe", "--audit", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(initialized.exists());
+    let report = stdout(&output);
+    assert_eq!(
+        report,
+        format!(
+            "ok    healthy.{ext}\n\
+             FAIL  lenient.{ext}\n      \
+             accepted a call with no arguments, though it requires 1\n\
+             1 of 2 plugins failed the audit\n",
+            ext = DLL_EXTENSION
+        )
+    );
+
+    std::fs::remove_file(&broken).unwrap();
+    let output = host(&["--audit-probe", "--audit", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0), "{}", stdout(&output));
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_file(&initialized).unwrap();
+
+    let output = host(&["--audit-probe", &repeat_path()]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn plugin_writes_through_host_opened_fd() {
+    let writer = mock(
+        r#"
+        use std::io::Write;
+        use std::os::unix::io::FromRawFd;
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"writer".as_ptr(),
+                arg_types: [PluginType::Fd].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {
+            let PluginValue::Fd(fd) = (unsafe { &*args }) else {
+                unreachable!();
+            };
+            // the descriptor is only borrowed, so the file mustn't close it
+            let mut file =
+                std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(*fd as i32) });
+            let written = b"written by the plugin\n";
+            file.write_all(written).unwrap();
+            PluginResult::Ok(PluginValue::UInt(written.len() as u64))
+        }
+        "#,
+    );
+    let out = common::temp_path("fd-output.txt");
+    std::fs::write(&out, "replaced").unwrap();
+    let output = host(&[writer.path().to_str().unwrap(), out.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: 22\n"));
+    // the host truncated the file before the call
+    assert_eq!(
+        std::fs::read_to_string(&out).unwrap(),
+        "written by the plugin\n"
+    );
+    std::fs::remove_file(&out).unwrap();
+}
+
+#[test]
+fn passes_plugin_cwd_as_base_dir() {
+    let reporter = mock(&format!(
+        r#"{}
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"reporter".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            let services = services();
+            let base_dir = (services.get_config)(services.context, c"base_dir".as_ptr());
+            if !base_dir.is_null() {{
+                (services.log)(services.context, 1, base_dir);
+            }}
+            PluginResult::Ok(PluginValue::UInt(0))
+        }}
+        "#,
+        HOST_SERVICES
+    ));
+    let path = reporter.path().to_str().unwrap();
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+    // the directory is canonicalized, so the plugin doesn't depend on the host's working directory
+    let relative = dir.join(".");
+    let output = host(&["--plugin-cwd", relative.to_str().unwrap(), path]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains(&format!("[plugin WARN] {}\n", dir.display())));
+
+    let output = host(&[path]);
+    assert!(!stderr(&output).contains("[plugin WARN]"));
+
+    let file = common::temp_path("not-a-dir");
+    std::fs::write(&file, "").unwrap();
+    let output = host(&["--plugin-cwd", file.to_str().unwrap(), path]);
+    assert_eq!(output.status.code(), Some(2));
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn reports_progress_of_timed_out_call() {
+    // reports that it's a quarter of the way through, then works until it's cancelled
+    let slow = mock(&format!(
+        r#"{}
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"slow".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::Bool,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            let s = services();
+            (s.report_progress)(s.context, 0.25);
+            while !(s.is_cancelled)(s.context) {{
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }}
+            PluginResult::Err {{
+                code: PluginErrorCode::Cancelled,
+                message: c"call was cancelled".to_owned().into_raw(),
+                payload: std::ptr::null_mut(),
+            }}
+        }}
+        "#,
+        HOST_SERVICES
+    ));
+    let output = host(&["--timeout", "0.2", slow.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(7), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("Call timed out after 200ms (25% complete)"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = host(&["--timeout", "0", &repeat_path(), "a", "1"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn redacts_secret_arguments() {
+    let login = mock(
+        r#"
+        #[repr(C)]
+        pub struct ArgDescriptor {
+            pub name: *const i8,
+            pub arg_type: PluginType,
+            pub optional: bool,
+            pub doc: *const i8,
+            pub secret: bool,
+            pub min: *const PluginValue,
+            pub max: *const PluginValue,
+            pub max_len: usize,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"login".as_ptr(),
+                arg_types: [PluginType::String, PluginType::UInt].as_ptr(),
+                arg_types_len: 2,
+                return_type: PluginType::Bool,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_describe_arg_v2(index: usize) -> ArgDescriptor {
+            let (name, arg_type, secret) = match index {
+                0 => (c"user".as_ptr(), PluginType::String, false),
+                1 => (c"pin".as_ptr(), PluginType::UInt, true),
+                _ => (std::ptr::null(), PluginType::Bool, false),
+            };
+            ArgDescriptor {
+                name,
+                arg_type,
+                optional: false,
+                doc: std::ptr::null(),
+                secret,
+                min: std::ptr::null(),
+                max: std::ptr::null(),
+                max_len: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::Bool(true))
+        }
+        "#,
+    );
+    let path = login.path().to_str().unwrap();
+
+    let output = host(&["--trace", path, "alice", "8675309"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let trace = stderr(&output);
+    assert!(
+        trace.contains("[trace] arg0: String = alice\n"),
+        "{}",
+        trace
+    );
+    assert!(trace.contains("[trace] arg1: UInt = ****\n"), "{}", trace);
+    assert!(!trace.contains("8675309"));
+
+    // nor is the value of a secret argument quoted when it can't be parsed
+    let output = host(&[path, "alice", "hunter2"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains("arg1"), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("hunter2"));
+
+    let recording = common::temp_path("secret-recording.jsonl");
+    let output = host(&["--record", recording.to_str().unwrap(), path, "alice", "1"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!recording.exists());
+}
+
+#[test]
+fn evaluates_nested_calls() {
+    let negate = plugin_path("negate");
+    let negate = negate.to_str().unwrap();
+    let output = host(&["--eval", r#"repeat(repeat("ab", 2), 3)"#, &repeat_path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: abababababab\n"));
+
+    let output = host(&["--eval", "negate(negate(-7))", negate, &repeat_path()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: -7\n"));
+
+    // the expression is checked before anything is called
+    let output = host(&[
+        "--eval",
+        r#"repeat("ab", negate(2))"#,
+        &repeat_path(),
+        negate,
+    ]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output)
+        .contains("repeat expects arg1 to be UInt, but it's passed a call returning Int"));
+    let output = host(&["--eval", "missing(1)", negate]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+}
+
+#[test]
+fn builds_metadata_from_config() {
+    // with a default count, repeat's metadata only declares the string argument
+    let output = host(&[
+        "--config",
+        "default_count=4",
+        "--trace",
+        &repeat_path(),
+        "ab",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("[trace] metadata arg_types: [String]\n"));
+    assert!(stdout(&output).contains("Plugin returned: abababab\n"));
+
+    let output = host(&["--config", "default_count=4", &repeat_path(), "ab", "2"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+
+    let output = host(&["--trace", &repeat_path(), "ab", "2"]);
+    assert!(stderr(&output).contains("[trace] metadata arg_types: [String, UInt]\n"));
+
+    // an invalid count fails plugin_init, before the metadata is built from it
+    let output = host(&["--config", "default_count=x", &repeat_path(), "ab"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).contains("default_count should be a number"));
+}
+
+#[test]
+fn prints_doubles_in_requested_format() {
+    let scale = plugin_path("scale");
+    let printed = |format: &str| {
+        stdout(&host(&[
+            "--float-format",
+            format,
+            scale.to_str().unwrap(),
+            "0.1",
+            "1",
+            "false",
+        ]))
+    };
+    assert!(printed("plain").contains("Plugin returned: 0.1\n"));
+    assert!(printed("fixed:3").contains("Plugin returned: 0.100\n"));
+    assert!(printed("scientific").contains("Plugin returned: 1e-1\n"));
+    assert!(printed("roundtrip").contains("Plugin returned: 0.1\n"));
+
+    let output = host(&["--float-format", "exact", scale.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn survives_aborting_plugin_in_subprocess() {
+    let aborter = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"aborter".as_ptr(),
+                arg_types: [PluginType::String].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            std::process::abort()
+        }
+        "#,
+    );
+    let path = aborter.path().to_str().unwrap();
+
+    // in process, the abort takes the host down with it
+    let output = host(&[path, "boom"]);
+    assert!(!output.status.success());
+    assert_ne!(output.status.code(), Some(6));
+
+    // in a worker, the host reports it like a panic
+    let output = host(&["--isolation", "subprocess", path, "boom"]);
+    assert_eq!(output.status.code(), Some(6), "{}", stderr(&output));
+    let errors = stderr(&output);
+    assert!(
+        errors.contains("Plugin worker exited unexpectedly"),
+        "{}",
+        errors
+    );
+
+    let output = host(&["--isolation", "subprocess", &repeat_path(), "ab", "2"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: abab\n"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn prints_argument_errors_as_json() {
+    let output = host(&["--format", "json", &repeat_path(), "cool", "abc"]);
+    assert_eq!(output.status.code(), Some(4));
+    let printed: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    let error = &printed["error"];
+    assert_eq!(error["code"], 4);
+    assert_eq!(
+        error["arg"],
+        serde_json::json!({
+            "index": 1,
+            "expected": "UInt",
+            "value": "abc",
+            "kind": "malformed",
+        })
+    );
+
+    let output = host(&["--format", "json", &repeat_path(), "cool", "2"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "{\"ok\":{\"String\":\"coolcool\"},\"warning\":null}\n"
+    );
+}
+
+#[test]
+fn prints_results_as_csv() {
+    let output = host(&["--format", "csv", &repeat_path(), r#"a,"b""#, "2"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "\"a,\"\"b\"\"a,\"\"b\"\"\"\n");
+
+    let output = host(&["--format", "csv", &repeat_path(), "ab", "2"]);
+    assert_eq!(stdout(&output), "abab\n");
+}
+
+#[test]
+fn searches_plugin_path_for_relative_plugins() {
+    let path = plugin_path("plugin");
+    let (dir, name) = (path.parent().unwrap(), path.file_name().unwrap());
+    let name = name.to_str().unwrap();
+    let elsewhere = std::env::temp_dir();
+    let missing = elsewhere.join("host-test-no-such-dir");
+
+    // not in the working directory, nor the first search directory, but in the second
+    let output = common::host_command(&[
+        "--plugin-path",
+        missing.to_str().unwrap(),
+        "--plugin-path",
+        dir.to_str().unwrap(),
+        name,
+        "ab",
+        "2",
+    ])
+    .current_dir(&elsewhere)
+    .output()
+    .unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: abab\n"));
+
+    // a bare name is found in the working directory, rather than the library path
+    let output = common::host_command(&[name, "ab", "2"])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+    let output = common::host_command(&["--plugin-path", missing.to_str().unwrap(), name])
+        .current_dir(&elsewhere)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(
+        stderr(&output).contains(&format!(
+            "Could not find plugin {}; tried:\n  ./{}\n  {}\n",
+            name,
+            name,
+            missing.join(name).display()
+        )),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn lists_capabilities_of_repeat() {
+    let output = host(&["--caps", &repeat_path()]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let output = stdout(&output);
+    let (host_table, plugin_table) = output.split_once("\n\n").unwrap();
+    let mut rows = host_table
+        .lines()
+        .map(|row| row.split_whitespace().collect::<Vec<_>>());
+    assert_eq!(rows.next().unwrap(), ["Capability", "Required", "Provided"]);
+    let rows: Vec<_> = rows.collect();
+    assert!(rows.iter().any(|row| row[0] == "log"), "{}", output);
+    // repeat uses the host services, but doesn't require any of them
+    assert!(rows.iter().all(|row| row[1] == "no"), "{}", output);
+
+    let rows: Vec<_> = plugin_table
+        .lines()
+        .skip(1)
+        .map(|row| row.split_whitespace().collect::<Vec<_>>())
+        .collect();
+    assert!(rows.contains(&vec!["THREAD_SAFE", "yes"]), "{}", output);
+    assert!(rows.contains(&vec!["PURE", "yes"]), "{}", output);
+
+    let output = host(&["--caps", &repeat_path(), "ab", "2"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn lists_only_matching_plugins() {
+    let repeat = repeat_path();
+    let negate = plugin_path("negate");
+    let scale = plugin_path("scale");
+    let list = |tags: &[&str]| {
+        let mut args = vec!["--list"];
+        for tag in tags {
+            args.extend(["--tag", tag]);
+        }
+        args.extend([
+            repeat.as_str(),
+            negate.to_str().unwrap(),
+            scale.to_str().unwrap(),
+        ]);
+        let output = host(&args);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        let table = stdout(&output);
+        let rows: Vec<_> = table
+            .lines()
+            .skip(1)
+            .map(|row| row.split("  ").next().unwrap().to_string())
+            .collect();
+        (rows, table)
+    };
+
+    let (names, _) = list(&[]);
+    assert_eq!(names, ["repeat", "negate", "scale"]);
+
+    // only repeat is tagged, and a plugin must have every tag given
+    let (names, table) = list(&["category=text"]);
+    assert_eq!(names, ["repeat"]);
+    assert!(table.contains("category=text, example"), "{}", table);
+    let (names, _) = list(&["category=text", "example"]);
+    assert_eq!(names, ["repeat"]);
+    let (names, _) = list(&["category=text", "experimental"]);
+    assert!(names.is_empty());
+
+    let output = host(&["--tag", "example", &repeat_path(), "ab", "2"]);
+    assert_eq!(output.status.code(), Some(2));
+}
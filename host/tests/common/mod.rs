@@ -0,0 +1,147 @@
+// Helpers shared by the integration tests, which load the sample plugin crates next to the host
+// (building them on first use) or mock plugins built from inline source. Each test binary only
+// uses some of these.
+#![allow(dead_code)]
+
+use host::{MockPlugin, Plugin, PluginError};
+use std::collections::HashMap;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Mutex, OnceLock};
+
+// The sample plugins already built by this test binary, by crate name
+static BUILT: OnceLock<Mutex<HashMap<String, PathBuf>>> = OnceLock::new();
+
+// Builds the sample plugin crate `name` (e.g. "plugin" for the repeat plugin) and returns the path
+// of its library
+pub fn plugin_path(name: &str) -> PathBuf {
+    let mut built = BUILT.get_or_init(Default::default).lock().unwrap();
+    if let Some(path) = built.get(name) {
+        return path.clone();
+    }
+
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join(name)
+        .join("Cargo.toml");
+    let target = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("plugins")
+        .join(name);
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--quiet", "--manifest-path"])
+        .arg(&manifest)
+        .arg("--target-dir")
+        .arg(&target)
+        .status()
+        .expect("could not run cargo");
+    assert!(status.success(), "could not build the {} plugin", name);
+
+    let crate_name = name.replace('-', "_");
+    let path = target
+        .join("debug")
+        .join(format!("{}{}{}", DLL_PREFIX, crate_name, DLL_SUFFIX));
+    built.insert(name.to_string(), path.clone());
+    path
+}
+
+// Loads the sample plugin crate `name` with no config
+pub fn load(name: &str) -> Plugin {
+    Plugin::load(plugin_path(name), HashMap::new()).unwrap()
+}
+
+// Loads the sample plugin crate `name` with the given config values
+pub fn load_with_config(name: &str, config: &[(&str, &str)]) -> Plugin {
+    Plugin::load(plugin_path(name), config_map(config)).unwrap()
+}
+
+pub fn config_map(config: &[(&str, &str)]) -> HashMap<CString, CString> {
+    config
+        .iter()
+        .map(|(key, value)| (CString::new(*key).unwrap(), CString::new(*value).unwrap()))
+        .collect()
+}
+
+// Source for mock plugins that use the host services, to include before their own: the services
+// struct (with the log field type it uses), an export of `plugin_init` that keeps it, and
+// `services()` to read it during a call
+pub const HOST_SERVICES: &str = r#"
+    use std::ffi::c_void;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    #[repr(C)]
+    pub struct KeyValue {
+        pub key: *const i8,
+        pub value: PluginValue,
+    }
+
+    #[repr(C)]
+    pub struct HostServices {
+        pub context: *const c_void,
+        pub log: extern "C" fn(*const c_void, i32, *const i8),
+        pub get_config: extern "C" fn(*const c_void, *const i8) -> *const i8,
+        pub is_cancelled: extern "C" fn(*const c_void) -> bool,
+        pub report_progress: extern "C" fn(*const c_void, f64),
+        pub emit: extern "C" fn(*const c_void, *const PluginValue) -> bool,
+        pub time_remaining: extern "C" fn(*const c_void) -> f64,
+        pub alloc: extern "C" fn(*const c_void, usize, usize) -> *mut u8,
+        pub free: extern "C" fn(*const c_void, *mut u8, usize, usize),
+        pub log_fields: extern "C" fn(*const c_void, i32, *const i8, *const KeyValue, usize),
+    }
+
+    static SERVICES: AtomicPtr<HostServices> = AtomicPtr::new(std::ptr::null_mut());
+
+    #[no_mangle]
+    pub extern "C" fn plugin_init(services: *const HostServices) -> *mut i8 {
+        SERVICES.store(services as *mut HostServices, Ordering::Release);
+        std::ptr::null_mut()
+    }
+
+    fn services() -> &'static HostServices {
+        unsafe { &*SERVICES.load(Ordering::Acquire) }
+    }
+"#;
+
+// Builds a mock plugin from inline source, panicking with the compiler's errors if it doesn't build
+pub fn mock(source: &str) -> MockPlugin {
+    MockPlugin::build(source).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// A path in the temporary directory that's unique to this test process, for files written by tests
+// (or by the plugins they load); it's removed first if it exists
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("host-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+// The error of a result whose value can't be printed, for which `unwrap_err` isn't available
+pub fn expect_err<T>(result: Result<T, PluginError>) -> PluginError {
+    match result {
+        Ok(_) => panic!("expected an error"),
+        Err(e) => e,
+    }
+}
+
+// Runs the host binary with `args`
+pub fn host(args: &[&str]) -> Output {
+    host_command(args).output().expect("could not run the host")
+}
+
+// A command to run the host binary with `args`, for tests that need to set it up further (e.g. to
+// pass stdin)
+pub fn host_command(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_host"));
+    command.args(args);
+    command
+}
+
+// The stdout and stderr of a host run, as strings
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
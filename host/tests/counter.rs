@@ -0,0 +1,66 @@
+// Tests of resetting a stateful plugin, through the sample counter plugin and a mock plugin that
+// can only be reset by reloading it
+mod common;
+
+use common::{load, mock};
+use host::{OptionalSymbol, OwnedPluginValue, Plugin, PluginError};
+use std::collections::HashMap;
+
+fn count(plugin: &Plugin) -> u64 {
+    match plugin.call(&[]).unwrap() {
+        OwnedPluginValue::UInt(count) => count,
+        value => panic!("expected a UInt, got {}", value),
+    }
+}
+
+#[test]
+fn reset_zeroes_counter_without_reloading() {
+    let plugin = load("counter");
+    assert!(plugin.has(OptionalSymbol::Reset));
+    assert_eq!([count(&plugin), count(&plugin), count(&plugin)], [1, 2, 3]);
+
+    plugin.reset().unwrap();
+    assert_eq!(count(&plugin), 1);
+    assert_eq!(count(&plugin), 2);
+
+    // which reset_or_reload uses rather than reloading the plugin
+    let plugin = plugin.reset_or_reload().unwrap();
+    assert_eq!(count(&plugin), 1);
+}
+
+#[test]
+fn reloads_plugin_without_reset_export() {
+    let counter = mock(
+        r#"
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"counter".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::UInt(CALLS.fetch_add(1, Ordering::Relaxed) + 1))
+        }
+        "#,
+    );
+    let plugin = Plugin::load(counter.path(), HashMap::new()).unwrap();
+    assert_eq!([count(&plugin), count(&plugin)], [1, 2]);
+
+    let e = plugin.reset().unwrap_err();
+    assert!(matches!(e, PluginError::Usage(_)), "{}", e);
+    assert_eq!(e.to_string(), "Plugin counter doesn't export plugin_reset");
+    assert_eq!(count(&plugin), 3);
+
+    let plugin = plugin.reset_or_reload().unwrap();
+    assert_eq!(count(&plugin), 1);
+}
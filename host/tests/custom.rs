@@ -0,0 +1,118 @@
+// Tests of custom value types, which the host passes as opaque payloads and renders through the
+// handlers registered for them
+mod common;
+
+use common::{expect_err, mock};
+use host::{CustomType, CustomTypes, FormatOptions, OwnedPluginValue, Plugin};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const POINT: u32 = 1;
+
+// A point with i32 coordinates, encoded as their little-endian bytes
+struct Point;
+
+impl CustomType for Point {
+    fn name(&self) -> &str {
+        "Point"
+    }
+
+    fn format(&self, payload: &[u8]) -> String {
+        let coord = |i: usize| i32::from_le_bytes(payload[i * 4..i * 4 + 4].try_into().unwrap());
+        format!("({}, {})", coord(0), coord(1))
+    }
+
+    fn parse(&self, s: &str) -> Result<Vec<u8>, String> {
+        let (x, y) = s.split_once(',').ok_or("expected x,y")?;
+        let x: i32 = x.trim().parse().map_err(|_| format!("bad x: {}", x))?;
+        let y: i32 = y.trim().parse().map_err(|_| format!("bad y: {}", y))?;
+        Ok([x.to_le_bytes(), y.to_le_bytes()].concat())
+    }
+}
+
+// A plugin that doubles a point, declared with raw types as the mock FFI types have no Custom
+const DOUBLE: &str = r#"
+    #[repr(C)]
+    pub struct RawMetadata {
+        name: *const i8,
+        arg_types: *const std::ffi::c_int,
+        arg_types_len: usize,
+        return_type: std::ffi::c_int,
+        required_host_capabilities: u64,
+    }
+
+    const POINT: std::ffi::c_int = 0x4000_0000 + 1;
+
+    #[no_mangle]
+    pub extern "C" fn plugin_metadata() -> RawMetadata {
+        RawMetadata {
+            name: c"double".as_ptr(),
+            arg_types: [POINT].as_ptr(),
+            arg_types_len: 1,
+            return_type: POINT,
+            required_host_capabilities: 0,
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {
+        let PluginValue::Custom { type_id: 1, ptr, len: 8 } = (unsafe { &*args }) else {
+            unreachable!()
+        };
+        let point = unsafe { std::slice::from_raw_parts(*ptr, 8) };
+        let doubled: Box<[u8]> = point
+            .chunks(4)
+            .flat_map(|c| (i32::from_le_bytes(c.try_into().unwrap()) * 2).to_le_bytes())
+            .collect();
+        let ptr = Box::into_raw(doubled) as *const u8;
+        PluginResult::Ok(PluginValue::Custom { type_id: 1, ptr, len: 8 })
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_free_bytes(ptr: *mut u8, len: usize) {
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) });
+    }
+"#;
+
+#[test]
+fn renders_custom_values_with_registered_handler() {
+    let mut types = CustomTypes::default();
+    types.register(POINT, Point);
+    let options = FormatOptions {
+        custom_types: Some(Arc::new(types)),
+        ..Default::default()
+    };
+
+    let mock = mock(DOUBLE);
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let arg = options
+        .custom_types
+        .as_ref()
+        .unwrap()
+        .parse(POINT, "3, -4")
+        .unwrap();
+    let value = plugin.call(&[arg]).unwrap();
+    assert_eq!(value.format(&options).unwrap(), "(6, -8)");
+
+    // without a handler, the payload is shown as raw bytes
+    let raw = value.format(&FormatOptions::default()).unwrap();
+    assert!(raw.starts_with("Custom(1) "), "{}", raw);
+}
+
+#[test]
+fn rejects_custom_values_without_handler() {
+    let types = CustomTypes::default();
+    let e = expect_err(types.parse(POINT, "3, -4"));
+    assert_eq!(e.to_string(), "No handler is registered for Custom(1)");
+
+    let mut types = CustomTypes::default();
+    types.register(POINT, Point);
+    assert!(matches!(
+        types.parse(POINT, "3"),
+        Err(host::PluginError::Argument(_))
+    ));
+    assert!(matches!(
+        types.parse(POINT, "3,4"),
+        Ok(OwnedPluginValue::Custom { type_id: POINT, .. })
+    ));
+}
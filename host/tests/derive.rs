@@ -0,0 +1,41 @@
+// Tests of the IntoPluginArgs derive macro, which require the derive feature
+#![cfg(feature = "derive")]
+mod common;
+
+use common::{expect_err, load};
+use host::{IntoPluginArgs, PluginError};
+
+#[derive(IntoPluginArgs)]
+struct Repeat {
+    string: String,
+    count: u64,
+}
+
+#[test]
+fn calls_repeat_with_derived_args() {
+    let plugin = load("plugin");
+    let args = Repeat {
+        string: "ab".to_string(),
+        count: 3,
+    };
+    let repeated: String = plugin.call_named(args).unwrap();
+    assert_eq!(repeated, "ababab");
+}
+
+// The fields are in the right order, but not named like the plugin's arguments
+#[derive(IntoPluginArgs)]
+struct Misnamed {
+    text: String,
+    count: u64,
+}
+
+#[test]
+fn rejects_fields_not_named_like_args() {
+    let plugin = load("plugin");
+    let args = Misnamed {
+        text: "ab".to_string(),
+        count: 3,
+    };
+    let e = expect_err(plugin.call_named::<_, String>(args));
+    assert!(matches!(e, PluginError::Argument(_)), "{}", e);
+}
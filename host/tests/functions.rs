@@ -0,0 +1,123 @@
+// Tests of plugins that export several functions, through the `arith` plugin, whose functions are
+// registered with `#[plugin_export]`
+mod common;
+
+use common::{expect_err, host, load, plugin_path, stdout};
+use host::{OwnedPluginValue, PluginError, PluginType};
+
+#[test]
+fn describes_each_exported_function() {
+    let plugin = load("arith");
+    let functions: Vec<_> = plugin
+        .functions()
+        .iter()
+        .map(|f| {
+            let arg_types: Vec<_> = f.arg_types.iter().map(PluginType::to_string).collect();
+            (f.name.as_str(), arg_types, f.return_type.to_string())
+        })
+        .collect();
+    assert_eq!(
+        functions,
+        [
+            (
+                "add",
+                vec!["Int".to_string(), "Int".to_string()],
+                "Int".to_string()
+            ),
+            (
+                "count",
+                vec!["String".to_string(), "String".to_string()],
+                "Int".to_string()
+            ),
+            ("halve", vec!["Double".to_string()], "Double".to_string()),
+            ("is_even", vec!["Int".to_string()], "Bool".to_string()),
+        ]
+    );
+    // the default function is the first of them
+    assert_eq!(plugin.metadata().name, "add");
+}
+
+#[test]
+fn calls_each_exported_function() {
+    let plugin = load("arith");
+    let args = [OwnedPluginValue::Int(i64::MAX), OwnedPluginValue::Int(1)];
+    match plugin.call_function("add", &args).unwrap() {
+        OwnedPluginValue::Int(x) => assert_eq!(x, i64::MAX),
+        value => panic!("expected an Int, got {}", value),
+    }
+    match plugin
+        .call_function("halve", &[OwnedPluginValue::Double(3.0)])
+        .unwrap()
+    {
+        OwnedPluginValue::Double(x) => assert_eq!(x, 1.5),
+        value => panic!("expected a Double, got {}", value),
+    }
+    match plugin
+        .call_function("is_even", &[OwnedPluginValue::Int(-4)])
+        .unwrap()
+    {
+        OwnedPluginValue::Bool(x) => assert!(x),
+        value => panic!("expected a Bool, got {}", value),
+    }
+
+    // arguments are checked against the function's own metadata
+    let e = expect_err(plugin.call_function("halve", &[OwnedPluginValue::Int(3)]));
+    assert!(matches!(e, PluginError::Argument(_)), "{}", e);
+    assert_eq!(
+        e.to_string(),
+        "arg0 has type Int, but the plugin expects Double"
+    );
+    let e = expect_err(plugin.call_function("sqrt", &[OwnedPluginValue::Double(4.0)]));
+    assert_eq!(e.to_string(), "Plugin add has no function named sqrt");
+}
+
+#[test]
+fn calls_exported_function_by_name() {
+    let arith = plugin_path("arith");
+    let output = host(&[arith.to_str().unwrap(), "halve", "3"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("Plugin returned: 1.5\n"));
+
+    let output = host(&["--functions", arith.to_str().unwrap()]);
+    assert!(output.status.success());
+    let table = stdout(&output);
+    let names: Vec<_> = table
+        .lines()
+        .skip_while(|line| line.starts_with("Loaded"))
+        .skip(1)
+        .map(|row| row.split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(names, ["add", "count", "halve", "is_even"], "{}", table);
+}
+
+#[test]
+fn omits_optional_function_arguments() {
+    let plugin = load("arith");
+    // `count`'s pattern is an `Option<&str>`, which `#[plugin_export]` registers as optional
+    let count = plugin
+        .functions()
+        .into_iter()
+        .find(|f| f.name == "count")
+        .unwrap();
+    assert_eq!(count.signature(), ["text: String", "[pattern: String]"]);
+    assert_eq!(count.required_args(), 1);
+
+    let text = || OwnedPluginValue::String(c"banana".into());
+    let count = |args: &[OwnedPluginValue]| match plugin.call_function("count", args) {
+        Ok(OwnedPluginValue::Int(x)) => x,
+        result => panic!("expected an Int, got {:?}", result.map(|v| v.to_string())),
+    };
+    // with the pattern, its occurrences are counted; without it, as None, the characters
+    assert_eq!(count(&[text(), OwnedPluginValue::String(c"an".into())]), 2);
+    assert_eq!(count(&[text()]), 6);
+    assert_eq!(count(&[text(), OwnedPluginValue::Null]), 6);
+
+    // the text is still required
+    let e = expect_err(plugin.call_function("count", &[]));
+    assert!(matches!(e, PluginError::Argument(_)), "{}", e);
+    let e = expect_err(plugin.call_function("count", &[OwnedPluginValue::Null, text()]));
+    assert_eq!(
+        e.to_string(),
+        "arg0 has type Null, but the plugin expects String"
+    );
+}
@@ -0,0 +1,138 @@
+// Tests of Json values, which require the serde feature
+#![cfg(feature = "serde")]
+mod common;
+
+use common::{expect_err, host, load, mock, stderr, stdout};
+use host::{FormatOptions, OwnedPluginValue, Plugin, PluginError};
+use std::collections::HashMap;
+
+// A plugin that returns a copy of its Json argument
+const ECHO: &str = r#"
+    #[no_mangle]
+    pub extern "C" fn plugin_metadata() -> PluginMetadata {
+        PluginMetadata {
+            name: c"echo".as_ptr(),
+            arg_types: [PluginType::Json].as_ptr(),
+            arg_types_len: 1,
+            return_type: PluginType::Json,
+            required_host_capabilities: 0,
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {
+        let PluginValue::Json(json) = (unsafe { &*args }) else {
+            unreachable!("the host checks the argument types");
+        };
+        let copy = unsafe { std::ffi::CStr::from_ptr(*json) }.to_owned();
+        PluginResult::Ok(PluginValue::Json(copy.into_raw()))
+    }
+"#;
+
+#[test]
+fn round_trips_nested_object() {
+    let mock = mock(ECHO);
+    let json =
+        r#"{"args":[{"type":"String"},{"max":100,"type":"UInt"}],"name":"repeat","pure":true}"#;
+    let output = host(&[mock.path().to_str().unwrap(), json]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).contains(&format!("Plugin returned: {}", json)),
+        "{}",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn keeps_key_order_of_returned_object() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"unsorted".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::Json,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            let json = c"{\"zeta\":1,\"alpha\":2,\"mid\":{\"y\":1,\"b\":[3,2]}}";
+            PluginResult::Ok(PluginValue::Json(json.to_owned().into_raw()))
+        }
+        "#,
+    );
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let value = plugin.call(&[]).unwrap();
+    let OwnedPluginValue::Json(json) = &value else {
+        panic!("expected a Json result");
+    };
+    let keys: Vec<_> = json.as_object().unwrap().keys().collect();
+    assert_eq!(keys, ["zeta", "alpha", "mid"]);
+    assert_eq!(
+        value.to_string(),
+        r#"{"zeta":1,"alpha":2,"mid":{"y":1,"b":[3,2]}}"#
+    );
+    assert_eq!(
+        value.format_csv(&FormatOptions::default()).unwrap(),
+        r#""zeta=1;alpha=2;mid=y=1;b=3,2;;""#
+    );
+}
+
+#[test]
+fn rejects_malformed_json() {
+    let mock_echo = mock(ECHO);
+    let output = host(&[mock_echo.path().to_str().unwrap(), r#"{"name": "#]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains("Invalid Json"));
+
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"malformed".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::Json,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::Json(c"{\"name\": ".to_owned().into_raw()))
+        }
+        "#,
+    );
+    let output = host(&[mock.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        stderr(&output).contains("Plugin returned invalid JSON"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn parses_error_payload() {
+    let plugin = load("negate");
+    let e = expect_err(plugin.call(&[OwnedPluginValue::Int(i64::MIN)]));
+    assert!(
+        matches!(&e, PluginError::WithPayload(error, _) if matches!(**error, PluginError::Plugin(_))),
+        "{:?}",
+        e
+    );
+    assert_eq!(e.exit_code(), 5);
+
+    let payload = e.payload_json().unwrap().unwrap();
+    assert_eq!(payload["arg"], "x");
+    assert_eq!(payload["suggested"], -i64::MAX);
+
+    // errors without a payload have none
+    let e = expect_err(plugin.call(&[OwnedPluginValue::UInt(1)]));
+    assert!(e.payload().is_none());
+}
@@ -0,0 +1,91 @@
+// Tests that calls free everything they allocate, which require the leak-check feature. The
+// allocator counts every allocation in the process, so this binary has a single test, to keep
+// other tests from allocating while it counts.
+#![cfg(feature = "leak-check")]
+mod common;
+
+use common::{host, load, mock, plugin_path, stderr};
+use host::{allocated_bytes, live_allocations, untracked_allocations, OwnedPluginValue, Plugin};
+use std::collections::HashMap;
+use std::ffi::CString;
+
+#[test]
+fn calls_free_their_arguments_and_results() {
+    let plugin = load("plugin");
+    for count in [0, 1, 1000] {
+        let live = live_allocations();
+        {
+            let args = vec![
+                OwnedPluginValue::String(CString::new("ab").unwrap()),
+                OwnedPluginValue::UInt(count),
+            ];
+            let value = plugin.call(&args).unwrap();
+            assert_eq!(value.to_string().len(), 2 * count as usize);
+        }
+        assert_eq!(live_allocations(), live, "leaked repeating {} times", count);
+    }
+    assert_eq!(untracked_allocations(), 0);
+
+    // repeat allocates its result through the host services, so the host's allocator sees the
+    // result allocated, and freed when it's dropped
+    {
+        let args = vec![
+            OwnedPluginValue::String(CString::new("ab").unwrap()),
+            OwnedPluginValue::UInt(3),
+        ];
+        let live = live_allocations();
+        let value = plugin.call(&args).unwrap();
+        assert_eq!(live_allocations(), live + 1);
+        drop(value);
+        assert_eq!(live_allocations(), live);
+    }
+    // whereas one allocated by the plugin's own allocator isn't seen by the host's
+    let own = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"own".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::String(c"own".to_owned().into_raw()))
+        }
+        "#,
+    );
+    let own = Plugin::load(own.path(), HashMap::new()).unwrap();
+    let live = live_allocations();
+    let value = own.call(&[]).unwrap();
+    assert_eq!(live_allocations(), live);
+    assert_eq!(value.to_string(), "own");
+
+    // an argument handed back to a call that owns its arguments isn't copied, so the call
+    // allocates far less than the argument's size
+    let passthrough = load("passthrough");
+    let live = live_allocations();
+    {
+        let args = vec![OwnedPluginValue::String(
+            CString::new(vec![b'a'; 1 << 20]).unwrap(),
+        )];
+        let allocated = allocated_bytes();
+        let value = passthrough.call_owned(args).unwrap();
+        assert!(allocated_bytes() - allocated < 1024);
+        assert_eq!(value.to_string().len(), 1 << 20);
+    }
+    assert_eq!(live_allocations(), live);
+
+    // the host binary reports the same for its call
+    let path = plugin_path("plugin");
+    let output = host(&[path.to_str().unwrap(), "ab", "3"]);
+    assert!(
+        stderr(&output).contains("[leak-check] 0 allocations leaked by the call"),
+        "{}",
+        stderr(&output)
+    );
+}
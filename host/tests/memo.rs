@@ -0,0 +1,136 @@
+// Tests of memoizing calls to pure plugins, which require the serde feature
+#![cfg(feature = "serde")]
+mod common;
+
+use common::{host_command, mock, plugin_path, stderr, stdout};
+use host::{MemoStats, OwnedPluginValue, Plugin, PluginError};
+use std::collections::HashMap;
+
+// A plugin advertising PLUGIN_PURE that returns how many times it's been called, so that a cached
+// result can be told apart from a fresh call
+const COUNTING: &str = r#"
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+
+    #[no_mangle]
+    pub extern "C" fn plugin_metadata() -> PluginMetadata {
+        PluginMetadata {
+            name: c"counting".as_ptr(),
+            arg_types: [PluginType::UInt].as_ptr(),
+            arg_types_len: 1,
+            return_type: PluginType::UInt,
+            required_host_capabilities: 0,
+        }
+    }
+
+    // PLUGIN_PURE
+    #[no_mangle]
+    pub extern "C" fn plugin_capabilities() -> u64 {
+        1 << 1
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+        PluginResult::Ok(PluginValue::UInt(CALLS.fetch_add(1, Ordering::Relaxed) + 1))
+    }
+"#;
+
+fn call(plugin: &Plugin, arg: u64) -> u64 {
+    match plugin.call(&[OwnedPluginValue::UInt(arg)]).unwrap() {
+        OwnedPluginValue::UInt(calls) => calls,
+        value => panic!("expected a UInt, got {}", value),
+    }
+}
+
+#[test]
+fn answers_repeated_call_from_cache() {
+    let mock = mock(COUNTING);
+    let mut plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    assert!(plugin.metadata().is_pure());
+    assert_eq!(plugin.memo_stats(), None);
+    plugin.set_memoize(1).unwrap();
+
+    assert_eq!(call(&plugin, 1), 1);
+    assert_eq!(call(&plugin, 1), 1);
+    assert_eq!(plugin.memo_stats(), Some(MemoStats { hits: 1, misses: 1 }));
+
+    // calls by name and typed calls share the cache
+    let by_name = plugin
+        .call_function("counting", &[OwnedPluginValue::UInt(1)])
+        .unwrap();
+    assert!(matches!(by_name, OwnedPluginValue::UInt(1)), "{}", by_name);
+    assert_eq!(
+        plugin
+            .call_typed::<_, u64>(vec![OwnedPluginValue::UInt(1)])
+            .unwrap(),
+        1
+    );
+    assert_eq!(plugin.memo_stats(), Some(MemoStats { hits: 3, misses: 1 }));
+
+    // with room for one result, a call with other arguments evicts the first one
+    assert_eq!(call(&plugin, 2), 2);
+    assert_eq!(call(&plugin, 1), 3);
+    assert_eq!(plugin.memo_stats(), Some(MemoStats { hits: 3, misses: 3 }));
+}
+
+#[test]
+fn refuses_plugin_not_advertising_pure() {
+    let mock = mock(&COUNTING.replace("1 << 1", "0"));
+    let mut plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    assert!(!plugin.metadata().is_pure());
+    let e = plugin.set_memoize(1).unwrap_err();
+    assert!(matches!(e, PluginError::Usage(_)), "{}", e);
+    assert_eq!(
+        e.to_string(),
+        "counting can't be memoized, as it doesn't advertise PLUGIN_PURE"
+    );
+
+    // so its calls aren't cached
+    assert_eq!(call(&plugin, 1), 1);
+    assert_eq!(call(&plugin, 1), 2);
+    assert_eq!(plugin.memo_stats(), None);
+}
+
+#[test]
+fn memoizes_stdin_lines() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let path = plugin_path("plugin");
+    let args = [
+        "--stdin",
+        "--memoize",
+        "8",
+        "--trace",
+        path.to_str().unwrap(),
+        "2",
+    ];
+    let mut child = host_command(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"a\nb\na\na\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "Loaded plugin repeat\n\
+         Plugin returned: aa\n\
+         Plugin returned: bb\n\
+         Plugin returned: aa\n\
+         Plugin returned: aa\n"
+    );
+    assert!(
+        stderr(&output).contains("[trace] answered 2 of 4 calls from the cache"),
+        "{}",
+        stderr(&output)
+    );
+}
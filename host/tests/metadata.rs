@@ -0,0 +1,360 @@
+// Tests of reading plugins' metadata, from the sample plugins and mock plugins
+mod common;
+
+use common::{expect_err, host, load, mock, plugin_path, stderr};
+use host::{OptionalSymbol, Plugin, PluginError, PluginType};
+use std::collections::HashMap;
+
+#[test]
+fn formats_repeat_raw_metadata() {
+    let plugin = load("plugin");
+    let raw = plugin.raw_metadata();
+    assert!(raw.contains("name: \"repeat\"\n"), "{}", raw);
+    assert!(raw.contains("arg_types: [String, UInt]\n"), "{}", raw);
+    assert!(raw.contains("return_type: String\n"), "{}", raw);
+}
+
+#[test]
+fn rejects_unknown_return_type() {
+    // the metadata is declared with a raw return type, as the FFI types can't hold an unknown one
+    let mock = mock(
+        r#"
+        #[repr(C)]
+        pub struct RawMetadata {
+            name: *const i8,
+            arg_types: *const PluginType,
+            arg_types_len: usize,
+            return_type: std::ffi::c_int,
+            required_host_capabilities: u64,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> RawMetadata {
+            RawMetadata {
+                name: c"future".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: 99,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::UInt(0))
+        }
+        "#,
+    );
+
+    let e = expect_err(Plugin::load(mock.path(), HashMap::new()));
+    assert!(matches!(e, PluginError::Load(_)));
+    assert!(
+        e.to_string()
+            .contains("unknown type 99 for the return value"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn reports_which_optional_symbols_plugins_export() {
+    use host::OptionalSymbol;
+
+    let exported = |plugin: &Plugin| -> Vec<OptionalSymbol> {
+        OptionalSymbol::ALL
+            .into_iter()
+            .filter(|symbol| plugin.has(*symbol))
+            .collect()
+    };
+    assert_eq!(
+        exported(&load("plugin")),
+        [
+            OptionalSymbol::DescribeArg,
+            OptionalSymbol::DescribeTag,
+            OptionalSymbol::Init,
+            OptionalSymbol::EntrypointOut,
+            OptionalSymbol::Capabilities
+        ]
+    );
+    assert_eq!(exported(&load("arith")), [OptionalSymbol::Functions]);
+    assert_eq!(
+        exported(&load("passthrough")),
+        [OptionalSymbol::EntrypointOwned]
+    );
+    assert!(exported(&load("negate")).is_empty());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn rejects_unterminated_name_without_overreading() {
+    // the name is the last 1500 bytes of a page with no terminator, followed by a page that can't
+    // be read, so reading past the host's limit would crash rather than find a zero byte
+    let unterminated = mock(
+        r#"
+        extern "C" {
+            fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, off: i64) -> *mut u8;
+            fn mprotect(addr: *mut u8, len: usize, prot: i32) -> i32;
+        }
+        const PAGE: usize = 4096;
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            let name = unsafe {
+                // PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS
+                let pages = mmap(std::ptr::null_mut(), 2 * PAGE, 3, 0x22, -1, 0);
+                assert!(pages as isize != -1);
+                std::ptr::write_bytes(pages, b'a', PAGE);
+                assert_eq!(mprotect(pages.add(PAGE), PAGE, 0), 0);
+                pages.add(PAGE - 1500)
+            };
+            PluginMetadata {
+                name: name as *const i8,
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::Bool,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::Bool(true))
+        }
+        "#,
+    );
+    let e = expect_err(Plugin::load(unterminated.path(), HashMap::new()));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    assert!(
+        e.to_string()
+            .ends_with("Plugin declared a name that isn't terminated within 1024 bytes"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn inspects_plugins_without_initializing_them() {
+    use host::OptionalSymbol;
+
+    let initialized = common::temp_path("inspect-initialized");
+    let mock = mock(&format!(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_init(_services: *const std::ffi::c_void) -> *mut i8 {{
+            std::fs::write({:?}, "").unwrap();
+            std::ptr::null_mut()
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"eager".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            PluginResult::Ok(PluginValue::UInt(0))
+        }}
+        "#,
+        initialized
+    ));
+
+    assert_eq!(Plugin::inspect(mock.path()).unwrap().name, "eager");
+    assert_eq!(Plugin::inspect_functions(mock.path()).unwrap().len(), 1);
+    let (metadata, exports) = Plugin::inspect_exports(mock.path()).unwrap();
+    assert_eq!(metadata.name, "eager");
+    assert_eq!(exports, [OptionalSymbol::Init]);
+    assert!(!initialized.exists());
+
+    // loading the plugin to call it does initialize it
+    Plugin::load(mock.path(), HashMap::new()).unwrap();
+    assert!(initialized.exists());
+    std::fs::remove_file(&initialized).unwrap();
+}
+
+#[test]
+fn reads_args_from_unversioned_describe_arg() {
+    // a plugin built before descriptors had the secret flag and bounds, whose descriptor ends
+    // after the doc
+    let mock = mock(
+        r#"
+        #[repr(C)]
+        pub struct ArgDescriptor {
+            name: *const i8,
+            arg_type: PluginType,
+            optional: bool,
+            doc: *const i8,
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"legacy".as_ptr(),
+                arg_types: [PluginType::String, PluginType::UInt].as_ptr(),
+                arg_types_len: 2,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_describe_arg(index: usize) -> ArgDescriptor {
+            let (name, arg_type) = match index {
+                0 => (c"password".as_ptr(), PluginType::String),
+                1 => (c"count".as_ptr(), PluginType::UInt),
+                _ => (std::ptr::null(), PluginType::UInt),
+            };
+            ArgDescriptor {
+                name,
+                arg_type,
+                optional: false,
+                doc: std::ptr::null(),
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::Null)
+        }
+        "#,
+    );
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    assert_eq!(plugin.symbol_version(OptionalSymbol::DescribeArg), Some(1));
+
+    let args = plugin.metadata().args.as_ref().unwrap();
+    let names: Vec<_> = args.iter().map(|arg| arg.name.as_str()).collect();
+    assert_eq!(names, ["password", "count"]);
+    for arg in args {
+        assert!(!arg.secret, "{}", arg.name);
+        assert_eq!((arg.min, arg.max, arg.max_len), (None, None, None));
+        assert_eq!(arg.constraints(), None);
+    }
+
+    // the example plugin exports both versions, and the host reads the newest
+    let repeat = load("plugin");
+    assert_eq!(repeat.symbol_version(OptionalSymbol::DescribeArg), Some(2));
+}
+
+// A plugin whose metadata has the given name and arg_types expressions, with `arg_types_len`
+// arguments
+fn with_metadata(name: &str, arg_types: &str, arg_types_len: usize) -> host::MockPlugin {
+    mock(&format!(
+        r#"
+        // bytes to point misaligned arg_types into
+        static BYTES: [u8; 16] = [0; 16];
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: {},
+                arg_types: {},
+                arg_types_len: {},
+                return_type: PluginType::Bool,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            PluginResult::Ok(PluginValue::Bool(true))
+        }}
+        "#,
+        name, arg_types, arg_types_len
+    ))
+}
+
+#[test]
+fn reads_metadata_with_null_optional_fields() {
+    // no arguments may be declared with null arg_types, and without argument descriptions or tags
+    let bare = with_metadata(r#"c"bare".as_ptr()"#, "std::ptr::null()", 0);
+    let metadata = Plugin::inspect(bare.path()).unwrap();
+    assert_eq!(metadata.name, "bare");
+    assert!(metadata.arg_types.is_empty());
+    assert_eq!(metadata.return_type, PluginType::Bool);
+    assert!(metadata.args.as_deref().unwrap_or_default().is_empty());
+    assert!(metadata.tags.is_empty());
+
+    let nameless = with_metadata("std::ptr::null()", "std::ptr::null()", 0);
+    let e = expect_err(Plugin::inspect(nameless.path()));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    assert_eq!(e.to_string(), "Plugin declared a null name");
+
+    let misaligned = with_metadata(
+        r#"c"misaligned".as_ptr()"#,
+        "unsafe { BYTES.as_ptr().add(1) as *const PluginType }",
+        2,
+    );
+    let e = expect_err(Plugin::inspect(misaligned.path()));
+    assert!(
+        e.to_string()
+            .starts_with("Plugin declares its arg_types at a misaligned address"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn checks_metadata_is_stable_across_reads() {
+    load("plugin").check_stable_metadata(10).unwrap();
+
+    // a plugin whose return type flips after its third read
+    let unstable = mock(
+        r#"
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static READS: AtomicUsize = AtomicUsize::new(0);
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            let return_type = if READS.fetch_add(1, Ordering::Relaxed) < 3 {
+                PluginType::UInt
+            } else {
+                PluginType::String
+            };
+            PluginMetadata {
+                name: c"unstable".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::UInt(0))
+        }
+        "#,
+    );
+    let plugin = Plugin::load(unstable.path(), HashMap::new()).unwrap();
+    let e = expect_err(plugin.check_stable_metadata(10));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    // the host reads the metadata twice while loading, so the change shows up on the third read
+    assert_eq!(
+        e.to_string(),
+        "Plugin metadata isn't stable: its return_type changed on read 3 of 11"
+    );
+
+    // and the host fails to load it with --check-stable-metadata
+    let path = unstable.path().to_str().unwrap();
+    let output = host(&["--check-stable-metadata", path]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(
+        stderr(&output).contains("its return_type changed"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(host(&[
+        "--check-stable-metadata",
+        plugin_path("plugin").to_str().unwrap(),
+        "a",
+        "2"
+    ])
+    .status
+    .success());
+}
@@ -0,0 +1,1022 @@
+// Tests of how the host handles plugins with pathological behavior, using mock plugins built from
+// inline source
+mod common;
+
+use common::{expect_err, host, mock, stderr, stdout, temp_path};
+use host::{Bytes, OwnedPluginValue, Plugin, PluginError};
+use std::collections::HashMap;
+
+// A plugin taking no arguments that returns the string `b"caf\xe9"` (Latin-1, not UTF-8)
+const INVALID_UTF8: &str = r#"
+    #[no_mangle]
+    pub extern "C" fn plugin_metadata() -> PluginMetadata {
+        PluginMetadata {
+            name: c"latin1".as_ptr(),
+            arg_types: std::ptr::null(),
+            arg_types_len: 0,
+            return_type: PluginType::String,
+            required_host_capabilities: 0,
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+        let string = unsafe { std::ffi::CString::from_vec_unchecked(b"caf\xe9".to_vec()) };
+        PluginResult::Ok(PluginValue::String(string.into_raw()))
+    }
+"#;
+
+#[test]
+fn invalid_utf8_is_replaced_by_default() {
+    let mock = mock(INVALID_UTF8);
+    let output = host(&[mock.path().to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Plugin returned: caf\u{fffd}"));
+}
+
+#[test]
+fn invalid_utf8_fails_in_strict_mode() {
+    let mock = mock(INVALID_UTF8);
+    let output = host(&["--strict-utf8", mock.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("Plugin returned invalid UTF-8"));
+}
+
+#[test]
+fn prints_partial_result_and_warning() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"partial".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::PartialOk {
+                value: PluginValue::UInt(3),
+                warning: std::ffi::CString::new("only 3 of 5 items processed").unwrap().into_raw(),
+            }
+        }
+        "#,
+    );
+
+    let output = host(&[mock.path().to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Plugin returned: 3"));
+    assert!(stderr(&output).contains("Warning: only 3 of 5 items processed"));
+}
+
+#[test]
+fn rejects_string_longer_than_limit() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"long".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            let string = std::ffi::CString::new("x".repeat(100)).unwrap();
+            PluginResult::Ok(PluginValue::String(string.into_raw()))
+        }
+        "#,
+    );
+
+    let mut plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    plugin.set_max_string_len(10);
+    let e = expect_err(plugin.call(&[]));
+    assert!(matches!(e, PluginError::Plugin(_)));
+    assert!(
+        e.to_string()
+            .contains("Plugin returned a string longer than the limit of 10 bytes"),
+        "{}",
+        e
+    );
+
+    // a string of exactly the limit is accepted
+    plugin.set_max_string_len(100);
+    assert_eq!(plugin.call(&[]).unwrap().to_string(), "x".repeat(100));
+
+    let output = host(&["--max-string-len", "10", mock.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("longer than the limit of 10 bytes"));
+}
+
+#[test]
+fn releases_each_handle_once() {
+    let releases = temp_path("releases");
+    let mock = mock(&format!(
+        r#"
+        use std::io::Write;
+        use std::sync::atomic::{{AtomicU64, Ordering}};
+
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"open".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::Handle,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            PluginResult::Ok(PluginValue::Handle(NEXT.fetch_add(1, Ordering::Relaxed)))
+        }}
+
+        // records each release in the test's file
+        #[no_mangle]
+        pub extern "C" fn plugin_release(token: u64) {{
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open({:?})
+                .unwrap();
+            writeln!(file, "{{}}", token).unwrap();
+        }}
+        "#,
+        releases
+    ));
+    let released = || std::fs::read_to_string(&releases).unwrap_or_default();
+
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let mut handles: Vec<_> = (0..3).map(|_| plugin.call(&[]).unwrap()).collect();
+    assert_eq!(released(), "");
+
+    drop(handles.remove(1));
+    assert_eq!(released(), "2\n");
+
+    // handles outlive the plugin, which keeps its library loaded until they're released
+    drop(plugin);
+    assert_eq!(released(), "2\n");
+    drop(handles);
+    assert_eq!(released(), "2\n1\n3\n");
+    let _ = std::fs::remove_file(&releases);
+}
+
+#[test]
+fn passes_handle_back_to_plugin_that_returned_it() {
+    // counters kept in a table by handle, with functions to create one, increment one and count
+    // the live ones
+    let counters = mock(
+        r#"
+        use std::collections::BTreeMap;
+        use std::sync::Mutex;
+
+        static COUNTERS: Mutex<BTreeMap<u64, u64>> = Mutex::new(BTreeMap::new());
+
+        fn function(
+            name: &'static std::ffi::CStr,
+            arg_types: &'static [PluginType],
+            return_type: PluginType,
+        ) -> PluginMetadata {
+            PluginMetadata {
+                name: name.as_ptr(),
+                arg_types: arg_types.as_ptr(),
+                arg_types_len: arg_types.len(),
+                return_type,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            plugin_describe_function(0)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_describe_function(index: usize) -> PluginMetadata {
+            match index {
+                0 => function(c"create", &[], PluginType::Handle),
+                1 => function(c"increment", &[PluginType::Handle], PluginType::UInt),
+                2 => function(c"live", &[], PluginType::UInt),
+                _ => PluginMetadata {
+                    name: std::ptr::null(),
+                    ..function(c"", &[], PluginType::UInt)
+                },
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, len: usize) -> PluginResult {
+            plugin_call(0, args, len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_call(index: usize, args: *const PluginValue, _len: usize) -> PluginResult {
+            let mut counters = COUNTERS.lock().unwrap();
+            match index {
+                0 => {
+                    let token = counters.keys().last().map_or(1, |last| last + 1);
+                    counters.insert(token, 0);
+                    PluginResult::Ok(PluginValue::Handle(token))
+                }
+                1 => {
+                    let PluginValue::Handle(token) = (unsafe { &*args }) else { unreachable!() };
+                    let count = counters.get_mut(token).unwrap();
+                    *count += 1;
+                    PluginResult::Ok(PluginValue::UInt(*count))
+                }
+                _ => PluginResult::Ok(PluginValue::UInt(counters.len() as u64)),
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_release(token: u64) {
+            COUNTERS.lock().unwrap().remove(&token);
+        }
+        "#,
+    );
+    let plugin = Plugin::load(counters.path(), HashMap::new()).unwrap();
+    let uint = |name: &str, args: &[OwnedPluginValue]| match plugin.call_function(name, args) {
+        Ok(OwnedPluginValue::UInt(count)) => count,
+        Ok(value) => panic!("expected a UInt, got {}", value),
+        Err(e) => panic!("{}", e),
+    };
+
+    let handle = plugin.call_function("create", &[]).unwrap();
+    assert!(matches!(handle, OwnedPluginValue::Handle(_)), "{}", handle);
+    let args = [handle];
+    assert_eq!(uint("increment", &args), 1);
+    assert_eq!(uint("increment", &args), 2);
+    assert_eq!(uint("live", &[]), 1);
+
+    // the handle means nothing to another instance of the plugin
+    let other = Plugin::load(counters.path(), HashMap::new()).unwrap();
+    let e = expect_err(other.call_function("increment", &args));
+    assert_eq!(
+        e.to_string(),
+        "arg0 is a handle returned by a different plugin"
+    );
+
+    drop(args);
+    assert_eq!(uint("live", &[]), 0);
+}
+
+#[test]
+fn frees_returned_bytes_through_plugin() {
+    let frees = temp_path("frees");
+    let mock = mock(&format!(
+        r#"
+        use std::io::Write;
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"echo".as_ptr(),
+                arg_types: [PluginType::Bytes].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::Bytes,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        // returns a copy of its argument, allocated by the plugin
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {{
+            let PluginValue::Bytes {{ ptr, len }} = (unsafe {{ &*args }}) else {{
+                unreachable!()
+            }};
+            let copy: Box<[u8]> = unsafe {{ std::slice::from_raw_parts(*ptr, *len) }}.into();
+            let len = copy.len();
+            let ptr = Box::into_raw(copy) as *const u8;
+            PluginResult::Ok(PluginValue::Bytes {{ ptr, len }})
+        }}
+
+        // frees the buffer with the allocator that allocated it, recording its contents in the
+        // test's file
+        #[no_mangle]
+        pub extern "C" fn plugin_free_bytes(ptr: *mut u8, len: usize) {{
+            let bytes = unsafe {{ Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) }};
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open({:?})
+                .unwrap();
+            writeln!(file, "{{:?}}", bytes).unwrap();
+        }}
+        "#,
+        frees
+    ));
+    let freed = || std::fs::read_to_string(&frees).unwrap_or_default();
+
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let arg = OwnedPluginValue::Bytes(Bytes::from(vec![0, 1, 255]));
+    let bytes = Bytes::try_from(plugin.call(&[arg]).unwrap()).unwrap();
+    assert_eq!(&*bytes, &[0, 1, 255]);
+    assert_eq!(freed(), "");
+
+    // the buffer is freed once, by the plugin, when the host drops it
+    drop(bytes);
+    assert_eq!(freed(), "[0, 1, 255]\n");
+    let _ = std::fs::remove_file(&frees);
+}
+
+#[test]
+fn rejects_unsupported_capability_before_init() {
+    let marker = temp_path("initialized");
+    let mock = mock(&format!(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"demanding".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::Bool,
+                // a capability no host provides
+                required_host_capabilities: 1 << 63,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            PluginResult::Ok(PluginValue::Bool(true))
+        }}
+
+        // leaves a marker in the test's file
+        #[no_mangle]
+        pub extern "C" fn plugin_init(_services: *const std::ffi::c_void) -> *mut i8 {{
+            std::fs::write({:?}, "").unwrap();
+            std::ptr::null_mut()
+        }}
+        "#,
+        marker
+    ));
+
+    let e = expect_err(Plugin::load(mock.path(), HashMap::new()));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    assert_eq!(
+        e.to_string(),
+        "Plugin requires host capabilities that aren't supported: unknown(0x8000000000000000)"
+    );
+    assert!(!marker.exists(), "plugin_init was called");
+}
+
+#[test]
+fn rejects_corrupt_result_discriminants() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"corrupt".as_ptr(),
+                arg_types: [PluginType::UInt].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::Bool,
+                required_host_capabilities: 0,
+            }
+        }
+
+        // A PluginResult as raw words, so that it can hold discriminants no PluginResult can:
+        // the result's tag, then its fields, which for Ok start with the value's tag
+        #[repr(C)]
+        pub struct Crafted {
+            tag: std::ffi::c_int,
+            fields: [u64; (std::mem::size_of::<PluginResult>() - 8) / 8],
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> Crafted {
+            let PluginValue::UInt(case) = (unsafe { &*args }) else {
+                unreachable!()
+            };
+            let mut crafted = Crafted {
+                tag: 0,
+                fields: [0; (std::mem::size_of::<PluginResult>() - 8) / 8],
+            };
+            match case {
+                // a result that's neither Ok, PartialOk nor Err
+                0 => crafted.tag = 7,
+                // an Ok Bool that's neither true nor false
+                _ => crafted.fields[1] = 2,
+            }
+            crafted
+        }
+        "#,
+    );
+
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    for (case, message) in [
+        (0, "unknown result discriminant 7"),
+        (1, "invalid Bool value 2"),
+    ] {
+        let e = expect_err(plugin.call(&[OwnedPluginValue::UInt(case)]));
+        assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+        assert_eq!(
+            e.to_string(),
+            format!("Plugin returned an invalid result: {}", message)
+        );
+    }
+}
+
+#[test]
+fn rejects_null_strings_in_results() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"null".as_ptr(),
+                arg_types: [PluginType::UInt].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {
+            let PluginValue::UInt(case) = (unsafe { &*args }) else {
+                unreachable!()
+            };
+            let null = std::ptr::null_mut();
+            match case {
+                0 => PluginResult::Ok(PluginValue::String(null)),
+                1 => PluginResult::PartialOk {
+                    value: PluginValue::String(c"partial".to_owned().into_raw()),
+                    warning: null,
+                },
+                _ => PluginResult::Err {
+                    code: PluginErrorCode::Failed,
+                    message: null,
+                    payload: null,
+                },
+            }
+        }
+        "#,
+    );
+
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    for (case, message) in [
+        (0, "Plugin returned a null string"),
+        (1, "Plugin returned a null warning"),
+        (2, "Plugin returned an error without a message"),
+    ] {
+        let e = expect_err(plugin.call(&[OwnedPluginValue::UInt(case)]));
+        assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+        assert_eq!(e.to_string(), message);
+    }
+}
+
+#[test]
+fn builds_mock_plugins_from_inline_source() {
+    let null = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"null".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::String,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::String(std::ptr::null()))
+        }
+        "#,
+    );
+    let output = host(&[null.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("Plugin returned a null string"));
+
+    // the library is removed with the mock
+    let path = null.path().to_path_buf();
+    assert!(path.is_file());
+    drop(null);
+    assert!(!path.exists());
+
+    let e = expect_err(host::MockPlugin::build("pub extern \"C\" fn broken( {}"));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    let message = e.to_string();
+    assert!(
+        message.starts_with("Could not build mock plugin: "),
+        "{}",
+        message
+    );
+    assert!(message.contains("error"), "{}", message);
+}
+
+// A plugin that exports a marker symbol named `marker`, besides the exports every plugin has
+#[cfg(target_os = "linux")]
+fn exporting(marker: &str) -> host::MockPlugin {
+    mock(&format!(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn {marker}() {{}}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"{marker}".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::Bool,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            PluginResult::Ok(PluginValue::Bool(true))
+        }}
+        "#
+    ))
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn keeps_plugin_symbols_local_by_default() {
+    use host::SymbolVisibility;
+    use std::ffi::CString;
+
+    // looks a symbol up among those visible to every library, as a later library's references
+    // would be resolved (RTLD_DEFAULT is null on Linux)
+    let probe = mock(
+        r#"
+        extern "C" {
+            fn dlsym(handle: *mut std::ffi::c_void, symbol: *const i8) -> *mut std::ffi::c_void;
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"probe".as_ptr(),
+                arg_types: [PluginType::String].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::Bool,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {
+            let PluginValue::String(symbol) = (unsafe { &*args }) else {
+                unreachable!();
+            };
+            let found = !unsafe { dlsym(std::ptr::null_mut(), *symbol) }.is_null();
+            PluginResult::Ok(PluginValue::Bool(found))
+        }
+        "#,
+    );
+    let local = exporting("local_visibility_marker");
+    let global = exporting("global_visibility_marker");
+    let _local = Plugin::load(local.path(), HashMap::new()).unwrap();
+    let _global =
+        Plugin::load_with_visibility(global.path(), HashMap::new(), SymbolVisibility::Global)
+            .unwrap();
+
+    let probe = Plugin::load(probe.path(), HashMap::new()).unwrap();
+    let visible = |symbol: &str| match probe
+        .call(&[OwnedPluginValue::String(CString::new(symbol).unwrap())])
+        .unwrap()
+    {
+        OwnedPluginValue::Bool(found) => found,
+        value => panic!("expected a Bool, got {}", value),
+    };
+    assert!(!visible("local_visibility_marker"));
+    assert!(visible("global_visibility_marker"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn names_missing_dependency_of_plugin() {
+    // a library linked against a mock plugin's, which is removed before it's loaded
+    let dependency = exporting("dependency_marker");
+    let source = temp_path("dependent.rs");
+    std::fs::write(
+        &source,
+        r#"
+        extern "C" {
+            fn dependency_marker();
+        }
+
+        #[no_mangle]
+        pub extern "C" fn call_dependency() {
+            unsafe { dependency_marker() }
+        }
+        "#,
+    )
+    .unwrap();
+    let dependent = temp_path("libdependent.so");
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = std::process::Command::new(rustc)
+        .args(["--crate-type", "cdylib", "--edition", "2021", "-L"])
+        .arg(dependency.path().parent().unwrap())
+        .args(["-l", "dylib=mock", "-o"])
+        .arg(&dependent)
+        .arg(&source)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    drop(dependency);
+
+    let e = expect_err(Plugin::load(&dependent, HashMap::new()));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    let message = e.to_string();
+    assert!(
+        message.contains("libmock.so: cannot open shared object file"),
+        "{}",
+        message
+    );
+    assert!(
+        message.contains("this is a library it depends on"),
+        "{}",
+        message
+    );
+    assert!(message.contains("LD_LIBRARY_PATH"), "{}", message);
+
+    // a missing plugin is reported as it is by the OS, without the hint
+    let _ = std::fs::remove_file(&dependent);
+    let message = expect_err(Plugin::load(&dependent, HashMap::new())).to_string();
+    assert!(
+        message.contains("cannot open shared object file"),
+        "{}",
+        message
+    );
+    assert!(!message.contains("LD_LIBRARY_PATH"), "{}", message);
+    let _ = std::fs::remove_file(&source);
+}
+
+// A plugin declaring `arg_types_len` arguments, whose types are at `arg_types` (an expression in the
+// plugin's source)
+fn declaring_args(arg_types: &str, arg_types_len: &str) -> host::MockPlugin {
+    mock(&format!(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"corrupt".as_ptr(),
+                arg_types: {},
+                arg_types_len: {},
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {{
+            PluginResult::Ok(PluginValue::UInt(0))
+        }}
+        "#,
+        arg_types, arg_types_len
+    ))
+}
+
+#[test]
+fn rejects_implausible_arg_types_len() {
+    let huge = declaring_args("[PluginType::UInt].as_ptr()", "usize::MAX");
+    let e = expect_err(Plugin::load(huge.path(), HashMap::new()));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    assert_eq!(
+        e.to_string(),
+        format!(
+            "Plugin declares {} arguments, more than the limit of 256",
+            usize::MAX
+        )
+    );
+
+    let null = declaring_args("std::ptr::null()", "2");
+    let e = expect_err(Plugin::load(null.path(), HashMap::new()));
+    assert_eq!(
+        e.to_string(),
+        "Plugin declares 2 arguments, but its arg_types are null"
+    );
+
+    // the limit itself is allowed
+    let most = declaring_args("[PluginType::UInt; 256].as_ptr()", "256");
+    let plugin = Plugin::load(most.path(), HashMap::new()).unwrap();
+    assert_eq!(plugin.metadata().arg_types.len(), 256);
+}
+
+#[test]
+fn rejects_list_longer_than_limit() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"list".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::List,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            // a single item, with a corrupt length that would read far past it
+            let item = Box::leak(Box::new(PluginValue::UInt(1)));
+            PluginResult::Ok(PluginValue::List { items: item, len: usize::MAX })
+        }
+        "#,
+    );
+
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let e = expect_err(plugin.call(&[]));
+    assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+    assert_eq!(
+        e.to_string(),
+        format!(
+            "Plugin returned a list of {} items, more than the limit of {}",
+            usize::MAX,
+            host::DEFAULT_MAX_LIST_LEN
+        )
+    );
+
+    let output = host(&["--max-list-len", "5", mock.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("more than the limit of 5"));
+}
+
+#[test]
+fn returns_inner_results_within_successful_call() {
+    // parses each of its argument's comma-separated items as a number, returning a list of
+    // per-item results; the empty string gives a result with a null value
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"parse".as_ptr(),
+                arg_types: [PluginType::String].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::List,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {
+            let PluginValue::String(s) = (unsafe { &*args }) else {
+                unreachable!();
+            };
+            let s = unsafe { std::ffi::CStr::from_ptr(*s) }.to_str().unwrap();
+            if s.is_empty() {
+                return PluginResult::Ok(PluginValue::ResultOk(std::ptr::null()));
+            }
+            let items: Box<[PluginValue]> = s
+                .split(',')
+                .map(|item| match item.parse::<u64>() {
+                    Ok(n) => PluginValue::ResultOk(Box::into_raw(Box::new(PluginValue::UInt(n)))),
+                    Err(_) => {
+                        let message = std::ffi::CString::new(format!("invalid number: {}", item));
+                        let message = PluginValue::String(message.unwrap().into_raw());
+                        PluginValue::ResultErr(Box::into_raw(Box::new(message)))
+                    }
+                })
+                .collect();
+            let len = items.len();
+            PluginResult::Ok(PluginValue::List { items: Box::into_raw(items) as *const PluginValue, len })
+        }
+        "#,
+    );
+
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let arg = |s: &str| [OwnedPluginValue::String(std::ffi::CString::new(s).unwrap())];
+    let OwnedPluginValue::List(items) = plugin.call(&arg("1,x,3")).unwrap() else {
+        panic!("expected a List");
+    };
+    let OwnedPluginValue::Result(Ok(value)) = &items[0] else {
+        panic!("expected an Ok, got {}", items[0]);
+    };
+    assert!(matches!(**value, OwnedPluginValue::UInt(1)));
+    let OwnedPluginValue::Result(Err(e)) = &items[1] else {
+        panic!("expected an Err, got {}", items[1]);
+    };
+    assert_eq!(e.to_string(), "invalid number: x");
+    assert_eq!(
+        OwnedPluginValue::List(items).to_string(),
+        "[Ok(1), Err(invalid number: x), Ok(3)]"
+    );
+
+    // the inner value must be there, even though the call succeeded
+    let e = expect_err(plugin.call(&arg("")));
+    assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+    assert_eq!(e.to_string(), "Plugin returned a Result with a null value");
+}
+
+#[test]
+fn marks_invalid_utf8_error_messages_in_strict_mode() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"garbled".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Err {
+                code: PluginErrorCode::Failed,
+                message: c"bad \xff msg".to_owned().into_raw(),
+                payload: std::ptr::null_mut(),
+            }
+        }
+        "#,
+    );
+
+    // the message is never rejected, as that would hide the error it describes
+    let mut plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let e = expect_err(plugin.call(&[]));
+    assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+    assert_eq!(e.to_string(), "bad \u{fffd} msg");
+
+    plugin.set_strict_utf8(true);
+    let e = expect_err(plugin.call(&[]));
+    assert_eq!(
+        e.to_string(),
+        "bad \u{fffd} msg (invalid UTF-8: invalid utf-8 sequence of 1 bytes from index 4)"
+    );
+
+    let output = host(&["--strict-utf8", mock.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("bad \u{fffd} msg (invalid UTF-8: "));
+}
+
+#[test]
+fn rejects_out_len_beyond_capacity() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"overflow".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Ok(PluginValue::UInt(0))
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint_out(
+            _args: *const PluginValue,
+            _args_len: usize,
+            out: *mut PluginValue,
+            out_capacity: usize,
+            out_len: *mut usize,
+        ) -> PluginResult {
+            for i in 0..out_capacity {
+                unsafe { out.add(i).write(PluginValue::UInt(i as u64)) };
+            }
+            // claims one more value than there was room for
+            unsafe { *out_len = out_capacity + 1 };
+            PluginResult::Ok(PluginValue::Null)
+        }
+        "#,
+    );
+
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    let e = expect_err(plugin.call_out(&[], 2));
+    assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+    assert_eq!(
+        e.to_string(),
+        "Plugin wrote 3 values, but there was only room for 2"
+    );
+}
+
+#[test]
+fn rejects_results_larger_than_limit() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"strings".as_ptr(),
+                arg_types: std::ptr::null(),
+                arg_types_len: 0,
+                return_type: PluginType::List,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            let items: Box<[PluginValue]> = (0..3)
+                .map(|_| PluginValue::String(c"abcd".to_owned().into_raw()))
+                .collect();
+            let len = items.len();
+            PluginResult::Ok(PluginValue::List { items: Box::into_raw(items) as *const PluginValue, len })
+        }
+        "#,
+    );
+
+    // the list's items, and the strings they hold with their terminators
+    let size = 3 * size_of::<OwnedPluginValue>() + 3 * 5;
+    let mut plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    plugin.set_max_result_bytes(size);
+    assert_eq!(plugin.call(&[]).unwrap().to_string(), "[abcd, abcd, abcd]");
+
+    plugin.set_max_result_bytes(size - 1);
+    let e = expect_err(plugin.call(&[]));
+    assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+    assert_eq!(
+        e.to_string(),
+        format!(
+            "Plugin returned a result larger than the limit of {} bytes",
+            size - 1
+        )
+    );
+
+    let output = host(&["--max-result-bytes", "10", mock.path().to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("larger than the limit of 10 bytes"));
+}
+
+#[test]
+fn reads_mapped_file_passed_as_bytes() {
+    let mock = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"count_a".as_ptr(),
+                arg_types: [PluginType::Bytes].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {
+            let PluginValue::Bytes { ptr, len } = (unsafe { &*args }) else {
+                unreachable!();
+            };
+            let bytes = if *len == 0 {
+                &[][..]
+            } else {
+                unsafe { std::slice::from_raw_parts(*ptr, *len) }
+            };
+            PluginResult::Ok(PluginValue::UInt(bytes.iter().filter(|b| **b == b'a').count() as u64))
+        }
+        "#,
+    );
+
+    // 64 MiB, with an 'a' at the start of every KiB
+    let data = temp_path("mapped.bin");
+    let mut contents = vec![b'.'; 64 << 20];
+    contents.iter_mut().step_by(1024).for_each(|b| *b = b'a');
+    std::fs::write(&data, &contents).unwrap();
+    drop(contents);
+
+    let bytes = Bytes::map(&data).unwrap();
+    assert!(bytes.is_mapped());
+    let arg = OwnedPluginValue::Bytes(bytes);
+    // the mapping isn't on the heap
+    assert_eq!(arg.heap_bytes(), 0);
+    let plugin = Plugin::load(mock.path(), HashMap::new()).unwrap();
+    assert_eq!(plugin.call(&[arg]).unwrap().to_string(), "65536");
+
+    let output = host(&[
+        "--map-bytes",
+        mock.path().to_str().unwrap(),
+        data.to_str().unwrap(),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: 65536\n"));
+
+    // empty files aren't mapped, but pass an empty buffer all the same
+    std::fs::write(&data, "").unwrap();
+    let empty = OwnedPluginValue::Bytes(Bytes::map(&data).unwrap());
+    assert_eq!(plugin.call(&[empty]).unwrap().to_string(), "0");
+    std::fs::remove_file(&data).unwrap();
+
+    let e = expect_err(Bytes::map(&data));
+    assert!(matches!(e, PluginError::Argument(_)), "{}", e);
+}
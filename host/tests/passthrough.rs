@@ -0,0 +1,78 @@
+// Tests of plugins handing an argument back as their result, through the `passthrough` plugin and
+// mock plugins
+mod common;
+
+use common::{expect_err, load, mock};
+use host::{OwnedPluginValue, Plugin};
+use std::collections::HashMap;
+use std::ffi::{c_char, CString};
+
+fn string_arg(s: &str) -> Vec<OwnedPluginValue> {
+    vec![OwnedPluginValue::String(CString::new(s).unwrap())]
+}
+
+fn string_ptr(value: &OwnedPluginValue) -> *const c_char {
+    match value {
+        OwnedPluginValue::String(s) => s.as_ptr(),
+        value => panic!("expected a String, got {}", value),
+    }
+}
+
+#[test]
+fn hands_back_owned_argument_without_copying() {
+    let plugin = load("passthrough");
+    let args = string_arg("borrowed");
+    let arg = string_ptr(&args[0]);
+    let value = plugin.call_owned(args).unwrap();
+    assert_eq!(string_ptr(&value), arg);
+    assert_eq!(value.to_string(), "borrowed");
+
+    // lent arguments are copied
+    let args = string_arg("lent");
+    let value = plugin.call(&args).unwrap();
+    assert_ne!(string_ptr(&value), string_ptr(&args[0]));
+    assert_eq!(value.to_string(), "lent");
+}
+
+#[test]
+fn rejects_arguments_handed_back_when_not_owned() {
+    // a plugin that hands back an argument from any entrypoint, or one it wasn't passed
+    let plugin = mock(
+        r#"
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {
+            PluginMetadata {
+                name: c"echo".as_ptr(),
+                arg_types: [PluginType::Int].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::Int,
+                required_host_capabilities: 0,
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(_args: *const PluginValue, _len: usize) -> PluginResult {
+            PluginResult::Arg(0)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint_owned(
+            _args: *const PluginValue,
+            _len: usize,
+        ) -> PluginResult {
+            PluginResult::Arg(1)
+        }
+        "#,
+    );
+    let plugin = Plugin::load(plugin.path(), HashMap::new()).unwrap();
+    let e = expect_err(plugin.call(&[OwnedPluginValue::Int(1)]));
+    assert_eq!(
+        e.to_string(),
+        "Plugin handed back arg0, but it was only lent its arguments"
+    );
+    let e = expect_err(plugin.call_owned(vec![OwnedPluginValue::Int(1)]));
+    assert_eq!(
+        e.to_string(),
+        "Plugin handed back arg1, but was only passed 1 arguments"
+    );
+}
@@ -0,0 +1,52 @@
+// Tests of recording calls and replaying them, which require the serde feature
+#![cfg(feature = "serde")]
+mod common;
+
+use common::{host, plugin_path, stdout, temp_path};
+
+#[test]
+fn replays_recorded_call() {
+    let repeat = plugin_path("plugin");
+    let repeat = repeat.to_str().unwrap();
+    let recording = temp_path("recording.jsonl");
+    let recording = recording.to_str().unwrap();
+
+    let output = host(&["--record", recording, repeat, "ab", "3"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Plugin returned: ababab"));
+
+    let output = host(&["--replay", recording, repeat]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("All recorded calls matched"));
+
+    // with a separator, the repeat plugin's result no longer matches the recording
+    let output = host(&["--config", "separator=-", "--replay", recording, repeat]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("ab-ab-ab"), "{}", stdout(&output));
+
+    let _ = std::fs::remove_file(recording);
+}
+
+#[test]
+fn replays_recording_from_before_versions() {
+    let repeat = common::load("plugin");
+    let recording = temp_path("unversioned.jsonl");
+    // written by a host that predates versioned recordings
+    std::fs::write(
+        &recording,
+        r#"{"args":[{"String":"ab"},{"UInt":3}],"function":"repeat","result":{"ok":{"String":"ababab"}}}"#,
+    )
+    .unwrap();
+    assert!(host::replay(&repeat, &recording).unwrap().is_empty());
+
+    // recordings from a newer host are rejected rather than misread
+    std::fs::write(
+        &recording,
+        r#"{"args":[{"String":"ab"},{"UInt":3}],"function":"repeat","result":{"ok":{"String":"ababab"}},"version":99}"#,
+    )
+    .unwrap();
+    let e = common::expect_err(host::replay(&repeat, &recording));
+    assert!(e.to_string().contains("version 99"), "{}", e);
+
+    let _ = std::fs::remove_file(recording);
+}
@@ -0,0 +1,241 @@
+// Tests of calling the sample repeat plugin through the host library
+mod common;
+
+use common::{expect_err, host, load, plugin_path, stderr, stdout};
+use host::{OwnedPluginValue, PluginError, PreparedArgs};
+use std::ffi::CString;
+
+fn repeat_args(string: &str, count: u64) -> Vec<OwnedPluginValue> {
+    vec![
+        OwnedPluginValue::String(CString::new(string).unwrap()),
+        OwnedPluginValue::UInt(count),
+    ]
+}
+
+#[test]
+fn rejects_non_utf8_string_argument() {
+    let plugin = load("plugin");
+    let args = vec![
+        OwnedPluginValue::try_from(&b"ab\xff"[..]).unwrap(),
+        OwnedPluginValue::UInt(2),
+    ];
+    let e = expect_err(plugin.call(&args));
+    assert!(matches!(e, PluginError::Argument(_)), "{}", e);
+    assert!(
+        e.to_string().contains("expected valid UTF-8 string"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn reuses_prepared_args_across_calls() {
+    let plugin = load("plugin");
+    let args = PreparedArgs::new(repeat_args("ab", 3));
+    let string_ptr = |args: &PreparedArgs| match &args.args()[0] {
+        OwnedPluginValue::String(s) => s.as_ptr(),
+        _ => unreachable!(),
+    };
+    let before = string_ptr(&args);
+
+    for _ in 0..100 {
+        let (value, warning) = plugin.call_prepared(&args).unwrap();
+        assert_eq!(value.to_string(), "ababab");
+        assert!(warning.is_none());
+    }
+    // the same argument buffer was passed to every call
+    assert_eq!(string_ptr(&args), before);
+}
+
+// A host type for the repeat plugin's arguments
+struct Repetition {
+    text: String,
+    times: u64,
+}
+
+impl From<Repetition> for Vec<OwnedPluginValue> {
+    fn from(repetition: Repetition) -> Self {
+        vec![
+            OwnedPluginValue::String(CString::new(repetition.text).unwrap()),
+            OwnedPluginValue::from(repetition.times),
+        ]
+    }
+}
+
+// A host type for its result
+#[derive(Debug, PartialEq)]
+struct Repeated(String);
+
+impl TryFrom<OwnedPluginValue> for Repeated {
+    type Error = PluginError;
+
+    fn try_from(value: OwnedPluginValue) -> Result<Self, Self::Error> {
+        String::try_from(value).map(Repeated)
+    }
+}
+
+#[test]
+fn calls_with_custom_host_types() {
+    let plugin = load("plugin");
+    let repetition = Repetition {
+        text: "ab".to_string(),
+        times: 2,
+    };
+    let repeated: Repeated = plugin.call_typed(repetition).unwrap();
+    assert_eq!(repeated, Repeated("abab".to_string()));
+
+    // the result is a String, which doesn't convert to a number
+    let repetition = Repetition {
+        text: "ab".to_string(),
+        times: 2,
+    };
+    let e = plugin.call_typed::<_, u64>(repetition).unwrap_err();
+    assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+    assert_eq!(e.to_string(), "Expected a UInt value, but got String");
+}
+
+#[test]
+fn calls_batch_sequentially_and_in_parallel() {
+    let plugin = load("plugin");
+    let mut batch: Vec<_> = (0..50).map(|i| repeat_args("ab", i)).collect();
+    // an invalid set of arguments fails on its own, without affecting the rest of the batch
+    batch[7] = vec![OwnedPluginValue::UInt(1)];
+
+    let check = |results: Vec<Result<OwnedPluginValue, PluginError>>| {
+        assert_eq!(results.len(), 50);
+        for (i, result) in results.into_iter().enumerate() {
+            if i == 7 {
+                assert!(matches!(expect_err(result), PluginError::Argument(_)));
+            } else {
+                assert_eq!(result.unwrap().to_string(), "ab".repeat(i));
+            }
+        }
+    };
+    check(plugin.call_batch(&batch));
+    check(plugin.call_batch_parallel(&batch, 4));
+    // more threads than calls
+    check(plugin.call_batch_parallel(&batch, 64));
+}
+
+#[test]
+fn converts_returned_values_back_to_ffi() {
+    use host::PluginValue;
+    use std::ffi::CStr;
+
+    let plugin = load("plugin");
+    let value = plugin.call(&repeat_args("ab", 2)).unwrap();
+    {
+        let borrowed = value.to_ffi();
+        let PluginValue::String(s) = borrowed.value() else {
+            panic!("expected a String");
+        };
+        assert_eq!(unsafe { CStr::from_ptr(*s) }.to_str(), Ok("abab"));
+    }
+
+    // nested values are kept alive by the guard too
+    let list = OwnedPluginValue::List(vec![value, OwnedPluginValue::UInt(3)]);
+    let borrowed = list.to_ffi();
+    let PluginValue::List { items, len: 2 } = borrowed.value() else {
+        panic!("expected a List of 2 items");
+    };
+    let items = unsafe { std::slice::from_raw_parts(*items, 2) };
+    let (PluginValue::String(s), PluginValue::UInt(3)) = (&items[0], &items[1]) else {
+        panic!("expected a String and a UInt");
+    };
+    assert_eq!(unsafe { CStr::from_ptr(*s) }.to_str(), Ok("abab"));
+    drop(borrowed);
+
+    // and the owned value can be passed back into a call
+    let OwnedPluginValue::List(mut items) = list else {
+        unreachable!();
+    };
+    items.truncate(1);
+    items.push(OwnedPluginValue::UInt(2));
+    assert_eq!(plugin.call(&items).unwrap().to_string(), "abababab");
+}
+
+#[test]
+fn reports_allocations_of_call() {
+    let plugin = load("plugin");
+    let (value, stats) = plugin.call_with_stats(&repeat_args("ab", 3)).unwrap();
+    assert_eq!(value.to_string(), "ababab");
+    // the array of two FFI values, as neither argument needs a buffer of its own
+    assert_eq!(stats.arg_bytes, 2 * size_of::<host::PluginValue>());
+    // "ababab" and its NUL
+    assert_eq!(stats.result_bytes, 7);
+}
+
+#[test]
+fn fills_out_parameters() {
+    let plugin = load("plugin");
+    let values = plugin.call_out(&repeat_args("ab", 3), 4).unwrap();
+    let values: Vec<_> = values.iter().map(|value| value.to_string()).collect();
+    assert_eq!(values, ["ababab", "6"]);
+
+    // the plugin needs room for both values
+    let e = expect_err(plugin.call_out(&repeat_args("ab", 3), 1));
+    assert!(
+        e.to_string().contains("out should have room for 2 values"),
+        "{}",
+        e
+    );
+
+    let e = expect_err(load("negate").call_out(&[OwnedPluginValue::Int(1)], 2));
+    assert!(matches!(e, PluginError::Usage(_)), "{}", e);
+}
+
+#[test]
+fn rejects_string_larger_than_result_limit() {
+    let mut plugin = load("plugin");
+    // "ababab" and its NUL
+    plugin.set_max_result_bytes(7);
+    assert_eq!(
+        plugin.call(&repeat_args("ab", 3)).unwrap().to_string(),
+        "ababab"
+    );
+
+    plugin.set_max_result_bytes(6);
+    let e = expect_err(plugin.call(&repeat_args("ab", 3)));
+    assert!(matches!(e, PluginError::Plugin(_)), "{}", e);
+    assert!(e.to_string().contains("larger than the limit of 6 bytes"));
+}
+
+#[test]
+fn rejects_count_outside_declared_bounds() {
+    let plugin = load("plugin");
+    let count = &plugin.metadata().args.as_ref().unwrap()[1];
+    assert_eq!(count.constraints().as_deref(), Some("at most 1000"));
+
+    let value = plugin.call(&repeat_args("a", 0)).unwrap();
+    assert_eq!(value.to_string(), "");
+
+    // rejected by the host, before the plugin is called
+    let e = expect_err(plugin.call(&repeat_args("a", 1001)));
+    assert!(matches!(e, PluginError::InvalidArg(_)), "{}", e);
+    assert!(
+        e.to_string()
+            .contains("count must be at most 1000, got 1001"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn repeats_empty_string() {
+    let plugin = load("plugin");
+    for count in [0, 1, 3] {
+        match plugin.call(&repeat_args("", count)).unwrap() {
+            OwnedPluginValue::String(s) => assert!(s.is_empty(), "{:?}", s),
+            value => panic!("expected a String, got {}", value),
+        }
+    }
+
+    // and through the CLI, where the empty argument is given as ""
+    let output = host(&[plugin_path("plugin").to_str().unwrap(), "", "3"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stdout(&output).ends_with("Plugin returned: \n"),
+        "{}",
+        stdout(&output)
+    );
+}
@@ -0,0 +1,44 @@
+// Tests of `--new-plugin`, which generates a plugin crate from the host's templates
+mod common;
+
+use common::{host, stderr, temp_path};
+use host::{OwnedPluginValue, Plugin};
+use std::collections::HashMap;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn generates_plugin_that_builds_and_loads() {
+    let dir = temp_path("scaffold");
+    let _ = std::fs::remove_dir_all(&dir);
+    let output = host(&["--new-plugin", "echo_int", dir.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let target = Path::new(env!("CARGO_TARGET_TMPDIR")).join("scaffold");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--quiet", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target)
+        .status()
+        .unwrap();
+    assert!(status.success(), "the generated crate doesn't build");
+
+    let library = target
+        .join("debug")
+        .join(format!("{}echo_int{}", DLL_PREFIX, DLL_SUFFIX));
+    let plugin = Plugin::load(&library, HashMap::new()).unwrap();
+    assert_eq!(plugin.metadata().name, "echo_int");
+    match plugin.call(&[OwnedPluginValue::Int(-7)]).unwrap() {
+        OwnedPluginValue::Int(x) => assert_eq!(x, -7),
+        value => panic!("expected an Int, got {}", value),
+    }
+
+    // an existing crate isn't overwritten, and names must be valid identifiers
+    let output = host(&["--new-plugin", "echo_int", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = host(&["--new-plugin", "Echo-Int", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,81 @@
+// Tests of marshalling scalar values end to end, through the sample plugins that take and return
+// them
+mod common;
+
+use common::{expect_err, host, load, plugin_path, stderr, stdout};
+use host::OwnedPluginValue;
+
+fn negate(plugin: &host::Plugin, x: i64) -> Result<i64, host::PluginError> {
+    plugin.call_typed(vec![OwnedPluginValue::Int(x)])
+}
+
+fn scale(plugin: &host::Plugin, x: f64, factor: i64, negate: bool) -> f64 {
+    let args = [
+        OwnedPluginValue::Double(x),
+        OwnedPluginValue::Int(factor),
+        OwnedPluginValue::Bool(negate),
+    ];
+    match plugin.call(&args).unwrap() {
+        OwnedPluginValue::Double(d) => d,
+        value => panic!("expected a Double, got {}", value),
+    }
+}
+
+#[test]
+fn round_trips_bool_and_double() {
+    let plugin = load("scale");
+    // values are passed bit for bit, so the results match the same arithmetic done here
+    assert_eq!(
+        scale(&plugin, 0.1, 3, false).to_bits(),
+        (0.1 * 3.0f64).to_bits()
+    );
+    assert_eq!(scale(&plugin, -2.5, 2, true), 5.0);
+    assert_eq!(scale(&plugin, 2.5, 2, false), 5.0);
+    assert_eq!(scale(&plugin, f64::MAX, 1, true), -f64::MAX);
+    assert_eq!(scale(&plugin, f64::INFINITY, 2, true), f64::NEG_INFINITY);
+    assert!(scale(&plugin, f64::NAN, 1, false).is_nan());
+    assert!(scale(&plugin, 0.0, 1, true).is_sign_negative());
+}
+
+#[test]
+fn parses_bool_and_double_args() {
+    let scale = plugin_path("scale");
+    let output = host(&[scale.to_str().unwrap(), "1.5", "2", "true"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Plugin returned: -3"));
+
+    let output = host(&[scale.to_str().unwrap(), "1.5", "2", "yes"]);
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[test]
+fn round_trips_signed_ints() {
+    let plugin = load("negate");
+    assert_eq!(negate(&plugin, -1).unwrap(), 1);
+    assert_eq!(negate(&plugin, 0).unwrap(), 0);
+    assert_eq!(negate(&plugin, i64::MAX).unwrap(), i64::MIN + 1);
+    assert_eq!(negate(&plugin, i64::MIN + 1).unwrap(), i64::MAX);
+
+    // i64::MIN has no negation, which the plugin reports rather than overflowing
+    let e = expect_err(negate(&plugin, i64::MIN));
+    assert!(
+        e.to_string().contains("cannot negate -9223372036854775808"),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn parses_negative_int_args() {
+    let negate = plugin_path("negate");
+    // arguments after the plugin path aren't taken for host options, even with a leading `-`
+    let output = host(&[negate.to_str().unwrap(), "-1"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: 1\n"));
+
+    let output = host(&[negate.to_str().unwrap(), "-9223372036854775808"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("cannot negate -9223372036854775808"));
+    // along with the error's payload
+    assert!(stderr(&output).contains(r#"payload: {"arg": "x", "suggested": -9223372036854775807}"#));
+}
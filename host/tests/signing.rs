@@ -0,0 +1,116 @@
+// Tests of verifying plugins' signatures before loading them, which require the signing feature
+#![cfg(feature = "signing")]
+mod common;
+
+use common::{expect_err, host, plugin_path, stderr, stdout};
+use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+use ed25519_dalek::pkcs8::EncodePublicKey;
+use ed25519_dalek::{Signer, SigningKey};
+use host::{signature_path, Plugin, PluginError, SymbolVisibility, TrustedKey};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// A copy of the negate plugin in a directory of its own, signed by `key` (unless it's None), along
+// with the PEM-encoded public key of `trusted`
+fn signed_plugin(name: &str, key: Option<&SigningKey>, trusted: &SigningKey) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("host-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir(&dir).unwrap();
+    let library = dir.join(plugin_path("negate").file_name().unwrap());
+    std::fs::copy(plugin_path("negate"), &library).unwrap();
+    if let Some(key) = key {
+        let signature = key.sign(&std::fs::read(&library).unwrap());
+        std::fs::write(signature_path(&library), signature.to_bytes()).unwrap();
+    }
+
+    let public_key = dir.join("key.pub.pem");
+    let pem = trusted
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .unwrap();
+    std::fs::write(&public_key, pem).unwrap();
+    (library, public_key)
+}
+
+fn load_verified(library: &Path, public_key: &Path) -> Result<Plugin, PluginError> {
+    let key = TrustedKey::from_file(public_key).unwrap();
+    Plugin::load_verified(library, HashMap::new(), SymbolVisibility::Local, &key)
+}
+
+#[test]
+fn loads_plugin_signed_by_trusted_key() {
+    let key = SigningKey::from_bytes(&[1; 32]);
+    let (library, public_key) = signed_plugin("signed", Some(&key), &key);
+    load_verified(&library, &public_key).unwrap();
+
+    let output = host(&[
+        "--verify-key",
+        public_key.to_str().unwrap(),
+        library.to_str().unwrap(),
+        "5",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Plugin returned: -5"));
+    std::fs::remove_dir_all(library.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn refuses_plugin_with_bad_signature() {
+    let (key, other) = (
+        SigningKey::from_bytes(&[1; 32]),
+        SigningKey::from_bytes(&[2; 32]),
+    );
+    let (library, public_key) = signed_plugin("wrong-key", Some(&other), &key);
+    let e = expect_err(load_verified(&library, &public_key));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    assert!(
+        e.to_string()
+            .ends_with("it isn't signed by the trusted key"),
+        "{}",
+        e
+    );
+
+    // a signature of the library before it was modified doesn't match either
+    let (library, public_key) = signed_plugin("tampered", Some(&key), &key);
+    let mut contents = std::fs::read(&library).unwrap();
+    contents.extend_from_slice(b"tampered");
+    std::fs::write(&library, contents).unwrap();
+    let e = expect_err(load_verified(&library, &public_key));
+    assert!(
+        e.to_string()
+            .ends_with("it isn't signed by the trusted key"),
+        "{}",
+        e
+    );
+
+    std::fs::write(signature_path(&library), b"too short").unwrap();
+    let e = expect_err(load_verified(&library, &public_key));
+    assert!(
+        e.to_string().contains("isn't an ed25519 signature"),
+        "{}",
+        e
+    );
+
+    let output = host(&[
+        "--verify-key",
+        public_key.to_str().unwrap(),
+        library.to_str().unwrap(),
+        "5",
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+    std::fs::remove_dir_all(library.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn refuses_unsigned_plugin() {
+    let key = SigningKey::from_bytes(&[1; 32]);
+    let (library, public_key) = signed_plugin("unsigned", None, &key);
+    let e = expect_err(load_verified(&library, &public_key));
+    assert!(matches!(e, PluginError::Load(_)), "{}", e);
+    assert!(
+        e.to_string().contains("could not read its signature"),
+        "{}",
+        e
+    );
+    std::fs::remove_dir_all(library.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,41 @@
+// Snapshots of what the host prints for calls to the repeat plugin, to catch unintended changes to
+// its output. Review changes with `cargo insta review`.
+mod common;
+
+use common::{host, plugin_path, stderr, stdout};
+
+// Runs the host on the repeat plugin with `args`, returning what it prints
+fn repeat(options: &[&str], args: &[&str]) -> String {
+    let path = plugin_path("plugin");
+    let mut command = options.to_vec();
+    command.push(path.to_str().unwrap());
+    command.extend(args);
+    let output = host(&command);
+    assert!(output.status.success(), "{}", stderr(&output));
+    stdout(&output)
+}
+
+#[test]
+fn repeats_string() {
+    insta::assert_snapshot!(repeat(&[], &["ab", "3"]));
+}
+
+#[test]
+fn repeats_string_zero_times() {
+    insta::assert_snapshot!(repeat(&[], &["ab", "0"]));
+}
+
+#[test]
+fn repeats_with_separator() {
+    insta::assert_snapshot!(repeat(&["--config", "separator=, "], &["ab", "3"]));
+}
+
+#[test]
+fn repeats_with_default_count() {
+    insta::assert_snapshot!(repeat(&["--config", "default_count=2"], &["xyz"]));
+}
+
+#[test]
+fn repeats_unicode() {
+    insta::assert_snapshot!(repeat(&[], &["héllo ", "2"]));
+}
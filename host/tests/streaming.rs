@@ -0,0 +1,151 @@
+// Tests of plugins streaming values to the host through `HostServices::emit`
+mod common;
+
+use common::{mock, HOST_SERVICES};
+use host::{OwnedPluginValue, Plugin};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// A plugin named `name` that emits the UInts from 1 to its argument, then returns Null
+fn counting(name: &str) -> host::MockPlugin {
+    mock(&format!(
+        r#"{}
+        #[no_mangle]
+        pub extern "C" fn plugin_metadata() -> PluginMetadata {{
+            PluginMetadata {{
+                name: c"{}".as_ptr(),
+                arg_types: [PluginType::UInt].as_ptr(),
+                arg_types_len: 1,
+                return_type: PluginType::UInt,
+                required_host_capabilities: 0,
+            }}
+        }}
+
+        #[no_mangle]
+        pub extern "C" fn plugin_entrypoint(args: *const PluginValue, _len: usize) -> PluginResult {{
+            let PluginValue::UInt(count) = (unsafe {{ &*args }}) else {{
+                unreachable!();
+            }};
+            let services = services();
+            for i in 1..=*count {{
+                if !(services.emit)(services.context, &PluginValue::UInt(i)) {{
+                    break;
+                }}
+            }}
+            PluginResult::Ok(PluginValue::Null)
+        }}
+        "#,
+        HOST_SERVICES, name
+    ))
+}
+
+fn load(mock: &host::MockPlugin) -> Plugin {
+    Plugin::load(mock.path(), HashMap::new()).unwrap()
+}
+
+// The items passed to `on_item`, as strings, shared with the closure
+type Items = Arc<Mutex<Vec<String>>>;
+
+fn record(items: &Items) -> impl FnMut(Result<OwnedPluginValue, host::PluginError>) + 'static {
+    let items = items.clone();
+    move |item| items.lock().unwrap().push(item.unwrap().to_string())
+}
+
+#[test]
+fn passes_emitted_values_to_closure() {
+    let mock = counting("outer");
+    let plugin = load(&mock);
+    let items = Items::default();
+    let (value, _) = plugin
+        .call_streaming(&[OwnedPluginValue::UInt(3)], record(&items))
+        .unwrap();
+    assert!(matches!(value, OwnedPluginValue::Null));
+    assert_eq!(*items.lock().unwrap(), ["1", "2", "3"]);
+
+    // outside of a streaming call, emitted values are refused
+    assert!(matches!(
+        plugin.call(&[OwnedPluginValue::UInt(3)]),
+        Ok(OwnedPluginValue::Null)
+    ));
+}
+
+#[test]
+fn streams_from_within_streaming_call() {
+    let (outer_mock, inner_mock) = (counting("outer"), counting("inner"));
+    let (outer, inner) = (load(&outer_mock), Arc::new(load(&inner_mock)));
+    let items = Items::default();
+
+    // each value the outer plugin emits starts a streaming call into the inner one
+    let on_outer = {
+        let items = items.clone();
+        move |item: Result<OwnedPluginValue, host::PluginError>| {
+            let item = item.unwrap();
+            items.lock().unwrap().push(format!("outer {}", item));
+            let nested = Items::default();
+            inner.call_streaming(&[item], record(&nested)).unwrap();
+            for value in nested.lock().unwrap().iter() {
+                items.lock().unwrap().push(format!("inner {}", value));
+            }
+        }
+    };
+    outer
+        .call_streaming(&[OwnedPluginValue::UInt(2)], on_outer)
+        .unwrap();
+    assert_eq!(
+        *items.lock().unwrap(),
+        ["outer 1", "inner 1", "outer 2", "inner 1", "inner 2"]
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn streams_emitted_values_asynchronously() {
+    let mock = counting("outer");
+    let plugin = Arc::new(load(&mock));
+    let items = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(async {
+            let mut stream = plugin.call_stream(vec![OwnedPluginValue::UInt(3)]);
+            let mut items = vec![];
+            while let Some(item) = stream.next().await {
+                items.push(item.unwrap().to_string());
+            }
+            items
+        });
+    // the call's Null result isn't yielded
+    assert_eq!(items, ["1", "2", "3"]);
+}
+
+#[test]
+fn refuses_reentrant_call_from_closure() {
+    let mock = counting("outer");
+    let plugin = Arc::new(load(&mock));
+    let errors = Items::default();
+
+    // each emitted value tries to call back into the plugin that's emitting it (the closure runs
+    // within the plugin's call, so it records what happened rather than panicking)
+    let on_item = {
+        let (plugin, errors) = (plugin.clone(), errors.clone());
+        move |item: Result<OwnedPluginValue, host::PluginError>| {
+            let outcome = match plugin.call(&[item.unwrap()]) {
+                Err(host::PluginError::Reentrant(message)) => message,
+                Err(e) => format!("unexpected error: {}", e),
+                Ok(value) => format!("unexpected value: {}", value),
+            };
+            errors.lock().unwrap().push(outcome);
+        }
+    };
+    let (value, _) = plugin
+        .call_streaming(&[OwnedPluginValue::UInt(2)], on_item)
+        .unwrap();
+    assert!(matches!(value, OwnedPluginValue::Null));
+    let refused = "Plugin outer was called again from within one of its own calls";
+    assert_eq!(*errors.lock().unwrap(), [refused, refused]);
+
+    // once the outer call returns, the plugin can be called again
+    assert!(matches!(
+        plugin.call(&[OwnedPluginValue::UInt(1)]),
+        Ok(OwnedPluginValue::Null)
+    ));
+}
@@ -0,0 +1,101 @@
+// Tests of the spans emitted around plugin calls, which require the tracing feature
+#![cfg(feature = "tracing")]
+mod common;
+
+use common::{expect_err, load};
+use host::OwnedPluginValue;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+// The fields of a span, as they were last recorded
+type Fields = BTreeMap<String, String>;
+
+// A layer that keeps the fields of each span it sees, by span id, and moves them to `closed` when
+// the span closes
+#[derive(Clone, Default)]
+struct Spans {
+    open: Arc<Mutex<BTreeMap<u64, (String, Fields)>>>,
+    closed: Arc<Mutex<Vec<(String, Fields)>>>,
+}
+
+struct Recorder<'a>(&'a mut Fields);
+
+impl Visit for Recorder<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+impl<S: tracing::Subscriber> Layer<S> for Spans {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        let mut fields = Fields::new();
+        attrs.record(&mut Recorder(&mut fields));
+        let name = attrs.metadata().name().to_string();
+        self.open
+            .lock()
+            .unwrap()
+            .insert(id.into_u64(), (name, fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some((_, fields)) = self.open.lock().unwrap().get_mut(&id.into_u64()) {
+            values.record(&mut Recorder(fields));
+        }
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        if let Some(span) = self.open.lock().unwrap().remove(&id.into_u64()) {
+            self.closed.lock().unwrap().push(span);
+        }
+    }
+}
+
+fn repeat_args(string: &str, count: u64) -> Vec<OwnedPluginValue> {
+    vec![
+        OwnedPluginValue::String(CString::new(string).unwrap()),
+        OwnedPluginValue::UInt(count),
+    ]
+}
+
+#[test]
+fn emits_span_per_call() {
+    let plugin = load("plugin");
+    let spans = Spans::default();
+    let subscriber = tracing_subscriber::registry().with(spans.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        plugin.call(&repeat_args("ab", 2)).unwrap();
+        // the plugin rejects strings that aren't UTF-8
+        let args = [
+            OwnedPluginValue::try_from(&b"ab\xff"[..]).unwrap(),
+            OwnedPluginValue::UInt(2),
+        ];
+        expect_err(plugin.call(&args));
+        // arguments the host rejects never reach the plugin, so there's no span for them
+        expect_err(plugin.call(&repeat_args("ab", u64::MAX)));
+    });
+
+    let closed = spans.closed.lock().unwrap();
+    assert_eq!(closed.len(), 2);
+    let (name, fields) = &closed[0];
+    assert_eq!(name, "plugin_call");
+    assert_eq!(fields["function"], "repeat");
+    assert_eq!(fields["arg_types"], "String, UInt");
+    assert!(fields["plugin"].contains("libplugin"), "{:?}", fields);
+    assert!(fields["duration_us"].parse::<u64>().is_ok(), "{:?}", fields);
+    assert_eq!(fields["outcome"], "ok");
+    assert!(!fields.contains_key("error"), "{:?}", fields);
+
+    let (_, fields) = &closed[1];
+    assert_eq!(fields["outcome"], "error");
+    assert!(fields["error"].contains("UTF-8"), "{:?}", fields);
+}
@@ -0,0 +1,29 @@
+// Tests of the async API, which requires the tokio feature
+#![cfg(feature = "tokio")]
+mod common;
+
+use common::load;
+use host::OwnedPluginValue;
+use std::ffi::CString;
+use std::future::Future;
+use std::sync::Arc;
+
+// Runs `future` to completion on a runtime of its own, which is enough for these tests (the host
+// only enables tokio's `rt` feature, so `#[tokio::test]` isn't available)
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[test]
+fn awaits_repeat_call() {
+    let plugin = Arc::new(load("plugin"));
+    let args = vec![
+        OwnedPluginValue::String(CString::new("ab").unwrap()),
+        OwnedPluginValue::UInt(3),
+    ];
+    let value = block_on(plugin.call_async(args)).unwrap();
+    assert_eq!(value.to_string(), "ababab");
+}
@@ -0,0 +1,6 @@
+// A call! argument of a type with no conversion to a plugin value
+fn call(plugin: host::Plugin) {
+    let _ = host::call!(plugin, "repeat", "hi", 3u8);
+}
+
+fn main() {}
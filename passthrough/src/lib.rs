@@ -0,0 +1,165 @@
+// An example plugin that returns its argument unchanged, implementing `passthrough(s: String) ->
+// String`, to show how a plugin can hand an argument back without copying it. Its entrypoint has
+// to return a copy, as the host still owns the argument once the call returns; when the host gives
+// its arguments up (`Plugin::call_owned`), it calls `plugin_entrypoint_owned` instead, which hands
+// the argument itself back as `PluginResult::Arg`.
+//
+// The FFI types are copied from the host, as in the `scale` plugin.
+use std::ffi::{CStr, CString};
+
+#[repr(C)]
+pub enum PluginValue {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    String(*const i8),
+    Json(*const i8),
+    Handle(u64),
+    List {
+        items: *const PluginValue,
+        len: usize,
+    },
+    Bytes {
+        ptr: *const u8,
+        len: usize,
+    },
+    Custom {
+        type_id: u32,
+        ptr: *const u8,
+        len: usize,
+    },
+    // No value, returned by a plugin that succeeded but has nothing to return (e.g., a lookup that
+    // found nothing); this is distinct from an error
+    Null,
+    // A file descriptor (on Windows, a HANDLE) for a file opened by the host, which controls which
+    // files the plugin can access. It's only borrowed by the plugin, which must not close it; the
+    // host closes it after the call. File descriptors can be passed as arguments, but not returned.
+    Fd(i64),
+    // The outcome of an operation within a successful call, like a Rust `Result`. Results passed
+    // as arguments are borrowed by the plugin; returned ones must point to a boxed value
+    // (`Box<PluginValue>`), which is freed by the host along with the value it contains.
+    ResultOk(*const PluginValue),
+    ResultErr(*const PluginValue),
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum PluginType {
+    Bool,
+    Int,
+    UInt,
+    Double,
+    String,
+    Json,
+    Handle,
+    List,
+    Bytes,
+    Fd,
+    Result,
+}
+
+#[repr(C)]
+pub enum PluginResult {
+    Ok(PluginValue),
+    PartialOk {
+        value: PluginValue,
+        warning: *mut i8,
+    },
+    // The host is responsible for freeing the error message
+    Err {
+        code: PluginErrorCode,
+        message: *mut i8,
+        // An optional JSON payload with structured details of the error (e.g., which argument was
+        // invalid), or null; the host is also responsible for freeing it
+        payload: *mut i8,
+    },
+    // The argument at this index, handed back rather than copied; only `plugin_entrypoint_owned`
+    // may return it
+    Arg(usize),
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum PluginErrorCode {
+    Failed,
+    InvalidArgument,
+    Panicked,
+    Cancelled,
+}
+
+#[repr(C)]
+pub struct PluginMetadata {
+    pub name: *const i8,
+    pub arg_types: *const PluginType,
+    pub arg_types_len: usize,
+    pub return_type: PluginType,
+    pub required_host_capabilities: u64,
+}
+
+#[no_mangle]
+pub extern "C" fn plugin_metadata() -> PluginMetadata {
+    PluginMetadata {
+        name: c"passthrough".as_ptr(),
+        arg_types: [PluginType::String].as_ptr(),
+        arg_types_len: 1,
+        return_type: PluginType::String,
+        required_host_capabilities: 0,
+    }
+}
+
+fn plugin_error(code: PluginErrorCode, message: impl Into<String>) -> PluginResult {
+    PluginResult::Err {
+        code,
+        message: CString::new(message.into()).unwrap().into_raw(),
+        payload: std::ptr::null_mut(),
+    }
+}
+
+// The string argument, or the error to return if the arguments are invalid
+fn string_arg<'a>(args: *const PluginValue, args_len: usize) -> Result<&'a CStr, PluginResult> {
+    // a buggy host could pass a null pointer, which we must not dereference
+    if args_len > 0 && args.is_null() {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "args is null",
+        ));
+    }
+
+    if args_len != 1 {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "args_len should be 1",
+        ));
+    }
+
+    match unsafe { &*args } {
+        PluginValue::String(s) if !s.is_null() => Ok(unsafe { CStr::from_ptr(*s) }),
+        _ => Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "arg0 is invalid; expected String",
+        )),
+    }
+}
+
+// See the `repeat` plugin for a description of how the entrypoint works
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn plugin_entrypoint(args: *const PluginValue, args_len: usize) -> PluginResult {
+    match string_arg(args, args_len) {
+        Ok(s) => PluginResult::Ok(PluginValue::String(CString::from(s).into_raw())),
+        Err(e) => e,
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn plugin_entrypoint_owned(
+    args: *const PluginValue,
+    args_len: usize,
+) -> PluginResult {
+    match string_arg(args, args_len) {
+        Ok(_) => PluginResult::Arg(0),
+        Err(e) => e,
+    }
+}
@@ -1,5 +1,12 @@
-use std::ffi::{CStr, CString};
+use std::collections::BTreeMap;
+use std::ffi::{c_void, CStr, CString};
+use std::fs::File;
+use std::mem::ManuallyDrop;
 use std::panic::catch_unwind;
+use std::path::{Path, PathBuf};
+use std::ptr::{null, null_mut};
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 
 // An FFI-safe value enum to support various input/output types
 #[repr(C)]
@@ -9,8 +16,48 @@ pub enum PluginValue {
     UInt(u64),
     Double(f64),
     // Strings are represented as a pointer to a null-terminated string; all strings are owned
-    // by the host. Returned strings must be freed by the host.
+    // by the host. Returned strings must be freed by the host. The pointer is never null: an
+    // empty string is a pointer to a lone null byte.
     String(*const i8),
+    // JSON is passed as a null-terminated UTF-8 string, with the same ownership rules as String
+    Json(*const i8),
+    // An opaque token for a resource owned by the plugin (e.g., an open file). A plugin that
+    // returns handles must export `plugin_release(token: u64)`, which the host calls exactly once
+    // for each returned handle when it's done with it.
+    Handle(u64),
+    // A list of values. Lists passed as arguments are only borrowed; returned lists must be
+    // allocated as a `Box<[PluginValue]>` and are freed by the host, along with their contents.
+    List {
+        items: *const PluginValue,
+        len: usize,
+    },
+    // A buffer of bytes. Bytes passed as arguments are only borrowed. Returned buffers stay owned
+    // by the plugin: a plugin that returns bytes must export `plugin_free_bytes(ptr, len)`, which
+    // the host calls exactly once for each returned buffer, so it's freed by our own allocator.
+    Bytes {
+        ptr: *const u8,
+        len: usize,
+    },
+    // A value of a custom type chosen by the plugin author, with the same ownership rules as
+    // Bytes. Custom types are declared in metadata by the raw value `0x4000_0000 + type_id`.
+    Custom {
+        type_id: u32,
+        ptr: *const u8,
+        len: usize,
+    },
+    // No value, returned by a plugin that succeeded but has nothing to return (e.g., a lookup that
+    // found nothing); this is distinct from an error. The host also passes it in place of an
+    // optional argument it omits.
+    Null,
+    // A file descriptor (on Windows, a HANDLE) for a file opened by the host, which controls which
+    // files the plugin can access. It's only borrowed by the plugin, which must not close it; the
+    // host closes it after the call. File descriptors can be passed as arguments, but not returned.
+    Fd(i64),
+    // The outcome of an operation within a successful call, like a Rust `Result`. Results passed
+    // as arguments are borrowed by the plugin; returned ones must point to a boxed value
+    // (`Box<PluginValue>`), which is freed by the host along with the value it contains.
+    ResultOk(*const PluginValue),
+    ResultErr(*const PluginValue),
 }
 
 #[repr(C)]
@@ -21,14 +68,45 @@ pub enum PluginType {
     UInt,
     Double,
     String,
+    Json,
+    Handle,
+    List,
+    Bytes,
+    Fd,
+    Result,
 }
 
 // An FFI-safe result type
 #[repr(C)]
 pub enum PluginResult {
     Ok(PluginValue),
+    // A successful result accompanied by a non-fatal warning (e.g., only some of the input could
+    // be processed). The host is responsible for freeing the warning message.
+    PartialOk {
+        value: PluginValue,
+        warning: *mut i8,
+    },
     // The host is responsible for freeing the error message
-    Err(*mut i8),
+    Err {
+        code: PluginErrorCode,
+        message: *mut i8,
+        // An optional JSON payload with structured details of the error (e.g., which argument was
+        // invalid), or null; the host is also responsible for freeing it
+        payload: *mut i8,
+    },
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum PluginErrorCode {
+    // A general failure in the plugin function
+    Failed,
+    // The arguments passed by the host were invalid
+    InvalidArgument,
+    // The plugin function panicked
+    Panicked,
+    // The host cancelled the call before it completed
+    Cancelled,
 }
 
 #[repr(C)]
@@ -37,21 +115,681 @@ pub struct PluginMetadata {
     pub arg_types: *const PluginType,
     pub arg_types_len: usize,
     pub return_type: PluginType,
+    // Bits for the host capabilities (e.g. logging) we can't run without; see `CAPABILITY_*`
+    pub required_host_capabilities: u64,
+}
+
+// Capabilities the host may provide, which a plugin can require in its metadata
+pub const CAPABILITY_LOG: u64 = 1 << 0;
+pub const CAPABILITY_CONFIG: u64 = 1 << 1;
+pub const CAPABILITY_CANCELLATION: u64 = 1 << 2;
+pub const CAPABILITY_JSON: u64 = 1 << 3;
+pub const CAPABILITY_PROGRESS: u64 = 1 << 4;
+pub const CAPABILITY_EMIT: u64 = 1 << 5;
+pub const CAPABILITY_DEADLINE: u64 = 1 << 6;
+pub const CAPABILITY_ALLOC: u64 = 1 << 7;
+pub const CAPABILITY_LOG_FIELDS: u64 = 1 << 8;
+
+// Capabilities a plugin may advertise through `plugin_capabilities`, telling the host how it may
+// call us: from several threads at once, and caching results (the same arguments always give the
+// same result)
+pub const PLUGIN_THREAD_SAFE: u64 = 1 << 0;
+pub const PLUGIN_PURE: u64 = 1 << 1;
+
+// Describes a single argument of the plugin function. Rather than growing `PluginMetadata` with
+// every optional piece of information, the host queries these one argument at a time. This is
+// version 2 of the descriptor, returned by `plugin_describe_arg_v2`; version 1 (`ArgDescriptorV1`)
+// lacks the secret flag and the bounds.
+#[repr(C)]
+pub struct ArgDescriptor {
+    // The name of the argument, or null if the index is out of range
+    pub name: *const i8,
+    pub arg_type: PluginType,
+    // Optional arguments must come after all required arguments, and may be omitted by the host,
+    // either by passing fewer arguments or by passing Null in their place
+    pub optional: bool,
+    // A human-readable description of the argument; may be null
+    pub doc: *const i8,
+    // Secret arguments (e.g. credentials) are redacted wherever the host would show their values
+    pub secret: bool,
+    // Inclusive bounds on an Int, UInt or Double argument, each pointing to a static value of the
+    // argument's type (e.g. `&PluginValue::UInt(100)`), or null if it's unbounded. The host rejects
+    // values outside them without calling the plugin.
+    pub min: *const PluginValue,
+    pub max: *const PluginValue,
+    // The maximum length in bytes of a String or Bytes argument, or 0 if it's unlimited
+    pub max_len: usize,
+}
+
+// The descriptor returned by the unversioned `plugin_describe_arg`, for hosts that predate secret
+// arguments and bounds
+#[repr(C)]
+pub struct ArgDescriptorV1 {
+    pub name: *const i8,
+    pub arg_type: PluginType,
+    pub optional: bool,
+    pub doc: *const i8,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+// A field of a structured log message, which the host only borrows
+#[repr(C)]
+pub struct KeyValue {
+    pub key: *const i8,
+    pub value: PluginValue,
+}
+
+// Services the host provides to the plugin, passed to `plugin_init`. The host guarantees that the
+// struct and its context remain valid until the plugin is unloaded, so we can store the pointer.
+#[repr(C)]
+pub struct HostServices {
+    pub context: *const c_void,
+    pub log: extern "C" fn(context: *const c_void, level: LogLevel, message: *const i8),
+    // Returns null if the key isn't set; the returned string is owned by the host
+    pub get_config: extern "C" fn(context: *const c_void, key: *const i8) -> *const i8,
+    // Returns true once the host has cancelled the current call, in which case we should stop
+    // early; like `time_remaining`, must be called on the thread the call was made on
+    pub is_cancelled: extern "C" fn(context: *const c_void) -> bool,
+    // Reports how far through the current call we are, as a fraction from 0 to 1
+    pub report_progress: extern "C" fn(context: *const c_void, fraction: f64),
+    // Streams a value produced by the current call to the host, which takes ownership of it if it
+    // returns true; must be called on the thread the call was made on
+    pub emit: extern "C" fn(context: *const c_void, value: *const PluginValue) -> bool,
+    // Returns the seconds left until the current call's deadline (0 once it has passed), or
+    // infinity if it doesn't have one
+    pub time_remaining: extern "C" fn(context: *const c_void) -> f64,
+    // Allocates memory with the host's allocator (returning null if it can't), for data we return
+    // that the host frees, so that it's freed by the allocator that allocated it. If we required
+    // `CAPABILITY_ALLOC`, everything we return (including bytes) would have to be allocated this way.
+    pub alloc: extern "C" fn(context: *const c_void, size: usize, align: usize) -> *mut u8,
+    // Frees memory allocated by `alloc`, given the same size and alignment
+    pub free: extern "C" fn(context: *const c_void, ptr: *mut u8, size: usize, align: usize),
+    // Logs a message along with key-value fields; hosts that predate it don't provide it, so a
+    // plugin that uses it must require `CAPABILITY_LOG_FIELDS`
+    pub log_fields: extern "C" fn(
+        context: *const c_void,
+        level: LogLevel,
+        message: *const i8,
+        fields: *const KeyValue,
+        fields_len: usize,
+    ),
+}
+
+static HOST_SERVICES: AtomicPtr<HostServices> = AtomicPtr::new(null_mut());
+
+// Called by the host once after loading the plugin, before any other calls but `plugin_metadata`.
+// Returns null on success, or an error message if the plugin can't be initialized.
+#[no_mangle]
+pub extern "C" fn plugin_init(services: *const HostServices) -> *mut i8 {
+    HOST_SERVICES.store(services as *mut HostServices, Ordering::Release);
+    // our metadata depends on this, so it's checked now rather than on the first call
+    if let Err(e) = default_count() {
+        return CString::new(e).unwrap().into_raw();
+    }
+    null_mut()
+}
+
+fn host_services() -> Option<&'static HostServices> {
+    unsafe { HOST_SERVICES.load(Ordering::Acquire).as_ref() }
+}
+
+// Reads a config value from the host, returning None if it's not set
+fn config(key: &str) -> Option<String> {
+    let services = host_services()?;
+    let key = CString::new(key).ok()?;
+    let value = (services.get_config)(services.context, key.as_ptr());
+    if value.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(value) }
+            .to_string_lossy()
+            .to_string(),
+    )
+}
+
+// Resolves a relative path against the base directory the host passes in the `base_dir` config
+// value (e.g., with `--plugin-cwd`), rather than the host process's working directory; paths are
+// left as they are if the host doesn't set one. Absolute paths are unaffected.
+pub fn resolve_path(path: impl AsRef<Path>) -> PathBuf {
+    resolve_path_in(config("base_dir").as_deref(), path)
+}
+
+// Resolves a relative path against `base_dir`, if there is one
+fn resolve_path_in(base_dir: Option<&str>, path: impl AsRef<Path>) -> PathBuf {
+    match base_dir {
+        Some(base_dir) => Path::new(base_dir).join(path),
+        None => path.as_ref().to_path_buf(),
+    }
+}
+
+// Logs a message through the host, which may filter it by level; does nothing if the host didn't
+// provide services
+fn log(level: LogLevel, message: &str) {
+    let (Some(services), Ok(message)) = (host_services(), CString::new(message)) else {
+        return;
+    };
+    (services.log)(services.context, level, message.as_ptr());
+}
+
+// Logs a message with fields attached, e.g. a `KeyValue` with the key `c"count".as_ptr()` and the
+// value `PluginValue::UInt(3)`, which the host renders as `count=3`. The fields are only borrowed,
+// so anything they point to is still ours to free. Only for plugins that require
+// `CAPABILITY_LOG_FIELDS`; does nothing if the host didn't provide services.
+pub fn log_fields(level: LogLevel, message: &str, fields: &[KeyValue]) {
+    let (Some(services), Ok(message)) = (host_services(), CString::new(message)) else {
+        return;
+    };
+    (services.log_fields)(
+        services.context,
+        level,
+        message.as_ptr(),
+        fields.as_ptr(),
+        fields.len(),
+    );
+}
+
+// Reports the progress of the current call to the host; does nothing if the host didn't provide
+// services
+fn report_progress(fraction: f64) {
+    if let Some(services) = host_services() {
+        (services.report_progress)(services.context, fraction);
+    }
+}
+
+// Streams a value to the host before the current call returns, e.g. to hand over results as
+// they're produced rather than all at once. If the host isn't streaming the call's output (or didn't
+// provide services), the value is handed back, as it's still ours to free.
+pub fn emit(value: PluginValue) -> Result<(), PluginValue> {
+    match host_services() {
+        // the host now owns whatever the value points to
+        Some(services) if (services.emit)(services.context, &value) => Ok(()),
+        _ => Err(value),
+    }
+}
+
+// Copies a string into memory allocated by the host, to return to it, so that the host frees it with
+// the allocator that allocated it rather than assuming ours is the same. Returns None if the host
+// couldn't allocate it. Without services, the string is allocated by us, as before.
+fn host_string(s: &CStr) -> Option<*const i8> {
+    let Some(services) = host_services() else {
+        return Some(s.to_owned().into_raw());
+    };
+    let bytes = s.to_bytes_with_nul();
+    let ptr = (services.alloc)(services.context, bytes.len(), 1);
+    if ptr.is_null() {
+        return None;
+    }
+    unsafe { ptr.copy_from_nonoverlapping(bytes.as_ptr(), bytes.len()) };
+    Some(ptr as *const i8)
+}
+
+// Checks whether the host has cancelled the current call, or its deadline has passed (in which
+// case the host is about to give up on it anyway)
+fn cancelled() -> bool {
+    host_services().is_some_and(|services| {
+        (services.is_cancelled)(services.context)
+            || (services.time_remaining)(services.context) <= 0.0
+    })
+}
+
+// Builds metadata at runtime, for plugins whose signature depends on their config. The host reads
+// the metadata once before calling `plugin_init` (to check the capabilities it requires), and again
+// after, when the config is available. It reads through the pointers in `PluginMetadata` after
+// `plugin_metadata` returns, and may ask for it more than once, so the name and argument types must
+// outlive the call; `DynamicMetadata` keeps them in a static, built once.
+pub struct MetadataBuilder {
+    name: CString,
+    arg_types: Vec<PluginType>,
+    return_type: PluginType,
+    required_host_capabilities: u64,
+}
+
+impl MetadataBuilder {
+    pub fn new(name: CString, return_type: PluginType) -> MetadataBuilder {
+        MetadataBuilder {
+            name,
+            arg_types: vec![],
+            return_type,
+            required_host_capabilities: 0,
+        }
+    }
+
+    pub fn arg(mut self, arg_type: PluginType) -> MetadataBuilder {
+        self.arg_types.push(arg_type);
+        self
+    }
+
+    pub fn require(mut self, capabilities: u64) -> MetadataBuilder {
+        self.required_host_capabilities |= capabilities;
+        self
+    }
+}
+
+// Metadata built on first use and kept for as long as the plugin is loaded, e.g.:
+//
+// static METADATA: DynamicMetadata = DynamicMetadata::new();
+// METADATA.get(|| MetadataBuilder::new(c"name".into(), PluginType::String).arg(...))
+//
+// Metadata built before the plugin is initialized, without its config, is kept separately from
+// that built after, so that the host doesn't keep seeing the unconfigured metadata.
+pub struct DynamicMetadata {
+    configured: OnceLock<MetadataBuilder>,
+    unconfigured: OnceLock<MetadataBuilder>,
+}
+
+impl DynamicMetadata {
+    pub const fn new() -> DynamicMetadata {
+        DynamicMetadata {
+            configured: OnceLock::new(),
+            unconfigured: OnceLock::new(),
+        }
+    }
+
+    // Returns the metadata, building it with `build` the first time before and after the plugin is
+    // initialized; later calls return the same metadata, pointing to the same name and argument
+    // types
+    pub fn get(&'static self, build: impl FnOnce() -> MetadataBuilder) -> PluginMetadata {
+        let metadata = if host_services().is_some() {
+            self.configured.get_or_init(build)
+        } else {
+            self.unconfigured.get_or_init(build)
+        };
+        PluginMetadata {
+            name: metadata.name.as_ptr(),
+            arg_types: metadata.arg_types.as_ptr(),
+            arg_types_len: metadata.arg_types.len(),
+            return_type: metadata.return_type,
+            required_host_capabilities: metadata.required_host_capabilities,
+        }
+    }
+}
+
+impl Default for DynamicMetadata {
+    fn default() -> Self {
+        DynamicMetadata::new()
+    }
 }
 
-// The metadata function that will be called by the host to get information about the plugin.
+// A table of objects handed to the host as handles, for plugins that produce expensive objects the
+// host passes back into later calls rather than re-creating them. Each object is kept alive until
+// the host releases its handle, through a `plugin_release` export that calls `release`:
+//
+// static TABLE: HandleTable<Model> = HandleTable::new();
+// #[no_mangle]
+// pub extern "C" fn plugin_release(token: u64) { TABLE.release(token); }
+//
+// Objects are shared as `Arc`s, so a call using an object isn't affected by it being released
+// concurrently.
+pub struct HandleTable<T> {
+    next: AtomicU64,
+    objects: Mutex<BTreeMap<u64, Arc<T>>>,
+}
+
+impl<T> HandleTable<T> {
+    pub const fn new() -> HandleTable<T> {
+        HandleTable {
+            // tokens start at 1, so that a zeroed value is never a live handle
+            next: AtomicU64::new(1),
+            objects: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Adds an object to the table, returning the handle to return to the host for it
+    pub fn insert(&self, object: T) -> PluginValue {
+        let token = self.next.fetch_add(1, Ordering::Relaxed);
+        self.objects.lock().unwrap().insert(token, Arc::new(object));
+        PluginValue::Handle(token)
+    }
+
+    // Looks up the object behind a Handle argument, failing if the value isn't a handle or its
+    // object has been released
+    pub fn arg(&self, value: &PluginValue) -> Result<Arc<T>, PluginResult> {
+        let PluginValue::Handle(token) = value else {
+            return Err(plugin_error(
+                PluginErrorCode::InvalidArgument,
+                "expected Handle",
+            ));
+        };
+        self.objects
+            .lock()
+            .unwrap()
+            .get(token)
+            .cloned()
+            .ok_or_else(|| {
+                plugin_error(
+                    PluginErrorCode::InvalidArgument,
+                    format!("handle {} isn't live", token),
+                )
+            })
+    }
+
+    // Removes the object behind a handle, returning whether it was live
+    pub fn release(&self, token: u64) -> bool {
+        self.objects.lock().unwrap().remove(&token).is_some()
+    }
+
+    // The number of live handles
+    pub fn len(&self) -> usize {
+        self.objects.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Default for HandleTable<T> {
+    fn default() -> Self {
+        HandleTable::new()
+    }
+}
+
+// The most times we'll repeat a string: enough for any sensible use, while keeping the result small
+// enough to allocate unless the string itself is huge. The host enforces this through the count's
+// descriptor, but hosts that don't read descriptors may not, so the entrypoint checks it too, as we
+// do `default_count`.
+const MAX_COUNT: u64 = 1000;
+
+// The count to repeat strings by, if the host configures one with `default_count`, in which case
+// we take only the string to repeat
+fn default_count() -> Result<Option<u64>, String> {
+    config("default_count")
+        .map(|count| {
+            count
+                .parse()
+                .ok()
+                .filter(|count| *count <= MAX_COUNT)
+                .ok_or_else(|| {
+                    format!(
+                        "default_count should be a number up to {}, not {:?}",
+                        MAX_COUNT, count
+                    )
+                })
+        })
+        .transpose()
+}
+
+// The metadata function that will be called by the host to get information about the plugin. Our
+// arguments depend on the config, so the metadata is built at runtime.
 #[no_mangle]
 pub extern "C" fn plugin_metadata() -> PluginMetadata {
-    PluginMetadata {
-        name: "repeat\0".as_ptr() as *const i8,
-        arg_types: [PluginType::String, PluginType::UInt].as_ptr(),
-        arg_types_len: 2,
-        return_type: PluginType::String,
+    static METADATA: DynamicMetadata = DynamicMetadata::new();
+    METADATA.get(|| {
+        let metadata =
+            MetadataBuilder::new(c"repeat".into(), PluginType::String).arg(PluginType::String);
+        // we only use host services when they're available, so none are required
+        match default_count() {
+            Ok(Some(_)) => metadata,
+            _ => metadata.arg(PluginType::UInt),
+        }
+    })
+}
+
+// An optional export that lets the host introspect each argument at runtime. All strings are
+// static, so the host does not need to free them. Exports whose signature has changed are
+// versioned by their name: hosts use the newest version they understand, so exporting older
+// versions too keeps us loadable by older hosts.
+#[no_mangle]
+pub extern "C" fn plugin_describe_arg_v2(index: usize) -> ArgDescriptor {
+    match index {
+        0 => ArgDescriptor {
+            name: c"string".as_ptr(),
+            arg_type: PluginType::String,
+            optional: false,
+            doc: c"the string to repeat".as_ptr(),
+            secret: false,
+            min: null(),
+            max: null(),
+            max_len: 0,
+        },
+        1 if !matches!(default_count(), Ok(Some(_))) => ArgDescriptor {
+            name: c"count".as_ptr(),
+            arg_type: PluginType::UInt,
+            optional: false,
+            doc: c"the number of times to repeat the string".as_ptr(),
+            secret: false,
+            min: null(),
+            max: &PluginValue::UInt(MAX_COUNT),
+            max_len: 0,
+        },
+        _ => ArgDescriptor {
+            name: null(),
+            arg_type: PluginType::String,
+            optional: false,
+            doc: null(),
+            secret: false,
+            min: null(),
+            max: null(),
+            max_len: 0,
+        },
+    }
+}
+
+// Version 1 of `plugin_describe_arg_v2`, for hosts that don't read the secret flag or bounds (and
+// so don't redact or enforce them)
+#[no_mangle]
+pub extern "C" fn plugin_describe_arg(index: usize) -> ArgDescriptorV1 {
+    let descriptor = plugin_describe_arg_v2(index);
+    ArgDescriptorV1 {
+        name: descriptor.name,
+        arg_type: descriptor.arg_type,
+        optional: descriptor.optional,
+        doc: descriptor.doc,
+    }
+}
+
+// An optional export advertising our capabilities. Our result only depends on the arguments (and
+// the config, which can't change once we're loaded), and we keep no state between calls.
+#[no_mangle]
+pub extern "C" fn plugin_capabilities() -> u64 {
+    PLUGIN_THREAD_SAFE | PLUGIN_PURE
+}
+
+// An optional export that tags the plugin, so that hosts managing many plugins can categorize and
+// filter them. Like argument descriptions, tags are static, and the host reads them one at a time
+// until we return null.
+static TAGS: [&CStr; 2] = [c"category=text", c"example"];
+
+#[no_mangle]
+pub extern "C" fn plugin_describe_tag(index: usize) -> *const i8 {
+    TAGS.get(index).map_or(null(), |tag| tag.as_ptr())
+}
+
+fn plugin_error(code: PluginErrorCode, message: impl Into<String>) -> PluginResult {
+    PluginResult::Err {
+        code,
+        message: CString::new(message.into()).unwrap().into_raw(),
+        payload: std::ptr::null_mut(),
+    }
+}
+
+// Reads the scalar arguments, failing if the value is of a different type. The host checks
+// arguments against our metadata before calling us, so these only fail if it's buggy.
+pub fn bool_arg(value: &PluginValue) -> Result<bool, PluginResult> {
+    match value {
+        PluginValue::Bool(b) => Ok(*b),
+        _ => Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected Bool",
+        )),
+    }
+}
+
+pub fn int_arg(value: &PluginValue) -> Result<i64, PluginResult> {
+    match value {
+        PluginValue::Int(i) => Ok(*i),
+        _ => Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected Int",
+        )),
+    }
+}
+
+pub fn uint_arg(value: &PluginValue) -> Result<u64, PluginResult> {
+    match value {
+        PluginValue::UInt(u) => Ok(*u),
+        _ => Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected UInt",
+        )),
+    }
+}
+
+pub fn double_arg(value: &PluginValue) -> Result<f64, PluginResult> {
+    match value {
+        PluginValue::Double(d) => Ok(*d),
+        _ => Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected Double",
+        )),
     }
 }
 
-fn plugin_error(message: impl Into<String>) -> PluginResult {
-    PluginResult::Err(CString::new(message.into()).unwrap().into_raw())
+// Borrows the contents of a String argument, failing if it isn't valid UTF-8. Like the other
+// borrowed arguments, the string must not outlive the call.
+pub fn str_arg(value: &PluginValue) -> Result<&str, PluginResult> {
+    let PluginValue::String(string) = value else {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected String",
+        ));
+    };
+    borrow_str(*string, "string")
+}
+
+// Borrows an optional String argument, given None if the host passed fewer arguments. Omitted
+// arguments may also be passed as Null, so both are read as None.
+pub fn optional_str_arg(value: Option<&PluginValue>) -> Result<Option<&str>, PluginResult> {
+    match value {
+        None | Some(PluginValue::Null) => Ok(None),
+        Some(value) => str_arg(value).map(Some),
+    }
+}
+
+// Views a null-terminated string passed by the host as a &str, which is the only place we need to
+// trust its pointer; `what` describes the string in errors
+fn borrow_str<'a>(ptr: *const i8, what: &str) -> Result<&'a str, PluginResult> {
+    if ptr.is_null() {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            format!("a {} argument is null", what),
+        ));
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_str().map_err(|_| {
+        plugin_error(
+            PluginErrorCode::InvalidArgument,
+            format!("expected valid UTF-8 {}", what),
+        )
+    })
+}
+
+// Borrows the items of a List argument as a slice. The items are only borrowed from the host, so
+// the slice must not outlive the call.
+pub fn list_arg(value: &PluginValue) -> Result<&[PluginValue], PluginResult> {
+    let PluginValue::List { items, len } = value else {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected List",
+        ));
+    };
+    borrow_slice(*items, *len)
+}
+
+// Borrows the contents of a Bytes argument as a slice, which like `list_arg` must not outlive the
+// call
+pub fn bytes_arg(value: &PluginValue) -> Result<&[u8], PluginResult> {
+    let PluginValue::Bytes { ptr, len } = value else {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected Bytes",
+        ));
+    };
+    borrow_slice(*ptr, *len)
+}
+
+// Views a pointer and length passed by the host as a slice, which is the only place we need to
+// trust them. An empty slice may be passed as a null (or dangling) pointer, which can't be used to
+// build a slice, so it's handled separately.
+fn borrow_slice<'a, T>(ptr: *const T, len: usize) -> Result<&'a [T], PluginResult> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if ptr.is_null() {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "a non-empty argument is null",
+        ));
+    }
+    Ok(unsafe { std::slice::from_raw_parts(ptr, len) })
+}
+
+// Borrows the file behind an Fd argument, which can be written to like any other file. The host
+// owns the descriptor and closes it after the call, so the file is wrapped in `ManuallyDrop` to
+// keep it from being closed here; it must not be used after the call returns.
+pub fn fd_arg(value: &PluginValue) -> Result<ManuallyDrop<File>, PluginResult> {
+    let PluginValue::Fd(fd) = value else {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected Fd",
+        ));
+    };
+    #[cfg(unix)]
+    let file = {
+        use std::os::unix::io::FromRawFd;
+        unsafe { File::from_raw_fd(*fd as i32) }
+    };
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::io::FromRawHandle;
+        unsafe { File::from_raw_handle(*fd as *mut c_void) }
+    };
+    Ok(ManuallyDrop::new(file))
+}
+
+// Reads a Json argument, returning an error result if it doesn't contain valid JSON
+#[cfg(feature = "serde")]
+pub fn json_arg(value: &PluginValue) -> Result<serde_json::Value, PluginResult> {
+    let PluginValue::Json(json) = value else {
+        return Err(plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected Json",
+        ));
+    };
+
+    serde_json::from_str(borrow_str(*json, "JSON")?).map_err(|e| {
+        plugin_error(
+            PluginErrorCode::InvalidArgument,
+            format!("invalid JSON: {}", e),
+        )
+    })
+}
+
+// Builds a successful Json result, which will be freed by the host
+#[cfg(feature = "serde")]
+pub fn json_result(value: &serde_json::Value) -> PluginResult {
+    // serialized JSON never contains a raw NUL byte, as it's escaped within strings
+    PluginResult::Ok(PluginValue::Json(
+        CString::new(value.to_string()).unwrap().into_raw(),
+    ))
+}
+
+// Builds a Result value, e.g. for the outcome of parsing one item of a list, which will be freed by
+// the host along with the value it holds. This is part of a successful call's answer, unlike an
+// error result, which fails the whole call.
+pub fn result_value(value: Result<PluginValue, PluginValue>) -> PluginValue {
+    match value {
+        Ok(value) => PluginValue::ResultOk(Box::into_raw(Box::new(value))),
+        Err(value) => PluginValue::ResultErr(Box::into_raw(Box::new(value))),
+    }
 }
 
 // The main plugin function that will be called by the host. It is annotated with #[no_mangle] to
@@ -65,36 +803,348 @@ fn plugin_error(message: impl Into<String>) -> PluginResult {
 //
 // In a real plugin system, you would likely want to generate this function using a macro to avoid
 // the boilerplate.
+//
+// The host is trusted to pass a valid pointer to `args_len` values, so the function itself is not
+// marked unsafe (though we do guard against a null pointer).
 #[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn plugin_entrypoint(args: *const PluginValue, args_len: usize) -> PluginResult {
-    // first we need to check if the arguments are valid
-    if args_len != 2 {
-        return plugin_error("args_len should be 2");
+    // first we need to check if the arguments are valid, starting with the pointer itself, as a
+    // buggy host could pass null
+    if args_len > 0 && args.is_null() {
+        return plugin_error(PluginErrorCode::InvalidArgument, "args is null");
+    }
+
+    let default_count = default_count().ok().flatten();
+    let expected_len = if default_count.is_some() { 1 } else { 2 };
+    if args_len != expected_len {
+        return plugin_error(
+            PluginErrorCode::InvalidArgument,
+            format!("args_len should be {}", expected_len),
+        );
     }
 
-    let PluginValue::String(string) = (unsafe { &*args.offset(0) }) else {
-        return plugin_error("arg0 is invalid; expected String");
+    let string = match str_arg(unsafe { &*args.offset(0) }) {
+        Ok(string) => string,
+        Err(e) => return e,
     };
 
-    let PluginValue::UInt(count) = (unsafe { &*args.offset(1) }) else {
-        return plugin_error("arg1 is invalid; expected UInt");
+    let count = match default_count {
+        Some(count) => count,
+        None => match uint_arg(unsafe { &*args.offset(1) }) {
+            Ok(count) => count,
+            Err(e) => return e,
+        },
     };
+    if count > MAX_COUNT {
+        return plugin_error(
+            PluginErrorCode::InvalidArgument,
+            format!("arg1 is invalid; expected at most {}", MAX_COUNT),
+        );
+    }
+
+    // an optional separator to place between each repetition, provided by the host's config
+    let separator = config("separator").unwrap_or_default();
+    log(
+        LogLevel::Debug,
+        &format!(
+            "repeating {:?} {} times with separator {:?}",
+            string, count, separator
+        ),
+    );
 
-    let string = match unsafe { CStr::from_ptr(*string) }.to_str() {
-        Ok(value) => value,
-        Err(_) => {
-            return plugin_error("arg0 is invalid; expected valid UTF-8 string");
+    match catch_unwind(|| repeat_impl(string, count, &separator, cancelled, report_progress)) {
+        Ok(Ok(value)) => match host_string(&CString::new(value).unwrap()) {
+            Some(string) => PluginResult::Ok(PluginValue::String(string)),
+            None => plugin_error(PluginErrorCode::Failed, "could not allocate the result"),
+        },
+        Ok(Err(RepeatError::Cancelled)) => {
+            plugin_error(PluginErrorCode::Cancelled, "call was cancelled")
         }
-    };
+        Ok(Err(RepeatError::TooLarge)) => {
+            plugin_error(PluginErrorCode::Failed, "result is too large to allocate")
+        }
+        Err(_) => plugin_error(PluginErrorCode::Panicked, "function panicked"),
+    }
+}
 
-    match catch_unwind(|| repeat_impl(string, *count)) {
-        Ok(value) => PluginResult::Ok(PluginValue::String(CString::new(value).unwrap().into_raw())),
-        Err(_) => plugin_error("function panicked"),
+// The out-parameter variant of `plugin_entrypoint`, which returns the repeated string and its length
+// in bytes as two separate values. The host owns the `out` array and takes ownership of the values
+// we write to it once we return `Ok(Null)`; on an error, we must not leave anything there for it to
+// free.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn plugin_entrypoint_out(
+    args: *const PluginValue,
+    args_len: usize,
+    out: *mut PluginValue,
+    out_capacity: usize,
+    out_len: *mut usize,
+) -> PluginResult {
+    if out.is_null() || out_len.is_null() || out_capacity < 2 {
+        return plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "out should have room for 2 values",
+        );
     }
+
+    let string = match plugin_entrypoint(args, args_len) {
+        PluginResult::Ok(PluginValue::String(string)) => string,
+        // the entrypoint only fails before allocating its result
+        result => return result,
+    };
+    let len = unsafe { CStr::from_ptr(string) }.to_bytes().len();
+    unsafe {
+        out.write(PluginValue::String(string));
+        out.add(1).write(PluginValue::UInt(len as u64));
+        *out_len = 2;
+    }
+    PluginResult::Ok(PluginValue::Null)
+}
+
+// Why `repeat_impl` didn't return a result
+#[derive(Debug, PartialEq)]
+enum RepeatError {
+    // The host cancelled the call
+    Cancelled,
+    // The result's size overflows, or it couldn't be allocated
+    TooLarge,
 }
 
 // The actual implementation of the plugin function. This is a normal Rust function that can be
-// tested and used in other Rust code.
-fn repeat_impl(arg1: &str, arg2: u64) -> String {
-    arg1.repeat(arg2 as usize)
+// tested and used in other Rust code. Long strings can take a while to repeat, so it checks before
+// each repetition whether it's been cancelled, and reports its progress. The result is allocated
+// up front, failing rather than aborting if there isn't room for it.
+fn repeat_impl(
+    arg1: &str,
+    arg2: u64,
+    separator: &str,
+    is_cancelled: impl Fn() -> bool,
+    report_progress: impl Fn(f64),
+) -> Result<String, RepeatError> {
+    let len = usize::try_from(arg2)
+        .ok()
+        .and_then(|count| (arg1.len() + separator.len()).checked_mul(count))
+        .ok_or(RepeatError::TooLarge)?;
+    let mut result = String::new();
+    result
+        .try_reserve_exact(len)
+        .map_err(|_| RepeatError::TooLarge)?;
+    for i in 0..arg2 {
+        if is_cancelled() {
+            return Err(RepeatError::Cancelled);
+        }
+        report_progress(i as f64 / arg2 as f64);
+        if i > 0 {
+            result.push_str(separator);
+        }
+        result.push_str(arg1);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The message of an error result, freeing it as the host would
+    fn error_message(result: PluginResult) -> String {
+        let PluginResult::Err { message, .. } = result else {
+            panic!("expected an error");
+        };
+        unsafe { CString::from_raw(message) }.into_string().unwrap()
+    }
+
+    #[test]
+    fn entrypoint_rejects_null_args() {
+        let result = plugin_entrypoint(null(), 2);
+        assert!(matches!(
+            result,
+            PluginResult::Err {
+                code: PluginErrorCode::InvalidArgument,
+                ..
+            }
+        ));
+        assert_eq!(error_message(result), "args is null");
+
+        // no arguments may be passed as a null pointer, but repeat needs some
+        assert_eq!(
+            error_message(plugin_entrypoint(null(), 0)),
+            "args_len should be 2"
+        );
+    }
+
+    #[test]
+    fn repeat_stops_once_cancelled() {
+        assert_eq!(
+            repeat_impl("ab", 3, "-", || false, |_| {}).unwrap(),
+            "ab-ab-ab"
+        );
+        assert_eq!(
+            repeat_impl("ab", 3, "-", || true, |_| {}),
+            Err(RepeatError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn repeat_fails_if_result_is_too_large() {
+        // the result's size overflows a usize
+        assert_eq!(
+            repeat_impl("ab", u64::MAX, "", || false, |_| {}),
+            Err(RepeatError::TooLarge)
+        );
+        // it fits in a usize, but is far more than can be allocated
+        let count = (usize::MAX / 4) as u64;
+        assert_eq!(
+            repeat_impl("ab", count, "", || false, |_| {}),
+            Err(RepeatError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn entrypoint_rejects_count_above_max() {
+        let args = [
+            PluginValue::String(c"ab".as_ptr()),
+            PluginValue::UInt(MAX_COUNT + 1),
+        ];
+        let result = plugin_entrypoint(args.as_ptr(), args.len());
+        assert_eq!(
+            error_message(result),
+            "arg1 is invalid; expected at most 1000"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_arg_parses_nested_object() {
+        let json = cr#"{"a":{"b":[1,2]}}"#;
+        let Ok(value) = json_arg(&PluginValue::Json(json.as_ptr())) else {
+            panic!("expected valid JSON");
+        };
+        assert_eq!(value["a"]["b"][1], 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_arg_rejects_malformed_json() {
+        let Err(e) = json_arg(&PluginValue::Json(c"{\"a\":".as_ptr())) else {
+            panic!("expected an error");
+        };
+        assert!(error_message(e).starts_with("invalid JSON"));
+    }
+
+    #[test]
+    fn list_arg_sums_int_items() {
+        let items = [
+            PluginValue::Int(3),
+            PluginValue::Int(-1),
+            PluginValue::Int(40),
+        ];
+        let list = PluginValue::List {
+            items: items.as_ptr(),
+            len: items.len(),
+        };
+        let sum = list_arg(&list)
+            .unwrap_or_else(|e| panic!("{}", error_message(e)))
+            .iter()
+            .map(|item| match item {
+                PluginValue::Int(x) => *x,
+                _ => panic!("expected an Int"),
+            })
+            .sum::<i64>();
+        assert_eq!(sum, 42);
+
+        // an empty list may be passed as a null pointer, but a non-empty one can't
+        let empty = PluginValue::List {
+            items: std::ptr::null(),
+            len: 0,
+        };
+        assert!(matches!(list_arg(&empty), Ok([])));
+        let Err(e) = list_arg(&PluginValue::List {
+            items: std::ptr::null(),
+            len: 2,
+        }) else {
+            panic!("expected an error");
+        };
+        assert_eq!(error_message(e), "a non-empty argument is null");
+        let Err(e) = list_arg(&PluginValue::Int(1)) else {
+            panic!("expected an error");
+        };
+        assert_eq!(error_message(e), "expected List");
+    }
+
+    #[test]
+    fn bytes_arg_borrows_contents() {
+        let bytes = [1u8, 2, 3];
+        let value = PluginValue::Bytes {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        };
+        assert!(matches!(bytes_arg(&value), Ok([1, 2, 3])));
+    }
+
+    #[test]
+    fn str_arg_borrows_utf8_string() {
+        let error = |value: &PluginValue| match str_arg(value) {
+            Ok(s) => panic!("expected an error, got {:?}", s),
+            Err(e) => error_message(e),
+        };
+
+        assert!(matches!(
+            str_arg(&PluginValue::String(c"cool".as_ptr())),
+            Ok("cool")
+        ));
+        assert_eq!(error(&PluginValue::UInt(1)), "expected String");
+        assert_eq!(
+            error(&PluginValue::String(null())),
+            "a string argument is null"
+        );
+        let invalid = c"ab\xff";
+        assert_eq!(
+            error(&PluginValue::String(invalid.as_ptr())),
+            "expected valid UTF-8 string"
+        );
+
+        // an optional string is None whether it's omitted or passed as Null
+        assert!(matches!(optional_str_arg(None), Ok(None)));
+        assert!(matches!(
+            optional_str_arg(Some(&PluginValue::Null)),
+            Ok(None)
+        ));
+        assert!(matches!(
+            optional_str_arg(Some(&PluginValue::String(c"hi".as_ptr()))),
+            Ok(Some("hi"))
+        ));
+    }
+
+    #[test]
+    fn scalar_args_check_their_type() {
+        assert!(matches!(bool_arg(&PluginValue::Bool(true)), Ok(true)));
+        assert!(matches!(int_arg(&PluginValue::Int(-2)), Ok(-2)));
+        assert!(matches!(uint_arg(&PluginValue::UInt(3)), Ok(3)));
+        assert!(matches!(double_arg(&PluginValue::Double(0.5)), Ok(0.5)));
+
+        let Err(e) = uint_arg(&PluginValue::Int(3)) else {
+            panic!("expected an error");
+        };
+        assert_eq!(error_message(e), "expected UInt");
+        let Err(e) = double_arg(&PluginValue::UInt(1)) else {
+            panic!("expected an error");
+        };
+        assert_eq!(error_message(e), "expected Double");
+    }
+
+    #[test]
+    fn resolves_relative_paths_against_base_dir() {
+        assert_eq!(resolve_path_in(None, "data.txt"), Path::new("data.txt"));
+        let base_dir = Some("/srv/plugin-data");
+        assert_eq!(
+            resolve_path_in(base_dir, "data.txt"),
+            Path::new("/srv/plugin-data/data.txt")
+        );
+        assert_eq!(
+            resolve_path_in(base_dir, "/etc/hosts"),
+            Path::new("/etc/hosts")
+        );
+    }
 }
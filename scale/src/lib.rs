@@ -0,0 +1,151 @@
+// A second example plugin, which exercises the Double, Int and Bool value types that the `repeat`
+// plugin doesn't use. It implements `scale(x: Double, factor: Int, negate: Bool) -> Double`.
+//
+// The FFI types must match the definitions in the host; they are copied here (rather than shared
+// through a common crate) to keep each plugin self-contained. Plugins that don't use optional
+// exports like `plugin_init` can simply leave them out.
+use std::ffi::CString;
+use std::panic::catch_unwind;
+
+#[repr(C)]
+pub enum PluginValue {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Double(f64),
+    String(*const i8),
+    Json(*const i8),
+    Handle(u64),
+    List {
+        items: *const PluginValue,
+        len: usize,
+    },
+    Bytes {
+        ptr: *const u8,
+        len: usize,
+    },
+    Custom {
+        type_id: u32,
+        ptr: *const u8,
+        len: usize,
+    },
+    // No value, returned by a plugin that succeeded but has nothing to return (e.g., a lookup that
+    // found nothing); this is distinct from an error
+    Null,
+    // A file descriptor (on Windows, a HANDLE) for a file opened by the host, which controls which
+    // files the plugin can access. It's only borrowed by the plugin, which must not close it; the
+    // host closes it after the call. File descriptors can be passed as arguments, but not returned.
+    Fd(i64),
+    // The outcome of an operation within a successful call, like a Rust `Result`. Results passed
+    // as arguments are borrowed by the plugin; returned ones must point to a boxed value
+    // (`Box<PluginValue>`), which is freed by the host along with the value it contains.
+    ResultOk(*const PluginValue),
+    ResultErr(*const PluginValue),
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum PluginType {
+    Bool,
+    Int,
+    UInt,
+    Double,
+    String,
+    Json,
+    Handle,
+    List,
+    Bytes,
+    Fd,
+    Result,
+}
+
+#[repr(C)]
+pub enum PluginResult {
+    Ok(PluginValue),
+    PartialOk {
+        value: PluginValue,
+        warning: *mut i8,
+    },
+    // The host is responsible for freeing the error message
+    Err {
+        code: PluginErrorCode,
+        message: *mut i8,
+        // An optional JSON payload with structured details of the error (e.g., which argument was
+        // invalid), or null; the host is also responsible for freeing it
+        payload: *mut i8,
+    },
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub enum PluginErrorCode {
+    Failed,
+    InvalidArgument,
+    Panicked,
+    Cancelled,
+}
+
+#[repr(C)]
+pub struct PluginMetadata {
+    pub name: *const i8,
+    pub arg_types: *const PluginType,
+    pub arg_types_len: usize,
+    pub return_type: PluginType,
+    pub required_host_capabilities: u64,
+}
+
+#[no_mangle]
+pub extern "C" fn plugin_metadata() -> PluginMetadata {
+    PluginMetadata {
+        name: c"scale".as_ptr(),
+        arg_types: [PluginType::Double, PluginType::Int, PluginType::Bool].as_ptr(),
+        arg_types_len: 3,
+        return_type: PluginType::Double,
+        required_host_capabilities: 0,
+    }
+}
+
+fn plugin_error(code: PluginErrorCode, message: impl Into<String>) -> PluginResult {
+    PluginResult::Err {
+        code,
+        message: CString::new(message.into()).unwrap().into_raw(),
+        payload: std::ptr::null_mut(),
+    }
+}
+
+// See the `repeat` plugin for a description of how the entrypoint works. As none of the arguments
+// or the result are heap-allocated, there's no memory to manage here.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn plugin_entrypoint(args: *const PluginValue, args_len: usize) -> PluginResult {
+    // a buggy host could pass a null pointer, which we must not dereference
+    if args_len > 0 && args.is_null() {
+        return plugin_error(PluginErrorCode::InvalidArgument, "args is null");
+    }
+
+    if args_len != 3 {
+        return plugin_error(PluginErrorCode::InvalidArgument, "args_len should be 3");
+    }
+
+    let args = unsafe { std::slice::from_raw_parts(args, args_len) };
+    let [PluginValue::Double(x), PluginValue::Int(factor), PluginValue::Bool(negate)] = args else {
+        return plugin_error(
+            PluginErrorCode::InvalidArgument,
+            "expected arguments of types Double, Int, Bool",
+        );
+    };
+
+    match catch_unwind(|| scale_impl(*x, *factor, *negate)) {
+        Ok(value) => PluginResult::Ok(PluginValue::Double(value)),
+        Err(_) => plugin_error(PluginErrorCode::Panicked, "function panicked"),
+    }
+}
+
+fn scale_impl(x: f64, factor: i64, negate: bool) -> f64 {
+    let scaled = x * factor as f64;
+    if negate {
+        -scaled
+    } else {
+        scaled
+    }
+}